@@ -1,5 +1,12 @@
 use clap::{Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
+use ytaudio_core::config::HooksConfig;
+use ytaudio_core::decoder::Clip;
+use ytaudio_core::error::DecodeError;
+use ytaudio_core::encoder::{BitDepth, Channels, EncodeOptions, FormatChoice};
+use ytaudio_core::metadata::OverwritePolicy;
+use ytaudio_core::{Config, OutputFormat};
+use ytaudio_upscale::{MAX_AUDIOSR_SEED, MIN_OUTPUT_RATE, MODEL_SAMPLE_RATE};
 
 #[derive(Parser)]
 #[command(name = "ytaudio")]
@@ -9,29 +16,30 @@ pub struct Cli {
     #[command(subcommand)]
     pub command: Option<Commands>,
 
-    /// YouTube URL to process (shorthand for `extract <URL>`)
+    /// YouTube URL or local audio file to process (shorthand for `extract <URL>`)
     #[arg(value_name = "URL")]
     pub url: Option<String>,
 
-    /// Enable neural upscaling (bandwidth extension)
+    /// Enable neural upscaling (bandwidth extension; or set upscale.enabled)
     #[arg(short, long)]
     pub enhance: bool,
 
-    /// Output format
-    #[arg(short, long, value_enum, default_value = "flac")]
-    pub format: OutputFormat,
+    /// Output format: flac, wav, mp3, aac, opus, vorbis, wavpack, or auto to
+    /// pick from the source (defaults to output.default_format, flac)
+    #[arg(short, long, value_name = "FORMAT")]
+    pub format: Option<FormatChoice>,
 
-    /// Apply LUFS normalization (-14 LUFS by default)
+    /// Apply LUFS normalization (-14 LUFS by default; or set normalize.enabled)
     #[arg(short, long)]
     pub normalize: bool,
 
-    /// Target LUFS level (requires --normalize)
-    #[arg(long, default_value = "-14.0", requires = "normalize")]
-    pub lufs: f32,
+    /// Target LUFS level (requires --normalize; defaults to normalize.target_lufs)
+    #[arg(long, requires = "normalize")]
+    pub lufs: Option<f32>,
 
-    /// Upscaling quality: best (AudioSR) or fast (FlashSR)
-    #[arg(short, long, value_enum, default_value = "fast")]
-    pub quality: UpscaleQuality,
+    /// Upscaling quality: best (AudioSR) or fast (FlashSR) (defaults to upscale.default_quality)
+    #[arg(short, long, value_enum)]
+    pub quality: Option<UpscaleQuality>,
 
     /// Output directory
     #[arg(short, long, default_value = ".")]
@@ -41,17 +49,32 @@ pub struct Cli {
     #[arg(short, long, action = clap::ArgAction::Count)]
     pub verbose: u8,
 
+    /// No progress bars or status notes, and only warnings in the log unless
+    /// -v is given; for cron jobs and other programs' terminals
+    #[arg(long)]
+    pub quiet: bool,
+
     /// Config file path
     #[arg(long)]
     pub config: Option<PathBuf>,
+
+    /// Apply the config's [profiles.<NAME>] table over the base settings
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
-    /// Extract audio from a single URL
+    /// Extract audio from one or more URLs, one after another
     Extract {
-        /// YouTube URL
-        url: String,
+        /// YouTube URLs or local audio files
+        #[arg(value_name = "URL", required = true)]
+        urls: Vec<String>,
+
+        /// Bug-report mode: no progress bar, trace logging, full subprocess output,
+        /// keep temp files; with several URLs, stop at the first failure
+        #[arg(long)]
+        debug_run: bool,
 
         #[command(flatten)]
         options: ExtractOptions,
@@ -59,49 +82,275 @@ pub enum Commands {
 
     /// Batch process multiple URLs
     Batch {
-        /// File containing URLs (one per line) or playlist URL
+        /// File containing URLs or local file paths (one per line), or `-` to
+        /// read them from stdin as they arrive
         #[arg(short, long)]
         input: PathBuf,
 
-        /// Maximum parallel downloads
+        /// Maximum tracks in flight; each stage is further limited by
+        /// batch.max_parallel_downloads, max_parallel_upscale and max_parallel_ffmpeg
         #[arg(short, long, default_value = "4")]
         parallel: usize,
 
+        /// Exit with status 0 even if some URLs failed, and keep going after a
+        /// failure even with batch.continue_on_error off
+        #[arg(long)]
+        continue_on_error: bool,
+
+        /// Normalize the batch as one album: measure every track, then apply a
+        /// single gain that brings the whole set to --lufs (instead of --normalize)
+        #[arg(long, conflicts_with = "normalize")]
+        normalize_album: bool,
+
+        /// Also pack every file written into this zip, as items finish, with a
+        /// manifest.json mapping each URL to its files
+        #[arg(long, value_name = "ZIP")]
+        archive_output: Option<PathBuf>,
+
+        /// Delete the loose files once --archive-output is written
+        #[arg(long, requires = "archive_output")]
+        remove_archived: bool,
+
+        /// Order to process the URLs in. Anything but `file` fetches every
+        /// URL's metadata first; URLs without it go last.
+        #[arg(long, value_enum, default_value = "file")]
+        order: BatchOrder,
+
+        /// POST a JSON summary to this webhook when the batch ends (defaults
+        /// to notify.webhook_url); a failed POST is only logged
+        #[arg(long, value_name = "URL", value_parser = parse_webhook_url)]
+        notify: Option<String>,
+
         #[command(flatten)]
         options: ExtractOptions,
     },
 
+    /// Show a video's metadata and audio formats without downloading
+    Info {
+        /// YouTube URL
+        url: String,
+
+        /// Only list the audio-only formats, best bitrate first, with the IDs
+        /// to pass to --format-selector
+        #[arg(long)]
+        list_formats: bool,
+    },
+
+    /// Report loudness (LUFS, LRA, true peak) without changing anything
+    Measure {
+        /// Audio file, or a directory to measure recursively with a summary
+        input: PathBuf,
+    },
+
+    /// Upscale a local audio file without downloading anything
+    Upscale {
+        /// Audio file to upscale (any format FFmpeg decodes)
+        input: PathBuf,
+
+        /// Output file; its extension picks the format (WAV if none)
+        output: PathBuf,
+
+        /// Upscaling quality (defaults to upscale.default_quality, fast)
+        #[arg(short, long, value_enum)]
+        quality: Option<UpscaleQuality>,
+
+        /// AudioSR seed, to reproduce a result (defaults to upscale.audiosr.seed, else random)
+        #[arg(long, value_parser = clap::value_parser!(u64).range(..=MAX_AUDIOSR_SEED))]
+        seed: Option<u64>,
+
+        /// Rate to write the upscaled audio at, 32000 to 48000 Hz (defaults to
+        /// upscale.target_rate, else the models' 48kHz)
+        #[arg(long, value_name = "HZ", value_parser = target_rate_parser())]
+        target_rate: Option<u32>,
+    },
+
     /// Check and install dependencies
-    Doctor,
+    Doctor {
+        /// Install missing Python packages and models (as update-models does), then re-check
+        #[arg(long)]
+        fix: bool,
+
+        /// Also load each model and upscale a short test tone on the configured
+        /// device, reporting how long it took
+        #[arg(long)]
+        deep: bool,
+    },
 
     /// Download/update neural models
     UpdateModels,
 
+    /// Remove the temp directories ytaudio left behind (--keep-temp, --resume,
+    /// interrupted runs). Don't run it while another ytaudio job is running.
+    Clean {
+        /// Also remove the downloaded FlashSR and AudioSR models
+        #[arg(long)]
+        models: bool,
+
+        /// Only list what would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+
     /// Show configuration
-    Config,
+    #[command(args_conflicts_with_subcommands = true)]
+    Config {
+        #[command(subcommand)]
+        action: Option<ConfigAction>,
+
+        /// Write the default configuration to the config file
+        #[arg(long)]
+        init: bool,
+
+        /// Overwrite an existing config file (with --init)
+        #[arg(long, requires = "init")]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    /// Check a config file without running anything; exits non-zero if any
+    /// value is invalid
+    Validate {
+        /// Config file to check (default: --config, or the default config location)
+        path: Option<PathBuf>,
+    },
 }
 
 #[derive(clap::Args, Clone)]
 pub struct ExtractOptions {
-    /// Enable neural upscaling (bandwidth extension)
+    /// Enable neural upscaling (bandwidth extension; or set upscale.enabled)
     #[arg(short, long)]
     pub enhance: bool,
 
-    /// Output format
-    #[arg(short, long, value_enum, default_value = "flac")]
-    pub format: OutputFormat,
+    /// Upscale even when the source already has content above 16kHz
+    #[arg(long, requires = "enhance")]
+    pub force_upscale: bool,
+
+    /// Output format: flac, wav, mp3, aac, opus, vorbis, wavpack, or auto to
+    /// pick from the source (defaults to output.default_format, flac)
+    #[arg(short, long, value_name = "FORMAT")]
+    pub format: Option<FormatChoice>,
+
+    /// Additional formats to write from the same processed audio (comma-separated)
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub also: Vec<OutputFormat>,
+
+    /// Bitrate in kbps for lossy formats (MP3, AAC, Opus, Vorbis), defaults to
+    /// output.bitrate; ignored for lossless ones
+    #[arg(long, value_name = "KBPS", value_parser = clap::value_parser!(u32).range(8..=512))]
+    pub bitrate: Option<u32>,
+
+    /// Use constant bitrate instead of VBR for lossy formats
+    #[arg(long)]
+    pub cbr: bool,
+
+    /// Sample format of the decoded audio and WAV output (defaults to output.bit_depth)
+    #[arg(long, value_enum)]
+    pub bit_depth: Option<BitDepth>,
+
+    /// FLAC compression level, 0 (fastest) to 12 (smallest) (defaults to output.flac_compression)
+    #[arg(long, value_name = "LEVEL", value_parser = clap::value_parser!(u8).range(0..=12))]
+    pub compression_level: Option<u8>,
+
+    /// Channel layout of the output (defaults to output.channels)
+    #[arg(long, value_enum)]
+    pub channels: Option<Channels>,
+
+    /// Downmix to mono (same as --channels mono)
+    #[arg(long, conflicts_with = "channels")]
+    pub mono: bool,
 
-    /// Apply LUFS normalization
+    /// Apply LUFS normalization (or set normalize.enabled)
     #[arg(short, long)]
     pub normalize: bool,
 
-    /// Target LUFS level
-    #[arg(long, default_value = "-14.0")]
-    pub lufs: f32,
+    /// Target LUFS level (defaults to normalize.target_lufs, -14)
+    #[arg(long)]
+    pub lufs: Option<f32>,
+
+    /// Resample to this rate in Hz (defaults to output.resample; otherwise the
+    /// source rate is kept, or 48kHz when upscaling)
+    #[arg(long, value_name = "HZ", value_parser = clap::value_parser!(u32).range(8000..=384000))]
+    pub resample: Option<u32>,
+
+    /// Keep hi-res sources at up to 96kHz/24-bit even with --enhance, and
+    /// store upscaled audio at 96kHz (or set output.preserve_hires)
+    #[arg(long, conflicts_with = "resample")]
+    pub preserve_hires: bool,
+
+    /// Rate the upscalers write, 32000 to 48000 Hz, e.g. 44100 for CD: the
+    /// models' 48kHz output is resampled once (defaults to upscale.target_rate)
+    #[arg(
+        long,
+        value_name = "HZ",
+        value_parser = target_rate_parser(),
+        conflicts_with = "preserve_hires"
+    )]
+    pub target_rate: Option<u32>,
+
+    /// High-pass filter cutoff in Hz to remove rumble, applied after decoding
+    /// (defaults to filters.highpass; 0 disables)
+    #[arg(long, value_name = "HZ", value_parser = clap::value_parser!(u32).range(0..=20000))]
+    pub highpass: Option<u32>,
+
+    /// Low-pass filter cutoff in Hz, applied after decoding (defaults to
+    /// filters.lowpass; 0 disables)
+    #[arg(long, value_name = "HZ", value_parser = clap::value_parser!(u32).range(0..=96000))]
+    pub lowpass: Option<u32>,
+
+    /// Keep only this part of the video, e.g. 1:23-1:53 (M:SS, H:MM:SS or
+    /// seconds). Only the clip is decoded, upscaled and normalized
+    #[arg(long, value_name = "START-END", value_parser = parse_clip)]
+    pub trim: Option<Clip>,
+
+    /// Cut leading and trailing silence before normalization (or set filters.trim_silence)
+    #[arg(long)]
+    pub trim_silence: bool,
+
+    /// Fade in over the first SECS seconds (defaults to filters.fade_in; 0 disables)
+    #[arg(long, value_name = "SECS", value_parser = parse_fade)]
+    pub fade_in: Option<f32>,
+
+    /// Fade out over the last SECS seconds (defaults to filters.fade_out; 0 disables)
+    #[arg(long, value_name = "SECS", value_parser = parse_fade)]
+    pub fade_out: Option<f32>,
+
+    /// Extra FFmpeg filter chain run after --highpass/--lowpass while
+    /// decoding, e.g. "firequalizer=gain_entry='entry(100,-3)'" (defaults to filters.extra_af)
+    #[arg(long = "af", value_name = "CHAIN")]
+    pub af: Option<String>,
+
+    /// Limit true peaks to normalize.true_peak, even without --normalize
+    #[arg(long)]
+    pub limit_peaks: bool,
+
+    /// Normalization mode (defaults to normalize.mode from config)
+    #[arg(long, value_enum)]
+    pub normalize_mode: Option<NormalizeMode>,
+
+    /// Peak-normalize instead: one gain brings the true peak to this level in
+    /// dBTP (e.g. -1), whatever the loudness. Replaces --normalize
+    #[arg(
+        long,
+        value_name = "DBTP",
+        allow_negative_numbers = true,
+        value_parser = parse_peak,
+        conflicts_with_all = ["normalize", "lufs", "normalize_mode"]
+    )]
+    pub peak: Option<f32>,
+
+    /// Upscaling quality (defaults to upscale.default_quality, fast)
+    #[arg(short, long, value_enum)]
+    pub quality: Option<UpscaleQuality>,
+
+    /// Device for the neural models: cpu, cuda:N or mps (defaults to upscale.device from config)
+    #[arg(long)]
+    pub device: Option<String>,
 
-    /// Upscaling quality
-    #[arg(short, long, value_enum, default_value = "fast")]
-    pub quality: UpscaleQuality,
+    /// AudioSR seed, to reproduce a result (defaults to upscale.audiosr.seed, else random)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(..=MAX_AUDIOSR_SEED))]
+    pub seed: Option<u64>,
 
     /// Output directory
     #[arg(short, long)]
@@ -110,48 +359,263 @@ pub struct ExtractOptions {
     /// Keep intermediate files (for debugging)
     #[arg(long)]
     pub keep_temp: bool,
+
+    /// Keep intermediate files of a failed run and reuse them when the same
+    /// video is run again with the same settings (removed once it succeeds)
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Cut SponsorBlock segments (--sponsorblock=cat1,cat2; bare flag = music_offtopic,sponsor)
+    #[arg(long, value_delimiter = ',', num_args = 0..=1, require_equals = true)]
+    pub sponsorblock: Option<Vec<String>>,
+
+    /// Output path under the output directory, e.g. "{artist} - {title}" or "{date}/{title}"
+    /// (placeholders: title, artist, uploader, id, date, ext; defaults to output.filename_template)
+    #[arg(long, value_name = "TEMPLATE", value_parser = parse_filename_template)]
+    pub output_template: Option<String>,
+
+    /// Skip videos listed in this yt-dlp download archive and record new ones
+    /// (defaults to download.archive_file)
+    #[arg(long, value_name = "FILE")]
+    pub archive: Option<PathBuf>,
+
+    /// yt-dlp format selector passed to -f, e.g. 251 or "bestaudio[ext=m4a]"
+    /// (defaults to download.format_selector, else best Opus/AAC audio)
+    #[arg(long, value_name = "SELECTOR", value_parser = parse_format_selector)]
+    pub format_selector: Option<String>,
+
+    /// Proxy URL for yt-dlp, e.g. socks5://127.0.0.1:1080 (defaults to download.proxy)
+    #[arg(long)]
+    pub proxy: Option<String>,
+
+    /// Cap the download rate, e.g. 1M or 500K (defaults to download.rate_limit)
+    #[arg(long, value_name = "RATE", value_parser = parse_rate_limit)]
+    pub limit_rate: Option<String>,
+
+    /// yt-dlp --extractor-args, e.g. "youtube:player_client=android" when the
+    /// default client is refused (defaults to download.extractor_args)
+    #[arg(long, value_name = "ARGS")]
+    pub extractor_args: Option<String>,
+
+    /// Embed lyrics from the video's subtitles, in download.lyrics_lang
+    #[arg(long)]
+    pub lyrics: bool,
+
+    /// Write one file per YouTube chapter ("<album> - NN - <chapter>")
+    #[arg(long)]
+    pub split_chapters: bool,
+
+    /// Don't fetch or embed the thumbnail as cover art (or set metadata.embed_artwork = false)
+    #[arg(long)]
+    pub no_artwork: bool,
+
+    /// Embed a spectrogram of the output as a second picture, MP3 and FLAC only
+    /// (or set metadata.embed_spectrogram)
+    #[arg(long)]
+    pub embed_spectrogram: bool,
+
+    /// Strip "(Official Music Video)" and the like from the title and split
+    /// "Artist - Title" (or set metadata.clean_titles)
+    #[arg(long)]
+    pub clean_titles: bool,
+
+    /// Write the audio without any tags, artwork, chapters or lyrics (no
+    /// video ID, channel or upload date), e.g. for sharing publicly
+    #[arg(long, conflicts_with_all = ["lyrics", "embed_spectrogram"])]
+    pub no_metadata: bool,
+
+    /// Print the planned stages and commands without running them
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Skip URLs whose output file already exists
+    #[arg(long)]
+    pub skip_existing: bool,
+
+    /// When an output file exists: skip, overwrite or rename to "name (1)"
+    /// (or set output.on_exists)
+    #[arg(long, value_name = "POLICY")]
+    pub on_exists: Option<OverwritePolicy>,
+
+    /// Skip the final ffprobe check that each output holds playable audio
+    #[arg(long)]
+    pub no_verify: bool,
+
+    /// Don't run hooks.post_success / hooks.post_failure from the config
+    #[arg(long)]
+    pub no_hooks: bool,
+
+    /// Print how long each pipeline stage took
+    #[arg(long)]
+    pub stats: bool,
 }
 
-#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum OutputFormat {
-    /// FLAC - Lossless compression (recommended)
-    Flac,
-    /// WAV - Uncompressed PCM
-    Wav,
-    /// MP3 - Lossy, widely compatible
-    Mp3,
-    /// AAC - Lossy, good quality/size ratio
-    Aac,
-    /// Opus - Lossy, best quality/size ratio
-    Opus,
-}
-
-impl OutputFormat {
-    #[allow(dead_code)]
-    pub fn extension(&self) -> &'static str {
-        match self {
-            OutputFormat::Flac => "flac",
-            OutputFormat::Wav => "wav",
-            OutputFormat::Mp3 => "mp3",
-            OutputFormat::Aac => "m4a",
-            OutputFormat::Opus => "opus",
+fn parse_filename_template(value: &str) -> Result<String, String> {
+    if ytaudio_core::metadata::is_valid_filename_template(value) {
+        Ok(value.to_string())
+    } else {
+        Err("expected a non-empty relative path without . or .. components".to_string())
+    }
+}
+
+fn parse_format_selector(value: &str) -> Result<String, String> {
+    if value.trim().is_empty() {
+        Err("expected a yt-dlp format selector like bestaudio".to_string())
+    } else {
+        Ok(value.to_string())
+    }
+}
+
+fn parse_fade(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(secs) if (0.0..=60.0).contains(&secs) => Ok(secs),
+        _ => Err("expected a length from 0 to 60 seconds".to_string()),
+    }
+}
+
+fn parse_clip(value: &str) -> Result<Clip, String> {
+    value.parse().map_err(|e: DecodeError| e.to_string())
+}
+
+fn parse_peak(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(dbtp) if (-30.0..=0.0).contains(&dbtp) => Ok(dbtp),
+        _ => Err("expected a true peak from -30 to 0 dBTP".to_string()),
+    }
+}
+
+fn parse_webhook_url(value: &str) -> Result<String, String> {
+    if ytaudio_core::config::is_webhook_url(value) {
+        Ok(value.to_string())
+    } else {
+        Err("expected an http:// or https:// URL".to_string())
+    }
+}
+
+fn parse_rate_limit(value: &str) -> Result<String, String> {
+    if ytaudio_core::config::is_rate_limit(value) {
+        Ok(value.to_string())
+    } else {
+        Err("expected a rate like 500K, 1.5M or 2G".to_string())
+    }
+}
+
+impl ExtractOptions {
+    /// Output format from the flag, falling back to config
+    pub fn output_format(&self, config: &Config) -> FormatChoice {
+        self.format.unwrap_or_else(|| {
+            config
+                .output
+                .default_format
+                .parse()
+                .unwrap_or(FormatChoice::Fixed(OutputFormat::Flac))
+        })
+    }
+
+    /// Whether to normalize loudness: the flag or config, unless `--peak`
+    /// asks for peak normalization instead
+    pub fn normalize(&self, config: &Config) -> bool {
+        self.peak.is_none() && (self.normalize || config.normalize.enabled)
+    }
+
+    /// Normalization target from the flag, falling back to config
+    pub fn target_lufs(&self, config: &Config) -> f32 {
+        self.lufs.unwrap_or(config.normalize.target_lufs)
+    }
+
+    /// Upscaler output rate from the flag, falling back to config; hi-res
+    /// mode stores upscales at 96kHz instead
+    pub fn target_rate(&self, config: &Config) -> Option<u32> {
+        match self.preserve_hires || config.output.preserve_hires {
+            true => None,
+            false => self.target_rate.or(config.upscale.target_rate),
+        }
+    }
+
+    /// Hooks from config, or none with `--no-hooks`
+    pub fn hooks(&self, config: &Config) -> HooksConfig {
+        if self.no_hooks {
+            HooksConfig::default()
+        } else {
+            config.hooks.clone()
+        }
+    }
+
+    /// Upscaling quality from the flag, falling back to config
+    pub fn upscale_quality(&self, config: &Config) -> UpscaleQuality {
+        self.quality.unwrap_or_else(|| UpscaleQuality::from_config(config))
+    }
+
+    /// Encoder settings from the flags, falling back to config
+    pub fn encode_options(&self, config: &Config) -> EncodeOptions {
+        EncodeOptions {
+            bitrate_kbps: self.bitrate.or(config.output.bitrate),
+            cbr: self.cbr,
+            // Config::load has already rejected invalid values
+            bit_depth: self
+                .bit_depth
+                .unwrap_or_else(|| config.output.bit_depth.parse().unwrap_or_default()),
+            flac_compression: self
+                .compression_level
+                .unwrap_or(config.output.flac_compression),
+            channels: if self.mono {
+                Channels::Mono
+            } else {
+                self.channels.unwrap_or_else(|| config.output.channels.parse().unwrap_or_default())
+            },
+            opus_application: config.output.opus.application.parse().unwrap_or_default(),
+            opus_vbr: config.output.opus.vbr.parse().unwrap_or_default(),
+        }
+    }
+
+    /// SponsorBlock categories from the flag, falling back to config
+    pub fn sponsorblock_categories(&self, config: &Config) -> Option<Vec<String>> {
+        match self.sponsorblock {
+            Some(ref categories) if categories.is_empty() => {
+                Some(vec!["music_offtopic".to_string(), "sponsor".to_string()])
+            }
+            Some(ref categories) => Some(categories.clone()),
+            None => config.download.sponsorblock.clone(),
         }
     }
 }
 
-impl std::fmt::Display for OutputFormat {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            OutputFormat::Flac => write!(f, "FLAC"),
-            OutputFormat::Wav => write!(f, "WAV"),
-            OutputFormat::Mp3 => write!(f, "MP3"),
-            OutputFormat::Aac => write!(f, "AAC"),
-            OutputFormat::Opus => write!(f, "Opus"),
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NormalizeMode {
+    /// EBU R128 loudnorm, re-encodes the audio
+    Loudnorm,
+    /// ReplayGain tags only, audio is left untouched
+    #[value(name = "replaygain")]
+    #[serde(rename = "replaygain")]
+    ReplayGain,
+    /// Dynamic normalization (dynaudnorm), brings up quiet passages in one pass
+    Dynamic,
+}
+
+impl From<NormalizeMode> for ytaudio_core::normalizer::NormalizeMode {
+    fn from(mode: NormalizeMode) -> Self {
+        match mode {
+            NormalizeMode::Loudnorm => Self::Loudnorm,
+            NormalizeMode::ReplayGain => Self::ReplayGainTags,
+            NormalizeMode::Dynamic => Self::Dynamic,
         }
     }
 }
 
+/// Processing order for `batch --order`
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BatchOrder {
+    /// As listed in the input
+    File,
+    /// Shortest first
+    DurationAsc,
+    /// Longest first
+    DurationDesc,
+    /// Newest upload (or date tag, for local files) first
+    Date,
+}
+
 #[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum UpscaleQuality {
@@ -161,6 +625,14 @@ pub enum UpscaleQuality {
     Fast,
 }
 
+impl UpscaleQuality {
+    /// `upscale.default_quality`, which Config::load has already validated
+    pub fn from_config(config: &Config) -> Self {
+        UpscaleQuality::from_str(&config.upscale.default_quality, true)
+            .unwrap_or(UpscaleQuality::Fast)
+    }
+}
+
 impl std::fmt::Display for UpscaleQuality {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -169,3 +641,8 @@ impl std::fmt::Display for UpscaleQuality {
         }
     }
 }
+
+/// `--target-rate` values the upscalers accept
+fn target_rate_parser() -> clap::builder::RangedI64ValueParser<u32> {
+    clap::value_parser!(u32).range(i64::from(MIN_OUTPUT_RATE)..=i64::from(MODEL_SAMPLE_RATE))
+}
@@ -1,153 +1,912 @@
 use anyhow::{Context, Result};
-use futures::stream::{self, StreamExt};
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use std::path::Path;
+use futures::future;
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::fs;
-use tokio::sync::Semaphore;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::JoinHandle;
+use tracing::warn;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
 
-use crate::args::{ExtractOptions, OutputFormat as CliFormat, UpscaleQuality as CliQuality};
+use crate::args::{BatchOrder, ExtractOptions, UpscaleQuality as CliQuality};
 use ytaudio_core::{
+    cancel::CancelFlag,
     config::Config,
-    pipeline::{Pipeline, PipelineConfig, OutputFormat, UpscaleQuality},
+    downloader::{Downloader, VideoMetadata},
+    error::{DownloadError, YtAudioError},
+    normalizer::{AlbumLoudness, NormalizeMode, REPLAYGAIN_REFERENCE_LUFS},
+    pipeline::{
+        Pipeline, PipelineConfig, PipelineInput, PipelineStage, StageTimings, UpscaleQuality,
+    },
 };
+use ytaudio_upscale::{FlashSR, FlashSRWorker};
 
+#[allow(clippy::too_many_arguments)]
 pub async fn run(
     input: &Path,
     parallel: usize,
+    continue_on_error: bool,
+    normalize_album: bool,
+    archive_output: Option<&Path>,
+    remove_archived: bool,
+    order: BatchOrder,
+    notify: Option<&str>,
     options: &ExtractOptions,
+    quiet: bool,
+    cancel: CancelFlag,
     config_path: Option<&Path>,
+    profile: Option<&str>,
 ) -> Result<()> {
-    let config = Config::load(config_path)?;
-
-    // Read URLs from file
-    let content = fs::read_to_string(input)
-        .await
-        .context("Failed to read input file")?;
-
-    let urls: Vec<String> = content
-        .lines()
-        .map(|l| l.trim())
-        .filter(|l| !l.is_empty() && !l.starts_with('#'))
-        .map(String::from)
-        .collect();
-
-    if urls.is_empty() {
-        println!("No URLs found in input file");
-        return Ok(());
+    let started = Instant::now();
+    let config = Config::load_profile(config_path, profile)?;
+    let notify = notify.or(config.notify.webhook_url.as_deref());
+    if normalize_album && options.peak.is_some() {
+        anyhow::bail!("--normalize-album and --peak cannot be used together");
+    }
+    if archive_output.is_some() && options.dry_run {
+        anyhow::bail!("--archive-output has nothing to pack in a --dry-run");
     }
 
-    let total_urls = urls.len();
-    println!("Processing {} URLs with {} parallel workers\n", total_urls, parallel);
+    // `-` streams URLs from stdin as a producer writes them, so the total is
+    // unknown. Album normalization needs every track counted up front, and
+    // --order every URL before sorting, so they read stdin to the end first.
+    let from_stdin = input == Path::new("-");
+    let streaming = from_stdin && !normalize_album && order == BatchOrder::File;
+    let (urls, total_urls): (BoxStream<'_, String>, Option<usize>) = if streaming {
+        (stdin_urls().boxed(), None)
+    } else {
+        let urls: Vec<String> = if from_stdin {
+            stdin_urls().collect().await
+        } else {
+            let content = fs::read_to_string(input)
+                .await
+                .context("Failed to read input file")?;
+            content.lines().filter_map(url_line).collect()
+        };
+        if urls.is_empty() {
+            println!("No URLs found in {}", if from_stdin { "stdin" } else { "input file" });
+            notify_empty(notify, started, cancel.is_cancelled()).await;
+            return Ok(());
+        }
+        let urls = match order {
+            BatchOrder::File => urls,
+            _ => match order_urls(urls, order, options, &config, parallel, quiet, &cancel).await {
+                Ok(urls) => urls,
+                Err(e) => {
+                    notify_empty(notify, started, cancel.is_cancelled()).await;
+                    return Err(e);
+                }
+            },
+        };
+        let total = urls.len();
+        (stream::iter(urls).boxed(), Some(total))
+    };
+
+    if !quiet {
+        match total_urls {
+            Some(total) => println!(
+                "Processing {} URLs with {} parallel workers\n",
+                total, parallel
+            ),
+            None => println!(
+                "Processing URLs from stdin with {} parallel workers\n",
+                parallel
+            ),
+        }
+    }
 
     let semaphore = Arc::new(Semaphore::new(parallel));
-    let multi = MultiProgress::new();
+    let upscale_limit = Arc::new(Semaphore::new(config.batch.max_parallel_upscale));
+    let download_limit = Arc::new(Semaphore::new(
+        config.batch.max_parallel_downloads.unwrap_or(parallel),
+    ));
+    let ffmpeg_limit = Arc::new(Semaphore::new(
+        config
+            .batch
+            .max_parallel_ffmpeg
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(4, |n| n.get())),
+    ));
+    let multi = if quiet {
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    } else {
+        MultiProgress::new()
+    };
 
     let spinner_style = ProgressStyle::with_template("{spinner:.cyan} {msg}")
         .unwrap()
         .tick_chars("=>-");
 
+    // Added first so it stays above the per-URL spinners; the ETA follows the
+    // rate at which URLs finish. From stdin the length grows as URLs are read.
+    let overall = multi.add(ProgressBar::new(total_urls.unwrap_or(0) as u64));
+    overall.set_style(
+        ProgressStyle::with_template(if total_urls.is_some() {
+            "[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} complete (ETA {eta})"
+        } else {
+            "[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} complete"
+        })?
+        .progress_chars("=>-"),
+    );
+    overall.enable_steady_tick(Duration::from_secs(1));
+
     let output_dir = options
         .output
         .clone()
         .unwrap_or_else(|| config.output.default_directory.clone());
 
-    let results: Vec<_> = stream::iter(urls.iter().enumerate())
+    // Created up front, so an unwritable path fails before any work is done
+    let archive = archive_output
+        .map(|path| OutputArchive::create(path, &output_dir))
+        .transpose()?;
+
+    // One FlashSR process serves the whole batch, loading the model once.
+    // Without a Python path each pipeline reports the error itself.
+    let flashsr_worker = (options.enhance || config.upscale.enabled)
+        .then(|| config.python_path().ok())
+        .flatten()
+        .map(|python| {
+            let device = options.device.clone().or_else(|| config.upscale.device.clone());
+            let flashsr = FlashSR::new(python)
+                .with_device(device)
+                .with_model_sha256(config.upscale.flashsr_sha256.clone())
+                .with_output_rate(options.target_rate(&config));
+            Arc::new(FlashSRWorker::new(flashsr).with_processes(config.batch.max_parallel_upscale))
+        });
+
+    // Album normalization runs in two phases: every track is processed and
+    // measured, then all of them get the same gain. A track waiting for the
+    // others hands its worker slot on, so the stream must not cap how many
+    // items are in flight; the semaphore alone bounds the work.
+    let album = total_urls
+        .filter(|_| normalize_album)
+        .map(|total| Arc::new(AlbumLoudness::new(total, options.target_lufs(&config))));
+    let in_flight = match (&album, total_urls) {
+        (Some(_), Some(total)) => total,
+        _ => parallel,
+    };
+
+    // --continue-on-error also keeps the batch going when the config says not to
+    let keep_going = continue_on_error || config.batch.continue_on_error;
+    let stopped = Arc::new(AtomicBool::new(false));
+
+    // Stop taking URLs on Ctrl-C rather than waiting for more input, and
+    // after the first failure unless keeping going
+    let results: Vec<_> = urls
+        .take_until(cancel.cancelled())
+        .take_while(|_| future::ready(!stopped.load(AtomicOrdering::Relaxed)))
+        .enumerate()
         .map(|(idx, url)| {
             let sem = semaphore.clone();
+            let cancel = cancel.clone();
             let opts = options.clone();
             let config = config.clone();
             let output_dir = output_dir.clone();
+            let flashsr_worker = flashsr_worker.clone();
+            let upscale_limit = upscale_limit.clone();
+            let download_limit = download_limit.clone();
+            let ffmpeg_limit = ffmpeg_limit.clone();
+            let album = album.clone();
+            let archive_tx = archive.as_ref().map(OutputArchive::sender);
+            let overall = overall.clone();
+            let stopped = stopped.clone();
             let pb = multi.add(ProgressBar::new_spinner());
             pb.set_style(spinner_style.clone());
-            let url = url.clone();
+            if total_urls.is_none() {
+                overall.inc_length(1);
+            }
+            let label = match total_urls {
+                Some(total) => format!("[{}/{}]", idx + 1, total),
+                None => format!("[{}]", idx + 1),
+            };
 
             async move {
-                let _permit = sem.acquire().await.unwrap();
-                pb.set_message(format!("[{}/{}] {}", idx + 1, total_urls, truncate(&url, 50)));
+                let permit = sem.acquire_owned().await.unwrap();
+                pb.set_message(format!("{} {}", label, truncate(&url, 50)));
                 pb.enable_steady_tick(std::time::Duration::from_millis(100));
 
                 // Convert CLI types to pipeline types
-                let format = match opts.format {
-                    CliFormat::Flac => OutputFormat::Flac,
-                    CliFormat::Wav => OutputFormat::Wav,
-                    CliFormat::Mp3 => OutputFormat::Mp3,
-                    CliFormat::Aac => OutputFormat::Aac,
-                    CliFormat::Opus => OutputFormat::Opus,
-                };
-
-                let upscale_quality = match opts.quality {
+                let upscale_quality = match opts.upscale_quality(&config) {
                     CliQuality::Best => UpscaleQuality::Best,
                     CliQuality::Fast => UpscaleQuality::Fast,
                 };
 
                 let pipeline_config = PipelineConfig {
-                    url: url.clone(),
+                    input: super::pipeline_input(&url, &config).await,
                     output_dir,
-                    format,
-                    enhance: opts.enhance,
+                    filename_template: opts
+                        .output_template
+                        .clone()
+                        .unwrap_or_else(|| config.output.filename_template.clone()),
+                    format: opts.output_format(&config),
+                    also: opts.also.clone(),
+                    encode_options: opts.encode_options(&config),
+                    enhance: opts.enhance || config.upscale.enabled,
+                    force_upscale: opts.force_upscale,
                     upscale_quality,
-                    normalize: opts.normalize,
-                    target_lufs: opts.lufs,
+                    normalize: opts.normalize(&config),
+                    normalize_mode: opts
+                        .normalize_mode
+                        .map(Into::into)
+                        .unwrap_or(config.normalize.mode),
+                    peak_target: opts.peak,
+                    limit_peaks: opts.limit_peaks,
+                    highpass: opts.highpass.or(config.filters.highpass),
+                    lowpass: opts.lowpass.or(config.filters.lowpass),
+                    trim_silence: opts.trim_silence || config.filters.trim_silence,
+                    fade_in: opts.fade_in.or(config.filters.fade_in),
+                    fade_out: opts.fade_out.or(config.filters.fade_out),
+                    extra_af: opts.af.clone().or_else(|| config.filters.extra_af.clone()),
+                    clip: opts.trim,
+                    resample: opts.resample.or(config.output.resample),
+                    preserve_hires: opts.preserve_hires || config.output.preserve_hires,
+                    target_rate: opts.target_rate(&config),
+                    target_lufs: opts.target_lufs(&config),
                     keep_temp: opts.keep_temp,
+                    resume: opts.resume,
+                    device: opts.device.clone().or_else(|| config.upscale.device.clone()),
+                    audiosr_seed: opts.seed,
+                    sponsorblock: opts.sponsorblock_categories(&config),
+                    format_selector: opts
+                        .format_selector
+                        .clone()
+                        .or_else(|| config.download.format_selector.clone()),
+                    proxy: opts.proxy.clone().or_else(|| config.download.proxy.clone()),
+                    rate_limit: opts
+                        .limit_rate
+                        .clone()
+                        .or_else(|| config.download.rate_limit.clone()),
+                    extractor_args: opts
+                        .extractor_args
+                        .clone()
+                        .or_else(|| config.download.extractor_args.clone()),
+                    max_retries: config.download.max_retries,
+                    archive_file: opts
+                        .archive
+                        .clone()
+                        .or_else(|| config.download.archive_file.clone()),
+                    lyrics: opts.lyrics.then(|| config.download.lyrics_lang.clone()),
+                    max_fallbacks: config.fallback.max_fallbacks,
+                    split_chapters: opts.split_chapters,
+                    dry_run: opts.dry_run,
+                    skip_existing: opts.skip_existing || config.batch.skip_existing,
+                    on_exists: opts
+                        .on_exists
+                        .unwrap_or_else(|| config.output.on_exists.parse().unwrap_or_default()),
+                    verify: !opts.no_verify,
+                    embed_artwork: !opts.no_artwork && config.metadata.embed_artwork,
+                    embed_spectrogram: opts.embed_spectrogram || config.metadata.embed_spectrogram,
+                    clean_titles: opts.clean_titles || config.metadata.clean_titles,
+                    no_metadata: opts.no_metadata,
+                    hooks: opts.hooks(&config),
                     paths: config.paths.clone(),
                 };
 
-                // Create a dummy channel (batch mode doesn't show per-item progress)
+                // Batch mode doesn't show per-item progress, only dry-run plans,
+                // whether the item was skipped, and its stage timings. The
+                // handler holds the worker slot until the run ends or starts
+                // waiting for the rest of the album.
                 let (tx, mut rx) = tokio::sync::mpsc::channel(1);
-                tokio::spawn(async move {
-                    while rx.recv().await.is_some() {}
+                let plan_pb = pb.clone();
+                let progress_handle = tokio::spawn(async move {
+                    let mut permit = Some(permit);
+                    let mut skipped = false;
+                    let mut timings = None;
+                    let mut also = Vec::new();
+                    while let Some(stage) = rx.recv().await {
+                        match stage {
+                            PipelineStage::Planned { stage, command } => {
+                                plan_pb.suspend(|| println!("{}: {}", stage, command));
+                            }
+                            PipelineStage::Skipped { .. } => skipped = true,
+                            PipelineStage::AlbumMeasured { .. } => drop(permit.take()),
+                            PipelineStage::Complete { duration, timings: t, also: files, .. } => {
+                                timings = Some((t, duration));
+                                also = files;
+                            }
+                            _ => {}
+                        }
+                    }
+                    (skipped, timings, also)
                 });
 
-                let pipeline = Pipeline::new(pipeline_config, tx);
+                let mut pipeline = Pipeline::new(pipeline_config, tx)
+                    .with_cancel(cancel)
+                    .with_app_config(config.clone())
+                    .with_upscale_limit(upscale_limit)
+                    .with_download_limit(download_limit)
+                    .with_ffmpeg_limit(ffmpeg_limit);
+                if let Some(worker) = flashsr_worker {
+                    pipeline = pipeline.with_flashsr_worker(worker);
+                }
+                if let Some(album) = album {
+                    pipeline = pipeline.with_album_loudness(album);
+                }
                 let result = pipeline.run().await;
+                drop(pipeline);
+                let (skipped, timings, also) = progress_handle.await.unwrap_or_default();
+                if let (Some(tx), Ok(path)) = (archive_tx, &result) {
+                    let files = std::iter::once(path.clone()).chain(also).collect();
+                    let _ = tx.send(ArchiveItem { source: url.clone(), files, written: !skipped });
+                }
 
                 match &result {
+                    Ok(path) if skipped => {
+                        pb.finish_with_message(format!(
+                            "{} Skipped (exists): {}",
+                            label,
+                            path.file_name().unwrap_or_default().to_string_lossy()
+                        ));
+                    }
                     Ok(path) => {
                         pb.finish_with_message(format!(
-                            "[{}/{}] Done: {}",
-                            idx + 1,
-                            total_urls,
+                            "{} {}: {}",
+                            label,
+                            if opts.dry_run { "Would write" } else { "Done" },
                             path.file_name().unwrap_or_default().to_string_lossy()
                         ));
                     }
+                    Err(e) if is_archived(e) => {
+                        pb.finish_with_message(format!(
+                            "{} Skipped (archived): {}",
+                            label,
+                            truncate(&url, 50)
+                        ));
+                    }
                     Err(e) => {
                         pb.finish_with_message(format!(
-                            "[{}/{}] Failed: {}",
-                            idx + 1,
-                            total_urls,
+                            "{} Failed: {}",
+                            label,
                             e
                         ));
                     }
                 }
+                overall.inc(1);
+                if !keep_going && result.as_ref().is_err_and(|e| !is_archived(e)) {
+                    stopped.store(true, AtomicOrdering::Relaxed);
+                }
 
-                (url, result)
+                ((url, skipped, result), timings)
             }
         })
-        .buffer_unordered(parallel)
+        .buffer_unordered(in_flight)
         .collect()
         .await;
+    overall.finish();
+
+    let archived = match archive {
+        Some(archive) => Some(archive.finish(remove_archived).await),
+        None => None,
+    };
 
-    // Summary
-    let succeeded: Vec<_> = results.iter().filter(|(_, r)| r.is_ok()).collect();
-    let failed: Vec<_> = results.iter().filter(|(_, r)| r.is_err()).collect();
+    // Ctrl-C before the first URL arrived is a cancellation, not empty input
+    if results.is_empty() && cancel.is_cancelled() {
+        notify_empty(notify, started, true).await;
+        return Err(YtAudioError::Cancelled.into());
+    }
+    if results.is_empty() {
+        println!("No URLs found on stdin");
+        notify_empty(notify, started, false).await;
+        return Ok(());
+    }
+
+    let album_tags = options
+        .normalize_mode
+        .map(NormalizeMode::from)
+        .unwrap_or(config.normalize.mode)
+        == NormalizeMode::ReplayGainTags;
+    if let Some(gain) = album.as_ref().and_then(|album| album.gain()).filter(|_| album_tags) {
+        println!(
+            "\nAlbum ReplayGain: {:+.2} dB ({:.1} LUFS over {} tracks)",
+            REPLAYGAIN_REFERENCE_LUFS - gain.integrated_lufs,
+            gain.integrated_lufs,
+            gain.tracks
+        );
+    } else if let Some(gain) = album.as_ref().and_then(|album| album.gain()) {
+        println!(
+            "\nAlbum gain: {:+.1} dB ({:.1} LUFS over {} tracks, target {:.1} LUFS)",
+            gain.gain,
+            gain.integrated_lufs,
+            gain.tracks,
+            options.target_lufs(&config)
+        );
+    }
+    let (results, timings): (Vec<_>, Vec<_>) = results.into_iter().unzip();
+
+    if options.stats {
+        // Summed over the completed URLs; stages overlap when running in parallel
+        let mut total = (StageTimings::default(), Duration::ZERO);
+        for (stages, duration) in timings.into_iter().flatten() {
+            total.0 += stages;
+            total.1 += duration;
+        }
+        super::print_timings(&total.0, total.1);
+    }
+
+    let summary = BatchSummary::new(&results, started.elapsed(), cancel.is_cancelled());
+    if stopped.load(AtomicOrdering::Relaxed) {
+        println!("\nStopped at the first failure (batch.continue_on_error is off)");
+    }
+    let outcome = report(results, continue_on_error);
+    if let Some(url) = notify {
+        summary.send(url).await;
+    }
+    // A broken archive fails the batch even when every item succeeded
+    if let (Some(Err(e)), Some(path)) = (archived, archive_output) {
+        return Err(e.context(format!("Failed to write {}", path.display())));
+    }
+    // Ctrl-C fails the remaining URLs; exit 130 whatever came before
+    if cancel.is_cancelled() {
+        return Err(YtAudioError::Cancelled.into());
+    }
+    outcome
+}
+
+/// Print the end-of-run summary for `(url, skipped, result)` items. Unless
+/// `continue_on_error`, any failure is returned so the process exits with the
+/// first failed URL's code.
+pub(crate) fn report(
+    results: Vec<(String, bool, Result<PathBuf, YtAudioError>)>,
+    continue_on_error: bool,
+) -> Result<()> {
+    let total_urls = results.len();
+    let succeeded = results.iter().filter(|(_, skipped, r)| !skipped && r.is_ok()).count();
+    let skipped = results.iter().filter(|(_, skipped, _)| *skipped).count();
+    let archived = results
+        .iter()
+        .filter(|(_, _, r)| r.as_ref().is_err_and(is_archived))
+        .count();
+    let failed: Vec<_> = results
+        .iter()
+        .filter(|(_, _, r)| r.as_ref().is_err_and(|e| !is_archived(e)))
+        .collect();
 
     println!("\n=== Batch Complete ===");
-    println!("Succeeded: {}", succeeded.len());
+    println!("Succeeded: {}", succeeded);
+    println!("Skipped: {}", skipped);
+    if archived > 0 {
+        println!("Already archived: {}", archived);
+    }
     println!("Failed: {}", failed.len());
 
     if !failed.is_empty() {
         println!("\nFailed URLs:");
-        for (url, result) in &failed {
+        for (url, _, result) in &failed {
             if let Err(e) = result {
                 println!("  {} - {}", url, e);
             }
         }
     }
 
+    // Exit with the first failure's code so scripts see which stage broke
+    let failed_count = failed.len();
+    if failed_count > 0 && !continue_on_error {
+        let first = results
+            .into_iter()
+            .find_map(|(_, _, r)| r.err().filter(|e| !is_archived(e)))
+            .expect("failed items are present");
+        return Err(anyhow::Error::new(first)
+            .context(format!("{} of {} URLs failed", failed_count, total_urls)));
+    }
+
     Ok(())
 }
 
+/// Sort `urls` for `--order`, fetching their metadata (ffprobe for local
+/// files) a few at a time. The sort is stable, and URLs whose metadata can't
+/// be read go last; the main pass then reports why they fail.
+async fn order_urls(
+    urls: Vec<String>,
+    order: BatchOrder,
+    options: &ExtractOptions,
+    config: &Config,
+    parallel: usize,
+    quiet: bool,
+    cancel: &CancelFlag,
+) -> Result<Vec<String>> {
+    if !quiet {
+        println!("Fetching metadata to order {} URLs", urls.len());
+    }
+    // A list of local files needs no yt-dlp
+    let downloader = config.yt_dlp_path().map(|yt_dlp| {
+        Downloader::new(yt_dlp, config.temp_dir())
+            .with_proxy(
+                options
+                    .proxy
+                    .clone()
+                    .or_else(|| config.download.proxy.clone()),
+            )
+            .with_rate_limit(
+                options
+                    .limit_rate
+                    .clone()
+                    .or_else(|| config.download.rate_limit.clone()),
+            )
+            .with_extractor_args(
+                options
+                    .extractor_args
+                    .clone()
+                    .or_else(|| config.download.extractor_args.clone()),
+            )
+            .with_timeout(Some(Duration::from_secs(config.download.timeout_secs)))
+    });
+
+    let fetches = config.batch.max_parallel_downloads.unwrap_or(parallel);
+    let mut items: Vec<(String, Option<VideoMetadata>)> = stream::iter(urls)
+        .map(|url| {
+            let downloader = &downloader;
+            async move {
+                let metadata = match super::pipeline_input(&url, config).await {
+                    PipelineInput::LocalFile(_, metadata) => Some(metadata),
+                    PipelineInput::YouTubeUrl(ref video) => match downloader {
+                        Ok(downloader) => downloader
+                            .fetch_metadata(video)
+                            .await
+                            .map_err(|e| e.to_string()),
+                        Err(e) => Err(e.to_string()),
+                    }
+                    .inspect_err(|e| warn!("No metadata for {}, ordering it last: {}", url, e))
+                    .ok(),
+                };
+                (url, metadata)
+            }
+        })
+        .buffered(fetches)
+        .take_until(cancel.cancelled())
+        .collect()
+        .await;
+    if cancel.is_cancelled() {
+        return Err(YtAudioError::Cancelled.into());
+    }
+
+    sort_by_order(&mut items, order);
+    Ok(items.into_iter().map(|(url, _)| url).collect())
+}
+
+/// Stable sort of `(url, metadata)` pairs for `order`; unknowns go last
+fn sort_by_order(items: &mut [(String, Option<VideoMetadata>)], order: BatchOrder) {
+    items.sort_by(|(_, a), (_, b)| {
+        let (a, b) = (a.as_ref(), b.as_ref());
+        match order {
+            BatchOrder::File => Ordering::Equal,
+            BatchOrder::DurationAsc => missing_last(
+                a.and_then(|m| m.duration),
+                b.and_then(|m| m.duration),
+                |a, b| a.total_cmp(b),
+            ),
+            BatchOrder::DurationDesc => missing_last(
+                a.and_then(|m| m.duration),
+                b.and_then(|m| m.duration),
+                |a, b| b.total_cmp(a),
+            ),
+            BatchOrder::Date => {
+                missing_last(a.and_then(upload_day), b.and_then(upload_day), |a, b| {
+                    b.cmp(a)
+                })
+            }
+        }
+    });
+}
+
+/// Compare with `cmp` when both are known, putting unknowns after the rest
+fn missing_last<T>(a: Option<T>, b: Option<T>, cmp: impl Fn(&T, &T) -> Ordering) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => cmp(&a, &b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// The upload date as `YYYYMMDD`, so yt-dlp's `20240131`, a `2024-01-31` or
+/// `2024-1-31` tag and a bare `2024` compare alike. A missing month or day
+/// counts as 0, putting a year-only date before that year's full dates.
+fn upload_day(metadata: &VideoMetadata) -> Option<u32> {
+    let date = metadata.upload_date.as_deref()?.trim();
+    let parts: Vec<&str> = if date.chars().all(|c| c.is_ascii_digit()) {
+        // Compact form: YYYY, YYYYMM or YYYYMMDD
+        let (year, rest) = date.split_at(date.len().min(4));
+        let (month, day) = rest.split_at(rest.len().min(2));
+        [year, month, day].into_iter().filter(|part| !part.is_empty()).collect()
+    } else {
+        date.split(|c: char| !c.is_ascii_digit()).filter(|part| !part.is_empty()).collect()
+    };
+    let mut fields = parts.iter().map(|part| part.parse::<u32>().ok());
+    let year = fields.next()??;
+    let month = fields.next().flatten().unwrap_or(0);
+    let day = fields.next().flatten().unwrap_or(0);
+    (year > 0 && month <= 12 && day <= 31).then_some(year * 10000 + month * 100 + day)
+}
+
+/// What `--notify` POSTs when the batch ends
+#[derive(Serialize)]
+struct BatchSummary {
+    /// One-line message: Discord shows `content`, Slack `text`
+    content: String,
+    text: String,
+    succeeded: usize,
+    skipped: usize,
+    failed: usize,
+    elapsed_secs: f64,
+    cancelled: bool,
+    failed_urls: Vec<FailedUrl>,
+}
+
+#[derive(Serialize)]
+struct FailedUrl {
+    url: String,
+    error: String,
+}
+
+impl BatchSummary {
+    /// Counted like [`report`]; archived items count as skipped
+    fn new(
+        results: &[(String, bool, Result<PathBuf, YtAudioError>)],
+        elapsed: Duration,
+        cancelled: bool,
+    ) -> Self {
+        let succeeded = results
+            .iter()
+            .filter(|(_, skipped, r)| !skipped && r.is_ok())
+            .count();
+        let skipped = results
+            .iter()
+            .filter(|(_, skipped, r)| *skipped || r.as_ref().is_err_and(is_archived))
+            .count();
+        let failed_urls: Vec<_> = results
+            .iter()
+            .filter_map(|(url, _, r)| match r {
+                Err(e) if !is_archived(e) => Some(FailedUrl {
+                    url: url.clone(),
+                    error: e.to_string(),
+                }),
+                _ => None,
+            })
+            .collect();
+
+        let message = format!(
+            "ytaudio batch {} after {}: {} succeeded, {} skipped, {} failed",
+            if cancelled { "cancelled" } else { "finished" },
+            super::info::format_duration(elapsed.as_secs_f64()),
+            succeeded,
+            skipped,
+            failed_urls.len()
+        );
+        Self {
+            content: message.clone(),
+            text: message,
+            succeeded,
+            skipped,
+            failed: failed_urls.len(),
+            elapsed_secs: elapsed.as_secs_f64(),
+            cancelled,
+            failed_urls,
+        }
+    }
+
+    /// POST the summary to `url`. Best effort: a failure is logged and the
+    /// batch's outcome stays as it was.
+    async fn send(&self, url: &str) {
+        let client = match reqwest::Client::builder().timeout(NOTIFY_TIMEOUT).build() {
+            Ok(client) => client,
+            Err(e) => {
+                warn!("Cannot send the batch notification: {}", e);
+                return;
+            }
+        };
+        match client.post(url).json(self).send().await.and_then(|r| r.error_for_status()) {
+            Ok(_) => {}
+            // Without the URL: webhook URLs carry their secret in the path
+            Err(e) => warn!("Batch notification failed: {}", e.without_url()),
+        }
+    }
+}
+
+/// Send the `--notify` summary for a batch that ended before any URL ran
+async fn notify_empty(url: Option<&str>, started: Instant, cancelled: bool) {
+    if let Some(url) = url {
+        BatchSummary::new(&[], started.elapsed(), cancelled).send(url).await;
+    }
+}
+
+/// How long the `--notify` POST may take before it is given up
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// A URL or path from one input line; blank lines and `#` comments yield nothing
+fn url_line(line: &str) -> Option<String> {
+    let line = line.trim();
+    (!line.is_empty() && !line.starts_with('#')).then(|| line.to_string())
+}
+
+/// URLs from stdin as each line arrives, filtered like an input file
+fn stdin_urls() -> impl Stream<Item = String> {
+    reader_urls(BufReader::new(tokio::io::stdin()))
+}
+
+/// URLs from `reader` as each line arrives
+fn reader_urls<R: AsyncBufRead + Unpin>(reader: R) -> impl Stream<Item = String> {
+    stream::unfold(reader.lines(), |mut lines| async move {
+        loop {
+            match lines.next_line().await {
+                Ok(Some(line)) => {
+                    if let Some(url) = url_line(&line) {
+                        return Some((url, lines));
+                    }
+                }
+                Ok(None) => return None,
+                Err(e) => {
+                    warn!("Stopped reading stdin: {}", e);
+                    return None;
+                }
+            }
+        }
+    })
+}
+
+/// Name of the file in `--archive-output` that maps each URL to its entries
+const MANIFEST_NAME: &str = "manifest.json";
+
+/// Files one item produced, sent to the archive writer when it finishes
+struct ArchiveItem {
+    source: String,
+    files: Vec<PathBuf>,
+    /// Whether this run wrote the files, rather than skipping to existing ones
+    written: bool,
+}
+
+#[derive(Serialize)]
+struct ManifestEntry {
+    source: String,
+    files: Vec<String>,
+}
+
+/// The `--archive-output` zip, filled as items finish. Entries are copied
+/// from disk by a blocking task, so memory use doesn't grow with the batch.
+struct OutputArchive {
+    path: PathBuf,
+    tx: mpsc::UnboundedSender<ArchiveItem>,
+    /// Yields the files packed, and of those the ones this run wrote
+    writer: JoinHandle<Result<Packed>>,
+}
+
+impl OutputArchive {
+    /// Create the zip at `path`. Entries are named by their path below
+    /// `output_dir`, so subdirectories from the filename template are kept.
+    fn create(path: &Path, output_dir: &Path) -> Result<Self> {
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        let output_dir = output_dir.to_path_buf();
+        let (tx, mut rx) = mpsc::unbounded_channel::<ArchiveItem>();
+        let writer = tokio::task::spawn_blocking(move || {
+            write_archive(file, &output_dir, std::iter::from_fn(|| rx.blocking_recv()))
+        });
+        Ok(Self { path: path.to_path_buf(), tx, writer })
+    }
+
+    fn sender(&self) -> mpsc::UnboundedSender<ArchiveItem> {
+        self.tx.clone()
+    }
+
+    /// Write the manifest and close the zip once every sender is gone; with
+    /// `remove_files`, delete the packed files. A zip that failed is removed.
+    async fn finish(self, remove_files: bool) -> Result<()> {
+        drop(self.tx);
+        let packed = match self.writer.await.context("Archive writer panicked").and_then(|r| r) {
+            Ok(packed) => packed,
+            Err(e) => {
+                let _ = fs::remove_file(&self.path).await;
+                return Err(e);
+            }
+        };
+        println!("\nArchived {} files to {}", packed.files, self.path.display());
+
+        // Outputs that skipped items only pointed at predate this run; keep them
+        if remove_files {
+            for path in &packed.written {
+                if let Err(e) = fs::remove_file(path).await {
+                    warn!("Failed to remove {}: {}", path.display(), e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What [`write_archive`] packed
+#[derive(Debug)]
+struct Packed {
+    /// Number of files in the zip, besides the manifest
+    files: usize,
+    /// Packed files that items of this run wrote, as opposed to existing
+    /// outputs they skipped to
+    written: Vec<PathBuf>,
+}
+
+/// Pack each item's files into a zip written to `file`, followed by the
+/// manifest. A file reported by several items is packed once.
+fn write_archive(
+    file: std::fs::File,
+    output_dir: &Path,
+    items: impl IntoIterator<Item = ArchiveItem>,
+) -> Result<Packed> {
+    let mut zip = ZipWriter::new(file);
+    let mut manifest = Vec::new();
+    // Entry name -> file, as skipped items can report a file again
+    let mut packed: HashMap<String, PathBuf> = HashMap::new();
+    let mut written = HashSet::new();
+    for item in items {
+        let mut files = Vec::new();
+        for path in item.files {
+            let name = entry_name(&path, output_dir);
+            match packed.get(&name) {
+                Some(existing) if *existing == path => {}
+                Some(existing) => {
+                    warn!(
+                        "{} and {} share the name {} in the archive, keeping the first",
+                        existing.display(),
+                        path.display(),
+                        name
+                    );
+                    continue;
+                }
+                None => {
+                    add_file(&mut zip, &path, &name)?;
+                    packed.insert(name.clone(), path.clone());
+                }
+            }
+            if item.written {
+                written.insert(path);
+            }
+            files.push(name);
+        }
+        manifest.push(ManifestEntry { source: item.source, files });
+    }
+
+    zip.start_file(MANIFEST_NAME, SimpleFileOptions::default())?;
+    serde_json::to_writer_pretty(&mut zip, &manifest)?;
+    zip.finish()?;
+    Ok(Packed { files: packed.len(), written: written.into_iter().collect() })
+}
+
+/// `path` relative to `output_dir` with `/` separators, or just its file
+/// name when it lies elsewhere
+fn entry_name(path: &Path, output_dir: &Path) -> String {
+    match path.strip_prefix(output_dir) {
+        Ok(relative) if !relative.as_os_str().is_empty() => relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+        _ => path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+    }
+}
+
+/// Copy one file into the zip. Audio codecs leave Deflate little to gain, so
+/// it is stored as it is.
+fn add_file(zip: &mut ZipWriter<std::fs::File>, path: &Path, name: &str) -> Result<()> {
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let size = file.metadata()?.len();
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Stored)
+        .large_file(size >= u64::from(u32::MAX));
+    zip.start_file(name, options)?;
+    std::io::copy(&mut file, zip).with_context(|| format!("Failed to pack {}", path.display()))?;
+    Ok(())
+}
+
+/// The video was skipped because the download archive already lists it
+pub(crate) fn is_archived(e: &YtAudioError) -> bool {
+    matches!(e, YtAudioError::Download(DownloadError::AlreadyArchived(_)))
+}
+
 fn truncate(s: &str, max_len: usize) -> String {
     if s.len() <= max_len {
         s.to_string()
@@ -155,3 +914,200 @@ fn truncate(s: &str, max_len: usize) -> String {
         format!("{}...", &s[..max_len - 3])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_batch_summary_counts() {
+        let results = vec![
+            ("a".to_string(), false, Ok(PathBuf::from("a.flac"))),
+            ("b".to_string(), true, Ok(PathBuf::from("b.flac"))),
+            ("c".to_string(), false, Err(DownloadError::AlreadyArchived("c".to_string()).into())),
+            ("d".to_string(), false, Err(YtAudioError::Cancelled)),
+        ];
+        let summary = BatchSummary::new(&results, Duration::from_secs(65), true);
+        assert_eq!((summary.succeeded, summary.skipped, summary.failed), (1, 2, 1));
+        assert_eq!(summary.failed_urls.len(), 1);
+        assert_eq!(summary.failed_urls[0].url, "d");
+        assert!(summary.cancelled);
+        assert_eq!(summary.content, summary.text);
+        assert!(
+            summary.content.starts_with("ytaudio batch cancelled after"),
+            "{}",
+            summary.content
+        );
+        assert!(
+            summary
+                .content
+                .ends_with("1 succeeded, 2 skipped, 1 failed"),
+            "{}",
+            summary.content
+        );
+
+        let empty = BatchSummary::new(&[], Duration::ZERO, false);
+        assert_eq!((empty.succeeded, empty.skipped, empty.failed), (0, 0, 0));
+    }
+
+    fn dated(
+        url: &str,
+        upload_date: Option<&str>,
+        duration: Option<f64>,
+    ) -> (String, Option<VideoMetadata>) {
+        let metadata = VideoMetadata {
+            upload_date: upload_date.map(str::to_string),
+            duration,
+            ..Default::default()
+        };
+        (url.to_string(), Some(metadata))
+    }
+
+    #[test]
+    fn test_upload_day() {
+        let day = |date: &str| {
+            upload_day(&VideoMetadata {
+                upload_date: Some(date.to_string()),
+                ..Default::default()
+            })
+        };
+        assert_eq!(day("20240131"), Some(20240131));
+        assert_eq!(day("2024-01-31"), Some(20240131));
+        assert_eq!(day("2024-1-5"), Some(20240105));
+        assert_eq!(day("2024"), Some(20240000));
+        assert_eq!(day("202401"), Some(20240100));
+        assert_eq!(day("2024-01-31T12:00:00"), Some(20240131));
+        assert_eq!(day(""), None);
+        assert_eq!(day("unknown"), None);
+        assert_eq!(upload_day(&VideoMetadata::default()), None);
+        // A year on its own is older than any full date in a later year
+        assert!(day("2024") > day("20231231"));
+        assert!(day("2024") < day("20240131"));
+    }
+
+    #[test]
+    fn test_missing_last() {
+        let cmp = |a: &u32, b: &u32| a.cmp(b);
+        assert_eq!(missing_last(Some(1), Some(2), cmp), Ordering::Less);
+        assert_eq!(missing_last(Some(2), None, cmp), Ordering::Less);
+        assert_eq!(missing_last(None, Some(2), cmp), Ordering::Greater);
+        assert_eq!(missing_last(None::<u32>, None, cmp), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_sort_by_order() {
+        let urls = |items: &[(String, Option<VideoMetadata>)]| {
+            items.iter().map(|(url, _)| url.clone()).collect::<Vec<_>>()
+        };
+        let items = vec![
+            dated("a", Some("2023-06-01"), Some(300.0)),
+            ("none".to_string(), None),
+            dated("b", Some("2024"), Some(60.0)),
+            dated("c", Some("20240131"), None),
+            dated("d", None, Some(120.0)),
+        ];
+
+        let mut sorted = items.clone();
+        sort_by_order(&mut sorted, BatchOrder::Date);
+        assert_eq!(urls(&sorted), ["c", "b", "a", "none", "d"]);
+
+        let mut sorted = items.clone();
+        sort_by_order(&mut sorted, BatchOrder::DurationAsc);
+        assert_eq!(urls(&sorted), ["b", "d", "a", "none", "c"]);
+
+        let mut sorted = items.clone();
+        sort_by_order(&mut sorted, BatchOrder::DurationDesc);
+        assert_eq!(urls(&sorted), ["a", "d", "b", "none", "c"]);
+
+        let mut sorted = items.clone();
+        sort_by_order(&mut sorted, BatchOrder::File);
+        assert_eq!(urls(&sorted), urls(&items));
+    }
+
+    #[test]
+    fn test_url_line() {
+        assert_eq!(url_line("  https://youtu.be/abc \n").as_deref(), Some("https://youtu.be/abc"));
+        assert_eq!(url_line("./local.wav").as_deref(), Some("./local.wav"));
+        assert_eq!(url_line(""), None);
+        assert_eq!(url_line("   "), None);
+        assert_eq!(url_line("# comment"), None);
+        assert_eq!(url_line("  # indented comment"), None);
+    }
+
+    #[tokio::test]
+    async fn test_reader_urls() {
+        let input: &[u8] =
+            b"https://youtu.be/a\n\n# skipped\r\n  https://youtu.be/b  \r\nhttps://youtu.be/c";
+        let urls: Vec<String> = reader_urls(input).collect().await;
+        assert_eq!(urls, ["https://youtu.be/a", "https://youtu.be/b", "https://youtu.be/c"]);
+
+        let empty: Vec<String> = reader_urls(&b""[..]).collect().await;
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_entry_name() {
+        let output_dir = Path::new("/music");
+        assert_eq!(
+            entry_name(Path::new("/music/Song.flac"), output_dir),
+            "Song.flac"
+        );
+        assert_eq!(
+            entry_name(Path::new("/music/Artist/Album/01.flac"), output_dir),
+            "Artist/Album/01.flac"
+        );
+        // Outside the output directory only the file name is kept
+        assert_eq!(entry_name(Path::new("/elsewhere/Song.opus"), output_dir), "Song.opus");
+        assert_eq!(entry_name(Path::new("/music"), output_dir), "music");
+    }
+
+    #[test]
+    fn test_write_archive_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().join("out");
+        std::fs::create_dir_all(output_dir.join("Artist")).unwrap();
+        let new = output_dir.join("Artist/New.flac");
+        let old = output_dir.join("Old.flac");
+        std::fs::write(&new, b"new").unwrap();
+        std::fs::write(&old, b"old").unwrap();
+
+        let items = vec![
+            ArchiveItem {
+                source: "https://youtu.be/new".to_string(),
+                files: vec![new.clone()],
+                written: true,
+            },
+            ArchiveItem {
+                source: "https://youtu.be/old".to_string(),
+                files: vec![old.clone()],
+                written: false,
+            },
+        ];
+        let zip_path = dir.path().join("batch.zip");
+        let packed = write_archive(
+            std::fs::File::create(&zip_path).unwrap(),
+            &output_dir,
+            items,
+        )
+        .unwrap();
+        assert_eq!(packed.files, 2);
+        // Only what this run wrote may be removed
+        assert_eq!(packed.written, vec![new]);
+
+        let mut zip = zip::ZipArchive::new(std::fs::File::open(&zip_path).unwrap()).unwrap();
+        let mut content = String::new();
+        zip.by_name("Artist/New.flac").unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "new");
+        let mut manifest = String::new();
+        zip.by_name(MANIFEST_NAME).unwrap().read_to_string(&mut manifest).unwrap();
+        let manifest: serde_json::Value = serde_json::from_str(&manifest).unwrap();
+        assert_eq!(
+            manifest,
+            serde_json::json!([
+                { "source": "https://youtu.be/new", "files": ["Artist/New.flac"] },
+                { "source": "https://youtu.be/old", "files": ["Old.flac"] },
+            ])
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use ytaudio_core::config::{Config, TEMP_PREFIX};
+
+/// HuggingFace Hub cache entries of the models ytaudio downloads: FlashSR
+/// from `update-models`, and each AudioSR variant on its first use
+const MODEL_PREFIXES: [&str; 2] = ["models--YatharthS--FlashSR", "models--haoheliu--audiosr"];
+
+/// Directories modified more recently than this may belong to a run (or a
+/// model download) still in progress, and are left alone
+const MIN_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Remove leftover temp directories, and with `models` the cached models,
+/// printing the space each one took. With `dry_run` they are only listed.
+pub fn run(
+    models: bool,
+    dry_run: bool,
+    config_path: Option<&Path>,
+    profile: Option<&str>,
+) -> Result<()> {
+    let config = Config::load_profile(config_path, profile)?;
+
+    let mut roots = vec![std::env::temp_dir()];
+    roots.extend(config.temp.directory.clone());
+    roots.dedup();
+    let mut targets = matching_dirs(&roots, &[TEMP_PREFIX]);
+    if models {
+        targets.extend(matching_dirs(&super::hf_hub_dirs(), &MODEL_PREFIXES));
+    }
+    let (targets, recent): (Vec<_>, Vec<_>) = targets
+        .into_iter()
+        .partition(|dir| older_than(dir, MIN_AGE));
+    for dir in &recent {
+        println!("Skipping {} (modified in the last hour, may be in use)", dir.display());
+    }
+
+    if targets.is_empty() {
+        println!("Nothing to clean");
+        return Ok(());
+    }
+
+    let mut freed = 0;
+    let mut failed = 0;
+    for dir in targets {
+        let size = disk_usage(&dir);
+        if dry_run {
+            println!("Would remove {} ({})", dir.display(), format_size(size));
+            freed += size;
+            continue;
+        }
+        match std::fs::remove_dir_all(&dir) {
+            Ok(()) => {
+                println!("Removed {} ({})", dir.display(), format_size(size));
+                freed += size;
+            }
+            Err(e) => {
+                println!("Failed to remove {}: {}", dir.display(), e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("\n{} {}", if dry_run { "Would free" } else { "Freed" }, format_size(freed));
+    if failed > 0 {
+        anyhow::bail!(
+            "{} director{} could not be removed",
+            failed,
+            if failed == 1 { "y" } else { "ies" }
+        );
+    }
+    Ok(())
+}
+
+/// Directories (not symlinks) directly in `roots` whose names start with one
+/// of `prefixes`. Missing roots are skipped.
+fn matching_dirs(roots: &[PathBuf], prefixes: &[&str]) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    for root in roots {
+        let Ok(entries) = std::fs::read_dir(root) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let matches = name
+                .to_str()
+                .is_some_and(|name| prefixes.iter().any(|p| name.starts_with(p)));
+            if matches && entry.file_type().is_ok_and(|t| t.is_dir()) {
+                found.push(entry.path());
+            }
+        }
+    }
+    found.sort();
+    found
+}
+
+/// Whether `dir` was last modified at least `age` ago; `false` when its
+/// modification time can't be read
+fn older_than(dir: &Path, age: Duration) -> bool {
+    std::fs::metadata(dir)
+        .and_then(|m| m.modified())
+        .is_ok_and(|modified| {
+            SystemTime::now()
+                .duration_since(modified)
+                .is_ok_and(|elapsed| elapsed >= age)
+        })
+}
+
+/// Total size of the files under `dir`, without following symlinks
+fn disk_usage(dir: &Path) -> u64 {
+    let mut total = 0;
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            match entry.file_type() {
+                Ok(t) if t.is_dir() => pending.push(entry.path()),
+                Ok(t) if t.is_file() => total += entry.metadata().map_or(0, |m| m.len()),
+                _ => {}
+            }
+        }
+    }
+    total
+}
+
+fn format_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "KiB", "MiB"] {
+        if size < 1024.0 {
+            return match unit {
+                "B" => format!("{} B", bytes),
+                _ => format!("{:.1} {}", size, unit),
+            };
+        }
+        size /= 1024.0;
+    }
+    format!("{:.1} GiB", size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matching_dirs() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("ytaudio-a")).unwrap();
+        std::fs::create_dir(root.path().join("ytaudio-b")).unwrap();
+        std::fs::create_dir(root.path().join("other")).unwrap();
+        std::fs::write(root.path().join("ytaudio-file"), b"").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(
+            root.path().join("ytaudio-a"),
+            root.path().join("ytaudio-link"),
+        )
+        .unwrap();
+
+        let roots = [root.path().to_path_buf(), root.path().join("missing")];
+        assert_eq!(
+            matching_dirs(&roots, &["ytaudio-"]),
+            [root.path().join("ytaudio-a"), root.path().join("ytaudio-b")]
+        );
+        assert!(matching_dirs(&roots, &["nothing-"]).is_empty());
+    }
+
+    #[test]
+    fn test_older_than() {
+        let root = tempfile::tempdir().unwrap();
+        assert!(!older_than(root.path(), MIN_AGE));
+        assert!(older_than(root.path(), Duration::ZERO));
+
+        let two_hours_ago = SystemTime::now() - Duration::from_secs(2 * 60 * 60);
+        std::fs::File::open(root.path()).unwrap().set_modified(two_hours_ago).unwrap();
+        assert!(older_than(root.path(), MIN_AGE));
+        assert!(!older_than(&root.path().join("missing"), Duration::ZERO));
+    }
+
+    #[test]
+    fn test_disk_usage() {
+        let root = tempfile::tempdir().unwrap();
+        assert_eq!(disk_usage(root.path()), 0);
+        std::fs::write(root.path().join("a"), [0; 100]).unwrap();
+        std::fs::create_dir_all(root.path().join("sub/deeper")).unwrap();
+        std::fs::write(root.path().join("sub/deeper/b"), [0; 23]).unwrap();
+        assert_eq!(disk_usage(root.path()), 123);
+        assert_eq!(disk_usage(&root.path().join("missing")), 0);
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0 MiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+}
@@ -1,11 +1,75 @@
-use anyhow::Result;
-use std::path::Path;
-use ytaudio_core::config::Config;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use ytaudio_core::config::{redact_url, Config};
 
-pub async fn run(config_path: Option<&Path>) -> Result<()> {
-    let config = Config::load(config_path)?;
+/// The `--config` path, or the default config location when none is given
+fn config_file(config_path: Option<&Path>) -> Result<PathBuf> {
+    match config_path {
+        Some(p) => Ok(p.to_path_buf()),
+        None => Ok(dirs::config_dir()
+            .context("Could not determine config directory")?
+            .join("ytaudio/config.toml")),
+    }
+}
 
-    println!("ytaudio configuration\n");
+/// Write `Config::default()` as TOML to the `--config` path, or the default
+/// config location when none is given
+pub async fn init(config_path: Option<&Path>, force: bool) -> Result<()> {
+    let path = config_file(config_path)?;
+
+    if path.exists() && !force {
+        anyhow::bail!(
+            "{} already exists (use --force to overwrite)",
+            path.display()
+        );
+    }
+
+    let contents = toml::to_string_pretty(&Config::default())
+        .context("Failed to serialize default config")?;
+
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(&path, contents)
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    println!("Wrote default config to {}", path.display());
+    Ok(())
+}
+
+/// Check `config_path`, or the default config file, for values that would
+/// be rejected at load time and list all of them
+pub fn validate(config_path: Option<&Path>) -> Result<()> {
+    let path = config_file(config_path)?;
+
+    let problems = Config::file_problems(&path)?;
+    if problems.is_empty() {
+        println!("{}: OK", path.display());
+        return Ok(());
+    }
+
+    println!("{}:", path.display());
+    for problem in &problems {
+        println!("  {}", problem);
+    }
+    let count = problems.len();
+    let first = problems.into_iter().next().expect("problems is not empty");
+    Err(anyhow::Error::new(first).context(format!(
+        "{} has {} problem{}",
+        path.display(),
+        count,
+        if count == 1 { "" } else { "s" }
+    )))
+}
+
+pub async fn run(config_path: Option<&Path>, profile: Option<&str>) -> Result<()> {
+    let config = Config::load_profile(config_path, profile)?;
+
+    match profile {
+        Some(name) => println!("ytaudio configuration (profile {:?})\n", name),
+        None => println!("ytaudio configuration\n"),
+    }
 
     println!("[paths]");
     if let Some(ref p) = config.paths.yt_dlp {
@@ -23,28 +87,185 @@ pub async fn run(config_path: Option<&Path>) -> Result<()> {
     } else {
         println!("  python = (auto-detect)");
     }
+    println!("  python_prefer = \"{}\"", config.paths.python_prefer.unwrap_or_default());
+
+    println!("\n[download]");
+    if let Some(ref c) = config.download.sponsorblock {
+        println!("  sponsorblock = {:?}", c);
+    } else {
+        println!("  sponsorblock = (disabled)");
+    }
+    if let Some(ref p) = config.download.proxy {
+        println!("  proxy = {:?}", p);
+    } else {
+        println!("  proxy = (none)");
+    }
+    if let Some(ref r) = config.download.rate_limit {
+        println!("  rate_limit = {:?}", r);
+    } else {
+        println!("  rate_limit = (unlimited)");
+    }
+    match config.download.extractor_args {
+        Some(ref args) => println!("  extractor_args = {:?}", args),
+        None => println!("  extractor_args = (none)"),
+    }
+    println!("  max_retries = {}", config.download.max_retries);
+    match config.download.timeout_secs {
+        0 => println!("  timeout_secs = (none)"),
+        secs => println!("  timeout_secs = {}", secs),
+    }
+    match config.download.archive_file {
+        Some(ref path) => println!("  archive_file = {:?}", path),
+        None => println!("  archive_file = (none)"),
+    }
+    println!("  lyrics_lang = {:?}", config.download.lyrics_lang);
+    match config.download.format_selector {
+        Some(ref selector) => println!("  format_selector = {:?}", selector),
+        None => println!("  format_selector = (default)"),
+    }
 
     println!("\n[output]");
     println!("  default_format = {:?}", config.output.default_format);
+    println!("  auto_lossy_format = {:?}", config.output.auto_lossy_format);
     println!("  default_directory = {:?}", config.output.default_directory);
+    match config.output.bitrate {
+        Some(kbps) => println!("  bitrate = {}", kbps),
+        None => println!("  bitrate = (per-format default)"),
+    }
+    println!("  filename_template = {:?}", config.output.filename_template);
+    println!("  on_exists = {:?}", config.output.on_exists);
+    println!("  bit_depth = {:?}", config.output.bit_depth);
+    println!("  flac_compression = {}", config.output.flac_compression);
+    println!("  channels = {:?}", config.output.channels);
+    match config.output.resample {
+        Some(rate) => println!("  resample = {}", rate),
+        None => println!("  resample = (source rate)"),
+    }
+    println!("  preserve_hires = {}", config.output.preserve_hires);
+
+    println!("\n[output.opus]");
+    println!("  application = {:?}", config.output.opus.application);
+    println!("  vbr = {:?}", config.output.opus.vbr);
+
+    println!("\n[filters]");
+    for (key, cutoff) in [
+        ("highpass", config.filters.highpass),
+        ("lowpass", config.filters.lowpass),
+    ] {
+        match cutoff {
+            Some(hz) if hz > 0 => println!("  {} = {}", key, hz),
+            _ => println!("  {} = (off)", key),
+        }
+    }
+    println!("  trim_silence = {}", config.filters.trim_silence);
+    for (key, fade) in [
+        ("fade_in", config.filters.fade_in),
+        ("fade_out", config.filters.fade_out),
+    ] {
+        match fade {
+            Some(secs) if secs > 0.0 => println!("  {} = {}", key, secs),
+            _ => println!("  {} = (off)", key),
+        }
+    }
+    match config.filters.extra_af {
+        Some(ref chain) => println!("  extra_af = {:?}", chain),
+        None => println!("  extra_af = (none)"),
+    }
+
+    println!("\n[decode]");
+    match config.decode.hwaccel {
+        Some(ref method) => println!("  hwaccel = {:?}", method),
+        None => println!("  hwaccel = (off)"),
+    }
 
     println!("\n[upscale]");
+    println!("  enabled = {}", config.upscale.enabled);
     println!("  default_quality = {:?}", config.upscale.default_quality);
+    if let Some(ref d) = config.upscale.device {
+        println!("  device = {:?}", d);
+    } else {
+        println!("  device = (auto-detect)");
+    }
+    if let Some(ref sha256) = config.upscale.flashsr_sha256 {
+        println!("  flashsr_sha256 = {:?}", sha256);
+    }
+    match config.upscale.target_rate {
+        Some(rate) => println!("  target_rate = {}", rate),
+        None => println!("  target_rate = (48000)"),
+    }
 
     println!("\n[upscale.audiosr]");
     println!("  ddim_steps = {}", config.upscale.audiosr.ddim_steps);
     println!("  guidance_scale = {}", config.upscale.audiosr.guidance_scale);
-    println!("  model = {:?}", config.upscale.audiosr.model);
+    println!("  model = \"{}\"", config.upscale.audiosr.model);
+    println!("  chunk_secs = {}", config.upscale.audiosr.chunk_secs);
+    println!("  overlap_secs = {}", config.upscale.audiosr.overlap_secs);
+    match config.upscale.audiosr.seed {
+        Some(seed) => println!("  seed = {}", seed),
+        None => println!("  seed = (random)"),
+    }
 
     println!("\n[normalize]");
     println!("  enabled = {}", config.normalize.enabled);
+    println!("  mode = {:?}", config.normalize.mode);
     println!("  target_lufs = {}", config.normalize.target_lufs);
     println!("  true_peak = {}", config.normalize.true_peak);
     println!("  lra = {}", config.normalize.lra);
+    println!("  dynamic_frame_ms = {}", config.normalize.dynamic_frame_ms);
+    println!("  dynamic_gauss_size = {}", config.normalize.dynamic_gauss_size);
+
+    println!("\n[metadata]");
+    println!("  embed_artwork = {}", config.metadata.embed_artwork);
+    println!("  square_artwork = {}", config.metadata.square_artwork);
+    println!("  id3_version = {:?}", config.metadata.id3_version);
+    println!("  embed_spectrogram = {}", config.metadata.embed_spectrogram);
+    println!("  clean_titles = {}", config.metadata.clean_titles);
 
     println!("\n[batch]");
     println!("  max_parallel = {}", config.batch.max_parallel);
+    println!("  max_parallel_upscale = {}", config.batch.max_parallel_upscale);
+    match config.batch.max_parallel_downloads {
+        Some(n) => println!("  max_parallel_downloads = {}", n),
+        None => println!("  max_parallel_downloads = (--parallel)"),
+    }
+    match config.batch.max_parallel_ffmpeg {
+        Some(n) => println!("  max_parallel_ffmpeg = {}", n),
+        None => println!("  max_parallel_ffmpeg = (CPU count)"),
+    }
     println!("  continue_on_error = {}", config.batch.continue_on_error);
+    println!("  skip_existing = {}", config.batch.skip_existing);
+
+    println!("\n[fallback]");
+    println!("  max_fallbacks = {}", config.fallback.max_fallbacks);
+    println!("  upscale = {}", config.fallback.upscale);
+
+    println!("\n[hooks]");
+    println!(
+        "  post_success = {}",
+        config
+            .hooks
+            .post_success
+            .as_deref()
+            .map_or("(none)".to_string(), |c| format!("{:?}", c))
+    );
+    println!(
+        "  post_failure = {}",
+        config
+            .hooks
+            .post_failure
+            .as_deref()
+            .map_or("(none)".to_string(), |c| format!("{:?}", c))
+    );
+
+    println!("\n[notify]");
+    println!(
+        "  webhook_url = {}",
+        config
+            .notify
+            .webhook_url
+            .as_deref()
+            .map_or("(none)".to_string(), |u| format!("{:?}", redact_url(u)))
+    );
 
     println!("\n[temp]");
     println!("  cleanup = {}", config.temp.cleanup);
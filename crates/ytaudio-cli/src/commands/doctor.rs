@@ -1,11 +1,176 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use std::path::Path;
 use std::process::Command;
+use std::time::Instant;
 use which::which;
+use ytaudio_core::config::{Config, PythonSource, TEMP_PREFIX};
+use ytaudio_core::error::ConfigError;
+use ytaudio_upscale::{UpscaleMethod, Upscaler, MODEL_SAMPLE_RATE};
 
-pub async fn run() -> Result<()> {
-    println!("ytaudio dependency check\n");
+/// Outcome of one dependency check
+struct Checks {
+    all_ok: bool,
+    /// Something `update-models` can install is missing (venv, pip packages, FlashSR model)
+    fixable: bool,
+}
+
+/// Check the dependencies. With `fix`, run the `update-models` setup when it
+/// can repair what is missing, then check again. Tools that need a system
+/// package manager (yt-dlp, ffmpeg, python3) are only reported. With `deep`,
+/// finish by running each model once (see [`check_models`]).
+pub async fn run(
+    config_path: Option<&Path>,
+    profile: Option<&str>,
+    fix: bool,
+    deep: bool,
+) -> Result<()> {
+    let checks = check(config_path, profile)?;
+    if fix && !checks.all_ok {
+        repair(config_path, profile, &checks).await?;
+    }
+    if deep {
+        check_models(config_path, profile).await?;
+    }
+    Ok(())
+}
+
+async fn repair(config_path: Option<&Path>, profile: Option<&str>, checks: &Checks) -> Result<()> {
+    if !checks.fixable {
+        println!("\nNothing --fix can install; see above for the system packages to add.");
+        return Ok(());
+    }
+    if which("python3").is_err() {
+        println!("\n--fix needs python3 to set up the environment; install it first.");
+        return Ok(());
+    }
+
+    println!("\nFixing: running update-models\n");
+    super::update_models::run(config_path, profile).await?;
+
+    println!();
+    check(config_path, profile)?;
+    Ok(())
+}
+
+/// Length of the test tone the deep check upscales
+const PROBE_SECS: f32 = 1.0;
+
+/// Load each model on the configured device and upscale a short tone with
+/// it. An import can succeed while building the model fails (a torch build
+/// without support for the device, missing weights), which only shows here.
+/// Fails when any model does, so scripts can rely on the exit code.
+async fn check_models(config_path: Option<&Path>, profile: Option<&str>) -> Result<()> {
+    let config = Config::load_profile(config_path, profile)?;
+    let device = config.upscale.device.clone();
+    println!(
+        "\nModel check (device: {})\n",
+        device.as_deref().unwrap_or("auto")
+    );
+
+    let python_path = match config.python_path() {
+        Ok(path) => path,
+        Err(e) => {
+            println!("Skipped: {}", e);
+            return Ok(());
+        }
+    };
+
+    let dir = tempfile::Builder::new()
+        .prefix(TEMP_PREFIX)
+        .tempdir()
+        .context("Failed to create a temp directory")?;
+    let input = dir.path().join("probe.wav");
+    std::fs::write(&input, probe_wav()).context("Failed to write the test tone")?;
+
+    let upscaler = Upscaler::new(python_path)
+        .with_device(device)
+        .with_flashsr_sha256(config.upscale.flashsr_sha256.clone());
+    let audiosr = &config.upscale.audiosr;
+    let methods = [
+        ("FlashSR:       ", UpscaleMethod::FlashSR),
+        (
+            "AudioSR:       ",
+            // Few steps: this checks that the model runs, not how it sounds
+            UpscaleMethod::AudioSR {
+                model: audiosr.model,
+                ddim_steps: 2,
+                guidance_scale: audiosr.guidance_scale,
+                chunk_secs: 0.0,
+                overlap_secs: 0.0,
+                seed: Some(0),
+            },
+        ),
+    ];
+
+    let mut all_ok = true;
+    for (label, method) in methods {
+        let output = dir.path().join(format!("{}.wav", method).to_lowercase());
+        let start = Instant::now();
+        let result = upscaler.upscale(&input, &output, method).await;
+        // Printed whole after the run, so the upscaler's log lines don't split it
+        print!("{}", label);
+        match result {
+            Ok(()) if output.exists() => println!("OK ({:.1}s)", start.elapsed().as_secs_f32()),
+            Ok(()) => {
+                println!("FAILED (no output written)");
+                all_ok = false;
+            }
+            Err(e) => {
+                println!("FAILED after {:.1}s", start.elapsed().as_secs_f32());
+                for line in e.to_string().lines() {
+                    println!("               {}", line);
+                }
+                all_ok = false;
+            }
+        }
+    }
+
+    println!();
+    if !all_ok {
+        anyhow::bail!(
+            "Some models failed to run. Check upscale.device, or run `ytaudio update-models`."
+        );
+    }
+    println!("All models run!");
+    Ok(())
+}
 
+/// A quiet 440Hz tone as a 16-bit mono WAV at the models' rate
+fn probe_wav() -> Vec<u8> {
+    let samples = (MODEL_SAMPLE_RATE as f32 * PROBE_SECS) as u32;
+    let data_len = samples * 2;
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+    wav.extend_from_slice(&MODEL_SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(MODEL_SAMPLE_RATE * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for i in 0..samples {
+        let t = i as f32 / MODEL_SAMPLE_RATE as f32;
+        let sample = (t * 440.0 * std::f32::consts::TAU).sin() * 0.1 * i16::MAX as f32;
+        wav.extend_from_slice(&(sample as i16).to_le_bytes());
+    }
+    wav
+}
+
+fn check(config_path: Option<&Path>, profile: Option<&str>) -> Result<Checks> {
+    // A config that fails to load is reported below; the checks go on with
+    // the system tools. A misspelled --profile is an error of its own.
+    let config = match Config::load_profile(config_path, profile) {
+        Err(e @ ConfigError::UnknownProfile { .. }) => return Err(e.into()),
+        config => config,
+    };
+
+    println!("ytaudio dependency check\n");
     let mut all_ok = true;
+    let mut fixable = false;
 
     // Check yt-dlp
     print!("yt-dlp:        ");
@@ -65,26 +230,34 @@ pub async fn run() -> Result<()> {
     // Check Python
     print!("python3:       ");
 
-    // Check for venv in multiple locations (macOS uses Application Support, Linux uses .local/share)
-    let venv_paths = [
-        dirs::data_dir().map(|d| d.join("ytaudio/venv/bin/python")),
-        dirs::home_dir().map(|d| d.join(".local/share/ytaudio/venv/bin/python")),
-    ];
+    // The interpreter the pipeline would use: paths.python, a venv (update-models'
+    // or an activated virtualenv/conda one, in paths.python_prefer order), python3
+    let interpreter = config
+        .as_ref()
+        .ok()
+        .and_then(|config| config.python_interpreter().ok())
+        .filter(|(_, source)| *source != PythonSource::System);
 
-    let venv_python = venv_paths
-        .into_iter()
-        .flatten()
-        .find(|p| p.exists());
+    if let Some((venv_python, source)) = interpreter {
+        // Packages missing from update-models' venv are what --fix installs;
+        // other environments are the user's to manage
+        let hint = match source {
+            PythonSource::Ytaudio => "Run: ytaudio update-models".to_string(),
+            _ => format!("Install it into {}", venv_python.display()),
+        };
 
-    if let Some(venv_python) = venv_python {
         let version = Command::new(&venv_python).arg("--version").output();
         match version {
             Ok(out) => {
                 let v = String::from_utf8_lossy(&out.stdout);
-                println!("OK ({}, venv)", v.trim().replace("Python ", ""));
+                println!("OK ({}, {})", v.trim().replace("Python ", ""), source);
+                if source != PythonSource::Ytaudio {
+                    println!("           {}", venv_python.display());
+                }
             }
             Err(_) => {
-                println!("VENV FOUND but failed to get version");
+                println!("{} FOUND but failed to get version", source);
+                println!("           {}", venv_python.display());
                 all_ok = false;
             }
         }
@@ -95,13 +268,17 @@ pub async fn run() -> Result<()> {
             .args(["-c", "import audiosr; print('installed')"])
             .output();
         match check {
-            Ok(out) if out.status.success() && String::from_utf8_lossy(&out.stdout).contains("installed") => {
+            Ok(out)
+                if out.status.success()
+                    && String::from_utf8_lossy(&out.stdout).contains("installed") =>
+            {
                 println!("OK");
             }
             _ => {
                 println!("NOT INSTALLED");
-                println!("               Run: ytaudio update-models");
+                println!("               {}", hint);
                 all_ok = false;
+                fixable |= source == PythonSource::Ytaudio;
             }
         }
 
@@ -116,8 +293,9 @@ pub async fn run() -> Result<()> {
             }
             _ => {
                 println!("NOT INSTALLED");
-                println!("               Run: ytaudio update-models");
+                println!("               {}", hint);
                 all_ok = false;
+                fixable |= source == PythonSource::Ytaudio;
             }
         }
 
@@ -132,8 +310,9 @@ pub async fn run() -> Result<()> {
             }
             _ => {
                 println!("NOT INSTALLED");
-                println!("               Run: ytaudio update-models");
+                println!("               {}", hint);
                 all_ok = false;
+                fixable |= source == PythonSource::Ytaudio;
             }
         }
     } else {
@@ -147,6 +326,7 @@ pub async fn run() -> Result<()> {
                         println!("OK ({}, system)", v.trim().replace("Python ", ""));
                         println!("           Note: Virtual environment not set up");
                         println!("           Run: ytaudio update-models");
+                        fixable = true;
                     }
                     Err(_) => {
                         println!("FOUND but failed to get version");
@@ -163,26 +343,75 @@ pub async fn run() -> Result<()> {
 
     // Check FlashSR model
     print!("FlashSR model: ");
-    // HuggingFace Hub uses ~/.cache on macOS, not ~/Library/Caches
-    let model_paths = [
-        dirs::home_dir().map(|d| d.join(".cache/huggingface/hub/models--YatharthS--FlashSR")),
-        dirs::cache_dir().map(|d| d.join("huggingface/hub/models--YatharthS--FlashSR")),
-    ];
-    let model_found = model_paths.into_iter().flatten().any(|p| p.exists());
+    let model_found = super::hf_hub_dirs()
+        .into_iter()
+        .any(|dir| dir.join("models--YatharthS--FlashSR").exists());
     if model_found {
         println!("OK (cached)");
     } else {
         println!("NOT DOWNLOADED");
         println!("               Run: ytaudio update-models");
         all_ok = false;
+        fixable = true;
+    }
+
+    // Network settings applied to every yt-dlp call
+    print!("proxy:         ");
+    match &config {
+        Ok(config) => {
+            match config.download.proxy {
+                Some(ref proxy) => println!("{} (download.proxy)", proxy),
+                None => println!("none"),
+            }
+            if let Some(ref rate) = config.download.rate_limit {
+                println!("rate limit:    {} (download.rate_limit)", rate);
+            }
+        }
+        Err(e) => {
+            println!("UNKNOWN (config failed to load: {})", e);
+            all_ok = false;
+        }
     }
 
     println!();
     if all_ok {
         println!("All dependencies OK!");
+    } else if fixable {
+        println!(
+            "Some dependencies are missing. Run `ytaudio doctor --fix` to install the Python ones."
+        );
     } else {
         println!("Some dependencies are missing. See above for installation instructions.");
     }
 
-    Ok(())
+    Ok(Checks { all_ok, fixable })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_wav() {
+        let wav = probe_wav();
+        let field = |at: usize| u32::from_le_bytes(wav[at..at + 4].try_into().unwrap());
+        let samples = (MODEL_SAMPLE_RATE as f32 * PROBE_SECS) as usize;
+
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(field(4) as usize, wav.len() - 8);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(field(24), MODEL_SAMPLE_RATE);
+        assert_eq!(field(28), MODEL_SAMPLE_RATE * 2);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(field(40) as usize, samples * 2);
+        assert_eq!(wav.len(), 44 + samples * 2);
+
+        // A quiet tone: not silent, and well below full scale
+        let peak = wav[44..]
+            .chunks_exact(2)
+            .map(|s| i16::from_le_bytes([s[0], s[1]]).unsigned_abs())
+            .max()
+            .unwrap();
+        assert!(peak > 0 && peak <= i16::MAX as u16 / 10 + 1, "{}", peak);
+    }
 }
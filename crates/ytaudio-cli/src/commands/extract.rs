@@ -1,53 +1,191 @@
 use anyhow::Result;
 use indicatif::{ProgressBar, ProgressStyle};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use tokio::sync::mpsc;
+use tracing::debug;
 
-use crate::args::{ExtractOptions, OutputFormat as CliFormat, UpscaleQuality as CliQuality};
+use crate::args::{ExtractOptions, UpscaleQuality as CliQuality};
 use ytaudio_core::{
+    cancel::CancelFlag,
     config::Config,
-    pipeline::{Pipeline, PipelineConfig, PipelineStage, OutputFormat, UpscaleQuality},
+    error::{DownloadError, YtAudioError},
+    pipeline::{Pipeline, PipelineConfig, PipelineStage, UpscaleQuality},
 };
 
-pub async fn run(url: &str, options: &ExtractOptions, config_path: Option<&Path>) -> Result<()> {
-    let config = Config::load(config_path)?;
+/// Extract each URL in turn, with a progress bar per URL. Several URLs end
+/// with the same summary as `batch`. `quiet` hides the bar and status notes,
+/// leaving the output paths and errors.
+pub async fn run(
+    urls: &[String],
+    options: &ExtractOptions,
+    debug_run: bool,
+    quiet: bool,
+    cancel: CancelFlag,
+    config_path: Option<&Path>,
+    profile: Option<&str>,
+) -> Result<()> {
+    let config = Config::load_profile(config_path, profile)?;
 
+    if let [url] = urls {
+        let (_, result) = extract_one(url, options, debug_run, quiet, &config, cancel).await?;
+        // Set off from the progress bar, which --quiet hides
+        let gap = if quiet { "" } else { "\n" };
+        return match result {
+            Ok(output) if options.dry_run => {
+                println!("{}Would write: {}", gap, output.display());
+                Ok(())
+            }
+            Ok(output) => {
+                println!("{}Output: {}", gap, output.display());
+                Ok(())
+            }
+            Err(YtAudioError::Download(DownloadError::AlreadyArchived(_))) => Ok(()),
+            Err(e) => {
+                // main() reports it again on exit; --quiet keeps just that line
+                if !quiet {
+                    eprintln!("\nError: {}", e);
+                }
+                Err(e.into())
+            }
+        };
+    }
+
+    let mut results = Vec::with_capacity(urls.len());
+    for (idx, url) in urls.iter().enumerate() {
+        if !quiet {
+            println!("[{}/{}] {}", idx + 1, urls.len(), url);
+        }
+        let (skipped, result) =
+            extract_one(url, options, debug_run, quiet, &config, cancel.clone()).await?;
+        let failed = result
+            .as_ref()
+            .is_err_and(|e| !super::batch::is_archived(e));
+        match result {
+            // Stop at Ctrl-C instead of failing every remaining URL
+            Err(YtAudioError::Cancelled) => return Err(YtAudioError::Cancelled.into()),
+            // A debug run documents one failure; stop while its temp files are the latest
+            Err(e) if debug_run && failed => {
+                return Err(e.into());
+            }
+            Ok(ref output) if !skipped => {
+                let verb = if options.dry_run { "Would write" } else { "Output" };
+                println!("{}: {}\n", verb, output.display());
+            }
+            _ if !quiet => println!(),
+            _ => {}
+        }
+        results.push((url.clone(), skipped, result));
+        if failed && !config.batch.continue_on_error {
+            println!("Stopped at the first failure (batch.continue_on_error is off)");
+            break;
+        }
+    }
+
+    super::batch::report(results, false)
+}
+
+/// Run the pipeline for one URL, returning whether it was skipped because the
+/// output already existed along with the pipeline result
+async fn extract_one(
+    url: &str,
+    options: &ExtractOptions,
+    debug_run: bool,
+    quiet: bool,
+    config: &Config,
+    cancel: CancelFlag,
+) -> Result<(bool, Result<PathBuf, YtAudioError>)> {
     let output_dir = options
         .output
         .clone()
         .unwrap_or_else(|| config.output.default_directory.clone());
 
     // Convert CLI types to pipeline types
-    let format = match options.format {
-        CliFormat::Flac => OutputFormat::Flac,
-        CliFormat::Wav => OutputFormat::Wav,
-        CliFormat::Mp3 => OutputFormat::Mp3,
-        CliFormat::Aac => OutputFormat::Aac,
-        CliFormat::Opus => OutputFormat::Opus,
-    };
-
-    let upscale_quality = match options.quality {
+    let upscale_quality = match options.upscale_quality(config) {
         CliQuality::Best => UpscaleQuality::Best,
         CliQuality::Fast => UpscaleQuality::Fast,
     };
 
     let pipeline_config = PipelineConfig {
-        url: url.to_string(),
+        input: super::pipeline_input(url, config).await,
         output_dir,
-        format,
-        enhance: options.enhance,
+        filename_template: options
+            .output_template
+            .clone()
+            .unwrap_or_else(|| config.output.filename_template.clone()),
+        format: options.output_format(config),
+        also: options.also.clone(),
+        encode_options: options.encode_options(config),
+        enhance: options.enhance || config.upscale.enabled,
+        force_upscale: options.force_upscale,
         upscale_quality,
-        normalize: options.normalize,
-        target_lufs: options.lufs,
-        keep_temp: options.keep_temp,
+        normalize: options.normalize(config),
+        normalize_mode: options
+            .normalize_mode
+            .map(Into::into)
+            .unwrap_or(config.normalize.mode),
+        peak_target: options.peak,
+        limit_peaks: options.limit_peaks,
+        highpass: options.highpass.or(config.filters.highpass),
+        lowpass: options.lowpass.or(config.filters.lowpass),
+        trim_silence: options.trim_silence || config.filters.trim_silence,
+        fade_in: options.fade_in.or(config.filters.fade_in),
+        fade_out: options.fade_out.or(config.filters.fade_out),
+        extra_af: options.af.clone().or_else(|| config.filters.extra_af.clone()),
+        clip: options.trim,
+        resample: options.resample.or(config.output.resample),
+        preserve_hires: options.preserve_hires || config.output.preserve_hires,
+        target_rate: options.target_rate(config),
+        target_lufs: options.target_lufs(config),
+        keep_temp: options.keep_temp || debug_run,
+        resume: options.resume,
+        device: options.device.clone().or_else(|| config.upscale.device.clone()),
+        audiosr_seed: options.seed,
+        sponsorblock: options.sponsorblock_categories(config),
+        format_selector: options
+            .format_selector
+            .clone()
+            .or_else(|| config.download.format_selector.clone()),
+        proxy: options
+            .proxy
+            .clone()
+            .or_else(|| config.download.proxy.clone()),
+        rate_limit: options
+            .limit_rate
+            .clone()
+            .or_else(|| config.download.rate_limit.clone()),
+        extractor_args: options
+            .extractor_args
+            .clone()
+            .or_else(|| config.download.extractor_args.clone()),
+        max_retries: config.download.max_retries,
+        archive_file: options.archive.clone().or_else(|| config.download.archive_file.clone()),
+        lyrics: options.lyrics.then(|| config.download.lyrics_lang.clone()),
+        max_fallbacks: config.fallback.max_fallbacks,
+        split_chapters: options.split_chapters,
+        dry_run: options.dry_run,
+        skip_existing: options.skip_existing,
+        on_exists: options
+            .on_exists
+            .unwrap_or_else(|| config.output.on_exists.parse().unwrap_or_default()),
+        verify: !options.no_verify,
+        embed_artwork: !options.no_artwork && config.metadata.embed_artwork,
+        embed_spectrogram: options.embed_spectrogram || config.metadata.embed_spectrogram,
+        clean_titles: options.clean_titles || config.metadata.clean_titles,
+        no_metadata: options.no_metadata,
+        hooks: options.hooks(config),
         paths: config.paths.clone(),
     };
 
     // Create progress channel
     let (tx, mut rx) = mpsc::channel(32);
 
-    // Create progress bar
-    let pb = ProgressBar::new(100);
+    // Create progress bar (hidden in debug runs so it doesn't interleave with
+    // logs, and with --quiet)
+    let pb = if debug_run || quiet {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(100)
+    };
     pb.set_style(
         ProgressStyle::with_template(
             "{spinner:.cyan} [{elapsed_precise}] {bar:40.cyan/blue} {msg}",
@@ -56,8 +194,17 @@ pub async fn run(url: &str, options: &ExtractOptions, config_path: Option<&Path>
     );
 
     // Spawn progress handler
+    let stats = options.stats;
     let progress_handle = tokio::spawn(async move {
+        // Status notes printed above the bar; --quiet drops them
+        let note = |message: String| {
+            if !quiet {
+                pb.suspend(|| println!("{}", message));
+            }
+        };
+        let mut skipped = false;
         while let Some(stage) = rx.recv().await {
+            debug!("Stage: {:?}", stage);
             match stage {
                 PipelineStage::Downloading { progress, title } => {
                     pb.set_position((progress * 20.0) as u64);
@@ -71,50 +218,140 @@ pub async fn run(url: &str, options: &ExtractOptions, config_path: Option<&Path>
                     pb.set_position(20 + (progress * 40.0) as u64);
                     pb.set_message(format!("Upscaling ({})...", method));
                 }
-                PipelineStage::Normalizing { target_lufs } => {
-                    pb.set_position(60);
+                PipelineStage::UpscaleSkipped { high_band_level } => {
+                    note(format!(
+                        "Source is already full-band ({:.1} dBFS above 16kHz), skipping upscaling \
+                         (--force-upscale to override)",
+                        high_band_level
+                    ));
+                }
+                PipelineStage::Trimming => {
+                    pb.set_message("Trimming silence...");
+                }
+                PipelineStage::Normalizing { target_lufs, progress } => {
+                    pb.set_position(60 + (progress * 10.0) as u64);
                     pb.set_message(format!("Normalizing to {:.1} LUFS...", target_lufs));
                 }
-                PipelineStage::Encoding { format } => {
-                    pb.set_position(75);
+                PipelineStage::Normalized { stats } => {
+                    note(format!(
+                        "Loudness: {:.1} -> {:.1} LUFS, true peak {:.1} -> {:.1} dBTP",
+                        stats.input_i, stats.output_i, stats.input_tp, stats.output_tp
+                    ));
+                }
+                PipelineStage::PeakMeasured { true_peak } => {
+                    if true_peak > 0.0 {
+                        note(format!("True peak {:.1} dBTP (clips on playback)", true_peak));
+                    }
+                }
+                PipelineStage::Limiting { ceiling } => {
+                    pb.set_position(60);
+                    pb.set_message(format!("Limiting peaks to {:.1} dBTP...", ceiling));
+                }
+                PipelineStage::ComputingReplayGain => {
+                    pb.set_position(60);
+                    pb.set_message("Computing ReplayGain...");
+                }
+                PipelineStage::NormalizingDynamic => {
+                    pb.set_position(60);
+                    pb.set_message("Normalizing dynamically...");
+                }
+                PipelineStage::NormalizingPeak { target_dbtp } => {
+                    pb.set_position(60);
+                    pb.set_message(format!("Normalizing peak to {:.1} dBTP...", target_dbtp));
+                }
+                PipelineStage::AlbumMeasured { integrated_lufs } => {
+                    pb.set_message(format!(
+                        "Measured {:.1} LUFS, waiting for the album...",
+                        integrated_lufs
+                    ));
+                }
+                PipelineStage::ApplyingAlbumGain { gain } => {
+                    pb.set_position(60);
+                    pb.set_message(format!("Applying album gain ({:+.1} dB)...", gain));
+                }
+                PipelineStage::Splitting { chapters } => {
+                    pb.set_position(70);
+                    pb.set_message(format!("Splitting into {} chapters...", chapters));
+                }
+                PipelineStage::Encoding { format, progress } => {
+                    pb.set_position(75 + (progress * 15.0) as u64);
                     pb.set_message(format!("Encoding to {}...", format));
                 }
                 PipelineStage::EmbeddingMetadata => {
                     pb.set_position(90);
                     pb.set_message("Embedding metadata...");
                 }
-                PipelineStage::Complete { output, duration } => {
+                PipelineStage::Verifying => {
+                    pb.set_position(95);
+                    pb.set_message("Verifying output...");
+                }
+                PipelineStage::Planned { stage, command } => {
+                    pb.suspend(|| println!("{}: {}", stage, command));
+                }
+                PipelineStage::Skipped { output } => {
+                    skipped = true;
+                    pb.finish_with_message(format!(
+                        "Skipped, already exists: {}",
+                        output.display()
+                    ));
+                }
+                PipelineStage::AlreadyArchived => {
+                    pb.finish_with_message("Skipped, already in the download archive");
+                }
+                PipelineStage::Resumed { stage, path } => {
+                    note(format!(
+                        "Reusing {} output from an earlier run: {}",
+                        stage,
+                        path.display()
+                    ));
+                }
+                PipelineStage::Fallback {
+                    stage,
+                    from,
+                    to,
+                    attempt,
+                    max,
+                } => {
+                    note(format!(
+                        "{} with {} failed, falling back to {} ({}/{})",
+                        stage, from, to, attempt, max
+                    ));
+                }
+                PipelineStage::Complete { output, duration, timings, .. } => {
                     pb.set_position(100);
                     pb.finish_with_message(format!(
                         "Done: {} ({:.1}s)",
                         output.display(),
                         duration.as_secs_f32()
                     ));
+                    if stats {
+                        super::print_timings(&timings, duration);
+                    }
                 }
                 PipelineStage::Failed { stage, error } => {
                     pb.abandon_with_message(format!("Failed at {}: {}", stage, error));
                 }
             }
         }
+        if !pb.is_finished() {
+            pb.finish_and_clear();
+        }
+        skipped
     });
 
     // Run pipeline
-    let pipeline = Pipeline::new(pipeline_config, tx);
+    let pipeline = Pipeline::new(pipeline_config, tx)
+        .with_cancel(cancel)
+        .with_app_config(config.clone());
     let result = pipeline.run().await;
 
+    // Close the progress channel so the handler can finish
+    drop(pipeline);
+
     // Wait for progress handler
-    progress_handle.await?;
+    let skipped = progress_handle.await?;
 
-    match result {
-        Ok(output) => {
-            println!("\nOutput: {}", output.display());
-            Ok(())
-        }
-        Err(e) => {
-            eprintln!("\nError: {}", e);
-            Err(e.into())
-        }
-    }
+    Ok((skipped, result))
 }
 
 fn truncate(s: &str, max_len: usize) -> String {
@@ -0,0 +1,98 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::time::Duration;
+use ytaudio_core::{config::Config, downloader::{Downloader, FormatInfo}};
+
+/// Print a video's metadata and audio-only formats, or with `list_formats`
+/// just the formats. Read-only: nothing is downloaded.
+pub async fn run(
+    url: &str,
+    list_formats: bool,
+    config_path: Option<&Path>,
+    profile: Option<&str>,
+) -> Result<()> {
+    let config = Config::load_profile(config_path, profile)?;
+
+    let downloader = Downloader::new(config.yt_dlp_path()?, config.temp_dir())
+        .with_proxy(config.download.proxy.clone())
+        .with_rate_limit(config.download.rate_limit.clone())
+        .with_extractor_args(config.download.extractor_args.clone())
+        .with_timeout(Some(Duration::from_secs(config.download.timeout_secs)));
+    let metadata = downloader
+        .fetch_metadata(url)
+        .await
+        .with_context(|| format!("Failed to fetch metadata for {}", url))?;
+
+    let mut audio: Vec<_> = metadata.formats.iter().filter(|f| f.is_audio_only()).collect();
+    if list_formats {
+        audio.sort_by(|a, b| b.abr.unwrap_or(0.0).total_cmp(&a.abr.unwrap_or(0.0)));
+        print_formats(&audio);
+        return Ok(());
+    }
+
+    println!("{}\n", metadata.title);
+    println!("  id:        {}", metadata.id);
+    println!("  uploader:  {}", metadata.uploader.as_deref().unwrap_or("-"));
+    if let Some(ref artist) = metadata.artist {
+        println!("  artist:    {}", artist);
+    }
+    if let Some(ref album) = metadata.album {
+        println!("  album:     {}", album);
+    }
+    println!("  uploaded:  {}", metadata.upload_date.as_deref().unwrap_or("-"));
+    println!(
+        "  duration:  {}",
+        metadata.duration.map(format_duration).unwrap_or_else(|| "-".to_string())
+    );
+    println!("  chapters:  {}", metadata.chapters.len());
+    for chapter in &metadata.chapters {
+        println!("    {}  {}", format_duration(chapter.start_time), chapter.title);
+    }
+
+    println!();
+    print_formats(&audio);
+
+    Ok(())
+}
+
+fn print_formats(audio: &[&FormatInfo]) {
+    if audio.is_empty() {
+        println!("No audio-only formats listed");
+        return;
+    }
+
+    println!("Audio formats\n");
+    println!(
+        "  {:<8} {:<6} {:<12} {:>8} {:>8} {:>10}",
+        "ID", "EXT", "CODEC", "KBPS", "RATE", "SIZE"
+    );
+    for format in audio {
+        println!(
+            "  {:<8} {:<6} {:<12} {:>8} {:>8} {:>10}",
+            format.format_id,
+            format.ext,
+            format.acodec.as_deref().unwrap_or("-"),
+            format.abr.map(|abr| format!("{:.0}", abr)).unwrap_or_else(|| "-".to_string()),
+            format.asr.map(|asr| asr.to_string()).unwrap_or_else(|| "-".to_string()),
+            format
+                .size()
+                .map(|bytes| format!(
+                    "{}{:.1} MiB",
+                    if format.filesize.is_none() { "~" } else { "" },
+                    bytes as f64 / (1024.0 * 1024.0)
+                ))
+                .unwrap_or_else(|| "-".to_string()),
+        );
+    }
+}
+
+/// Seconds as `h:mm:ss`, or `m:ss` under an hour
+pub(crate) fn format_duration(secs: f64) -> String {
+    let total = secs.round() as u64;
+    let (hours, minutes, seconds) = (total / 3600, total / 60 % 60, total % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
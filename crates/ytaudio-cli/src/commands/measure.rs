@@ -0,0 +1,149 @@
+use anyhow::Result;
+use futures::stream::{self, StreamExt};
+use std::path::{Path, PathBuf};
+use ytaudio_core::{config::Config, normalizer::{LoudnessReport, Normalizer}, YtAudioError};
+
+/// Extensions picked up when measuring a directory
+const AUDIO_EXTENSIONS: &[&str] = &[
+    "flac", "wav", "mp3", "m4a", "aac", "opus", "ogg", "oga", "wv", "aif", "aiff", "mka", "webm",
+];
+
+/// Width of a histogram bucket in LU
+const BUCKET_LU: f32 = 2.0;
+
+/// Longest histogram bar in characters
+const MAX_BAR: usize = 40;
+
+/// Report integrated loudness, loudness range and true peak for a file, or
+/// for every audio file under a directory followed by a summary. Nothing is
+/// written.
+pub async fn run(input: &Path, config_path: Option<&Path>, profile: Option<&str>) -> Result<()> {
+    let config = Config::load_profile(config_path, profile)?;
+
+    let is_dir = input.is_dir();
+    let files = if is_dir {
+        let mut files = Vec::new();
+        collect_audio_files(input, &mut files)?;
+        files.sort();
+        files
+    } else if input.is_file() {
+        vec![input.to_path_buf()]
+    } else {
+        anyhow::bail!("Input not found: {}", input.display());
+    };
+    if files.is_empty() {
+        println!("No audio files found in {}", input.display());
+        return Ok(());
+    }
+
+    // ebur128 is single-threaded, so measure a few files at once
+    let jobs = std::thread::available_parallelism().map_or(4, |n| n.get());
+    let normalizer = &Normalizer::new(config.ffmpeg_path()?);
+    let mut results = stream::iter(&files)
+        .map(|file| async move { (file, normalizer.measure_ebur128(file).await) })
+        .buffered(jobs);
+
+    println!("{:>7} {:>6} {:>7}  FILE", "LUFS", "LRA", "PEAK");
+    let mut reports = Vec::new();
+    let mut first_error = None;
+    while let Some((file, result)) = results.next().await {
+        let name = file.strip_prefix(input).ok().filter(|_| is_dir).unwrap_or(file);
+        match result {
+            Ok(report) => {
+                println!(
+                    "{:>7.1} {:>6.1} {:>7.1}  {}",
+                    report.integrated_lufs,
+                    report.lra,
+                    report.true_peak,
+                    name.display()
+                );
+                reports.push(report);
+            }
+            Err(e) => {
+                println!("{:>7} {:>6} {:>7}  {} ({})", "-", "-", "-", name.display(), e);
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    if is_dir {
+        print_summary(&reports, files.len() - reports.len());
+    }
+
+    // Fail with the stage's exit code only when nothing could be measured
+    match first_error {
+        Some(e) if reports.is_empty() => Err(YtAudioError::from(e).into()),
+        _ => Ok(()),
+    }
+}
+
+/// Audio files under `dir`, recursively. Symlinked directories are not
+/// followed, so a link back up the tree cannot loop.
+fn collect_audio_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            collect_audio_files(&path, files)?;
+        } else if path.is_file() && is_audio_file(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| AUDIO_EXTENSIONS.iter().any(|a| ext.eq_ignore_ascii_case(a)))
+}
+
+/// Loudness spread across the measured files, with a histogram of the
+/// integrated loudness in [`BUCKET_LU`] steps
+fn print_summary(reports: &[LoudnessReport], failed: usize) {
+    println!("\n=== Summary ===");
+    println!("Measured: {}", reports.len());
+    if failed > 0 {
+        println!("Failed: {}", failed);
+    }
+    if reports.is_empty() {
+        return;
+    }
+
+    let mut lufs: Vec<f32> = reports.iter().map(|r| r.integrated_lufs).collect();
+    lufs.sort_by(f32::total_cmp);
+    let mean = lufs.iter().sum::<f32>() / lufs.len() as f32;
+    println!(
+        "Integrated: {:.1} to {:.1} LUFS (median {:.1}, mean {:.1})",
+        lufs[0],
+        lufs[lufs.len() - 1],
+        lufs[lufs.len() / 2],
+        mean
+    );
+    let clipping = reports.iter().filter(|r| r.true_peak > 0.0).count();
+    if clipping > 0 {
+        println!("True peak above 0 dBTP (clips on playback): {}", clipping);
+    }
+
+    let bucket = |value: f32| (value / BUCKET_LU).floor() as i32;
+    let (low, high) = (bucket(lufs[0]), bucket(lufs[lufs.len() - 1]));
+    let mut counts = vec![0usize; (high - low + 1) as usize];
+    for value in &lufs {
+        counts[(bucket(*value) - low) as usize] += 1;
+    }
+    let largest = counts.iter().copied().max().unwrap_or(1);
+
+    println!();
+    for (offset, count) in counts.iter().enumerate().rev() {
+        let start = (low + offset as i32) as f32 * BUCKET_LU;
+        let bar = (count * MAX_BAR).div_ceil(largest);
+        println!(
+            "{:>5.0} to {:>5.0} LUFS | {:<width$} {}",
+            start,
+            start + BUCKET_LU,
+            "#".repeat(bar),
+            count,
+            width = MAX_BAR
+        );
+    }
+}
@@ -1,5 +1,104 @@
 pub mod batch;
+pub mod clean;
 pub mod config;
 pub mod doctor;
 pub mod extract;
+pub mod info;
+pub mod measure;
 pub mod update_models;
+pub mod upscale;
+
+use anyhow::Result;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use ytaudio_core::cancel::CancelFlag;
+use ytaudio_core::downloader::{normalize_url, validate_youtube_url};
+use ytaudio_core::metadata::local_file_metadata;
+use ytaudio_core::pipeline::{PipelineInput, StageTimings};
+use ytaudio_core::{Config, YtAudioError};
+
+/// Cancel flag tripped by Ctrl-C, so an interrupted run kills its child
+/// process and cleans up temp files. Once installed, Ctrl-C no longer ends
+/// the process by itself; a second press exits at once with code 130.
+pub fn cancel_on_ctrl_c() -> CancelFlag {
+    let cancel = CancelFlag::new();
+    let flag = cancel.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        eprintln!("\nCancelling and cleaning up (Ctrl-C again to exit now)");
+        flag.cancel();
+
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(YtAudioError::Cancelled.exit_code());
+        }
+    });
+    cancel
+}
+
+/// Run a command with no cancellation points of its own until it finishes or
+/// `cancel` trips. A cancelled command is dropped, which kills its child
+/// processes and removes its temp directories.
+pub async fn until_cancelled(
+    cancel: &CancelFlag,
+    command: impl Future<Output = Result<()>>,
+) -> Result<()> {
+    tokio::select! {
+        biased;
+        _ = cancel.cancelled() => Err(YtAudioError::Cancelled.into()),
+        result = command => result,
+    }
+}
+
+/// HuggingFace Hub cache directories the models may be downloaded to:
+/// `HF_HUB_CACHE` or `HF_HOME/hub` when set, else the default, which is
+/// under ~/.cache on macOS too rather than ~/Library/Caches
+pub fn hf_hub_dirs() -> Vec<PathBuf> {
+    let configured = std::env::var_os("HF_HUB_CACHE")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HF_HOME").map(|home| PathBuf::from(home).join("hub")));
+    if let Some(dir) = configured {
+        return vec![dir];
+    }
+    let mut dirs: Vec<PathBuf> = [
+        dirs::home_dir().map(|d| d.join(".cache/huggingface/hub")),
+        dirs::cache_dir().map(|d| d.join("huggingface/hub")),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    dirs.dedup();
+    dirs
+}
+
+/// Pipeline input for a command-line argument: an existing file that is not a
+/// YouTube URL is processed in place, tagged from its own tags and file name.
+/// Video URLs are canonicalized with [`normalize_url`].
+pub async fn pipeline_input(arg: &str, config: &Config) -> PipelineInput {
+    let path = Path::new(arg);
+    if validate_youtube_url(arg) || !path.is_file() {
+        let url = normalize_url(arg).map_or_else(|| arg.to_string(), |normalized| normalized.url);
+        return PipelineInput::YouTubeUrl(url);
+    }
+
+    // A missing FFmpeg only costs the tags here; the pipeline reports it
+    let ffmpeg_path = config.ffmpeg_path().unwrap_or_else(|_| PathBuf::from("ffmpeg"));
+    PipelineInput::LocalFile(path.to_path_buf(), local_file_metadata(&ffmpeg_path, path).await)
+}
+
+/// Print a per-stage timing table (`--stats`); `total` also covers the time
+/// spent between stages
+pub fn print_timings(timings: &StageTimings, total: Duration) {
+    println!("\n{:<10} {:>9} {:>6}", "Stage", "Time", "Share");
+    for (stage, elapsed) in timings.stages() {
+        let share = if total.is_zero() {
+            0.0
+        } else {
+            elapsed.as_secs_f64() / total.as_secs_f64() * 100.0
+        };
+        println!("{:<10} {:>8.1}s {:>5.0}%", stage, elapsed.as_secs_f64(), share);
+    }
+    println!("{:<10} {:>8.1}s", "total", total.as_secs_f64());
+}
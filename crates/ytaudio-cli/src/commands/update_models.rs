@@ -1,10 +1,15 @@
 use anyhow::{Context, Result};
+use std::path::Path;
 use std::process::Command;
 use which::which;
+use ytaudio_core::Config;
 
-pub async fn run() -> Result<()> {
+pub async fn run(config_path: Option<&Path>, profile: Option<&str>) -> Result<()> {
     println!("Setting up Python environment and neural models...\n");
 
+    // The FlashSR model is checked against the configured digest, if any
+    let expected_sha256 = Config::load_profile(config_path, profile)?.upscale.flashsr_sha256;
+
     // Get data directory for venv
     let data_dir = dirs::data_dir()
         .context("Could not determine data directory")?
@@ -74,21 +79,39 @@ pub async fn run() -> Result<()> {
         }
     }
 
-    // Download FlashSR model
+    // Download FlashSR model and check it against the configured digest
     println!("\nDownloading FlashSR ONNX model...");
     let download_script = r#"
+import hashlib
+import sys
 from huggingface_hub import hf_hub_download
 path = hf_hub_download(repo_id='YatharthS/FlashSR', filename='model.onnx', subfolder='onnx')
 print(f'Downloaded to: {path}')
+digest = hashlib.sha256()
+with open(path, 'rb') as f:
+    for chunk in iter(lambda: f.read(1 << 20), b''):
+        digest.update(chunk)
+print(f'SHA256: {digest.hexdigest()}')
+expected = sys.argv[1].lower()
+if expected and digest.hexdigest() != expected:
+    print(f'Expected SHA256: {expected}', file=sys.stderr)
+    sys.exit(7)
 "#;
 
     let status = Command::new(&venv_python)
-        .args(["-c", download_script])
+        .args(["-c", download_script, expected_sha256.as_deref().unwrap_or("")])
         .status()
         .context("Failed to download FlashSR model")?;
 
-    if !status.success() {
-        println!("Warning: Failed to download FlashSR model. It will be downloaded on first use.");
+    match status.code() {
+        Some(0) => {}
+        Some(7) => anyhow::bail!(
+            "FlashSR model does not match the expected SHA256; the download or the Hugging Face \
+             cache is corrupted. Delete the file above and run 'ytaudio update-models' again."
+        ),
+        _ => println!(
+            "Warning: Failed to download FlashSR model. It will be downloaded on first use."
+        ),
     }
 
     println!("\n=== Setup Complete ===");
@@ -0,0 +1,100 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use ytaudio_core::{
+    config::{Config, TEMP_PREFIX},
+    decoder::Decoder,
+    encoder::{EncodeOptions, Encoder, OutputFormat},
+    error::YtAudioError,
+    metadata::local_file_metadata,
+    pipeline::{UpscaleQuality, UPSCALE_SAMPLE_RATE},
+};
+use ytaudio_upscale::Upscaler;
+
+use crate::args::UpscaleQuality as CliQuality;
+
+pub async fn run(
+    input: &Path,
+    output: &Path,
+    quality: Option<CliQuality>,
+    seed: Option<u64>,
+    target_rate: Option<u32>,
+    config_path: Option<&Path>,
+    profile: Option<&str>,
+) -> Result<()> {
+    let mut config = Config::load_profile(config_path, profile)?;
+    config.upscale.audiosr.seed = seed.or(config.upscale.audiosr.seed);
+    if !input.is_file() {
+        anyhow::bail!("Input file not found: {}", input.display());
+    }
+
+    // The output format follows the extension; no extension means WAV
+    let format = match output.extension().and_then(|e| e.to_str()) {
+        Some(ext) => ext.parse::<OutputFormat>()?,
+        None => OutputFormat::Wav,
+    };
+
+    let ffmpeg_path = config.ffmpeg_path()?;
+    let python_path = config.python_path()?;
+
+    let mut builder = tempfile::Builder::new();
+    builder.prefix(TEMP_PREFIX);
+    let temp_dir = match config.temp.directory {
+        Some(ref dir) => {
+            std::fs::create_dir_all(dir)?;
+            builder.tempdir_in(dir)?
+        }
+        None => builder.tempdir()?,
+    };
+    let decoded_wav = temp_dir.path().join("decoded.wav");
+    let upscaled_wav = temp_dir.path().join("upscaled.wav");
+
+    let encode_options = EncodeOptions {
+        // Config::load has already rejected invalid values
+        bit_depth: config.output.bit_depth.parse().unwrap_or_default(),
+        flac_compression: config.output.flac_compression,
+        opus_application: config.output.opus.application.parse().unwrap_or_default(),
+        opus_vbr: config.output.opus.vbr.parse().unwrap_or_default(),
+        ..EncodeOptions::default()
+    };
+
+    // Inputs already at the upscaler's rate and bit depth are copied, not re-decoded
+    let source = local_file_metadata(&ffmpeg_path, input).await;
+    Decoder::new(ffmpeg_path.clone())
+        .with_bit_depth(encode_options.bit_depth)
+        .with_source_codec(source.acodec)
+        .decode_to_wav(input, &decoded_wav, Some(UPSCALE_SAMPLE_RATE))
+        .await
+        .map_err(YtAudioError::from)?;
+
+    let quality = match quality.unwrap_or_else(|| CliQuality::from_config(&config)) {
+        CliQuality::Best => UpscaleQuality::Best,
+        CliQuality::Fast => UpscaleQuality::Fast,
+    };
+    Upscaler::new(python_path)
+        .with_device(config.upscale.device.clone())
+        .with_flashsr_sha256(config.upscale.flashsr_sha256.clone())
+        .with_output_rate(target_rate.or(config.upscale.target_rate))
+        .upscale(&decoded_wav, &upscaled_wav, quality.method(&config))
+        .await
+        .map_err(YtAudioError::from)?;
+
+    if let Some(parent) = output.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    match format {
+        OutputFormat::Wav => {
+            // Copy rather than rename: the temp dir may be on another filesystem
+            std::fs::copy(&upscaled_wav, output)
+                .with_context(|| format!("Failed to write {}", output.display()))?;
+        }
+        _ => {
+            Encoder::new(ffmpeg_path)
+                .encode(&upscaled_wav, output, format, &encode_options)
+                .await
+                .map_err(YtAudioError::from)?;
+        }
+    }
+
+    println!("Upscaled {} -> {}", input.display(), output.display());
+    Ok(())
+}
@@ -4,15 +4,40 @@ mod commands;
 use anyhow::Result;
 use clap::Parser;
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+use ytaudio_core::{error::ConfigError, YtAudioError};
 
-use args::{Cli, Commands};
+use args::{Cli, Commands, ConfigAction};
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(exit_code(&e));
+    }
+}
+
+/// Exit code for a failed command: the pipeline stage's code from
+/// [`YtAudioError::exit_code`], or 1 for anything else
+fn exit_code(error: &anyhow::Error) -> i32 {
+    for cause in error.chain() {
+        if let Some(e) = cause.downcast_ref::<YtAudioError>() {
+            return e.exit_code();
+        }
+        if cause.is::<ConfigError>() {
+            return ConfigError::EXIT_CODE;
+        }
+    }
+    1
+}
+
+async fn run() -> Result<()> {
     let cli = Cli::parse();
 
-    // Initialize logging based on verbosity
+    // Initialize logging based on verbosity (--debug-run implies trace)
+    let debug_run = matches!(cli.command, Some(Commands::Extract { debug_run: true, .. }));
     let filter = match cli.verbose {
+        _ if debug_run => "ytaudio=trace",
+        0 if cli.quiet => "ytaudio=warn",
         0 => "ytaudio=info",
         1 => "ytaudio=debug",
         2 => "ytaudio=trace",
@@ -24,32 +49,163 @@ async fn main() -> Result<()> {
         .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(filter)))
         .init();
 
-    // Handle commands
+    // Handle commands. Those that run FFmpeg or Python for long or write temp
+    // files take Ctrl-C as a cancel: children are killed and temp files
+    // removed before exiting with 130. The rest keep the default handling.
     match cli.command {
-        Some(Commands::Extract { url, options }) => {
-            commands::extract::run(&url, &options, cli.config.as_deref()).await
+        Some(Commands::Extract { urls, debug_run, options }) => {
+            let cancel = commands::cancel_on_ctrl_c();
+            commands::extract::run(
+                &urls,
+                &options,
+                debug_run,
+                cli.quiet,
+                cancel,
+                cli.config.as_deref(),
+                cli.profile.as_deref(),
+            )
+            .await
         }
         Some(Commands::Batch {
             input,
             parallel,
+            continue_on_error,
+            normalize_album,
+            archive_output,
+            remove_archived,
+            order,
+            notify,
             options,
-        }) => commands::batch::run(&input, parallel, &options, cli.config.as_deref()).await,
-        Some(Commands::Doctor) => commands::doctor::run().await,
-        Some(Commands::UpdateModels) => commands::update_models::run().await,
-        Some(Commands::Config) => commands::config::run(cli.config.as_deref()).await,
+        }) => {
+            commands::batch::run(
+                &input,
+                parallel,
+                continue_on_error,
+                normalize_album,
+                archive_output.as_deref(),
+                remove_archived,
+                order,
+                notify.as_deref(),
+                &options,
+                cli.quiet,
+                commands::cancel_on_ctrl_c(),
+                cli.config.as_deref(),
+                cli.profile.as_deref(),
+            )
+            .await
+        }
+        Some(Commands::Info { url, list_formats }) => {
+            commands::info::run(
+                &url,
+                list_formats,
+                cli.config.as_deref(),
+                cli.profile.as_deref(),
+            )
+            .await
+        }
+        Some(Commands::Measure { input }) => {
+            let cancel = commands::cancel_on_ctrl_c();
+            let measure =
+                commands::measure::run(&input, cli.config.as_deref(), cli.profile.as_deref());
+            commands::until_cancelled(&cancel, measure).await
+        }
+        Some(Commands::Upscale { input, output, quality, seed, target_rate }) => {
+            let cancel = commands::cancel_on_ctrl_c();
+            let upscale = commands::upscale::run(
+                &input,
+                &output,
+                quality,
+                seed,
+                target_rate,
+                cli.config.as_deref(),
+                cli.profile.as_deref(),
+            );
+            commands::until_cancelled(&cancel, upscale).await
+        }
+        Some(Commands::Doctor { fix, deep }) => {
+            commands::doctor::run(cli.config.as_deref(), cli.profile.as_deref(), fix, deep).await
+        }
+        Some(Commands::UpdateModels) => {
+            commands::update_models::run(cli.config.as_deref(), cli.profile.as_deref()).await
+        }
+        Some(Commands::Clean { models, dry_run }) => {
+            commands::clean::run(models, dry_run, cli.config.as_deref(), cli.profile.as_deref())
+        }
+        Some(Commands::Config { action: Some(ConfigAction::Validate { path }), .. }) => {
+            commands::config::validate(path.as_deref().or(cli.config.as_deref()))
+        }
+        Some(Commands::Config { init: true, force, .. }) => {
+            commands::config::init(cli.config.as_deref(), force).await
+        }
+        Some(Commands::Config { .. }) => {
+            commands::config::run(cli.config.as_deref(), cli.profile.as_deref()).await
+        }
         None => {
             // If URL provided directly, treat as extract command
             if let Some(url) = cli.url {
                 let options = args::ExtractOptions {
                     enhance: cli.enhance,
+                    force_upscale: false,
                     format: cli.format,
+                    also: Vec::new(),
+                    bitrate: None,
+                    cbr: false,
+                    bit_depth: None,
+                    compression_level: None,
+                    channels: None,
+                    mono: false,
                     normalize: cli.normalize,
                     lufs: cli.lufs,
+                    normalize_mode: None,
+                    peak: None,
                     quality: cli.quality,
+                    device: None,
+                    seed: None,
                     output: Some(cli.output),
                     keep_temp: false,
+                    resume: false,
+                    sponsorblock: None,
+                    limit_peaks: false,
+                    resample: None,
+                    preserve_hires: false,
+                    target_rate: None,
+                    highpass: None,
+                    lowpass: None,
+                    trim_silence: false,
+                    fade_in: None,
+                    fade_out: None,
+                    af: None,
+                    trim: None,
+                    output_template: None,
+                    archive: None,
+                    format_selector: None,
+                    proxy: None,
+                    limit_rate: None,
+                    extractor_args: None,
+                    lyrics: false,
+                    split_chapters: false,
+                    no_artwork: false,
+                    dry_run: false,
+                    skip_existing: false,
+                    on_exists: None,
+                    embed_spectrogram: false,
+                    clean_titles: false,
+                    no_metadata: false,
+                    no_verify: false,
+                    no_hooks: false,
+                    stats: false,
                 };
-                commands::extract::run(&url, &options, cli.config.as_deref()).await
+                let cancel = commands::cancel_on_ctrl_c();
+                commands::extract::run(
+                    &[url],
+                    &options,
+                    false,
+                    cli.quiet,
+                    cancel,
+                    cli.config.as_deref(),
+                    cli.profile.as_deref(),
+                )
+                .await
             } else {
                 // No URL, print help
                 use clap::CommandFactory;
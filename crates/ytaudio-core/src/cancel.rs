@@ -0,0 +1,70 @@
+//! Cooperative cancellation for a running pipeline
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+/// Shared flag that stops a [`Pipeline`](crate::pipeline::Pipeline) mid-run.
+///
+/// Clones share state, so keep one to call [`CancelFlag::cancel`] from a UI or
+/// signal handler. The external process running at the time is killed.
+#[derive(Debug, Clone, Default)]
+pub struct CancelFlag {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancelFlag {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation; idempotent
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Resolve once [`CancelFlag::cancel`] has been called
+    pub async fn cancelled(&self) {
+        loop {
+            // Register before checking so a cancel in between is not missed
+            let notified = self.inner.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel_wakes_waiter() {
+        let flag = CancelFlag::new();
+        let waiter = tokio::spawn({
+            let flag = flag.clone();
+            async move { flag.cancelled().await }
+        });
+
+        assert!(!flag.is_cancelled());
+        flag.cancel();
+        waiter.await.unwrap();
+        assert!(flag.is_cancelled());
+
+        // Already-cancelled flags resolve immediately
+        flag.cancelled().await;
+    }
+}
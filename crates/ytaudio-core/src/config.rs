@@ -1,20 +1,36 @@
 //! Configuration management for ytaudio
 
+use crate::encoder::{
+    BitDepth, Channels, FormatChoice, OpusApplication, OpusVbr, OutputFormat,
+    DEFAULT_FLAC_COMPRESSION, MAX_FLAC_COMPRESSION,
+};
 use crate::error::ConfigError;
+use crate::metadata::{
+    is_valid_filename_template, Id3Version, OverwritePolicy, DEFAULT_FILENAME_TEMPLATE,
+};
+use crate::normalizer::{NormalizeMode, DEFAULT_DYNAMIC_FRAME_MS, DEFAULT_DYNAMIC_GAUSS_SIZE};
 use figment::{
     providers::{Env, Format, Serialized, Toml},
     Figment,
 };
 use serde::{Deserialize, Serialize};
+use ytaudio_upscale::{is_valid_output_rate, AudioSRModel, MAX_AUDIOSR_SEED};
 use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub paths: PathsConfig,
+    pub download: DownloadConfig,
     pub output: OutputConfig,
+    pub filters: FiltersConfig,
+    pub decode: DecodeConfig,
     pub upscale: UpscaleConfig,
     pub normalize: NormalizeConfig,
+    pub metadata: MetadataConfig,
     pub batch: BatchConfig,
+    pub fallback: FallbackConfig,
+    pub hooks: HooksConfig,
+    pub notify: NotifyConfig,
     pub temp: TempConfig,
 }
 
@@ -26,20 +42,156 @@ pub struct PathsConfig {
     pub ffmpeg: Option<PathBuf>,
     /// Path to Python binary (auto-detected if not set)
     pub python: Option<PathBuf>,
+    /// Which environment auto-detection tries first when `python` is not set
+    /// (the `update-models` venv when unset)
+    pub python_prefer: Option<PythonPrefer>,
+}
+
+/// Order in which [`Config::python_path`] looks for an interpreter. Both
+/// fall back to the other kind, then to `python3` on the PATH.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PythonPrefer {
+    /// The venv `update-models` sets up
+    #[default]
+    Ytaudio,
+    /// The activated virtualenv/poetry (`VIRTUAL_ENV`) or conda (`CONDA_PREFIX`) environment
+    Active,
+}
+
+impl std::fmt::Display for PythonPrefer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            PythonPrefer::Ytaudio => "ytaudio",
+            PythonPrefer::Active => "active",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Where [`Config::python_interpreter`] found the interpreter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PythonSource {
+    /// `paths.python`
+    Configured,
+    /// The venv `update-models` sets up
+    Ytaudio,
+    /// `$VIRTUAL_ENV`, set by an activated venv or by poetry
+    VirtualEnv,
+    /// `$CONDA_PREFIX`, set by an activated conda environment
+    Conda,
+    /// `python3` on the PATH
+    System,
+}
+
+impl std::fmt::Display for PythonSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            PythonSource::Configured => "paths.python",
+            PythonSource::Ytaudio => "venv",
+            PythonSource::VirtualEnv => "VIRTUAL_ENV",
+            PythonSource::Conda => "conda",
+            PythonSource::System => "system",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DownloadConfig {
+    /// SponsorBlock categories to cut out (e.g. ["music_offtopic", "sponsor"])
+    pub sponsorblock: Option<Vec<String>>,
+    /// Proxy URL passed to yt-dlp (e.g. "socks5://127.0.0.1:1080")
+    pub proxy: Option<String>,
+    /// Maximum download rate in yt-dlp notation (e.g. "1M", "500K")
+    pub rate_limit: Option<String>,
+    /// yt-dlp --extractor-args for every call (e.g. "youtube:player_client=android")
+    pub extractor_args: Option<String>,
+    /// Retries for transient failures (network errors, HTTP 403/429), with exponential backoff
+    pub max_retries: u32,
+    /// Kill a yt-dlp call running longer than this many seconds (0 disables)
+    pub timeout_secs: u64,
+    /// yt-dlp download archive; videos already listed are skipped
+    pub archive_file: Option<PathBuf>,
+    /// Subtitle language fetched by --lyrics, in yt-dlp's --sub-langs syntax (e.g. "en", "en.*")
+    pub lyrics_lang: String,
+    /// yt-dlp -f selector replacing the built-in Opus/AAC preference (e.g. "251"
+    /// or "bestaudio[ext=m4a]")
+    pub format_selector: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OutputConfig {
-    /// Default output format
+    /// Default output format, or "auto" to pick one from the source
     pub default_format: String,
+    /// Lossy format "auto" keeps for low-bitrate lossy sources
+    pub auto_lossy_format: String,
     /// Default output directory
     pub default_directory: PathBuf,
+    /// Lossy bitrate in kbps, replacing the per-format default (8-512)
+    pub bitrate: Option<u32>,
+    /// Resample to this rate in Hz; keeps the source rate when not set (48kHz when upscaling)
+    pub resample: Option<u32>,
+    /// Keep hi-res sources (up to 96kHz, at least 24-bit) when upscaling is on, and
+    /// store upscaled audio at 96kHz; sources above 96kHz are brought down to it
+    pub preserve_hires: bool,
+    /// Sample format of the decoded audio and WAV output: "16", "24" or "32f" (float)
+    pub bit_depth: String,
+    /// FLAC compression level, 0 (fastest) to 12 (smallest)
+    pub flac_compression: u8,
+    /// Channel layout: "source", "mono" or "stereo"
+    pub channels: String,
+    /// Output path relative to the directory, e.g. "{artist} - {title}" or "{date}/{title}"
+    pub filename_template: String,
+    /// When the output file exists: "skip", "overwrite" or "rename" (adds " (1)", " (2)", ...)
+    pub on_exists: String,
+    /// libopus tuning for Opus output
+    pub opus: OpusConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpusConfig {
+    /// "audio" for music, "voip" for speech, "lowdelay" for minimal latency
+    pub application: String,
+    /// "on" (VBR), "off" (CBR) or "constrained" VBR; --cbr forces "off"
+    pub vbr: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FiltersConfig {
+    /// High-pass cutoff in Hz against rumble, applied right after decoding (0 or unset disables)
+    pub highpass: Option<u32>,
+    /// Low-pass cutoff in Hz, applied right after decoding (0 or unset disables)
+    pub lowpass: Option<u32>,
+    /// Cut leading and trailing silence (below -60 dBFS) before normalization
+    pub trim_silence: bool,
+    /// Fade-in length in seconds (0 or unset disables)
+    pub fade_in: Option<f32>,
+    /// Fade-out length in seconds (0 or unset disables)
+    pub fade_out: Option<f32>,
+    /// FFmpeg -af chain of your own, run after highpass/lowpass while decoding
+    pub extra_af: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DecodeConfig {
+    /// FFmpeg `-hwaccel` method ("auto", "cuda", "videotoolbox", ...) for
+    /// inputs muxed with video; off when not set
+    pub hwaccel: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UpscaleConfig {
+    /// Enable upscaling by default
+    pub enabled: bool,
     /// Default upscaling quality: "fast" (FlashSR) or "best" (AudioSR)
     pub default_quality: String,
+    /// Device for the neural models: "cpu", "cuda:0", "mps" (auto-selected if not set)
+    pub device: Option<String>,
+    /// Expected SHA256 of the FlashSR model; checked before every load when set
+    pub flashsr_sha256: Option<String>,
+    /// Rate the upscalers write in Hz, 32000 to 48000 (the models' 48kHz if not set)
+    pub target_rate: Option<u32>,
     /// AudioSR-specific settings
     pub audiosr: AudioSRConfig,
 }
@@ -51,27 +203,87 @@ pub struct AudioSRConfig {
     /// Guidance scale (default: 3.5)
     pub guidance_scale: f32,
     /// Model variant: "basic" or "speech"
-    pub model: String,
+    pub model: AudioSRModel,
+    /// Split inputs longer than this into windows, in seconds (0 disables)
+    pub chunk_secs: f32,
+    /// Overlap between windows, crossfaded at the joins (default: 1.0)
+    pub overlap_secs: f32,
+    /// Diffusion seed (0 to 4294967295); a random one per run when not set
+    pub seed: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NormalizeConfig {
     /// Enable normalization by default
     pub enabled: bool,
+    /// "loudnorm" (re-encode), "replaygain" (tags only) or "dynamic" (dynaudnorm, single pass)
+    pub mode: NormalizeMode,
     /// Target LUFS level (default: -14.0)
     pub target_lufs: f32,
     /// True peak limit (default: -1.0)
     pub true_peak: f32,
     /// Loudness range (default: 11.0)
     pub lra: f32,
+    /// Dynamic mode frame length in ms, 10 to 8000 (default: 500)
+    pub dynamic_frame_ms: u32,
+    /// Dynamic mode Gaussian smoothing window in frames, odd, 3 to 301 (default: 31)
+    pub dynamic_gauss_size: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetadataConfig {
+    /// Embed the video thumbnail as cover art (false skips fetching it too)
+    pub embed_artwork: bool,
+    /// Center-crop the 16:9 video thumbnail to a square before embedding it as cover art
+    pub square_artwork: bool,
+    /// ID3v2 revision for MP3 files: "2.3" (read by older players) or "2.4"
+    pub id3_version: String,
+    /// Embed a spectrogram of the output as a second picture (MP3 and FLAC only)
+    pub embed_spectrogram: bool,
+    /// Strip "(Official Music Video)", "[Lyrics]", "(HD)" and the like from titles,
+    /// and take the artist from "Artist - Title" when the video names none
+    pub clean_titles: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BatchConfig {
     /// Maximum parallel downloads
     pub max_parallel: usize,
-    /// Continue on error
+    /// How many of the parallel items may upscale at once (one per GPU is best)
+    pub max_parallel_upscale: usize,
+    /// How many items may run yt-dlp at once (defaults to the batch's `--parallel`)
+    pub max_parallel_downloads: Option<usize>,
+    /// How many FFmpeg stages may run at once (defaults to the CPU count)
+    pub max_parallel_ffmpeg: Option<usize>,
+    /// Keep processing the remaining items after one fails; when off, the
+    /// first failure ends the batch
     pub continue_on_error: bool,
+    /// Skip URLs whose output file already exists (resume an interrupted batch)
+    pub skip_existing: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FallbackConfig {
+    /// Maximum automatic fallbacks per item, across all stages (0 disables)
+    pub max_fallbacks: u32,
+    /// Retry a failed AudioSR upscale with FlashSR instead of failing the item
+    pub upscale: bool,
+}
+
+/// Shell commands run after each item; see [`crate::hooks`] for the placeholders
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HooksConfig {
+    /// Run after an item is written, e.g. `beet import -q {output}`
+    pub post_success: Option<String>,
+    /// Run after an item fails, with `{error}` set
+    pub post_failure: Option<String>,
+}
+
+/// Where to report that a batch has finished
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NotifyConfig {
+    /// POST a JSON summary here when a batch ends (Discord, Slack or any webhook)
+    pub webhook_url: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -89,29 +301,92 @@ impl Default for Config {
                 yt_dlp: None,
                 ffmpeg: None,
                 python: None,
+                python_prefer: None,
+            },
+            download: DownloadConfig {
+                sponsorblock: None,
+                proxy: None,
+                rate_limit: None,
+                extractor_args: None,
+                max_retries: 3,
+                timeout_secs: 3600,
+                archive_file: None,
+                lyrics_lang: "en".to_string(),
+                format_selector: None,
             },
             output: OutputConfig {
                 default_format: "flac".to_string(),
+                auto_lossy_format: "opus".to_string(),
                 default_directory: PathBuf::from("."),
+                bitrate: None,
+                resample: None,
+                preserve_hires: false,
+                bit_depth: BitDepth::default().to_string(),
+                flac_compression: DEFAULT_FLAC_COMPRESSION,
+                channels: Channels::default().to_string(),
+                filename_template: DEFAULT_FILENAME_TEMPLATE.to_string(),
+                on_exists: OverwritePolicy::default().to_string(),
+                opus: OpusConfig {
+                    application: OpusApplication::default().to_string(),
+                    vbr: OpusVbr::default().to_string(),
+                },
+            },
+            filters: FiltersConfig {
+                highpass: None,
+                lowpass: None,
+                trim_silence: false,
+                fade_in: None,
+                fade_out: None,
+                extra_af: None,
+            },
+            decode: DecodeConfig {
+                hwaccel: None,
             },
             upscale: UpscaleConfig {
+                enabled: false,
                 default_quality: "fast".to_string(),
+                device: None,
+                flashsr_sha256: None,
+                target_rate: None,
                 audiosr: AudioSRConfig {
                     ddim_steps: 50,
                     guidance_scale: 3.5,
-                    model: "basic".to_string(),
+                    model: AudioSRModel::Basic,
+                    chunk_secs: 30.0,
+                    overlap_secs: 1.0,
+                    seed: None,
                 },
             },
             normalize: NormalizeConfig {
                 enabled: false,
+                mode: NormalizeMode::Loudnorm,
                 target_lufs: -14.0,
                 true_peak: -1.0,
                 lra: 11.0,
+                dynamic_frame_ms: DEFAULT_DYNAMIC_FRAME_MS,
+                dynamic_gauss_size: DEFAULT_DYNAMIC_GAUSS_SIZE,
+            },
+            metadata: MetadataConfig {
+                embed_artwork: true,
+                square_artwork: true,
+                id3_version: Id3Version::default().to_string(),
+                embed_spectrogram: false,
+                clean_titles: false,
             },
             batch: BatchConfig {
                 max_parallel: 4,
+                max_parallel_upscale: 1,
+                max_parallel_downloads: None,
+                max_parallel_ffmpeg: None,
                 continue_on_error: true,
+                skip_existing: false,
+            },
+            fallback: FallbackConfig {
+                max_fallbacks: 2,
+                upscale: false,
             },
+            hooks: HooksConfig::default(),
+            notify: NotifyConfig::default(),
             temp: TempConfig {
                 cleanup: true,
                 directory: None,
@@ -123,8 +398,16 @@ impl Default for Config {
 impl Config {
     /// Load configuration from file and environment
     pub fn load(config_file: Option<&Path>) -> Result<Self, ConfigError> {
-        let mut figment = Figment::new()
-            .merge(Serialized::defaults(Config::default()));
+        Self::load_profile(config_file, None)
+    }
+
+    /// [`Config::load`] with the `[profiles.<name>]` table of the config
+    /// files merged over them; environment variables still win
+    pub fn load_profile(
+        config_file: Option<&Path>,
+        profile: Option<&str>,
+    ) -> Result<Self, ConfigError> {
+        let mut figment = Figment::new().merge(Serialized::defaults(Config::default()));
 
         // Load from default config directory
         if let Some(config_dir) = dirs::config_dir() {
@@ -139,10 +422,421 @@ impl Config {
             figment = figment.merge(Toml::file(path));
         }
 
+        if let Some(name) = profile {
+            figment = select_profile(figment, name)?;
+        }
+
         // Load from environment
         figment = figment.merge(Env::prefixed("YTAUDIO_").split("_"));
 
-        figment.extract().map_err(|e| ConfigError::LoadError(e.to_string()))
+        let mut config: Config = figment
+            .extract()
+            .map_err(|e| ConfigError::LoadError(e.to_string()))?;
+        if let Some(e) = config.expand_paths(|name| std::env::var(name).ok()).into_iter().next() {
+            return Err(e);
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Everything wrong with the config file at `path`, read on its own over
+    /// the defaults (without the user config or `YTAUDIO_*` variables): keys
+    /// ytaudio doesn't know, paths that don't expand and the values
+    /// [`Config::problems`] rejects, for the base settings and for each
+    /// `[profiles.<name>]` table merged over them. Fails only when the file
+    /// can't be read or its base settings don't deserialize.
+    pub fn file_problems(path: &Path) -> Result<Vec<ConfigError>, ConfigError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::LoadError(format!("{}: {}", path.display(), e)))?;
+        let mut table: toml::Table = text
+            .parse()
+            .map_err(|e| ConfigError::LoadError(format!("{}: {}", path.display(), e)))?;
+        let schema = match serde_json::to_value(Config::default()) {
+            Ok(serde_json::Value::Object(schema)) => schema,
+            _ => serde_json::Map::new(),
+        };
+
+        let mut unknown = Vec::new();
+        let mut profiles = Vec::new();
+        let profile_tables = table.remove("profiles");
+        unknown_keys(&table, &schema, "", &mut unknown);
+        match profile_tables {
+            Some(toml::Value::Table(tables)) => {
+                for (name, profile) in tables {
+                    match profile {
+                        toml::Value::Table(profile) => {
+                            unknown_keys(
+                                &profile,
+                                &schema,
+                                &format!("profiles.{}.", name),
+                                &mut unknown,
+                            );
+                            profiles.push(name);
+                        }
+                        _ => unknown.push(format!("profiles.{}", name)),
+                    }
+                }
+            }
+            Some(_) => unknown.push("profiles".to_string()),
+            None => {}
+        }
+        let mut problems: Vec<ConfigError> =
+            unknown.into_iter().map(ConfigError::UnknownKey).collect();
+
+        let figment = Figment::new()
+            .merge(Serialized::defaults(Config::default()))
+            .merge(Toml::string(&text));
+        problems.extend(Self::extract_problems(figment.clone())?);
+        for name in profiles {
+            match select_profile(figment.clone(), &name).and_then(Self::extract_problems) {
+                Ok(found) => problems.extend(found.into_iter().map(|e| in_profile(&name, e))),
+                Err(e) => problems.push(in_profile(&name, e)),
+            }
+        }
+        Ok(problems)
+    }
+
+    /// Path and value problems of the config `figment` deserializes to
+    fn extract_problems(figment: Figment) -> Result<Vec<ConfigError>, ConfigError> {
+        let mut config: Config = figment
+            .extract()
+            .map_err(|e| ConfigError::LoadError(e.to_string()))?;
+        let mut problems = config.expand_paths(|name| std::env::var(name).ok());
+        problems.extend(config.problems());
+        Ok(problems)
+    }
+
+    /// Expand `~` and `$VAR` / `${VAR}` in the path settings, looking
+    /// variables up with `lookup`. Returns an error for each path naming an
+    /// unset variable; those are left as they were.
+    fn expand_paths(&mut self, lookup: impl Fn(&str) -> Option<String>) -> Vec<ConfigError> {
+        let home = dirs::home_dir();
+        let mut problems = Vec::new();
+        let mut expand =
+            |key: &str, path: &mut PathBuf| match expand_path(path, home.as_deref(), &lookup) {
+                Ok(expanded) => *path = expanded,
+                Err(name) => problems.push(ConfigError::InvalidValue(format!(
+                    "{} = {:?} (environment variable {} is not set)",
+                    key, path, name
+                ))),
+            };
+
+        for (key, path) in [
+            ("paths.yt_dlp", &mut self.paths.yt_dlp),
+            ("paths.ffmpeg", &mut self.paths.ffmpeg),
+            ("paths.python", &mut self.paths.python),
+            ("temp.directory", &mut self.temp.directory),
+        ] {
+            if let Some(path) = path {
+                expand(key, path);
+            }
+        }
+        expand("output.default_directory", &mut self.output.default_directory);
+        problems
+    }
+
+    /// Load the config again, e.g. when a long-running program sees the file
+    /// change. On error `self` is left as it was, so a half-edited file
+    /// doesn't take down the running config. Pipelines built afterwards
+    /// need the new value passed to [`crate::pipeline::Pipeline::with_app_config`].
+    /// Pass the profile the config was first loaded with to keep it applied.
+    pub fn reload(
+        &mut self,
+        config_file: Option<&Path>,
+        profile: Option<&str>,
+    ) -> Result<(), ConfigError> {
+        *self = Self::load_profile(config_file, profile)?;
+        Ok(())
+    }
+
+    /// Check values that deserialize fine but would fail deep in the pipeline,
+    /// reporting the first bad one
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        match self.problems().into_iter().next() {
+            Some(problem) => Err(problem),
+            None => Ok(()),
+        }
+    }
+
+    /// Every value [`Config::validate`] would reject, in config order
+    pub fn problems(&self) -> Vec<ConfigError> {
+        fn invalid(key: &str, value: impl std::fmt::Debug, expected: &str) -> ConfigError {
+            ConfigError::InvalidValue(format!("{} = {:?} (expected {})", key, value, expected))
+        }
+        let mut problems = Vec::new();
+
+        if let Some(ref rate) = self.download.rate_limit {
+            if !is_rate_limit(rate) {
+                problems.push(invalid("download.rate_limit", rate, "a rate like 500K, 1.5M or 2G"));
+            }
+        }
+
+        if let Some(ref selector) = self.download.format_selector {
+            if selector.trim().is_empty() {
+                problems.push(invalid(
+                    "download.format_selector",
+                    selector,
+                    "a yt-dlp format selector like \"bestaudio\"",
+                ));
+            }
+        }
+
+        if self.download.lyrics_lang.trim().is_empty() {
+            problems.push(invalid(
+                "download.lyrics_lang",
+                &self.download.lyrics_lang,
+                "a subtitle language like \"en\"",
+            ));
+        }
+
+        if self.output.default_format.parse::<FormatChoice>().is_err() {
+            problems.push(invalid(
+                "output.default_format",
+                &self.output.default_format,
+                "flac, wav, mp3, aac, opus, vorbis, wavpack or auto",
+            ));
+        }
+
+        if !self
+            .output
+            .auto_lossy_format
+            .parse::<OutputFormat>()
+            .is_ok_and(|format| !format.is_lossless())
+        {
+            problems.push(invalid(
+                "output.auto_lossy_format",
+                &self.output.auto_lossy_format,
+                "a lossy format: mp3, aac, opus or vorbis",
+            ));
+        }
+
+        if let Some(kbps) = self.output.bitrate {
+            if !(8..=512).contains(&kbps) {
+                problems.push(invalid("output.bitrate", kbps, "8 to 512 kbps"));
+            }
+        }
+
+        if self.output.bit_depth.parse::<BitDepth>().is_err() {
+            problems.push(invalid(
+                "output.bit_depth",
+                &self.output.bit_depth,
+                "\"16\", \"24\" or \"32f\"",
+            ));
+        }
+
+        if self.output.channels.parse::<Channels>().is_err() {
+            problems.push(invalid(
+                "output.channels",
+                &self.output.channels,
+                "source, mono or stereo",
+            ));
+        }
+
+        if self.output.flac_compression > MAX_FLAC_COMPRESSION {
+            problems.push(invalid(
+                "output.flac_compression",
+                self.output.flac_compression,
+                "0 to 12",
+            ));
+        }
+
+        if self
+            .output
+            .opus
+            .application
+            .parse::<OpusApplication>()
+            .is_err()
+        {
+            problems.push(invalid(
+                "output.opus.application",
+                &self.output.opus.application,
+                "audio, voip or lowdelay",
+            ));
+        }
+
+        if self.output.opus.vbr.parse::<OpusVbr>().is_err() {
+            problems.push(invalid(
+                "output.opus.vbr",
+                &self.output.opus.vbr,
+                "on, off or constrained",
+            ));
+        }
+
+        if self.metadata.id3_version.parse::<Id3Version>().is_err() {
+            problems.push(invalid(
+                "metadata.id3_version",
+                &self.metadata.id3_version,
+                "\"2.3\" or \"2.4\"",
+            ));
+        }
+
+        if self.output.on_exists.parse::<OverwritePolicy>().is_err() {
+            problems.push(invalid(
+                "output.on_exists",
+                &self.output.on_exists,
+                "skip, overwrite or rename",
+            ));
+        }
+
+        if let Some(rate) = self.output.resample {
+            if !(8000..=384000).contains(&rate) {
+                problems.push(invalid(
+                    "output.resample",
+                    rate,
+                    "a sample rate between 8000 and 384000 Hz",
+                ));
+            }
+        }
+
+        if let (Some(highpass @ 1..), Some(lowpass @ 1..)) =
+            (self.filters.highpass, self.filters.lowpass)
+        {
+            if highpass >= lowpass {
+                problems.push(invalid(
+                    "filters.highpass",
+                    highpass,
+                    "a cutoff below filters.lowpass",
+                ));
+            }
+        }
+
+        for (key, fade) in [
+            ("filters.fade_in", self.filters.fade_in),
+            ("filters.fade_out", self.filters.fade_out),
+        ] {
+            if let Some(secs) = fade.filter(|secs| !(0.0..=60.0).contains(secs)) {
+                problems.push(invalid(key, secs, "0 to 60 seconds"));
+            }
+        }
+
+        if let Some(ref chain) = self.filters.extra_af {
+            if chain.trim().is_empty() {
+                problems.push(invalid(
+                    "filters.extra_af",
+                    chain,
+                    "an FFmpeg filter chain like \"aresample=resampler=soxr\"",
+                ));
+            }
+        }
+
+        if let Some(ref hwaccel) = self.decode.hwaccel {
+            if hwaccel.is_empty()
+                || !hwaccel
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'_')
+            {
+                problems.push(invalid(
+                    "decode.hwaccel",
+                    hwaccel,
+                    "an FFmpeg -hwaccel method like \"auto\" or \"cuda\"",
+                ));
+            }
+        }
+
+        if !is_valid_filename_template(&self.output.filename_template) {
+            problems.push(invalid(
+                "output.filename_template",
+                &self.output.filename_template,
+                "a non-empty relative path without . or .. components",
+            ));
+        }
+
+        if !matches!(self.upscale.default_quality.as_str(), "fast" | "best") {
+            problems.push(invalid(
+                "upscale.default_quality",
+                &self.upscale.default_quality,
+                "fast or best",
+            ));
+        }
+
+        if let Some(ref sha256) = self.upscale.flashsr_sha256 {
+            if sha256.len() != 64 || !sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+                problems.push(invalid("upscale.flashsr_sha256", sha256, "64 hex digits"));
+            }
+        }
+
+        let audiosr = &self.upscale.audiosr;
+        if audiosr.ddim_steps == 0 {
+            problems.push(invalid("upscale.audiosr.ddim_steps", audiosr.ddim_steps, "at least 1"));
+        }
+        if audiosr.chunk_secs < 0.0 {
+            problems.push(invalid("upscale.audiosr.chunk_secs", audiosr.chunk_secs, "0 or more"));
+        }
+        if audiosr.chunk_secs > 0.0 && !(0.0..audiosr.chunk_secs).contains(&audiosr.overlap_secs) {
+            problems.push(invalid(
+                "upscale.audiosr.overlap_secs",
+                audiosr.overlap_secs,
+                "at least 0 and below chunk_secs",
+            ));
+        }
+        if let Some(seed) = audiosr.seed.filter(|seed| *seed > MAX_AUDIOSR_SEED) {
+            problems.push(invalid("upscale.audiosr.seed", seed, "0 to 4294967295"));
+        }
+        if let Some(rate) = self.upscale.target_rate.filter(|rate| !is_valid_output_rate(*rate)) {
+            problems.push(invalid("upscale.target_rate", rate, "32000 to 48000"));
+        }
+
+        if !(-70.0..=-5.0).contains(&self.normalize.target_lufs) {
+            problems.push(invalid(
+                "normalize.target_lufs",
+                self.normalize.target_lufs,
+                "-70.0 to -5.0",
+            ));
+        }
+        if !(-9.0..=0.0).contains(&self.normalize.true_peak) {
+            problems.push(invalid("normalize.true_peak", self.normalize.true_peak, "-9.0 to 0.0"));
+        }
+        if !(1.0..=50.0).contains(&self.normalize.lra) {
+            problems.push(invalid("normalize.lra", self.normalize.lra, "1.0 to 50.0"));
+        }
+        if !(10..=8000).contains(&self.normalize.dynamic_frame_ms) {
+            problems.push(invalid(
+                "normalize.dynamic_frame_ms",
+                self.normalize.dynamic_frame_ms,
+                "10 to 8000",
+            ));
+        }
+        if !(3..=301).contains(&self.normalize.dynamic_gauss_size)
+            || self.normalize.dynamic_gauss_size % 2 == 0
+        {
+            problems.push(invalid(
+                "normalize.dynamic_gauss_size",
+                self.normalize.dynamic_gauss_size,
+                "an odd number from 3 to 301",
+            ));
+        }
+
+        if self.batch.max_parallel == 0 {
+            problems.push(invalid("batch.max_parallel", self.batch.max_parallel, "at least 1"));
+        }
+
+        if self.batch.max_parallel_upscale == 0 {
+            problems.push(invalid(
+                "batch.max_parallel_upscale",
+                self.batch.max_parallel_upscale,
+                "at least 1",
+            ));
+        }
+
+        if self.batch.max_parallel_downloads == Some(0) {
+            problems.push(invalid("batch.max_parallel_downloads", 0, "at least 1"));
+        }
+
+        if self.batch.max_parallel_ffmpeg == Some(0) {
+            problems.push(invalid("batch.max_parallel_ffmpeg", 0, "at least 1"));
+        }
+
+        if let Some(ref url) = self.notify.webhook_url {
+            if !is_webhook_url(url) {
+                problems.push(invalid(
+                    "notify.webhook_url",
+                    redact_url(url),
+                    "an http:// or https:// URL",
+                ));
+            }
+        }
+
+        problems
     }
 
     /// Get yt-dlp path, auto-detecting if not configured
@@ -165,33 +859,614 @@ impl Config {
         }
     }
 
-    /// Get Python path, preferring venv if available
+    /// Get Python path, preferring a venv if available
     pub fn python_path(&self) -> Result<PathBuf, ConfigError> {
+        self.python_interpreter().map(|(path, _)| path)
+    }
+
+    /// [`Config::python_path`] along with the kind of environment it is in
+    pub fn python_interpreter(&self) -> Result<(PathBuf, PythonSource), ConfigError> {
         if let Some(ref path) = self.paths.python {
-            return Ok(path.clone());
+            return Ok((path.clone(), PythonSource::Configured));
         }
 
-        // Check for venv Python in multiple locations
-        let venv_paths = [
-            // macOS standard (dirs::data_dir())
-            dirs::data_dir().map(|d| d.join("ytaudio/venv/bin/python")),
-            // XDG standard (~/.local/share)
-            dirs::home_dir().map(|d| d.join(".local/share/ytaudio/venv/bin/python")),
-        ];
-
-        for path in venv_paths.into_iter().flatten() {
-            if path.exists() {
-                return Ok(path);
-            }
+        if let Some(found) = self.find_venv_python(|name| std::env::var(name).ok()) {
+            return Ok(found);
         }
 
         // Fall back to system Python
         which::which("python3")
+            .map(|path| (path, PythonSource::System))
             .map_err(|_| ConfigError::InvalidValue("python3 not found in PATH".to_string()))
     }
 
-    /// Get temp directory
+    /// The first existing venv interpreter, in `paths.python_prefer` order,
+    /// with the activated environments read through `var`
+    fn find_venv_python(
+        &self,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Option<(PathBuf, PythonSource)> {
+        // update-models' venv: macOS standard (dirs::data_dir()), then XDG (~/.local/share)
+        let ytaudio = [
+            dirs::data_dir().map(|d| d.join("ytaudio/venv/bin/python")),
+            dirs::home_dir().map(|d| d.join(".local/share/ytaudio/venv/bin/python")),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|path| (path, PythonSource::Ytaudio));
+
+        let active = [
+            ("VIRTUAL_ENV", PythonSource::VirtualEnv),
+            ("CONDA_PREFIX", PythonSource::Conda),
+        ]
+        .into_iter()
+        .filter_map(|(name, source)| {
+            let prefix = var(name).filter(|prefix| !prefix.is_empty())?;
+            Some((Path::new(&prefix).join("bin/python"), source))
+        });
+
+        let candidates: Vec<_> = match self.paths.python_prefer.unwrap_or_default() {
+            PythonPrefer::Ytaudio => ytaudio.chain(active).collect(),
+            PythonPrefer::Active => active.chain(ytaudio).collect(),
+        };
+        candidates.into_iter().find(|(path, _)| path.exists())
+    }
+
+    /// Get temp directory. Everything ytaudio creates in it starts with
+    /// [`TEMP_PREFIX`].
     pub fn temp_dir(&self) -> PathBuf {
         self.temp.directory.clone().unwrap_or_else(std::env::temp_dir)
     }
 }
+
+/// Name prefix of the temp directories ytaudio creates, so that `ytaudio
+/// clean` can find the ones left behind
+pub const TEMP_PREFIX: &str = "ytaudio-";
+
+/// Merge the `[profiles.<name>]` table of `figment` over the rest of it
+fn select_profile(figment: Figment, name: &str) -> Result<Figment, ConfigError> {
+    let key = format!("profiles.{}", name);
+    if name.contains('.') || !figment.contains(&key) {
+        let available = figment
+            .find_value("profiles")
+            .ok()
+            .and_then(|profiles| profiles.into_dict())
+            .map(|profiles| profiles.into_keys().collect())
+            .unwrap_or_default();
+        return Err(ConfigError::UnknownProfile { name: name.to_string(), available });
+    }
+    let profile = figment.focus(&key);
+    Ok(figment.merge(profile))
+}
+
+/// Collect the keys of `table` (named from `prefix`) that `schema`, the
+/// defaults serialized to JSON, doesn't have, looking into nested tables
+fn unknown_keys(
+    table: &toml::Table,
+    schema: &serde_json::Map<String, serde_json::Value>,
+    prefix: &str,
+    unknown: &mut Vec<String>,
+) {
+    for (key, value) in table {
+        let name = format!("{}{}", prefix, key);
+        match (schema.get(key), value) {
+            (None, _) => unknown.push(name),
+            (Some(serde_json::Value::Object(inner)), toml::Value::Table(table)) => {
+                unknown_keys(table, inner, &format!("{}.", name), unknown)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// `e` with the profile it was found in
+fn in_profile(name: &str, e: ConfigError) -> ConfigError {
+    match e {
+        ConfigError::InvalidValue(msg) => {
+            ConfigError::InvalidValue(format!("[profiles.{}] {}", name, msg))
+        }
+        ConfigError::LoadError(msg) => {
+            ConfigError::LoadError(format!("[profiles.{}] {}", name, msg))
+        }
+        other => other,
+    }
+}
+
+/// Expand a leading `~` to `home` and `$VAR` / `${VAR}` through `lookup`.
+/// `$` not followed by a variable name is kept as is. Fails with the name of
+/// the first variable that `lookup` doesn't know.
+fn expand_path(
+    path: &Path,
+    home: Option<&Path>,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<PathBuf, String> {
+    // Non-UTF-8 paths can't contain anything to expand that we could read
+    let Some(text) = path.to_str() else {
+        return Ok(path.to_path_buf());
+    };
+
+    let (mut out, rest) = match (text.strip_prefix('~'), home) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            (home.to_string_lossy().into_owned(), rest)
+        }
+        _ => (String::new(), text),
+    };
+
+    let mut chars = rest.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c != '$' {
+            out.push(c);
+            continue;
+        }
+        let tail = &rest[i + 1..];
+        let (name, len) = if let Some(braced) = tail.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            }
+        } else {
+            let end = tail
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(tail.len());
+            (&tail[..end], end)
+        };
+        if name.is_empty() {
+            out.push('$');
+            continue;
+        }
+        out.push_str(&lookup(name).ok_or_else(|| name.to_string())?);
+        while chars.next_if(|&(j, _)| j <= i + len).is_some() {}
+    }
+    Ok(PathBuf::from(out))
+}
+
+/// An http(s) URL with a host, as `--notify` and `notify.webhook_url` take
+pub fn is_webhook_url(value: &str) -> bool {
+    ["https://", "http://"]
+        .iter()
+        .filter_map(|scheme| value.strip_prefix(scheme))
+        .any(|rest| {
+            !rest.is_empty() && !rest.starts_with('/') && !rest.contains(char::is_whitespace)
+        })
+}
+
+/// `url` with everything after the host hidden, for printing a webhook URL
+/// (they carry their secret in the path or query)
+pub fn redact_url(url: &str) -> String {
+    let (scheme, rest) = url.split_once("://").unwrap_or(("", url));
+    let host_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (host, tail) = rest.split_at(host_end);
+    let hidden = if tail.is_empty() { "" } else { "/***" };
+    if scheme.is_empty() {
+        format!("{}{}", host, hidden)
+    } else {
+        format!("{}://{}{}", scheme, host, hidden)
+    }
+}
+
+/// yt-dlp rate notation: bytes per second as plain decimal digits (with at
+/// most one `.`), then an optional K/M/G suffix
+pub fn is_rate_limit(value: &str) -> bool {
+    let digits = ['K', 'M', 'G', 'k', 'm', 'g']
+        .into_iter()
+        .find_map(|suffix| value.strip_suffix(suffix))
+        .unwrap_or(value);
+    digits.bytes().all(|b| b.is_ascii_digit() || b == b'.')
+        && digits.bytes().filter(|&b| b == b'.').count() <= 1
+        && digits.parse::<f64>().is_ok_and(|n| n > 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_venv_python_active() {
+        let dir = tempfile::tempdir().unwrap();
+        let env = |name: &str| {
+            let prefix = dir.path().join(name);
+            std::fs::create_dir_all(prefix.join("bin")).unwrap();
+            std::fs::write(prefix.join("bin/python"), "").unwrap();
+            prefix.to_string_lossy().into_owned()
+        };
+        let virtual_env = env("venv");
+        let conda = env("conda");
+
+        let mut config = Config::default();
+        config.paths.python_prefer = Some(PythonPrefer::Active);
+        let vars = |name: &str| match name {
+            "VIRTUAL_ENV" => Some(virtual_env.clone()),
+            "CONDA_PREFIX" => Some(conda.clone()),
+            _ => None,
+        };
+        assert_eq!(
+            config.find_venv_python(vars),
+            Some((Path::new(&virtual_env).join("bin/python"), PythonSource::VirtualEnv))
+        );
+
+        // An environment without an interpreter is skipped
+        let vars = |name: &str| match name {
+            "VIRTUAL_ENV" => Some(dir.path().join("missing").to_string_lossy().into_owned()),
+            "CONDA_PREFIX" => Some(conda.clone()),
+            _ => None,
+        };
+        assert_eq!(
+            config.find_venv_python(vars),
+            Some((Path::new(&conda).join("bin/python"), PythonSource::Conda))
+        );
+
+        config.paths.python = Some(PathBuf::from("/opt/python3"));
+        assert_eq!(
+            config.python_interpreter().unwrap(),
+            (PathBuf::from("/opt/python3"), PythonSource::Configured)
+        );
+    }
+
+    #[test]
+    fn test_expand_path() {
+        let home = Path::new("/home/me");
+        let lookup = |name: &str| match name {
+            "MUSIC" => Some("/srv/music".to_string()),
+            "SUB" => Some("flac".to_string()),
+            _ => None,
+        };
+        let expand = |text: &str| expand_path(Path::new(text), Some(home), lookup);
+
+        assert_eq!(expand("~").unwrap(), PathBuf::from("/home/me"));
+        assert_eq!(expand("~/Music").unwrap(), PathBuf::from("/home/me/Music"));
+        assert_eq!(expand("~other/Music").unwrap(), PathBuf::from("~other/Music"));
+        assert_eq!(expand("$MUSIC/$SUB").unwrap(), PathBuf::from("/srv/music/flac"));
+        assert_eq!(expand("${MUSIC}_${SUB}").unwrap(), PathBuf::from("/srv/music_flac"));
+        assert_eq!(expand("a/$/b$").unwrap(), PathBuf::from("a/$/b$"));
+        assert_eq!(expand("${MUSIC").unwrap(), PathBuf::from("${MUSIC"));
+        assert_eq!(expand("/usr/bin/ffmpeg").unwrap(), PathBuf::from("/usr/bin/ffmpeg"));
+        assert_eq!(expand("$NOPE/x").unwrap_err(), "NOPE");
+    }
+
+    #[test]
+    fn test_expand_paths_reports_key() {
+        let mut config = Config::default();
+        config.paths.ffmpeg = Some(PathBuf::from("$FFMPEG_HOME/bin/ffmpeg"));
+        config.temp.directory = Some(PathBuf::from("${SCRATCH}/tmp"));
+        let errors =
+            config.expand_paths(|name| (name == "SCRATCH").then(|| "/scratch".to_string()));
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        let err = errors[0].to_string();
+        assert!(err.contains("paths.ffmpeg") && err.contains("FFMPEG_HOME"), "{}", err);
+
+        config.paths.ffmpeg = Some(PathBuf::from("${FFMPEG_HOME}/bin/ffmpeg"));
+        assert!(config.expand_paths(|name| Some(format!("/{}", name.to_lowercase()))).is_empty());
+        assert_eq!(config.paths.ffmpeg, Some(PathBuf::from("/ffmpeg_home/bin/ffmpeg")));
+        assert_eq!(config.temp.directory, Some(PathBuf::from("/scratch/tmp")));
+        assert_eq!(config.output.default_directory, PathBuf::from("."));
+    }
+
+    #[test]
+    fn test_problems_lists_every_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "[output]\nbitrate = 4\n\n[batch]\nmax_parallel = 0\n").unwrap();
+
+        let problems: Vec<String> = Config::file_problems(&path)
+            .unwrap()
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].contains("output.bitrate = 4"), "{:?}", problems);
+        assert!(problems[1].contains("batch.max_parallel = 0"), "{:?}", problems);
+
+        assert!(Config::file_problems(&dir.path().join("missing.toml")).is_err());
+    }
+
+    #[test]
+    fn test_file_problems_paths_keys_and_profiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            r#"
+                typo = 1
+
+                [paths]
+                ffmpeg = "$YTAUDIO_TEST_UNSET_VARIABLE/ffmpeg"
+
+                [output]
+                bitrat = 320
+
+                [profiles.podcast.output]
+                bitrate = 4
+
+                [profiles.archive.upscale]
+                enabeld = true
+            "#,
+        )
+        .unwrap();
+
+        let problems: Vec<String> = Config::file_problems(&path)
+            .unwrap()
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(
+            problems.iter().filter(|p| p.starts_with("Unknown config key")).count(),
+            3,
+            "{:?}",
+            problems
+        );
+        for key in ["typo", "output.bitrat", "profiles.archive.upscale.enabeld"] {
+            assert!(
+                problems.iter().any(|p| p.ends_with(&format!(": {}", key))),
+                "{} in {:?}",
+                key,
+                problems
+            );
+        }
+        // The path problem is found in the base settings and again under each profile
+        assert!(
+            problems
+                .iter()
+                .any(|p| p.contains("paths.ffmpeg") && !p.contains("[profiles")),
+            "{:?}",
+            problems
+        );
+        assert!(
+            problems
+                .iter()
+                .any(|p| p.contains("[profiles.podcast] output.bitrate = 4")),
+            "{:?}",
+            problems
+        );
+        assert!(
+            !problems
+                .iter()
+                .any(|p| p.contains("[profiles.archive] output.bitrate")),
+            "{:?}",
+            problems
+        );
+    }
+
+    #[test]
+    fn test_validate_defaults() {
+        assert!(Config::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_reports_key() {
+        let mut config = Config::default();
+        config.output.default_format = "flacc".to_string();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("output.default_format = \"flacc\""), "{}", err);
+
+        let mut config = Config::default();
+        config.output.default_format = "auto".to_string();
+        assert!(config.validate().is_ok());
+        config.output.auto_lossy_format = "flac".to_string();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("output.auto_lossy_format"), "{}", err);
+
+        let mut config = Config::default();
+        config.normalize.target_lufs = 50.0;
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("normalize.target_lufs"), "{}", err);
+
+        let mut config = Config::default();
+        config.normalize.dynamic_gauss_size = 30;
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("normalize.dynamic_gauss_size"), "{}", err);
+
+        let mut config = Config::default();
+        config.batch.max_parallel = 0;
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.batch.max_parallel_upscale = 0;
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("batch.max_parallel_upscale"), "{}", err);
+
+        let mut config = Config::default();
+        config.upscale.target_rate = Some(96000);
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("upscale.target_rate"), "{}", err);
+        config.upscale.target_rate = Some(44100);
+        assert!(config.validate().is_ok());
+
+        let mut config = Config::default();
+        config.batch.max_parallel_ffmpeg = Some(0);
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("batch.max_parallel_ffmpeg"), "{}", err);
+        config.batch.max_parallel_ffmpeg = Some(2);
+        config.batch.max_parallel_downloads = Some(1);
+        assert!(config.validate().is_ok());
+
+        let mut config = Config::default();
+        config.filters.highpass = Some(8000);
+        config.filters.lowpass = Some(4000);
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("filters.highpass"), "{}", err);
+        config.filters.lowpass = Some(0);
+        assert!(config.validate().is_ok());
+
+        let mut config = Config::default();
+        config.filters.fade_out = Some(-1.0);
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("filters.fade_out"), "{}", err);
+
+        let mut config = Config::default();
+        config.filters.extra_af = Some(" ".to_string());
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("filters.extra_af"), "{}", err);
+
+        let mut config = Config::default();
+        config.metadata.id3_version = "3".to_string();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("metadata.id3_version"), "{}", err);
+
+        let mut config = Config::default();
+        config.output.on_exists = "replace".to_string();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("output.on_exists"), "{}", err);
+
+        let mut config = Config::default();
+        config.output.opus.application = "music".to_string();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("output.opus.application"), "{}", err);
+        config.output.opus.application = "voip".to_string();
+        config.output.opus.vbr = "constrained".to_string();
+        assert!(config.validate().is_ok());
+
+        let mut config = Config::default();
+        config.decode.hwaccel = Some("auto; rm".to_string());
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("decode.hwaccel"), "{}", err);
+        config.decode.hwaccel = Some("videotoolbox".to_string());
+        assert!(config.validate().is_ok());
+
+        let mut config = Config::default();
+        config.upscale.flashsr_sha256 = Some("abc123".to_string());
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("upscale.flashsr_sha256"), "{}", err);
+        config.upscale.flashsr_sha256 = Some("0f".repeat(32));
+        assert!(config.validate().is_ok());
+
+        let mut config = Config::default();
+        config.notify.webhook_url = Some("discord.com/api/webhooks/1/abc".to_string());
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("notify.webhook_url"), "{}", err);
+        assert!(!err.contains("abc"), "{}", err);
+        config.notify.webhook_url = Some("https://discord.com/api/webhooks/1/abc".to_string());
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_redact_url() {
+        assert_eq!(
+            redact_url("https://discord.com/api/webhooks/1/abc"),
+            "https://discord.com/***"
+        );
+        assert_eq!(
+            redact_url("https://hooks.example.com?token=abc"),
+            "https://hooks.example.com/***"
+        );
+        assert_eq!(redact_url("http://localhost:8080"), "http://localhost:8080");
+        assert_eq!(redact_url("discord.com/api/webhooks/1/abc"), "discord.com/***");
+    }
+
+    #[test]
+    fn test_audiosr_model_from_toml() {
+        let load = |toml: &str| {
+            Figment::new()
+                .merge(Serialized::defaults(Config::default()))
+                .merge(Toml::string(toml))
+                .extract::<Config>()
+                .map_err(|e| e.to_string())
+        };
+        let config = load("[upscale.audiosr]\nmodel = \"speech\"").unwrap();
+        assert_eq!(config.upscale.audiosr.model, AudioSRModel::Speech);
+
+        let err = load("[upscale.audiosr]\nmodel = \"music\"").unwrap_err();
+        assert!(err.contains("basic") && err.contains("speech"), "{}", err);
+
+        let config = load("[upscale.audiosr]\nseed = 1234").unwrap();
+        assert_eq!(config.upscale.audiosr.seed, Some(1234));
+        let err = load("[upscale.audiosr]\nseed = 4294967296").unwrap().validate().unwrap_err();
+        assert!(err.to_string().contains("upscale.audiosr.seed"), "{}", err);
+    }
+
+    #[test]
+    fn test_select_profile() {
+        let figment = Figment::new()
+            .merge(Serialized::defaults(Config::default()))
+            .merge(Toml::string(
+                r#"
+                [output]
+                default_format = "flac"
+                channels = "stereo"
+
+                [profiles.podcast.output]
+                default_format = "mp3"
+                bitrate = 128
+
+                [profiles.podcast.normalize]
+                target_lufs = -16.0
+
+                [profiles.archive.upscale]
+                enabled = true
+                "#,
+            ));
+
+        let config: Config = select_profile(figment.clone(), "podcast").unwrap().extract().unwrap();
+        assert_eq!(config.output.default_format, "mp3");
+        assert_eq!(config.output.bitrate, Some(128));
+        assert_eq!(config.normalize.target_lufs, -16.0);
+        // Keys the profile leaves alone keep the base value
+        assert_eq!(config.output.channels, "stereo");
+        assert!(!config.upscale.enabled);
+
+        let err = select_profile(figment, "music").unwrap_err().to_string();
+        assert!(err.contains("\"music\"") && err.contains("archive, podcast"), "{}", err);
+    }
+
+    #[test]
+    fn test_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "[output]\ndefault_format = \"mp3\"\n").unwrap();
+
+        let mut config = Config::default();
+        config.reload(Some(&path), None).unwrap();
+        assert_eq!(config.output.default_format, "mp3");
+
+        // A bad edit leaves the loaded config in place
+        std::fs::write(&path, "[batch]\nmax_parallel = 0\n").unwrap();
+        assert!(config.reload(Some(&path), None).is_err());
+        assert_eq!(config.output.default_format, "mp3");
+        assert_eq!(config.batch.max_parallel, 4);
+    }
+
+    #[test]
+    fn test_reload_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "[output]\nbitrate = 320\n\n[profiles.podcast.output]\nbitrate = 128\n",
+        )
+        .unwrap();
+        let mut config = Config::load_profile(Some(&path), Some("podcast")).unwrap();
+        assert_eq!(config.output.bitrate, Some(128));
+
+        // The profile still applies over the edited base settings
+        std::fs::write(
+            &path,
+            "[output]\nbitrate = 256\ndefault_format = \"mp3\"\n\n[profiles.podcast.output]\nbitrate = 96\n",
+        )
+        .unwrap();
+        config.reload(Some(&path), Some("podcast")).unwrap();
+        assert_eq!(config.output.bitrate, Some(96));
+        assert_eq!(config.output.default_format, "mp3");
+
+        // A profile removed from the file is an error, not a fall back to the base
+        std::fs::write(&path, "[output]\nbitrate = 256\n").unwrap();
+        assert!(config.reload(Some(&path), Some("podcast")).is_err());
+        assert_eq!(config.output.bitrate, Some(96));
+    }
+
+    #[test]
+    fn test_is_rate_limit() {
+        assert!(is_rate_limit("1M"));
+        assert!(is_rate_limit("500K"));
+        assert!(is_rate_limit("1.5m"));
+        assert!(is_rate_limit("65536"));
+        assert!(!is_rate_limit("fast"));
+        assert!(!is_rate_limit("M"));
+        assert!(!is_rate_limit("0K"));
+        assert!(!is_rate_limit("1KM"));
+        assert!(!is_rate_limit("5mk"));
+        assert!(!is_rate_limit("inf"));
+        assert!(!is_rate_limit("1e3"));
+        assert!(!is_rate_limit("+2"));
+        assert!(!is_rate_limit("1.2.3K"));
+        assert!(!is_rate_limit("."));
+    }
+}
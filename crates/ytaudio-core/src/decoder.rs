@@ -1,63 +1,552 @@
 //! Audio decoder using FFmpeg
 
+use crate::encoder::{BitDepth, Channels};
 use crate::error::DecodeError;
+use crate::process::{
+    failure_stderr, ffmpeg_loglevel, ffmpeg_output, ffmpeg_run, probe_sample_rate,
+    DEFAULT_SAMPLE_RATE,
+};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use tokio::process::Command;
-use tracing::{debug, info};
+use tracing::{debug, info, trace};
+
+/// Cutoff above which [`Decoder::high_band_level`] measures content
+pub const HIGH_BAND_CUTOFF_HZ: u32 = 16000;
+
+/// Level below which [`Decoder::audible_range`] treats audio as silence
+pub const SILENCE_THRESHOLD_DB: i32 = -60;
+
+/// Shortest pause [`Decoder::audible_range`] counts as silence, in seconds
+const MIN_SILENCE_SECS: f64 = 0.1;
+
+/// Containers that may carry a video stream next to the audio; hardware
+/// decoding is only tried for these
+const MUXED_EXTENSIONS: &[&str] = &["webm", "mkv"];
+
+/// Part of the input to keep, in seconds from its start
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Clip {
+    pub start: f64,
+    pub end: f64,
+}
+
+impl Clip {
+    pub fn length(&self) -> f64 {
+        self.end - self.start
+    }
+
+    /// Fail unless the clip lies within an input `duration` seconds long
+    pub fn check_within(&self, duration: f64) -> Result<(), DecodeError> {
+        if self.end > duration {
+            return Err(DecodeError::InvalidClip {
+                range: self.to_string(),
+                reason: format!("the audio is only {} long", format_time(duration)),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// `START-END`, each as seconds, `M:SS` or `H:MM:SS`, with optional
+/// fractions (`1:23-1:53`, `83.5-113`, `1:02:03-1:04:00`)
+impl FromStr for Clip {
+    type Err = DecodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| DecodeError::InvalidClip {
+            range: s.to_string(),
+            reason: reason.to_string(),
+        };
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| invalid("expected START-END, e.g. 1:23-1:53"))?;
+        let start = parse_time(start)
+            .ok_or_else(|| invalid("the start is not a time like 1:23 or 83.5"))?;
+        let end =
+            parse_time(end).ok_or_else(|| invalid("the end is not a time like 1:53 or 113"))?;
+        if start >= end {
+            return Err(invalid("the start must come before the end"));
+        }
+        Ok(Clip { start, end })
+    }
+}
+
+impl std::fmt::Display for Clip {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", format_time(self.start), format_time(self.end))
+    }
+}
+
+/// Seconds from `SS`, `M:SS` or `H:MM:SS`; only the seconds may have a
+/// fraction, and every field after the first must be under 60
+fn parse_time(time: &str) -> Option<f64> {
+    let fields: Vec<&str> = time.trim().split(':').collect();
+    if fields.len() > 3 {
+        return None;
+    }
+    let last = fields.len() - 1;
+    fields.iter().enumerate().try_fold(0.0, |total, (i, field)| {
+        // Digits only: no signs, exponents or `inf`
+        let allowed = |c: char| c.is_ascii_digit() || (i == last && c == '.');
+        if field.is_empty() || !field.chars().all(allowed) {
+            return None;
+        }
+        let value: f64 = field.parse().ok()?;
+        (i == 0 || value < 60.0).then_some(total * 60.0 + value)
+    })
+}
+
+/// `M:SS` or `H:MM:SS`, with up to three decimals when not whole
+fn format_time(secs: f64) -> String {
+    let millis = (secs * 1000.0).round() as u64;
+    let fraction = match millis % 1000 {
+        0 => String::new(),
+        ms => format!(".{:03}", ms).trim_end_matches('0').to_string(),
+    };
+    let whole = millis / 1000;
+    let (hours, minutes, seconds) = (whole / 3600, whole / 60 % 60, whole % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}{}", hours, minutes, seconds, fraction)
+    } else {
+        format!("{}:{:02}{}", minutes, seconds, fraction)
+    }
+}
 
 #[derive(Debug)]
 pub struct Decoder {
     ffmpeg_path: PathBuf,
+    /// FFmpeg `-af` chain applied while decoding
+    filters: Option<String>,
+    /// User filter chain appended to `filters`
+    extra_filters: Option<String>,
+    bit_depth: BitDepth,
+    channels: Channels,
+    /// FFmpeg `-hwaccel` method for muxed inputs
+    hwaccel: Option<String>,
+    /// Audio codec of the input, when the downloader or ffprobe reported it
+    source_codec: Option<String>,
+    /// Highest rate a kept source rate may have
+    max_sample_rate: Option<u32>,
+    /// Only this part of the input is decoded
+    clip: Option<Clip>,
 }
 
 impl Decoder {
     pub fn new(ffmpeg_path: PathBuf) -> Self {
-        Self { ffmpeg_path }
+        Self {
+            ffmpeg_path,
+            filters: None,
+            extra_filters: None,
+            bit_depth: BitDepth::default(),
+            channels: Channels::default(),
+            hwaccel: None,
+            source_codec: None,
+            max_sample_rate: None,
+            clip: None,
+        }
+    }
+
+    /// Decode only `clip` of the input, seeking to its start, so later
+    /// stages never see the rest
+    pub fn with_clip(mut self, clip: Option<Clip>) -> Self {
+        self.clip = clip;
+        self
+    }
+
+    /// When the source rate is kept, resample sources above `rate` down to it
+    pub fn with_max_sample_rate(mut self, rate: Option<u32>) -> Self {
+        self.max_sample_rate = rate;
+        self
+    }
+
+    /// Codec of the input audio (`VideoMetadata::acodec`). A WAV already in
+    /// the target PCM format with nothing to resample, filter or remix is
+    /// copied instead of run through FFmpeg.
+    pub fn with_source_codec(mut self, codec: Option<String>) -> Self {
+        self.source_codec = codec;
+        self
+    }
+
+    /// Let FFmpeg use a hardware decoder (`-hwaccel`, e.g. "auto") for
+    /// webm/mkv inputs. Audio itself is always decoded in software, so this
+    /// only matters for files muxed with video; if FFmpeg fails with it, the
+    /// decode is retried without.
+    pub fn with_hwaccel(mut self, hwaccel: Option<String>) -> Self {
+        self.hwaccel = hwaccel;
+        self
+    }
+
+    /// Mix the decoded WAV to this layout, so every later stage (and the
+    /// upscalers) works on the channels that end up in the output
+    pub fn with_channels(mut self, channels: Channels) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    /// Sample format of the decoded WAV (24-bit by default)
+    pub fn with_bit_depth(mut self, bit_depth: BitDepth) -> Self {
+        self.bit_depth = bit_depth;
+        self
     }
 
-    /// Decode audio to 48kHz 24-bit PCM WAV for processing
-    pub async fn decode_to_wav(&self, input: &Path, output: &Path) -> Result<(), DecodeError> {
+    /// Apply a high-pass and/or low-pass filter at the given cutoffs (Hz)
+    /// while decoding, before any other processing. `None` or 0 leaves that
+    /// side unfiltered.
+    pub fn with_filters(mut self, highpass: Option<u32>, lowpass: Option<u32>) -> Self {
+        let filters: Vec<String> = [("highpass", highpass), ("lowpass", lowpass)]
+            .into_iter()
+            .filter_map(|(name, cutoff)| {
+                cutoff
+                    .filter(|&f| f > 0)
+                    .map(|f| format!("{}=f={}", name, f))
+            })
+            .collect();
+        self.filters = (!filters.is_empty()).then(|| filters.join(","));
+        self
+    }
+
+    /// Append a filter chain of the user's own (FFmpeg `-af` syntax, e.g.
+    /// `aresample=resampler=soxr`) after the high-/low-pass filters. It is
+    /// passed through unchecked; see [`Decoder::check_filters`].
+    pub fn with_extra_filters(mut self, chain: Option<String>) -> Self {
+        self.extra_filters = chain.filter(|c| !c.trim().is_empty());
+        self
+    }
+
+    /// The full `-af` chain: the cutoff filters, then the user's
+    fn filter_chain(&self) -> Option<String> {
+        match (&self.filters, &self.extra_filters) {
+            (Some(filters), Some(extra)) => Some(format!("{},{}", filters, extra)),
+            (filters, extra) => filters.clone().or_else(|| extra.clone()),
+        }
+    }
+
+    /// Run the extra filter chain over a tenth of a second of generated
+    /// silence, so a typo fails up front with FFmpeg's complaint instead of
+    /// after a download
+    pub async fn check_filters(&self) -> Result<(), DecodeError> {
+        let Some(ref chain) = self.extra_filters else {
+            return Ok(());
+        };
+        let mut cmd = Command::new(&self.ffmpeg_path);
+        cmd.kill_on_drop(true).args([
+            "-hide_banner",
+            "-loglevel", "error",
+            "-f", "lavfi",
+            "-i", "anullsrc=r=48000:cl=stereo",
+            "-t", "0.1",
+            "-af", chain,
+            "-f", "null",
+            "-",
+        ]);
+        let output = ffmpeg_output(&mut cmd).await?;
+        if !output.status.success() {
+            return Err(DecodeError::InvalidFilters {
+                chain: chain.clone(),
+                stderr: failure_stderr(&output),
+            });
+        }
+        Ok(())
+    }
+
+    /// Build the FFmpeg invocation used by [`Decoder::decode_to_wav`];
+    /// `-ar` is only passed when `sample_rate` is set
+    pub fn command(&self, input: &Path, output: &Path, sample_rate: Option<u32>) -> Command {
+        self.command_with(input, output, sample_rate, self.hwaccel_for(input))
+    }
+
+    fn command_with(
+        &self,
+        input: &Path,
+        output: &Path,
+        sample_rate: Option<u32>,
+        hwaccel: Option<&str>,
+    ) -> Command {
+        let mut cmd = Command::new(&self.ffmpeg_path);
+        cmd.kill_on_drop(true);
+        cmd.args(["-hide_banner", "-loglevel", ffmpeg_loglevel()]);
+        if let Some(method) = hwaccel {
+            cmd.args(["-hwaccel", method]);
+        }
+        if let Some(clip) = self.clip {
+            // As input options, so FFmpeg seeks rather than decoding up to the start
+            cmd.args(["-ss", &format!("{:.3}", clip.start), "-to", &format!("{:.3}", clip.end)]);
+        }
+        cmd.args([
+            "-i", input.to_str().unwrap(),
+            // Only the audio is needed; never decode a muxed video stream
+            "-vn",
+            "-c:a", self.bit_depth.pcm_codec(),
+        ]);
+        if let Some(filters) = self.filter_chain() {
+            cmd.args(["-af", &filters]);
+        }
+        if let Some(rate) = sample_rate {
+            cmd.args(["-ar", &rate.to_string()]);
+        }
+        if let Some(count) = self.channels.count() {
+            cmd.args(["-ac", &count.to_string()]);
+        }
+        // Overwrite output
+        cmd.args(["-y", output.to_str().unwrap()]);
+        cmd
+    }
+
+    /// Decode audio to PCM WAV at the configured bit depth, resampling to
+    /// `sample_rate` if given and the source differs. With `None` the source
+    /// rate is kept. Returns the sample rate of the written WAV.
+    pub async fn decode_to_wav(
+        &self,
+        input: &Path,
+        output: &Path,
+        sample_rate: Option<u32>,
+    ) -> Result<u32, DecodeError> {
         info!("Decoding {} to WAV", input.display());
+        if let Some(clip) = self.clip {
+            info!("Keeping {}", clip);
+        }
+        if let Some(filters) = self.filter_chain() {
+            info!("Filtering: {}", filters);
+        }
+
+        let source_rate = probe_sample_rate(&self.ffmpeg_path, input).await;
+        let resample = resample_rate(sample_rate, source_rate, self.max_sample_rate);
+        let rate = resample.or(source_rate).unwrap_or(DEFAULT_SAMPLE_RATE);
+        match resample {
+            Some(target) => debug!("Resampling {:?} Hz -> {} Hz", source_rate, target),
+            None => debug!("Keeping source rate of {} Hz", rate),
+        }
+
+        if resample.is_none() && self.is_passthrough(input) {
+            debug!(
+                "Source is already {} WAV, copying instead of decoding",
+                self.bit_depth.pcm_codec()
+            );
+            tokio::fs::copy(input, output).await?;
+            return Ok(rate);
+        }
 
-        let status = Command::new(&self.ffmpeg_path)
-            .args([
-                "-hide_banner",
-                "-loglevel", "error",
-                "-i", input.to_str().unwrap(),
-                // Output format: 48kHz 24-bit PCM
-                "-c:a", "pcm_s24le",
-                "-ar", "48000",
-                // Overwrite output
-                "-y",
-                output.to_str().unwrap(),
-            ])
-            .status()
-            .await?;
-
-        if !status.success() {
-            return Err(DecodeError::FfmpegFailed(status.code()));
+        let hwaccel = self.hwaccel_for(input);
+        let mut result =
+            ffmpeg_run(&mut self.command_with(input, output, resample, hwaccel)).await?;
+        if !result.status.success() && hwaccel.is_some() {
+            debug!("Decoding with -hwaccel failed, retrying in software");
+            result = ffmpeg_run(&mut self.command_with(input, output, resample, None)).await?;
+        }
+
+        if !result.status.success() {
+            return Err(DecodeError::failed(&result));
         }
 
         debug!("Decoded to: {}", output.display());
+        Ok(rate)
+    }
+
+    /// Whether decoding `input` would only rewrite the same samples: a WAV in
+    /// the target PCM codec, with no filters or channel change (resampling is
+    /// checked by the caller)
+    fn is_passthrough(&self, input: &Path) -> bool {
+        let is_wav = input
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("wav"));
+        is_wav
+            && self.source_codec.as_deref() == Some(self.bit_depth.pcm_codec())
+            && self.filter_chain().is_none()
+            && self.clip.is_none()
+            && self.channels == Channels::Source
+    }
+
+    /// The `-hwaccel` method to use for `input`: only set for muxed containers
+    fn hwaccel_for(&self, input: &Path) -> Option<&str> {
+        let muxed = input
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| MUXED_EXTENSIONS.iter().any(|m| ext.eq_ignore_ascii_case(m)));
+        self.hwaccel.as_deref().filter(|_| muxed)
+    }
+
+    /// Build the FFmpeg invocation for [`Decoder::high_band_level`]
+    pub fn high_band_command(&self, input: &Path) -> Command {
+        // Four cascaded 2-pole high-passes (48 dB/octave) keep the band just
+        // below the cutoff, where lossy encoders stop, out of the measurement
+        let highpass = format!("highpass=f={}", HIGH_BAND_CUTOFF_HZ);
+        let filter = format!("{0},{0},{0},{0},volumedetect", highpass);
+
+        let mut cmd = Command::new(&self.ffmpeg_path);
+        cmd.kill_on_drop(true);
+        cmd.args([
+            "-hide_banner",
+            "-nostats",
+            // volumedetect reports at info level
+            "-loglevel", "info",
+            "-i", input.to_str().unwrap(),
+            "-af", &filter,
+            "-f", "null",
+            "-",
+        ]);
+        cmd
+    }
+
+    /// Mean level in dBFS of the content above [`HIGH_BAND_CUTOFF_HZ`], to
+    /// tell full-band sources from band-limited ones. `None` when FFmpeg
+    /// reports no measurement.
+    pub async fn high_band_level(&self, input: &Path) -> Result<Option<f32>, DecodeError> {
+        let output = ffmpeg_output(&mut self.high_band_command(input)).await?;
+        if !output.status.success() {
+            return Err(DecodeError::failed(&output));
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        trace!("High band measurement stderr: {}", stderr);
+        Ok(parse_mean_volume(&stderr))
+    }
+
+    /// Build the FFmpeg invocation for [`Decoder::audible_range`]
+    pub fn silence_command(&self, input: &Path) -> Command {
+        let filter = format!(
+            "silencedetect=noise={}dB:d={}",
+            SILENCE_THRESHOLD_DB, MIN_SILENCE_SECS
+        );
+
+        let mut cmd = Command::new(&self.ffmpeg_path);
+        cmd.kill_on_drop(true);
+        cmd.args([
+            "-hide_banner",
+            "-nostats",
+            // silencedetect reports at info level
+            "-loglevel", "info",
+            "-i", input.to_str().unwrap(),
+            "-af", &filter,
+            "-f", "null",
+            "-",
+        ]);
+        cmd
+    }
+
+    /// Start and end in seconds of `input` (`duration` long) without its
+    /// leading and trailing silence. Pauses inside the audio are kept, and
+    /// an entirely silent input is returned whole.
+    pub async fn audible_range(
+        &self,
+        input: &Path,
+        duration: f64,
+    ) -> Result<(f64, f64), DecodeError> {
+        let output = ffmpeg_output(&mut self.silence_command(input)).await?;
+        if !output.status.success() {
+            return Err(DecodeError::failed(&output));
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        trace!("Silence detection stderr: {}", stderr);
+        Ok(parse_audible_range(&stderr, duration))
+    }
+
+    /// Build the FFmpeg invocation used by [`Decoder::trim`]
+    pub fn trim_command(
+        &self,
+        input: &Path,
+        output: &Path,
+        (start, end): (f64, f64),
+        fade_in: f64,
+        fade_out: f64,
+    ) -> Command {
+        let mut filters = vec![
+            format!("atrim=start={:.3}:end={:.3}", start, end),
+            "asetpts=PTS-STARTPTS".to_string(),
+        ];
+        if fade_in > 0.0 {
+            filters.push(format!("afade=t=in:st=0:d={:.3}", fade_in));
+        }
+        if fade_out > 0.0 {
+            let length = end - start;
+            let fade_out = fade_out.min(length);
+            filters.push(format!("afade=t=out:st={:.3}:d={:.3}", length - fade_out, fade_out));
+        }
+
+        let mut cmd = Command::new(&self.ffmpeg_path);
+        cmd.kill_on_drop(true);
+        cmd.args([
+            "-hide_banner",
+            "-loglevel", ffmpeg_loglevel(),
+            "-i", input.to_str().unwrap(),
+            "-af", &filters.join(","),
+            "-c:a", self.bit_depth.pcm_codec(),
+            "-y",
+            output.to_str().unwrap(),
+        ]);
+        cmd
+    }
+
+    /// Keep the `start..end` range (in seconds) of a WAV, fading in over the
+    /// first `fade_in` and out over the last `fade_out` seconds (0 disables)
+    pub async fn trim(
+        &self,
+        input: &Path,
+        output: &Path,
+        range: (f64, f64),
+        fade_in: f64,
+        fade_out: f64,
+    ) -> Result<(), DecodeError> {
+        debug!("Trimming {} to {:.3}s-{:.3}s", input.display(), range.0, range.1);
+
+        let result =
+            ffmpeg_run(&mut self.trim_command(input, output, range, fade_in, fade_out)).await?;
+        if !result.status.success() {
+            return Err(DecodeError::failed(&result));
+        }
+
+        Ok(())
+    }
+
+    /// Cut the `start..end` range (in seconds) of a WAV into a new WAV at the
+    /// configured bit depth
+    pub async fn cut_segment(
+        &self,
+        input: &Path,
+        output: &Path,
+        start: f64,
+        end: f64,
+    ) -> Result<(), DecodeError> {
+        debug!("Cutting {:.3}s-{:.3}s from {}", start, end, input.display());
+
+        let mut cmd = Command::new(&self.ffmpeg_path);
+        cmd.kill_on_drop(true).args([
+            "-hide_banner",
+            "-loglevel", ffmpeg_loglevel(),
+            "-i", input.to_str().unwrap(),
+            // Seeking after -i is sample-accurate
+            "-ss", &format!("{:.3}", start),
+            "-to", &format!("{:.3}", end),
+            "-c:a", self.bit_depth.pcm_codec(),
+            "-y",
+            output.to_str().unwrap(),
+        ]);
+        let result = ffmpeg_run(&mut cmd).await?;
+
+        if !result.status.success() {
+            return Err(DecodeError::failed(&result));
+        }
+
         Ok(())
     }
 
     /// Get audio file info (sample rate, channels, duration)
     pub async fn get_audio_info(&self, input: &Path) -> Result<AudioInfo, DecodeError> {
-        let output = Command::new(&self.ffmpeg_path)
-            .args([
-                "-hide_banner",
-                "-i", input.to_str().unwrap(),
-                "-f", "null",
-                "-"
-            ])
-            .output()
-            .await?;
+        let mut cmd = Command::new(&self.ffmpeg_path);
+        cmd.kill_on_drop(true).args([
+            "-hide_banner",
+            "-i", input.to_str().unwrap(),
+            "-f", "null",
+            "-"
+        ]);
+        let output = ffmpeg_output(&mut cmd).await?;
 
         // FFmpeg outputs info to stderr
         let stderr = String::from_utf8_lossy(&output.stderr);
 
-        let sample_rate = parse_sample_rate(&stderr).unwrap_or(48000);
+        let sample_rate = parse_sample_rate(&stderr).unwrap_or(DEFAULT_SAMPLE_RATE);
         let channels = parse_channels(&stderr).unwrap_or(2);
         let duration = parse_duration(&stderr).unwrap_or(0.0);
 
@@ -76,6 +565,18 @@ pub struct AudioInfo {
     pub duration: f64,
 }
 
+/// Rate to resample to for a `requested` rate (`None` keeps the source rate,
+/// up to `max`), or `None` to leave the audio as it is
+fn resample_rate(requested: Option<u32>, source: Option<u32>, max: Option<u32>) -> Option<u32> {
+    match (requested, source) {
+        (Some(target), Some(source)) if target == source => None,
+        (Some(target), _) => Some(target),
+        (None, Some(source)) => max.filter(|&max| source > max),
+        // Unknown source rate: settle on a known one for the later stages
+        (None, None) => Some(DEFAULT_SAMPLE_RATE),
+    }
+}
+
 fn parse_sample_rate(ffmpeg_output: &str) -> Option<u32> {
     // Look for pattern like "48000 Hz" or "44100 Hz"
     let re = regex::Regex::new(r"(\d+) Hz").ok()?;
@@ -83,6 +584,44 @@ fn parse_sample_rate(ffmpeg_output: &str) -> Option<u32> {
     caps.get(1)?.as_str().parse().ok()
 }
 
+/// `mean_volume` from FFmpeg's volumedetect summary
+fn parse_mean_volume(ffmpeg_output: &str) -> Option<f32> {
+    let line = ffmpeg_output.lines().rfind(|l| l.contains("mean_volume:"))?;
+    let value = line.split("mean_volume:").nth(1)?.trim().trim_end_matches("dB").trim();
+    value.parse().ok()
+}
+
+/// Audible range from silencedetect's `silence_start`/`silence_end` lines:
+/// a silence starting at 0 is leading, one still open at the end (or ending
+/// there) is trailing
+fn parse_audible_range(ffmpeg_output: &str, duration: f64) -> (f64, f64) {
+    // Slack for the rounding in silencedetect's timestamps
+    const EPSILON: f64 = 0.01;
+
+    let mut silences: Vec<(f64, Option<f64>)> = Vec::new();
+    for line in ffmpeg_output.lines() {
+        let value = |key: &str| -> Option<f64> {
+            line.split(key).nth(1)?.split_whitespace().next()?.parse().ok()
+        };
+        if let Some(start) = value("silence_start:") {
+            silences.push((start, None));
+        } else if let (Some(end), Some(last)) = (value("silence_end:"), silences.last_mut()) {
+            last.1 = Some(end);
+        }
+    }
+
+    let start = match silences.first() {
+        Some(&(start, end)) if start <= EPSILON => end.unwrap_or(duration),
+        _ => 0.0,
+    };
+    let end = match silences.last() {
+        Some(&(start, end)) if end.is_none_or(|end| end >= duration - EPSILON) => start.max(0.0),
+        _ => duration,
+    };
+
+    if start < end { (start, end) } else { (0.0, duration) }
+}
+
 fn parse_channels(ffmpeg_output: &str) -> Option<u8> {
     if ffmpeg_output.contains("stereo") {
         Some(2)
@@ -107,3 +646,244 @@ fn parse_duration(ffmpeg_output: &str) -> Option<f64> {
 
     Some(hours * 3600.0 + minutes * 60.0 + seconds + centiseconds / 100.0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::args;
+
+    #[test]
+    fn test_command_resamples_only_when_asked() {
+        let decoder = Decoder::new(PathBuf::from("ffmpeg"));
+        let args = |rate| {
+            args(&decoder.command(Path::new("in.opus"), Path::new("out.wav"), rate))
+        };
+
+        assert!(!args(None).contains(&"-ar".to_string()));
+        assert!(args(Some(44100)).windows(2).any(|w| w == ["-ar", "44100"]));
+    }
+
+    #[test]
+    fn test_resample_rate() {
+        assert_eq!(resample_rate(Some(48000), Some(48000), None), None);
+        assert_eq!(resample_rate(Some(48000), Some(44100), None), Some(48000));
+        assert_eq!(resample_rate(None, Some(192000), None), None);
+        assert_eq!(resample_rate(None, Some(192000), Some(96000)), Some(96000));
+        assert_eq!(resample_rate(None, Some(88200), Some(96000)), None);
+        assert_eq!(resample_rate(None, None, Some(96000)), Some(DEFAULT_SAMPLE_RATE));
+    }
+
+    #[test]
+    fn test_is_passthrough() {
+        let decoder = || {
+            Decoder::new(PathBuf::from("ffmpeg")).with_source_codec(Some("pcm_s24le".to_string()))
+        };
+        let wav = Path::new("in.wav");
+
+        assert!(decoder().is_passthrough(wav));
+        assert!(!decoder().is_passthrough(Path::new("in.w64")));
+        assert!(!Decoder::new(PathBuf::from("ffmpeg")).is_passthrough(wav));
+        assert!(!decoder().with_bit_depth(BitDepth::Int16).is_passthrough(wav));
+        assert!(!decoder().with_filters(Some(30), None).is_passthrough(wav));
+        assert!(!decoder().with_channels(Channels::Mono).is_passthrough(wav));
+    }
+
+    #[test]
+    fn test_command_hwaccel_only_for_muxed_inputs() {
+        let decoder = Decoder::new(PathBuf::from("ffmpeg")).with_hwaccel(Some("auto".to_string()));
+        let args = |input| {
+            args(&decoder.command(Path::new(input), Path::new("out.wav"), None))
+        };
+
+        let muxed = args("video.MKV");
+        assert!(muxed.windows(2).any(|w| w == ["-hwaccel", "auto"]));
+        assert!(muxed.iter().position(|a| a == "-hwaccel") < muxed.iter().position(|a| a == "-i"));
+        assert!(!args("audio.opus").contains(&"-hwaccel".to_string()));
+        assert!(args("audio.opus").contains(&"-vn".to_string()));
+    }
+
+    #[test]
+    fn test_parse_audible_range() {
+        let stderr = "[silencedetect @ 0x1] silence_start: 0\n\
+                      [silencedetect @ 0x1] silence_end: 1.25 | silence_duration: 1.25\n\
+                      [silencedetect @ 0x1] silence_start: 60.5\n\
+                      [silencedetect @ 0x1] silence_end: 61 | silence_duration: 0.5\n\
+                      [silencedetect @ 0x1] silence_start: 178.2\n";
+        assert_eq!(parse_audible_range(stderr, 180.0), (1.25, 178.2));
+
+        // A final silence_end at EOF still counts as trailing
+        let closed = "silence_start: 178.2\nsilence_end: 180 | silence_duration: 1.8\n";
+        assert_eq!(parse_audible_range(closed, 180.0), (0.0, 178.2));
+
+        assert_eq!(parse_audible_range("", 180.0), (0.0, 180.0));
+        assert_eq!(parse_audible_range("silence_start: 0\n", 180.0), (0.0, 180.0));
+    }
+
+    #[test]
+    fn test_trim_command_fades() {
+        let decoder = Decoder::new(PathBuf::from("ffmpeg"));
+        let args = args(&decoder.trim_command(
+            Path::new("in.wav"),
+            Path::new("out.wav"),
+            (1.5, 61.5),
+            2.0,
+            3.0,
+        ));
+        let filter = "atrim=start=1.500:end=61.500,asetpts=PTS-STARTPTS,\
+                      afade=t=in:st=0:d=2.000,afade=t=out:st=57.000:d=3.000";
+        assert!(args.windows(2).any(|w| w == ["-af", filter]), "{:?}", args);
+    }
+
+    #[test]
+    fn test_parse_mean_volume() {
+        let stderr = "[Parsed_volumedetect_4 @ 0x1] n_samples: 1234\n\
+                      [Parsed_volumedetect_4 @ 0x1] mean_volume: -71.3 dB\n\
+                      [Parsed_volumedetect_4 @ 0x1] max_volume: -40.2 dB\n";
+        assert_eq!(parse_mean_volume(stderr), Some(-71.3));
+        assert_eq!(parse_mean_volume("no summary"), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_decode_to_wav_runs_ffmpeg() {
+        use crate::test_support::{arg_after, StubFfmpeg};
+
+        let stub = StubFfmpeg::new();
+        let input = stub.dir().join("song.opus");
+        let output = stub.dir().join("decoded.wav");
+        let rate = Decoder::new(stub.path())
+            .decode_to_wav(&input, &output, Some(44100))
+            .await
+            .unwrap();
+        assert_eq!(rate, 44100);
+
+        let calls = stub.calls();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(arg_after(&calls[0], "-i"), input.to_str());
+        assert_eq!(arg_after(&calls[0], "-c:a"), Some("pcm_s24le"));
+        assert_eq!(arg_after(&calls[0], "-ar"), Some("44100"));
+        assert_eq!(calls[0].last().map(String::as_str), output.to_str());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_cut_segment_keeps_bit_depth() {
+        use crate::test_support::{arg_after, StubFfmpeg};
+
+        let stub = StubFfmpeg::new();
+        let input = stub.dir().join("processed.wav");
+        let output = stub.dir().join("segment_01.wav");
+        let decoder = Decoder::new(stub.path()).with_bit_depth(BitDepth::Float32);
+        decoder.cut_segment(&input, &output, 1.0, 2.5).await.unwrap();
+
+        let calls = stub.calls();
+        assert_eq!(arg_after(&calls[0], "-ss"), Some("1.000"));
+        assert_eq!(arg_after(&calls[0], "-to"), Some("2.500"));
+        assert_eq!(arg_after(&calls[0], "-c:a"), Some(BitDepth::Float32.pcm_codec()));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_decode_to_wav_retries_without_hwaccel() {
+        use crate::test_support::StubFfmpeg;
+
+        let stub = StubFfmpeg::new()
+            .with_stderr("Invalid data found when processing input\n")
+            .with_exit_code(1);
+        let err = Decoder::new(stub.path())
+            .with_hwaccel(Some("cuda".to_string()))
+            .decode_to_wav(&stub.dir().join("video.webm"), &stub.dir().join("decoded.wav"), None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Invalid data found"), "{}", err);
+
+        let calls = stub.calls();
+        assert_eq!(calls.len(), 2);
+        assert!(calls[0].contains(&"-hwaccel".to_string()));
+        assert!(!calls[1].contains(&"-hwaccel".to_string()));
+    }
+
+    #[test]
+    fn test_parse_clip() {
+        let clip = |s: &str| s.parse::<Clip>().map(|c| (c.start, c.end));
+        assert_eq!(clip("1:23-1:53").unwrap(), (83.0, 113.0));
+        assert_eq!(clip("83.5-113").unwrap(), (83.5, 113.0));
+        assert_eq!(clip(" 1:02:03 - 1:04:00.25 ").unwrap(), (3723.0, 3840.25));
+        assert_eq!(clip("90:00-95:00").unwrap(), (5400.0, 5700.0));
+        for bad in [
+            "1:53-1:23",
+            "10-10",
+            "1:23",
+            "1:60-2:00",
+            "1:61:00-2:00:00",
+            "-5-10",
+            "1e2-200",
+            "a-b",
+            "1:2:3:4-5",
+        ] {
+            assert!(
+                matches!(bad.parse::<Clip>(), Err(DecodeError::InvalidClip { .. })),
+                "{}",
+                bad
+            );
+        }
+
+        let clip: Clip = "1:23-1:53.5".parse().unwrap();
+        assert_eq!(clip.to_string(), "1:23-1:53.5");
+        assert_eq!(Clip { start: 59.9996, end: 3723.0 }.to_string(), "1:00-1:02:03");
+        assert!(clip.check_within(120.0).is_ok());
+        let err = clip.check_within(100.0).unwrap_err().to_string();
+        assert!(err.contains("only 1:40 long"), "{}", err);
+    }
+
+    #[test]
+    fn test_command_clip() {
+        let clip = Some(Clip { start: 83.0, end: 113.0 });
+        let decoder = Decoder::new(PathBuf::from("ffmpeg")).with_clip(clip);
+        let args = args(&decoder.command(Path::new("in.opus"), Path::new("out.wav"), None));
+        let input = args.iter().position(|a| a == "-i").unwrap();
+        assert_eq!(args[input - 4..input], ["-ss", "83.000", "-to", "113.000"]);
+    }
+
+    #[test]
+    fn test_command_filters() {
+        let args = |decoder: Decoder| {
+            args(&decoder.command(Path::new("in.opus"), Path::new("out.wav"), None))
+        };
+        let decoder = || Decoder::new(PathBuf::from("ffmpeg"));
+
+        assert!(!args(decoder().with_filters(None, Some(0))).contains(&"-af".to_string()));
+        assert!(args(decoder().with_filters(Some(30), None))
+            .windows(2)
+            .any(|w| w == ["-af", "highpass=f=30"]));
+        assert!(args(decoder().with_filters(Some(30), Some(16000)))
+            .windows(2)
+            .any(|w| w == ["-af", "highpass=f=30,lowpass=f=16000"]));
+        let extra = || Some("aresample=resampler=soxr".to_string());
+        assert!(args(decoder().with_extra_filters(extra()))
+            .windows(2)
+            .any(|w| w == ["-af", "aresample=resampler=soxr"]));
+        assert!(args(decoder().with_filters(Some(30), None).with_extra_filters(extra()))
+            .windows(2)
+            .any(|w| w == ["-af", "highpass=f=30,aresample=resampler=soxr"]));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_check_filters_reports_ffmpeg_error() {
+        use crate::test_support::{arg_after, StubFfmpeg};
+
+        let stub = StubFfmpeg::new().with_stderr("No such filter: 'eqaulizer'\n").with_exit_code(1);
+        Decoder::new(stub.path()).check_filters().await.unwrap();
+        assert!(stub.calls().is_empty());
+
+        let err = Decoder::new(stub.path())
+            .with_extra_filters(Some("eqaulizer=f=100".to_string()))
+            .check_filters()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DecodeError::InvalidFilters { .. }), "{:?}", err);
+        assert!(err.to_string().contains("No such filter"), "{}", err);
+        assert_eq!(arg_after(&stub.calls()[0], "-af"), Some("eqaulizer=f=100"));
+    }
+}
@@ -1,25 +1,58 @@
 //! YouTube audio downloader using yt-dlp
 
 use crate::error::DownloadError;
+use crate::process::log_command;
 use serde::Deserialize;
+use std::future::Future;
 use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::Command;
-use tracing::{debug, info};
+use tracing::{debug, info, trace, warn};
 
 #[derive(Debug)]
 pub struct Downloader {
     yt_dlp_path: PathBuf,
     temp_dir: PathBuf,
+    sponsorblock: Option<Vec<String>>,
+    proxy: Option<String>,
+    rate_limit: Option<String>,
+    extractor_args: Option<String>,
+    max_retries: u32,
+    archive_file: Option<PathBuf>,
+    lyrics_lang: Option<String>,
+    format_selector: Option<String>,
+    timeout: Option<Duration>,
+    thumbnail: bool,
 }
 
+/// yt-dlp `-f` selector used unless overridden: best audio, preferring Opus,
+/// then AAC, so the stream can be copied without re-encoding
+pub const DEFAULT_FORMAT_SELECTOR: &str = "bestaudio[acodec=opus]/bestaudio[acodec=aac]/bestaudio";
+
+/// Wait before the first retry; doubles with each further attempt
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(2);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Timeouts retried at most this often per download, whatever the retry
+/// limit: each one has already taken the whole `download.timeout_secs`
+const MAX_TIMEOUT_RETRIES: u32 = 1;
+
+/// `--extractor-args` retried once when YouTube answers the default client
+/// with its bot check: the Android client is often let through
+pub const BOT_CHECK_EXTRACTOR_ARGS: &str = "youtube:player_client=android";
+
 #[derive(Debug)]
 pub struct DownloadResult {
     pub audio_path: PathBuf,
     pub metadata: VideoMetadata,
     pub thumbnail_path: Option<PathBuf>,
+    /// Subtitle file (.vtt or .srt) fetched for lyrics, when one existed
+    pub subtitles_path: Option<PathBuf>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct VideoMetadata {
     pub id: String,
     pub title: String,
@@ -29,93 +62,469 @@ pub struct VideoMetadata {
     pub album: Option<String>,
     #[serde(default)]
     pub uploader: Option<String>,
+    /// Page of the uploading channel
+    #[serde(default)]
+    pub channel_url: Option<String>,
+    /// Canonical URL of the video page
+    #[serde(default)]
+    pub webpage_url: Option<String>,
     #[serde(default)]
     pub upload_date: Option<String>,
     #[serde(default)]
     pub duration: Option<f64>,
     #[serde(default)]
     pub description: Option<String>,
+    /// Extension of the downloaded file
     #[serde(default)]
     pub ext: String,
+    /// Codec of the downloaded audio as yt-dlp or ffprobe names it
+    /// (`opus`, `mp4a.40.2`, `pcm_s24le`)
+    #[serde(default)]
+    pub acodec: Option<String>,
+    /// Average bitrate of the downloaded audio in kbps
+    #[serde(default)]
+    pub abr: Option<f64>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub chapters: Vec<Chapter>,
+    /// Streams yt-dlp can fetch for this video
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub formats: Vec<FormatInfo>,
+    /// Position in the playlist being downloaded (1-based); absent for single videos
+    #[serde(default)]
+    pub playlist_index: Option<usize>,
+    #[serde(default)]
+    pub playlist_count: Option<usize>,
+    #[serde(default)]
+    pub playlist_title: Option<String>,
+    /// Track number and total, set when a video is split into several files
+    #[serde(skip)]
+    pub track: Option<(usize, usize)>,
+    /// Lyrics embedded as the `lyrics` tag, converted from the subtitles
+    #[serde(skip)]
+    pub lyrics: Option<String>,
+}
+
+impl VideoMetadata {
+    /// Canonical video URL: yt-dlp's `webpage_url`, or the watch URL built from the ID
+    pub fn source_url(&self) -> Option<String> {
+        self.webpage_url.clone().or_else(|| {
+            (!self.id.is_empty()).then(|| format!("https://youtube.com/watch?v={}", self.id))
+        })
+    }
+}
+
+/// Chapter marker from the yt-dlp metadata (times in seconds)
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Chapter {
+    pub start_time: f64,
+    pub end_time: f64,
+    #[serde(default)]
+    pub title: String,
+}
+
+/// One downloadable stream from the yt-dlp metadata
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FormatInfo {
+    pub format_id: String,
+    #[serde(default)]
+    pub ext: String,
+    /// Audio codec, `"none"` for video-only streams
+    #[serde(default)]
+    pub acodec: Option<String>,
+    /// Video codec, `"none"` for audio-only streams
+    #[serde(default)]
+    pub vcodec: Option<String>,
+    /// Average audio bitrate in kbps
+    #[serde(default)]
+    pub abr: Option<f64>,
+    /// Audio sample rate in Hz
+    #[serde(default)]
+    pub asr: Option<u32>,
+    #[serde(default)]
+    pub filesize: Option<u64>,
+    /// yt-dlp's estimate from bitrate and duration, when the exact size is unknown
+    #[serde(default)]
+    pub filesize_approx: Option<u64>,
+}
+
+impl FormatInfo {
+    /// True for streams carrying audio and no video
+    pub fn is_audio_only(&self) -> bool {
+        self.acodec.as_deref().is_some_and(|c| c != "none")
+            && self.vcodec.as_deref().is_none_or(|c| c == "none")
+    }
+
+    /// Size in bytes, exact when yt-dlp knows it and estimated otherwise
+    pub fn size(&self) -> Option<u64> {
+        self.filesize.or(self.filesize_approx)
+    }
+}
+
+/// yt-dlp emits `null` rather than omitting fields it has no data for
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
 }
 
 impl Downloader {
     pub fn new(yt_dlp_path: PathBuf, temp_dir: PathBuf) -> Self {
-        Self { yt_dlp_path, temp_dir }
+        Self {
+            yt_dlp_path,
+            temp_dir,
+            sponsorblock: None,
+            proxy: None,
+            rate_limit: None,
+            extractor_args: None,
+            max_retries: 0,
+            archive_file: None,
+            lyrics_lang: None,
+            format_selector: None,
+            timeout: None,
+            thumbnail: true,
+        }
     }
 
-    /// Download audio from YouTube URL
-    pub async fn download(&self, url: &str) -> Result<DownloadResult, DownloadError> {
-        info!("Downloading audio from: {}", url);
+    /// Fetch the video thumbnail as JPEG for cover art (on by default)
+    pub fn with_thumbnail(mut self, thumbnail: bool) -> Self {
+        self.thumbnail = thumbnail;
+        self
+    }
 
+    /// Kill yt-dlp and fail with [`DownloadError::Timeout`] when a single
+    /// call runs longer than this; unlimited when `None`
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout.filter(|t| !t.is_zero());
+        self
+    }
+
+    /// Remove the given SponsorBlock categories from the downloaded audio
+    pub fn with_sponsorblock(mut self, categories: Option<Vec<String>>) -> Self {
+        self.sponsorblock = categories.filter(|c| !c.is_empty());
+        self
+    }
+
+    /// Route every yt-dlp request through a proxy (`http://`, `socks5://`, ...)
+    pub fn with_proxy(mut self, proxy: Option<String>) -> Self {
+        self.proxy = proxy.filter(|p| !p.is_empty());
+        self
+    }
+
+    /// Cap the download rate, in yt-dlp's notation (`1M`, `500K`)
+    pub fn with_rate_limit(mut self, rate_limit: Option<String>) -> Self {
+        self.rate_limit = rate_limit.filter(|r| !r.is_empty());
+        self
+    }
+
+    /// Pass `--extractor-args` to every yt-dlp call, e.g.
+    /// `youtube:player_client=android`. Also turns off the automatic
+    /// [`BOT_CHECK_EXTRACTOR_ARGS`] retry, which would override it.
+    pub fn with_extractor_args(mut self, extractor_args: Option<String>) -> Self {
+        self.extractor_args = extractor_args.filter(|a| !a.trim().is_empty());
+        self
+    }
+
+    /// Record downloaded video IDs in `archive_file` (yt-dlp's
+    /// `--download-archive`) and skip IDs already listed there, reported as
+    /// [`DownloadError::AlreadyArchived`]
+    pub fn with_archive(mut self, archive_file: Option<PathBuf>) -> Self {
+        self.archive_file = archive_file;
+        self
+    }
+
+    /// Retry transient failures (network errors, HTTP 403/429/5xx) up to
+    /// `max_retries` times; 0 disables retrying
+    pub fn with_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Fetch subtitles in `lang` (yt-dlp `--sub-langs` syntax) for lyrics,
+    /// preferring uploaded ones over automatic captions
+    pub fn with_lyrics(mut self, lang: Option<String>) -> Self {
+        self.lyrics_lang = lang.filter(|l| !l.is_empty());
+        self
+    }
+
+    /// Replace [`DEFAULT_FORMAT_SELECTOR`] with a custom yt-dlp `-f` selector
+    /// (a format ID, or an expression like `bestaudio[acodec=opus]`)
+    pub fn with_format_selector(mut self, selector: Option<String>) -> Self {
+        self.format_selector = selector.filter(|s| !s.trim().is_empty());
+        self
+    }
+
+    fn network_args(&self, cmd: &mut Command, extractor_args: Option<&str>) {
+        if let Some(ref proxy) = self.proxy {
+            cmd.args(["--proxy", proxy]);
+        }
+        if let Some(ref rate) = self.rate_limit {
+            cmd.args(["--limit-rate", rate]);
+        }
+        if let Some(args) = extractor_args {
+            cmd.args(["--extractor-args", args]);
+        }
+    }
+
+    /// Build the yt-dlp invocation used by [`Downloader::download`]
+    pub fn command(&self, url: &str) -> Command {
+        self.command_with(url, self.extractor_args.as_deref())
+    }
+
+    fn command_with(&self, url: &str, extractor_args: Option<&str>) -> Command {
         // Create output template
         let output_template = self.temp_dir.join("%(id)s.%(ext)s");
 
-        // Run yt-dlp with JSON output
-        let output = Command::new(&self.yt_dlp_path)
-            .args([
-                // Format selection: best audio, prefer Opus
-                "-f", "bestaudio[acodec=opus]/bestaudio[acodec=aac]/bestaudio",
-                // Extract audio without re-encoding (keep original codec)
-                "--extract-audio",
-                "--audio-format", "best",
-                // Keep original codec to avoid quality loss
-                "--postprocessor-args", "ExtractAudio:-acodec copy",
-                // Get metadata
-                "--write-info-json",
-                // Get thumbnail
-                "--write-thumbnail",
-                "--convert-thumbnails", "jpg",
-                // Output template
-                "-o", output_template.to_str().unwrap(),
-                // Print JSON to stdout for metadata parsing
-                "--print-json",
-                // Don't download if already exists
-                "--no-overwrites",
-                // URL
-                url,
-            ])
-            .output()
-            .await?;
+        let mut cmd = Command::new(&self.yt_dlp_path);
+        cmd.kill_on_drop(true);
+        cmd.args([
+            "-f", self.format_selector.as_deref().unwrap_or(DEFAULT_FORMAT_SELECTOR),
+            // Extract audio without re-encoding (keep original codec)
+            "--extract-audio",
+            "--audio-format", "best",
+            // Keep original codec to avoid quality loss
+            "--postprocessor-args", "ExtractAudio:-acodec copy",
+            // Get metadata
+            "--write-info-json",
+            // Output template
+            "-o", output_template.to_str().unwrap(),
+            // Print JSON to stdout for metadata parsing
+            "--print-json",
+            // Keep reporting progress despite --print-json's quiet mode, one line per update
+            "--progress",
+            "--newline",
+            // Don't download if already exists
+            "--no-overwrites",
+        ]);
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            debug!("yt-dlp stderr: {}", stderr);
+        if self.thumbnail {
+            cmd.args(["--write-thumbnail", "--convert-thumbnails", "jpg"]);
+        }
+
+        if let Some(ref categories) = self.sponsorblock {
+            cmd.args(["--sponsorblock-remove", &categories.join(",")]);
+        }
+
+        if let Some(ref archive) = self.archive_file {
+            cmd.arg("--download-archive").arg(archive);
+        }
+
+        if let Some(ref lang) = self.lyrics_lang {
+            // yt-dlp only falls back to automatic captions when no uploaded
+            // subtitles exist for the language
+            cmd.args(["--write-subs", "--write-auto-subs", "--sub-langs", lang]);
+            cmd.args(["--sub-format", "vtt/srt/best"]);
+        }
 
-            if stderr.contains("Video unavailable") || stderr.contains("Private video") {
-                return Err(DownloadError::VideoUnavailable(url.to_string()));
+        self.network_args(&mut cmd, extractor_args);
+
+        // URL
+        cmd.arg(url);
+        cmd
+    }
+
+    /// Download audio from YouTube URL
+    pub async fn download(&self, url: &str) -> Result<DownloadResult, DownloadError> {
+        self.download_with_progress(url, |_| {}).await
+    }
+
+    /// Download audio, calling `on_progress` with the downloaded fraction (0.0-1.0)
+    /// as yt-dlp reports it. Transient failures are retried with exponential
+    /// backoff, up to the limit set by [`Downloader::with_retries`]. A bot
+    /// check is retried once with [`BOT_CHECK_EXTRACTOR_ARGS`], unless
+    /// extractor args were set explicitly.
+    pub async fn download_with_progress(
+        &self,
+        url: &str,
+        mut on_progress: impl FnMut(f32),
+    ) -> Result<DownloadResult, DownloadError> {
+        let mut attempt = 0;
+        let mut timeouts = 0;
+        let mut extractor_args = self.extractor_args.as_deref();
+        loop {
+            match self.download_once(url, extractor_args, &mut on_progress).await {
+                Err(e) if retry_bot_check(&e, &mut extractor_args) => {}
+                Err(e) if should_retry(&e, attempt, self.max_retries, timeouts) => {
+                    attempt += 1;
+                    if matches!(e, DownloadError::Timeout(_)) {
+                        timeouts += 1;
+                    }
+                    let delay = retry_delay(attempt);
+                    warn!(
+                        "Download failed ({}), retrying in {}s (attempt {}/{})",
+                        e,
+                        delay.as_secs(),
+                        attempt,
+                        self.max_retries
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
             }
-            if stderr.contains("is not a valid URL") {
-                return Err(DownloadError::InvalidUrl(url.to_string()));
+        }
+    }
+
+    async fn download_once(
+        &self,
+        url: &str,
+        extractor_args: Option<&str>,
+        mut on_progress: impl FnMut(f32),
+    ) -> Result<DownloadResult, DownloadError> {
+        info!("Downloading audio from: {}", url);
+
+        let (status, stdout, stderr) = self.with_deadline(async {
+            let mut cmd = self.command_with(url, extractor_args);
+            log_command(&cmd);
+            let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+            let mut stdout_lines =
+                BufReader::new(child.stdout.take().expect("stdout is piped")).lines();
+            let mut stderr_lines =
+                BufReader::new(child.stderr.take().expect("stderr is piped")).lines();
+
+            // Progress lines may arrive on either stream; everything else is kept
+            // for the JSON (stdout) and failure classification (stderr)
+            let (mut stdout, mut stderr) = (String::new(), String::new());
+            let (mut stdout_done, mut stderr_done) = (false, false);
+            while !(stdout_done && stderr_done) {
+                let (line, buffer, done) = tokio::select! {
+                    line = stdout_lines.next_line(), if !stdout_done => {
+                        (line?, &mut stdout, &mut stdout_done)
+                    }
+                    line = stderr_lines.next_line(), if !stderr_done => {
+                        (line?, &mut stderr, &mut stderr_done)
+                    }
+                };
+                match line {
+                    Some(line) => match parse_progress(&line) {
+                        Some(fraction) => on_progress(fraction),
+                        None => {
+                            buffer.push_str(&line);
+                            buffer.push('\n');
+                        }
+                    },
+                    None => *done = true,
+                }
             }
+            Ok((child.wait().await?, stdout, stderr))
+        }).await?;
+
+        trace!("yt-dlp stdout: {}", stdout);
+        trace!("yt-dlp stderr: {}", stderr);
 
-            return Err(DownloadError::YtDlpFailed(output.status.code()));
+        if !status.success() {
+            return Err(classify_failure(url, status.code(), &stderr));
         }
 
-        // Parse JSON output
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let metadata: VideoMetadata = serde_json::from_str(&stdout)
+        // Parse the JSON line printed once the download finishes. yt-dlp
+        // succeeds without printing one when the ID is already archived.
+        let Some(json) = stdout.lines().rfind(|l| l.starts_with('{')) else {
+            if self.archive_file.is_some() {
+                info!("Already in the download archive: {}", url);
+                return Err(DownloadError::AlreadyArchived(url.to_string()));
+            }
+            return Err(DownloadError::MetadataParse("yt-dlp printed no metadata".to_string()));
+        };
+        let mut metadata: VideoMetadata = serde_json::from_str(json)
             .map_err(|e| DownloadError::MetadataParse(e.to_string()))?;
 
+        // Cutting segments shifts the timeline, so the reported chapter
+        // timestamps no longer line up with the audio
+        if self.sponsorblock.is_some() && !metadata.chapters.is_empty() {
+            warn!("SponsorBlock removal is active, dropping chapter markers");
+            metadata.chapters.clear();
+        }
+
         debug!("Downloaded: {} ({})", metadata.title, metadata.id);
 
         // Find the audio file
-        let audio_path = self.find_audio_file(&metadata.id)?;
+        let audio_path = self.find_audio_file(&metadata.id, &metadata.ext)?;
 
         // Find thumbnail if it exists
         let thumbnail_path = self.find_thumbnail(&metadata.id);
 
+        let subtitles_path = match self.lyrics_lang {
+            Some(_) => self.find_subtitles(&metadata.id).await,
+            None => None,
+        };
+
         Ok(DownloadResult {
             audio_path,
             metadata,
             thumbnail_path,
+            subtitles_path,
         })
     }
 
-    fn find_audio_file(&self, video_id: &str) -> Result<PathBuf, DownloadError> {
-        // Look for common audio extensions
-        let extensions = ["opus", "m4a", "webm", "mp3", "ogg", "aac"];
+    /// Run one yt-dlp call under the configured timeout. The child is spawned
+    /// with `kill_on_drop`, so abandoning the call on expiry kills it.
+    async fn with_deadline<T>(
+        &self,
+        call: impl Future<Output = Result<T, DownloadError>>,
+    ) -> Result<T, DownloadError> {
+        match self.timeout {
+            Some(limit) => tokio::time::timeout(limit, call).await.unwrap_or_else(|_| {
+                warn!("yt-dlp still running after {}s, killing it", limit.as_secs());
+                Err(DownloadError::Timeout(limit.as_secs()))
+            }),
+            None => call.await,
+        }
+    }
+
+    /// Fetch the video metadata without downloading any media. A bot check
+    /// is retried as in [`Downloader::download_with_progress`].
+    pub async fn fetch_metadata(&self, url: &str) -> Result<VideoMetadata, DownloadError> {
+        let mut extractor_args = self.extractor_args.as_deref();
+        loop {
+            match self.fetch_metadata_once(url, extractor_args).await {
+                Err(e) if retry_bot_check(&e, &mut extractor_args) => {}
+                result => return result,
+            }
+        }
+    }
+
+    async fn fetch_metadata_once(
+        &self,
+        url: &str,
+        extractor_args: Option<&str>,
+    ) -> Result<VideoMetadata, DownloadError> {
+        let mut cmd = Command::new(&self.yt_dlp_path);
+        cmd.kill_on_drop(true);
+        cmd.args(["--no-playlist", "--dump-json", "--skip-download"]);
+        self.network_args(&mut cmd, extractor_args);
+        cmd.arg(url);
+        log_command(&cmd);
+        let output = self.with_deadline(async { Ok(cmd.output().await?) }).await?;
+
+        if !output.status.success() {
+            return Err(classify_failure(
+                url,
+                output.status.code(),
+                &String::from_utf8_lossy(&output.stderr),
+            ));
+        }
 
+        serde_json::from_slice(&output.stdout)
+            .map_err(|e| DownloadError::MetadataParse(e.to_string()))
+    }
+
+    /// The downloaded file: `<id>.<ext>` as yt-dlp reported it, or else the
+    /// first of the common audio extensions present
+    fn find_audio_file(
+        &self,
+        video_id: &str,
+        reported_ext: &str,
+    ) -> Result<PathBuf, DownloadError> {
+        if !reported_ext.is_empty() {
+            let path = self.temp_dir.join(format!("{}.{}", video_id, reported_ext));
+            if path.exists() {
+                debug!("Found audio file: {}", path.display());
+                return Ok(path);
+            }
+            debug!("{} not found, scanning for other extensions", path.display());
+        }
+
+        let extensions = ["opus", "m4a", "webm", "mp3", "ogg", "aac"];
         for ext in extensions {
             let path = self.temp_dir.join(format!("{}.{}", video_id, ext));
             if path.exists() {
@@ -142,6 +551,125 @@ impl Downloader {
             None
         }
     }
+
+    /// Subtitles are written as `<id>.<lang>.<ext>`; take the first usable one
+    async fn find_subtitles(&self, video_id: &str) -> Option<PathBuf> {
+        let prefix = format!("{}.", video_id);
+        let mut entries = tokio::fs::read_dir(&self.temp_dir).await.ok()?;
+        let mut found = Vec::new();
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(&prefix)
+                && path
+                    .extension()
+                    .is_some_and(|ext| ext == "vtt" || ext == "srt")
+            {
+                found.push(path);
+            }
+        }
+        found.sort();
+        found.into_iter().next()
+    }
+}
+
+/// Fraction from a yt-dlp `--newline` progress line like
+/// `[download]  42.3% of   3.52MiB at    1.20MiB/s ETA 00:02`
+fn parse_progress(line: &str) -> Option<f32> {
+    let rest = line.strip_prefix("[download]")?.trim_start();
+    let (percent, _) = rest.split_once('%')?;
+    let percent: f32 = percent.trim().parse().ok()?;
+    Some((percent / 100.0).clamp(0.0, 1.0))
+}
+
+/// Map a failed yt-dlp run to the most specific error its stderr allows
+fn classify_failure(url: &str, exit_code: Option<i32>, stderr: &str) -> DownloadError {
+    debug!("yt-dlp stderr: {}", stderr);
+
+    // Checked first: YouTube prefixes both with "Video unavailable"
+    const GEO_ERRORS: [&str; 4] = [
+        "available in your country",
+        "available from your location",
+        "geo restriction",
+        "geo-restrict",
+    ];
+    const AGE_ERRORS: [&str; 3] = [
+        "confirm your age",
+        "age-restricted",
+        "inappropriate for some users",
+    ];
+    let lower = stderr.to_lowercase();
+    if GEO_ERRORS.iter().any(|e| lower.contains(e)) {
+        return DownloadError::GeoRestricted(url.to_string());
+    }
+    if AGE_ERRORS.iter().any(|e| lower.contains(e)) {
+        return DownloadError::AgeRestricted(url.to_string());
+    }
+    // "Sign in to confirm you're not a bot", with either apostrophe
+    if lower.contains("not a bot") {
+        return DownloadError::BotCheck(url.to_string());
+    }
+
+    if stderr.contains("Video unavailable") || stderr.contains("Private video") {
+        return DownloadError::VideoUnavailable(url.to_string());
+    }
+    if stderr.contains("is not a valid URL") {
+        return DownloadError::InvalidUrl(url.to_string());
+    }
+
+    // e.g. "ERROR: unable to download video data: HTTP Error 403: Forbidden"
+    if let Some(status) = stderr
+        .split("HTTP Error ")
+        .nth(1)
+        .and_then(|rest| rest.get(..3))
+        .and_then(|code| code.parse().ok())
+    {
+        return DownloadError::Http(status);
+    }
+
+    const NETWORK_ERRORS: [&str; 7] = [
+        "timed out",
+        "Connection reset",
+        "Connection refused",
+        "Network is unreachable",
+        "Temporary failure in name resolution",
+        "Remote end closed connection",
+        "IncompleteRead",
+    ];
+    if let Some(line) = stderr
+        .lines()
+        .find(|line| NETWORK_ERRORS.iter().any(|e| line.contains(e)))
+    {
+        return DownloadError::Network(line.trim().to_string());
+    }
+
+    DownloadError::YtDlpFailed(exit_code)
+}
+
+/// Whether to retry a call that failed with `error`: after a bot check, once,
+/// switching `extractor_args` to [`BOT_CHECK_EXTRACTOR_ARGS`]. Explicitly set
+/// extractor args are never replaced.
+fn retry_bot_check(error: &DownloadError, extractor_args: &mut Option<&str>) -> bool {
+    if !matches!(error, DownloadError::BotCheck(_)) || extractor_args.is_some() {
+        return false;
+    }
+    warn!("YouTube asked to confirm this is not a bot, retrying with {}", BOT_CHECK_EXTRACTOR_ARGS);
+    *extractor_args = Some(BOT_CHECK_EXTRACTOR_ARGS);
+    true
+}
+
+/// Whether a download that failed with `error` gets another attempt, after
+/// `attempt` retries so far, `timeouts` of them following a timeout
+fn should_retry(error: &DownloadError, attempt: u32, max_retries: u32, timeouts: u32) -> bool {
+    let timed_out = matches!(error, DownloadError::Timeout(_));
+    error.is_transient() && attempt < max_retries && (!timed_out || timeouts < MAX_TIMEOUT_RETRIES)
+}
+
+/// Backoff before retry `attempt` (1-based): 2s, 4s, 8s, ... capped at a minute
+fn retry_delay(attempt: u32) -> Duration {
+    RETRY_BASE_DELAY
+        .saturating_mul(1 << (attempt - 1).min(16))
+        .min(RETRY_MAX_DELAY)
 }
 
 /// Validate that a string looks like a YouTube URL
@@ -153,16 +681,379 @@ pub fn validate_youtube_url(url: &str) -> bool {
         || url.contains("music.youtube.com")
 }
 
+/// The 11-character video ID in a watch, youtu.be, shorts, live or embed URL
+pub fn youtube_video_id(url: &str) -> Option<&str> {
+    let candidate = if let Some((_, query)) = url.split_once('?').filter(|_| url.contains("/watch"))
+    {
+        query.split('&').find_map(|pair| pair.strip_prefix("v="))?
+    } else {
+        ["youtu.be/", "/shorts/", "/live/", "/embed/"]
+            .iter()
+            .find_map(|marker| url.split_once(marker).map(|(_, rest)| rest))?
+    };
+
+    let id = candidate.split(['?', '&', '#', '/']).next()?;
+    let valid = id.len() == 11
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    valid.then_some(id)
+}
+
+/// Hosts [`normalize_url`] recognizes, without the scheme
+const YOUTUBE_HOSTS: &[&str] = &[
+    "youtube.com",
+    "www.youtube.com",
+    "m.youtube.com",
+    "music.youtube.com",
+    "youtu.be",
+    "www.youtube-nocookie.com",
+];
+
+/// A single-video URL in canonical form, see [`normalize_url`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NormalizedUrl {
+    /// The 11-character video ID
+    pub id: String,
+    /// `https://www.youtube.com/watch?v=<id>`
+    pub url: String,
+}
+
+/// Canonicalize a video URL on any YouTube host (youtu.be, shorts, live,
+/// embed, music.youtube.com, m.youtube.com) into a plain watch URL.
+///
+/// Everything but the video ID is dropped, including a `list=` parameter that
+/// would have yt-dlp fetch the whole playlist. `None` for other hosts and for
+/// URLs without a video ID, such as playlists, which are best passed on as is.
+pub fn normalize_url(url: &str) -> Option<NormalizedUrl> {
+    let without_scheme = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .unwrap_or(url);
+    let host = without_scheme.split(['/', '?', '#']).next()?;
+    if !YOUTUBE_HOSTS.iter().any(|h| host.eq_ignore_ascii_case(h)) {
+        return None;
+    }
+
+    let id = youtube_video_id(url)?;
+    Some(NormalizedUrl {
+        id: id.to_string(),
+        url: format!("https://www.youtube.com/watch?v={}", id),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::args;
 
     #[test]
     fn test_validate_youtube_url() {
         assert!(validate_youtube_url("https://www.youtube.com/watch?v=dQw4w9WgXcQ"));
         assert!(validate_youtube_url("https://youtu.be/dQw4w9WgXcQ"));
-        assert!(validate_youtube_url("https://youtube.com/playlist?list=PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf"));
-        assert!(validate_youtube_url("https://music.youtube.com/watch?v=dQw4w9WgXcQ"));
+        assert!(validate_youtube_url(
+            "https://youtube.com/playlist?list=PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf"
+        ));
+        assert!(validate_youtube_url(
+            "https://music.youtube.com/watch?v=dQw4w9WgXcQ"
+        ));
         assert!(!validate_youtube_url("https://example.com/video"));
     }
+
+    #[test]
+    fn test_youtube_video_id() {
+        let id = Some("dQw4w9WgXcQ");
+        assert_eq!(
+            youtube_video_id("https://www.youtube.com/watch?v=dQw4w9WgXcQ"),
+            id
+        );
+        assert_eq!(
+            youtube_video_id("https://music.youtube.com/watch?list=RD&v=dQw4w9WgXcQ&t=3"),
+            id
+        );
+        assert_eq!(youtube_video_id("https://youtu.be/dQw4w9WgXcQ?si=abc"), id);
+        assert_eq!(
+            youtube_video_id("https://youtube.com/shorts/dQw4w9WgXcQ"),
+            id
+        );
+        assert_eq!(
+            youtube_video_id(
+                "https://youtube.com/playlist?list=PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf"
+            ),
+            None
+        );
+        assert_eq!(youtube_video_id("https://youtu.be/short"), None);
+    }
+
+    #[test]
+    fn test_normalize_url() {
+        let canonical = Some(NormalizedUrl {
+            id: "dQw4w9WgXcQ".to_string(),
+            url: "https://www.youtube.com/watch?v=dQw4w9WgXcQ".to_string(),
+        });
+        for url in [
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://youtube.com/watch?v=dQw4w9WgXcQ&list=PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf&index=2",
+            "http://m.youtube.com/watch?feature=share&v=dQw4w9WgXcQ",
+            "https://music.youtube.com/watch?v=dQw4w9WgXcQ&si=abc",
+            "https://youtu.be/dQw4w9WgXcQ?t=42",
+            "youtu.be/dQw4w9WgXcQ",
+            "https://www.youtube.com/shorts/dQw4w9WgXcQ",
+            "https://www.youtube.com/live/dQw4w9WgXcQ?feature=share",
+            "https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ",
+        ] {
+            assert_eq!(normalize_url(url), canonical, "{}", url);
+        }
+
+        assert_eq!(
+            normalize_url("https://youtube.com/playlist?list=PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf"),
+            None
+        );
+        assert_eq!(normalize_url("https://example.com/embed/dQw4w9WgXcQ"), None);
+        assert_eq!(normalize_url("https://notyoutube.com/watch?v=dQw4w9WgXcQ"), None);
+    }
+
+    #[test]
+    fn test_command_thumbnail() {
+        let args = |downloader: Downloader| {
+            args(&downloader.command("https://youtu.be/dQw4w9WgXcQ"))
+        };
+        let downloader = || Downloader::new(PathBuf::from("yt-dlp"), PathBuf::from("."));
+
+        assert!(args(downloader()).contains(&"--write-thumbnail".to_string()));
+        assert!(
+            !args(downloader().with_thumbnail(false)).contains(&"--write-thumbnail".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deadline_times_out() {
+        let downloader = Downloader::new(PathBuf::from("yt-dlp"), PathBuf::from("."))
+            .with_timeout(Some(Duration::from_millis(10)));
+        let stalled = downloader.with_deadline(async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        });
+        assert!(matches!(stalled.await, Err(DownloadError::Timeout(_))));
+
+        let quick = downloader.with_deadline(async { Ok(1) });
+        assert_eq!(quick.await.unwrap(), 1);
+    }
+
+    #[test]
+    fn test_classify_failure() {
+        let url = "https://youtu.be/x";
+        let err = classify_failure(
+            url,
+            Some(1),
+            "ERROR: unable to download video data: HTTP Error 403: Forbidden",
+        );
+        assert!(matches!(err, DownloadError::Http(403)) && err.is_transient());
+
+        let err = classify_failure(url, Some(1), "ERROR: [youtube] x: Video unavailable");
+        assert!(matches!(err, DownloadError::VideoUnavailable(_)) && !err.is_transient());
+
+        let err = classify_failure(
+            url,
+            Some(1),
+            "WARNING: retrying\nERROR: <urlopen error timed out>",
+        );
+        assert!(err.is_transient());
+
+        let err = classify_failure(url, Some(2), "ERROR: something else");
+        assert!(matches!(err, DownloadError::YtDlpFailed(Some(2))) && !err.is_transient());
+    }
+
+    #[test]
+    fn test_classify_restrictions() {
+        const GEO: [&str; 3] = [
+            "ERROR: [youtube] dQw4w9WgXcQ: Video unavailable. The uploader has not made this video \
+             available in your country",
+            "ERROR: [youtube] dQw4w9WgXcQ: This video is not available in your country",
+            "ERROR: [youtube] dQw4w9WgXcQ: The uploader has not made this video available from \
+             your location due to geo restriction",
+        ];
+        const AGE: [&str; 2] = [
+            "ERROR: [youtube] dQw4w9WgXcQ: Sign in to confirm your age. This video may be \
+             inappropriate for some users. Use --cookies-from-browser or --cookies for the \
+             authentication.",
+            "WARNING: [youtube] Skipping player response\nERROR: [youtube] dQw4w9WgXcQ: This video \
+             is age-restricted",
+        ];
+
+        let url = "https://youtu.be/dQw4w9WgXcQ";
+        for stderr in GEO {
+            let err = classify_failure(url, Some(1), stderr);
+            assert!(matches!(err, DownloadError::GeoRestricted(_)), "{}", stderr);
+            assert!(err.to_string().contains("proxy") && !err.is_transient());
+        }
+        for stderr in AGE {
+            let err = classify_failure(url, Some(1), stderr);
+            assert!(matches!(err, DownloadError::AgeRestricted(_)), "{}", stderr);
+            assert!(err.to_string().contains("cookies") && !err.is_transient());
+        }
+
+        let err = classify_failure(
+            url,
+            Some(1),
+            "ERROR: [youtube] dQw4w9WgXcQ: Sign in to confirm you’re not a bot. This helps protect \
+             our community.",
+        );
+        assert!(matches!(err, DownloadError::BotCheck(_)), "{}", err);
+        assert!(err.to_string().contains("--extractor-args") && !err.is_transient());
+
+        // A plain removal is still just unavailable
+        let err = classify_failure(
+            url,
+            Some(1),
+            "ERROR: [youtube] dQw4w9WgXcQ: Video unavailable. This video has been removed by the \
+             uploader",
+        );
+        assert!(matches!(err, DownloadError::VideoUnavailable(_)));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_bot_check_retries_with_android_client() {
+        use crate::test_support::{arg_after, StubFfmpeg};
+
+        let stub = StubFfmpeg::new()
+            .with_stderr("ERROR: [youtube] x: Sign in to confirm you're not a bot\n")
+            .with_exit_code(1);
+        let downloader = Downloader::new(stub.path(), stub.dir().to_path_buf());
+        let err = downloader.download("https://youtu.be/x").await.unwrap_err();
+        assert!(matches!(err, DownloadError::BotCheck(_)), "{}", err);
+        let calls = stub.calls();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(arg_after(&calls[0], "--extractor-args"), None);
+        assert_eq!(arg_after(&calls[1], "--extractor-args"), Some(BOT_CHECK_EXTRACTOR_ARGS));
+
+        // Explicit extractor args are used as given, without the retry
+        let downloader =
+            downloader.with_extractor_args(Some("youtube:player_client=ios".to_string()));
+        downloader.download("https://youtu.be/x").await.unwrap_err();
+        let calls = stub.calls();
+        assert_eq!(calls.len(), 3);
+        assert_eq!(arg_after(&calls[2], "--extractor-args"), Some("youtube:player_client=ios"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_fetch_metadata_bot_check_retries_with_android_client() {
+        use crate::test_support::{arg_after, StubFfmpeg};
+
+        let stub = StubFfmpeg::new()
+            .with_stderr("ERROR: [youtube] x: Sign in to confirm you're not a bot\n")
+            .with_exit_code(1);
+        let downloader = Downloader::new(stub.path(), stub.dir().to_path_buf());
+        let err = downloader.fetch_metadata("https://youtu.be/x").await.unwrap_err();
+        assert!(matches!(err, DownloadError::BotCheck(_)), "{}", err);
+        let calls = stub.calls();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(arg_after(&calls[0], "--extractor-args"), None);
+        assert_eq!(arg_after(&calls[1], "--extractor-args"), Some(BOT_CHECK_EXTRACTOR_ARGS));
+    }
+
+    #[test]
+    fn test_should_retry() {
+        let network = DownloadError::Network("reset".to_string());
+        assert!(should_retry(&network, 0, 3, 0));
+        assert!(should_retry(&network, 2, 3, 2));
+        assert!(!should_retry(&network, 3, 3, 0));
+        assert!(!should_retry(&DownloadError::VideoUnavailable("x".to_string()), 0, 3, 0));
+
+        // A timeout gets one more attempt at most
+        let timeout = DownloadError::Timeout(3600);
+        assert!(should_retry(&timeout, 0, 3, 0));
+        assert!(should_retry(&timeout, 1, 3, 0));
+        assert!(!should_retry(&timeout, 1, 3, 1));
+        assert!(!should_retry(&timeout, 0, 0, 0));
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), Duration::from_secs(2));
+        assert_eq!(retry_delay(3), Duration::from_secs(8));
+        assert_eq!(retry_delay(10), RETRY_MAX_DELAY);
+    }
+
+    #[test]
+    fn test_parse_progress() {
+        assert_eq!(
+            parse_progress("[download]  42.5% of   3.52MiB at    1.20MiB/s ETA 00:02"),
+            Some(0.425)
+        );
+        assert_eq!(parse_progress("[download] 100% of    3.52MiB in 00:00:03"), Some(1.0));
+        assert_eq!(parse_progress("[download] Destination: /tmp/abc123.webm"), None);
+        assert_eq!(parse_progress("[youtube] abc123: Downloading webpage"), None);
+    }
+
+    #[test]
+    fn test_deserialize_chapters() {
+        let json = r#"{
+            "id": "abc123",
+            "title": "Full Album",
+            "ext": "opus",
+            "chapters": [
+                {"start_time": 0.0, "end_time": 185.5, "title": "Intro"},
+                {"start_time": 185.5, "end_time": 412.0, "title": "Second Song"}
+            ]
+        }"#;
+        let metadata: VideoMetadata = serde_json::from_str(json).unwrap();
+        assert_eq!(metadata.chapters.len(), 2);
+        assert_eq!(
+            metadata.chapters[1],
+            Chapter { start_time: 185.5, end_time: 412.0, title: "Second Song".to_string() }
+        );
+
+        let json = r#"{"id": "abc123", "title": "Single", "chapters": null}"#;
+        let metadata: VideoMetadata = serde_json::from_str(json).unwrap();
+        assert!(metadata.chapters.is_empty());
+    }
+
+    #[test]
+    fn test_deserialize_formats() {
+        let json = r#"{
+            "id": "abc123",
+            "title": "Song",
+            "ext": "webm",
+            "acodec": "opus",
+            "formats": [
+                {"format_id": "251", "ext": "webm", "acodec": "opus", "vcodec": "none", "abr": 129.5, "asr": 48000, "filesize": 3500000},
+                {"format_id": "137", "ext": "mp4", "acodec": "none", "vcodec": "avc1.640028", "abr": null, "asr": null},
+                {"format_id": "18", "ext": "mp4", "acodec": "mp4a.40.2", "vcodec": "avc1.42001E"},
+                {"format_id": "140", "ext": "m4a", "acodec": "mp4a.40.2", "vcodec": "none", "filesize": null, "filesize_approx": 2000000}
+            ]
+        }"#;
+        let metadata: VideoMetadata = serde_json::from_str(json).unwrap();
+        let audio: Vec<_> = metadata.formats.iter().filter(|f| f.is_audio_only()).collect();
+        assert_eq!(audio.len(), 2);
+        assert_eq!(audio[0].format_id, "251");
+        assert_eq!(audio[0].asr, Some(48000));
+        assert_eq!(audio[0].size(), Some(3500000));
+        assert_eq!(audio[1].size(), Some(2000000));
+        assert_eq!(metadata.acodec.as_deref(), Some("opus"));
+    }
+
+    #[test]
+    fn test_find_audio_file_prefers_reported_ext() {
+        let temp = tempfile::tempdir().unwrap();
+        let downloader = Downloader::new(PathBuf::from("yt-dlp"), temp.path().to_path_buf());
+        for name in ["abc123.opus", "abc123.webm"] {
+            std::fs::write(temp.path().join(name), b"").unwrap();
+        }
+
+        assert_eq!(
+            downloader.find_audio_file("abc123", "webm").unwrap(),
+            temp.path().join("abc123.webm")
+        );
+        // Missing or unreported: fall back to the scan order
+        assert_eq!(
+            downloader.find_audio_file("abc123", "m4a").unwrap(),
+            temp.path().join("abc123.opus")
+        );
+        assert_eq!(
+            downloader.find_audio_file("abc123", "").unwrap(),
+            temp.path().join("abc123.opus")
+        );
+        assert!(downloader.find_audio_file("missing", "webm").is_err());
+    }
 }
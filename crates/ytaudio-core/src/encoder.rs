@@ -1,17 +1,33 @@
 //! Audio encoder using FFmpeg
 
 use crate::error::EncodeError;
+use crate::process::{ffmpeg_loglevel, ffmpeg_output_with_progress};
+use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use tokio::process::Command;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Output audio format, shared by the CLI, config and pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
 pub enum OutputFormat {
+    /// FLAC - Lossless compression (recommended)
     Flac,
+    /// WAV - Uncompressed PCM
     Wav,
+    /// MP3 - Lossy, widely compatible
     Mp3,
+    /// AAC - Lossy, good quality/size ratio
     Aac,
+    /// Opus - Lossy, best quality/size ratio
     Opus,
+    /// Ogg Vorbis - Lossy, broad portable player support
+    Vorbis,
+    /// WavPack - Lossless compression
+    #[cfg_attr(feature = "clap", value(name = "wavpack"))]
+    WavPack,
 }
 
 impl OutputFormat {
@@ -22,17 +38,31 @@ impl OutputFormat {
             OutputFormat::Mp3 => "mp3",
             OutputFormat::Aac => "m4a",
             OutputFormat::Opus => "opus",
+            OutputFormat::Vorbis => "ogg",
+            OutputFormat::WavPack => "wv",
         }
     }
 
-    pub fn from_str(s: &str) -> Option<Self> {
+    /// Lossless formats ignore bitrate settings
+    pub fn is_lossless(&self) -> bool {
+        matches!(self, OutputFormat::Flac | OutputFormat::Wav | OutputFormat::WavPack)
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = EncodeError;
+
+    /// Accepts format names and file extensions, case-insensitively
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_lowercase().as_str() {
-            "flac" => Some(OutputFormat::Flac),
-            "wav" => Some(OutputFormat::Wav),
-            "mp3" => Some(OutputFormat::Mp3),
-            "aac" | "m4a" => Some(OutputFormat::Aac),
-            "opus" => Some(OutputFormat::Opus),
-            _ => None,
+            "flac" => Ok(OutputFormat::Flac),
+            "wav" => Ok(OutputFormat::Wav),
+            "mp3" => Ok(OutputFormat::Mp3),
+            "aac" | "m4a" => Ok(OutputFormat::Aac),
+            "opus" => Ok(OutputFormat::Opus),
+            "vorbis" | "ogg" => Ok(OutputFormat::Vorbis),
+            "wavpack" | "wv" => Ok(OutputFormat::WavPack),
+            _ => Err(EncodeError::UnsupportedFormat(s.to_string())),
         }
     }
 }
@@ -45,6 +75,299 @@ impl std::fmt::Display for OutputFormat {
             OutputFormat::Mp3 => write!(f, "MP3"),
             OutputFormat::Aac => write!(f, "AAC"),
             OutputFormat::Opus => write!(f, "Opus"),
+            OutputFormat::Vorbis => write!(f, "Vorbis"),
+            OutputFormat::WavPack => write!(f, "WavPack"),
+        }
+    }
+}
+
+/// Lossy sources at or above this bitrate (kbps) are kept lossless by
+/// [`FormatChoice::Auto`]; below it, re-encoding costs little
+pub const AUTO_LOSSLESS_MIN_KBPS: f64 = 256.0;
+
+/// Output format as requested: a fixed one, or `auto` to pick per source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatChoice {
+    Fixed(OutputFormat),
+    /// FLAC for lossless, high-bitrate or upscaled audio, otherwise a lossy
+    /// format at the source's bitrate (see [`FormatChoice::resolve`])
+    Auto,
+}
+
+impl FormatChoice {
+    /// The format to write for a source encoded with `codec` at `bitrate_kbps`,
+    /// plus the bitrate to encode at when it should match the source.
+    ///
+    /// `auto` keeps FLAC when upscaling added content the source never had,
+    /// when the source is lossless, at least [`AUTO_LOSSLESS_MIN_KBPS`] or of
+    /// unknown codec. A lower-bitrate lossy source is written as `lossy` at
+    /// its own bitrate (within 32-320 kbps), since storing its compression
+    /// artifacts losslessly only takes space.
+    pub fn resolve(
+        self,
+        lossy: OutputFormat,
+        codec: Option<&str>,
+        bitrate_kbps: Option<f64>,
+        upscaled: bool,
+    ) -> (OutputFormat, Option<u32>) {
+        let codec = match self {
+            FormatChoice::Fixed(format) => return (format, None),
+            FormatChoice::Auto => codec,
+        };
+        let keep_lossless = upscaled
+            || codec.is_none_or(is_lossless_codec)
+            || bitrate_kbps.is_some_and(|kbps| kbps >= AUTO_LOSSLESS_MIN_KBPS)
+            || lossy.is_lossless();
+        if keep_lossless {
+            (OutputFormat::Flac, None)
+        } else {
+            (lossy, bitrate_kbps.map(|kbps| (kbps.round() as u32).clamp(32, 320)))
+        }
+    }
+}
+
+impl FromStr for FormatChoice {
+    type Err = EncodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            Ok(FormatChoice::Auto)
+        } else {
+            s.parse().map(FormatChoice::Fixed)
+        }
+    }
+}
+
+impl std::fmt::Display for FormatChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormatChoice::Fixed(format) => write!(f, "{}", format),
+            FormatChoice::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+/// Whether FFmpeg's (or yt-dlp's) name for a codec is a lossless one
+fn is_lossless_codec(codec: &str) -> bool {
+    codec.starts_with("pcm_")
+        || matches!(
+            codec,
+            "flac" | "alac" | "wavpack" | "ape" | "tta" | "truehd" | "mlp"
+        )
+}
+
+/// Sample format of the decoded working WAV and of WAV output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum BitDepth {
+    /// 16-bit integer, for older hardware
+    #[cfg_attr(feature = "clap", value(name = "16"))]
+    Int16,
+    /// 24-bit integer
+    #[default]
+    #[cfg_attr(feature = "clap", value(name = "24"))]
+    Int24,
+    /// 32-bit float, no quantization before upscaling
+    #[cfg_attr(feature = "clap", value(name = "32f"))]
+    Float32,
+}
+
+impl BitDepth {
+    /// FFmpeg PCM codec for this depth
+    pub fn pcm_codec(&self) -> &'static str {
+        match self {
+            BitDepth::Int16 => "pcm_s16le",
+            BitDepth::Int24 => "pcm_s24le",
+            BitDepth::Float32 => "pcm_f32le",
+        }
+    }
+
+    /// Bytes per sample in a WAV at this depth
+    pub fn bytes_per_sample(&self) -> u32 {
+        match self {
+            BitDepth::Int16 => 2,
+            BitDepth::Int24 => 3,
+            BitDepth::Float32 => 4,
+        }
+    }
+}
+
+impl FromStr for BitDepth {
+    type Err = EncodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "16" => Ok(BitDepth::Int16),
+            "24" => Ok(BitDepth::Int24),
+            "32f" => Ok(BitDepth::Float32),
+            _ => Err(EncodeError::UnsupportedFormat(format!("bit depth {}", s))),
+        }
+    }
+}
+
+impl std::fmt::Display for BitDepth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BitDepth::Int16 => write!(f, "16"),
+            BitDepth::Int24 => write!(f, "24"),
+            BitDepth::Float32 => write!(f, "32f"),
+        }
+    }
+}
+
+/// Channel layout of the processed audio and output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Channels {
+    /// Keep the source's channels
+    #[default]
+    Source,
+    /// Downmix to one channel, e.g. for dual-mono speech
+    Mono,
+    /// Two channels; a mono source is duplicated to both
+    Stereo,
+}
+
+impl Channels {
+    /// FFmpeg `-ac` value; `None` leaves the layout alone
+    pub fn count(&self) -> Option<u32> {
+        match self {
+            Channels::Source => None,
+            Channels::Mono => Some(1),
+            Channels::Stereo => Some(2),
+        }
+    }
+}
+
+impl FromStr for Channels {
+    type Err = EncodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "source" => Ok(Channels::Source),
+            "mono" => Ok(Channels::Mono),
+            "stereo" => Ok(Channels::Stereo),
+            _ => Err(EncodeError::UnsupportedFormat(format!("channel layout {}", s))),
+        }
+    }
+}
+
+impl std::fmt::Display for Channels {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Channels::Source => write!(f, "source"),
+            Channels::Mono => write!(f, "mono"),
+            Channels::Stereo => write!(f, "stereo"),
+        }
+    }
+}
+
+/// What libopus tunes its encoding for (`-application`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OpusApplication {
+    /// Music and other general audio
+    #[default]
+    Audio,
+    /// Speech intelligibility, e.g. podcasts and talks
+    Voip,
+    /// Lowest latency, at some cost in quality
+    LowDelay,
+}
+
+impl FromStr for OpusApplication {
+    type Err = EncodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "audio" => Ok(OpusApplication::Audio),
+            "voip" => Ok(OpusApplication::Voip),
+            "lowdelay" => Ok(OpusApplication::LowDelay),
+            _ => Err(EncodeError::UnsupportedFormat(format!("Opus application {}", s))),
+        }
+    }
+}
+
+impl std::fmt::Display for OpusApplication {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OpusApplication::Audio => write!(f, "audio"),
+            OpusApplication::Voip => write!(f, "voip"),
+            OpusApplication::LowDelay => write!(f, "lowdelay"),
+        }
+    }
+}
+
+/// libopus rate control (`-vbr`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OpusVbr {
+    /// Unconstrained VBR, the best quality for a given size
+    #[default]
+    On,
+    /// Constant bitrate
+    Off,
+    /// VBR held close to the target bitrate, for streaming
+    Constrained,
+}
+
+impl FromStr for OpusVbr {
+    type Err = EncodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "on" => Ok(OpusVbr::On),
+            "off" => Ok(OpusVbr::Off),
+            "constrained" => Ok(OpusVbr::Constrained),
+            _ => Err(EncodeError::UnsupportedFormat(format!("Opus VBR mode {}", s))),
+        }
+    }
+}
+
+impl std::fmt::Display for OpusVbr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OpusVbr::On => write!(f, "on"),
+            OpusVbr::Off => write!(f, "off"),
+            OpusVbr::Constrained => write!(f, "constrained"),
+        }
+    }
+}
+
+/// FLAC `-compression_level` unless configured: close to the smallest files
+/// without the slow exhaustive search of level 12
+pub const DEFAULT_FLAC_COMPRESSION: u8 = 8;
+
+/// Highest FLAC compression level FFmpeg accepts
+pub const MAX_FLAC_COMPRESSION: u8 = 12;
+
+/// Overrides for the encoders' default quality settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodeOptions {
+    /// Target bitrate in kbps, replacing the per-format default
+    pub bitrate_kbps: Option<u32>,
+    /// Constant bitrate instead of the encoder's VBR/ABR mode
+    pub cbr: bool,
+    /// Sample format of WAV output
+    pub bit_depth: BitDepth,
+    /// FLAC compression level, 0 (fastest) to 12 (smallest)
+    pub flac_compression: u8,
+    /// Output channel layout
+    pub channels: Channels,
+    /// libopus `-application`
+    pub opus_application: OpusApplication,
+    /// libopus `-vbr`; `cbr` forces it off
+    pub opus_vbr: OpusVbr,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            bitrate_kbps: None,
+            cbr: false,
+            bit_depth: BitDepth::default(),
+            flac_compression: DEFAULT_FLAC_COMPRESSION,
+            channels: Channels::default(),
+            opus_application: OpusApplication::default(),
+            opus_vbr: OpusVbr::default(),
         }
     }
 }
@@ -52,11 +375,46 @@ impl std::fmt::Display for OutputFormat {
 #[derive(Debug)]
 pub struct Encoder {
     ffmpeg_path: PathBuf,
+    strip_tags: bool,
 }
 
 impl Encoder {
     pub fn new(ffmpeg_path: PathBuf) -> Self {
-        Self { ffmpeg_path }
+        Self { ffmpeg_path, strip_tags: false }
+    }
+
+    /// Drop the tags the input carries (`-map_metadata -1`) instead of
+    /// copying them into the output
+    pub fn with_strip_tags(mut self, strip: bool) -> Self {
+        self.strip_tags = strip;
+        self
+    }
+
+    /// Build the FFmpeg invocation used by [`Encoder::encode`]
+    pub fn command(
+        &self,
+        input: &Path,
+        output: &Path,
+        format: OutputFormat,
+        options: &EncodeOptions,
+    ) -> Command {
+        let mut cmd = Command::new(&self.ffmpeg_path);
+        cmd.kill_on_drop(true);
+        cmd.args(["-hide_banner", "-loglevel", ffmpeg_loglevel()]);
+        // Progress goes to stdout for `encode_with_progress`; -nostats drops
+        // the same figures from stderr
+        cmd.args(["-nostats", "-progress", "pipe:1"]);
+        cmd.arg("-i").arg(input);
+        cmd.args(Self::get_codec_args(format, options));
+        if let Some(count) = options.channels.count() {
+            cmd.args(["-ac", &count.to_string()]);
+        }
+        if self.strip_tags {
+            cmd.args(["-map_metadata", "-1"]);
+        }
+
+        cmd.arg("-y").arg(output);
+        cmd
     }
 
     /// Encode audio to target format
@@ -65,52 +423,373 @@ impl Encoder {
         input: &Path,
         output: &Path,
         format: OutputFormat,
+        options: &EncodeOptions,
     ) -> Result<(), EncodeError> {
-        info!("Encoding to {} format", format);
-
-        let codec_args = Self::get_codec_args(format);
+        self.encode_with_progress(input, output, format, options, None, |_| {}).await
+    }
 
-        let mut cmd = Command::new(&self.ffmpeg_path);
-        cmd.args(["-hide_banner", "-loglevel", "error"]);
-        cmd.arg("-i").arg(input);
+    /// [`Encoder::encode`], calling `on_progress` with the fraction of the
+    /// input's `duration` (in seconds) encoded so far, as FFmpeg reports it.
+    /// Without a duration no progress is reported.
+    pub async fn encode_with_progress(
+        &self,
+        input: &Path,
+        output: &Path,
+        format: OutputFormat,
+        options: &EncodeOptions,
+        duration: Option<f64>,
+        on_progress: impl FnMut(f32),
+    ) -> Result<(), EncodeError> {
+        info!("Encoding to {} format", format);
 
-        for arg in codec_args {
-            cmd.arg(arg);
+        if format.is_lossless() && (options.bitrate_kbps.is_some() || options.cbr) {
+            warn!("Bitrate settings have no effect on {}, ignoring them", format);
+        }
+        if format == OutputFormat::Opus && options.cbr && options.opus_vbr != OpusVbr::Off {
+            warn!(
+                "--cbr overrides output.opus.vbr = \"{}\", encoding Opus at a constant bitrate",
+                options.opus_vbr
+            );
         }
 
-        cmd.arg("-y").arg(output);
-
-        let status = cmd.status().await?;
+        let mut cmd = self.command(input, output, format, options);
+        let result = ffmpeg_output_with_progress(&mut cmd, duration, on_progress).await?;
 
-        if !status.success() {
-            return Err(EncodeError::FfmpegFailed(status.code()));
+        if !result.status.success() {
+            return Err(EncodeError::failed(&result));
         }
 
         debug!("Encoded to: {}", output.display());
         Ok(())
     }
 
-    fn get_codec_args(format: OutputFormat) -> Vec<&'static str> {
-        match format {
+    fn get_codec_args(format: OutputFormat, options: &EncodeOptions) -> Vec<String> {
+        let bitrate = |default_kbps: u32| {
+            format!("{}k", options.bitrate_kbps.unwrap_or(default_kbps))
+        };
+
+        let args: Vec<String> = match format {
             OutputFormat::Flac => vec![
-                "-c:a", "flac",
-                "-compression_level", "12",
+                "-c:a".into(),
+                "flac".into(),
+                "-compression_level".into(),
+                options
+                    .flac_compression
+                    .min(MAX_FLAC_COMPRESSION)
+                    .to_string(),
             ],
             OutputFormat::Wav => vec![
-                "-c:a", "pcm_s24le",
-            ],
-            OutputFormat::Mp3 => vec![
-                "-c:a", "libmp3lame",
-                "-q:a", "0",  // VBR highest quality (~245 kbps)
+                "-c:a".into(), options.bit_depth.pcm_codec().into(),
             ],
+            OutputFormat::Mp3 => match (options.bitrate_kbps, options.cbr) {
+                // VBR highest quality (~245 kbps)
+                (None, false) => vec![
+                    "-c:a".into(),
+                    "libmp3lame".into(),
+                    "-q:a".into(),
+                    "0".into(),
+                ],
+                (_, true) => vec![
+                    "-c:a".into(),
+                    "libmp3lame".into(),
+                    "-b:a".into(),
+                    bitrate(320),
+                ],
+                // Average bitrate: VBR steered towards the requested rate
+                (Some(_), false) => vec![
+                    "-c:a".into(), "libmp3lame".into(),
+                    "-abr".into(), "1".into(),
+                    "-b:a".into(), bitrate(245),
+                ],
+            },
             OutputFormat::Aac => vec![
-                "-c:a", "aac",
-                "-b:a", "256k",
+                "-c:a".into(), "aac".into(),
+                "-b:a".into(), bitrate(256),
             ],
-            OutputFormat::Opus => vec![
-                "-c:a", "libopus",
-                "-b:a", "192k",
+            OutputFormat::Opus => {
+                let vbr = if options.cbr { OpusVbr::Off } else { options.opus_vbr };
+                vec![
+                    "-c:a".into(), "libopus".into(),
+                    "-b:a".into(), bitrate(192),
+                    "-application".into(), options.opus_application.to_string(),
+                    "-vbr".into(), vbr.to_string(),
+                ]
+            }
+            OutputFormat::Vorbis => match (options.bitrate_kbps, options.cbr) {
+                // Quality-based VBR (~256 kbps)
+                (None, false) => vec!["-c:a".into(), "libvorbis".into(), "-q:a".into(), "8".into()],
+                (_, true) => vec![
+                    "-c:a".into(), "libvorbis".into(),
+                    "-b:a".into(), bitrate(256),
+                    "-minrate".into(), bitrate(256),
+                    "-maxrate".into(), bitrate(256),
+                ],
+                (Some(_), false) => vec![
+                    "-c:a".into(),
+                    "libvorbis".into(),
+                    "-b:a".into(),
+                    bitrate(256),
+                ],
+            },
+            OutputFormat::WavPack => vec![
+                "-c:a".into(), "wavpack".into(),
             ],
+        };
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(format: OutputFormat, bitrate_kbps: Option<u32>, cbr: bool) -> Vec<String> {
+        Encoder::get_codec_args(format, &EncodeOptions { bitrate_kbps, cbr, ..Default::default() })
+    }
+
+    #[test]
+    fn test_codec_args_bitrate_override() {
+        assert_eq!(
+            args(OutputFormat::Mp3, None, false),
+            ["-c:a", "libmp3lame", "-q:a", "0"]
+        );
+        assert_eq!(
+            args(OutputFormat::Mp3, Some(128), true),
+            ["-c:a", "libmp3lame", "-b:a", "128k"]
+        );
+        assert_eq!(
+            args(OutputFormat::Mp3, Some(160), false),
+            ["-c:a", "libmp3lame", "-abr", "1", "-b:a", "160k"]
+        );
+        assert_eq!(args(OutputFormat::Aac, Some(96), false), ["-c:a", "aac", "-b:a", "96k"]);
+        assert_eq!(
+            args(OutputFormat::Opus, Some(64), true),
+            ["-c:a", "libopus", "-b:a", "64k", "-application", "audio", "-vbr", "off"]
+        );
+        // Lossless formats ignore the override
+        assert_eq!(
+            args(OutputFormat::Flac, Some(128), true),
+            args(OutputFormat::Flac, None, false)
+        );
+    }
+
+    #[test]
+    fn test_codec_args_opus_tuning() {
+        assert_eq!(
+            args(OutputFormat::Opus, None, false),
+            ["-c:a", "libopus", "-b:a", "192k", "-application", "audio", "-vbr", "on"]
+        );
+        let options = EncodeOptions {
+            opus_application: OpusApplication::Voip,
+            opus_vbr: OpusVbr::Constrained,
+            ..Default::default()
+        };
+        assert_eq!(
+            Encoder::get_codec_args(OutputFormat::Opus, &options),
+            ["-c:a", "libopus", "-b:a", "192k", "-application", "voip", "-vbr", "constrained"]
+        );
+        // --cbr wins over the configured VBR mode
+        let options = EncodeOptions { cbr: true, ..options };
+        assert_eq!(Encoder::get_codec_args(OutputFormat::Opus, &options)[7], "off");
+
+        assert_eq!("lowdelay".parse::<OpusApplication>().unwrap(), OpusApplication::LowDelay);
+        assert!("music".parse::<OpusApplication>().is_err());
+        assert!("cvbr".parse::<OpusVbr>().is_err());
+    }
+
+    #[test]
+    fn test_format_choice_auto() {
+        let auto = |codec, kbps, upscaled| {
+            FormatChoice::Auto.resolve(OutputFormat::Opus, codec, kbps, upscaled)
+        };
+        // A typical YouTube stream stays Opus at its own bitrate
+        assert_eq!(auto(Some("opus"), Some(129.5), false), (OutputFormat::Opus, Some(130)));
+        assert_eq!(auto(Some("mp4a.40.2"), None, false), (OutputFormat::Opus, None));
+        // Upscaled, lossless, high-bitrate or unknown sources stay lossless
+        assert_eq!(auto(Some("opus"), Some(129.5), true), (OutputFormat::Flac, None));
+        assert_eq!(auto(Some("pcm_s24le"), None, false), (OutputFormat::Flac, None));
+        assert_eq!(auto(Some("flac"), Some(900.0), false), (OutputFormat::Flac, None));
+        assert_eq!(auto(Some("mp3"), Some(320.0), false), (OutputFormat::Flac, None));
+        assert_eq!(auto(None, Some(128.0), false), (OutputFormat::Flac, None));
+
+        assert_eq!(
+            FormatChoice::Auto.resolve(OutputFormat::Mp3, Some("aac"), Some(20.0), false),
+            (OutputFormat::Mp3, Some(32))
+        );
+        assert_eq!(
+            FormatChoice::Fixed(OutputFormat::Wav).resolve(
+                OutputFormat::Opus,
+                Some("opus"),
+                Some(128.0),
+                false
+            ),
+            (OutputFormat::Wav, None)
+        );
+        assert_eq!("AUTO".parse::<FormatChoice>().unwrap(), FormatChoice::Auto);
+        assert_eq!("m4a".parse::<FormatChoice>().unwrap(), FormatChoice::Fixed(OutputFormat::Aac));
+    }
+
+    #[test]
+    fn test_codec_args_vorbis_wavpack() {
+        assert_eq!(
+            args(OutputFormat::Vorbis, None, false),
+            ["-c:a", "libvorbis", "-q:a", "8"]
+        );
+        assert_eq!(
+            args(OutputFormat::Vorbis, Some(160), false),
+            ["-c:a", "libvorbis", "-b:a", "160k"]
+        );
+        // --cbr pins the bitrate, at 256 kbps when none is given
+        assert_eq!(
+            args(OutputFormat::Vorbis, None, true),
+            ["-c:a", "libvorbis", "-b:a", "256k", "-minrate", "256k", "-maxrate", "256k"]
+        );
+        assert_eq!(
+            args(OutputFormat::Vorbis, Some(160), true),
+            ["-c:a", "libvorbis", "-b:a", "160k", "-minrate", "160k", "-maxrate", "160k"]
+        );
+        assert_eq!(args(OutputFormat::WavPack, None, false), ["-c:a", "wavpack"]);
+        assert_eq!(args(OutputFormat::WavPack, Some(128), true), ["-c:a", "wavpack"]);
+    }
+
+    #[test]
+    fn test_codec_args_flac_compression() {
+        assert_eq!(
+            args(OutputFormat::Flac, None, false),
+            ["-c:a", "flac", "-compression_level", "8"]
+        );
+        let options = EncodeOptions {
+            flac_compression: 0,
+            ..Default::default()
+        };
+        assert_eq!(
+            Encoder::get_codec_args(OutputFormat::Flac, &options),
+            ["-c:a", "flac", "-compression_level", "0"]
+        );
+    }
+
+    #[test]
+    fn test_codec_args_wav_bit_depth() {
+        assert_eq!(args(OutputFormat::Wav, None, false), ["-c:a", "pcm_s24le"]);
+        let options = EncodeOptions { bit_depth: BitDepth::Float32, ..Default::default() };
+        assert_eq!(Encoder::get_codec_args(OutputFormat::Wav, &options), ["-c:a", "pcm_f32le"]);
+        assert_eq!("16".parse::<BitDepth>().unwrap(), BitDepth::Int16);
+        assert!("32".parse::<BitDepth>().is_err());
+    }
+
+    #[test]
+    fn test_command_channels() {
+        let encoder = Encoder::new(PathBuf::from("ffmpeg"));
+        let args = |channels: Channels| {
+            let options = EncodeOptions {
+                channels,
+                ..Default::default()
+            };
+            let cmd = encoder.command(
+                Path::new("in.wav"),
+                Path::new("out.opus"),
+                OutputFormat::Opus,
+                &options,
+            );
+            crate::test_support::args(&cmd)
+        };
+        assert!(args(Channels::Mono).windows(2).any(|w| w == ["-ac", "1"]));
+        assert!(args(Channels::Stereo).windows(2).any(|w| w == ["-ac", "2"]));
+        assert!(!args(Channels::Source).iter().any(|a| a == "-ac"));
+        assert_eq!("Mono".parse::<Channels>().unwrap(), Channels::Mono);
+    }
+
+    #[test]
+    fn test_command_strip_tags() {
+        let args = |encoder: Encoder| {
+            let cmd = encoder.command(
+                Path::new("in.wav"),
+                Path::new("out.flac"),
+                OutputFormat::Flac,
+                &EncodeOptions::default(),
+            );
+            crate::test_support::args(&cmd)
+        };
+        let strip = ["-map_metadata", "-1"];
+        assert!(!args(Encoder::new(PathBuf::from("ffmpeg")))
+            .windows(2)
+            .any(|w| w == strip));
+        assert!(
+            args(Encoder::new(PathBuf::from("ffmpeg")).with_strip_tags(true))
+                .windows(2)
+                .any(|w| w == strip)
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_encode_runs_codec_for_each_format() {
+        use crate::test_support::{arg_after, StubFfmpeg};
+
+        let stub = StubFfmpeg::new();
+        let encoder = Encoder::new(stub.path());
+        let input = stub.dir().join("processed.wav");
+        let expected = [
+            (OutputFormat::Flac, "flac"),
+            (OutputFormat::Wav, "pcm_s24le"),
+            (OutputFormat::Mp3, "libmp3lame"),
+            (OutputFormat::Aac, "aac"),
+            (OutputFormat::Opus, "libopus"),
+            (OutputFormat::Vorbis, "libvorbis"),
+            (OutputFormat::WavPack, "wavpack"),
+        ];
+        for (format, _) in expected {
+            let output = stub.dir().join(format!("encoded.{}", format.extension()));
+            encoder.encode(&input, &output, format, &EncodeOptions::default()).await.unwrap();
+        }
+
+        let calls = stub.calls();
+        assert_eq!(calls.len(), expected.len());
+        for (call, (format, codec)) in calls.iter().zip(expected) {
+            assert_eq!(arg_after(call, "-i"), input.to_str());
+            assert_eq!(arg_after(call, "-progress"), Some("pipe:1"));
+            assert_eq!(
+                arg_after(call, "-c:a"),
+                Some(codec),
+                "{}: {:?}",
+                format,
+                call
+            );
+            assert_eq!(
+                call.last().map(String::as_str),
+                stub.dir()
+                    .join(format!("encoded.{}", format.extension()))
+                    .to_str()
+            );
+        }
+        assert_eq!(arg_after(&calls[4], "-b:a"), Some("192k"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_encode_failure_keeps_stderr() {
+        use crate::test_support::StubFfmpeg;
+
+        let stub = StubFfmpeg::new().with_stderr("Unknown encoder 'libopus'\n").with_exit_code(1);
+        let output = stub.dir().join("encoded.opus");
+        let err = Encoder::new(stub.path())
+            .encode(
+                &stub.dir().join("processed.wav"),
+                &output,
+                OutputFormat::Opus,
+                &EncodeOptions::default(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, EncodeError::FfmpegFailed { code: Some(1), .. }), "{:?}", err);
+        assert!(err.to_string().contains("Unknown encoder 'libopus'"), "{}", err);
+    }
+
+    #[test]
+    fn test_format_names_round_trip() {
+        for format in [OutputFormat::Vorbis, OutputFormat::WavPack] {
+            assert_eq!(format.extension().parse::<OutputFormat>().unwrap(), format);
+            assert_eq!(format.to_string().parse::<OutputFormat>().unwrap(), format);
         }
     }
 }
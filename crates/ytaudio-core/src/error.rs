@@ -1,7 +1,10 @@
 //! Error types for ytaudio-core
 
+use std::process::Output;
 use thiserror::Error;
 
+use crate::process::failure_stderr;
+
 pub type Result<T> = std::result::Result<T, YtAudioError>;
 
 #[derive(Error, Debug)]
@@ -32,6 +35,40 @@ pub enum YtAudioError {
 
     #[error("Pipeline error: {0}")]
     Pipeline(String),
+
+    #[error("Pipeline cancelled")]
+    Cancelled,
+}
+
+impl YtAudioError {
+    /// Process exit code for this failure, so scripts can tell stages apart:
+    ///
+    /// | Code | Failure |
+    /// |------|---------|
+    /// | 1    | other pipeline error |
+    /// | 10   | download (unavailable video, network, yt-dlp) |
+    /// | 11   | decode |
+    /// | 12   | upscale |
+    /// | 13   | encode |
+    /// | 14   | normalization |
+    /// | 15   | metadata embedding |
+    /// | 20   | configuration (including missing tools) |
+    /// | 74   | I/O |
+    /// | 130  | cancelled (Ctrl-C) |
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            YtAudioError::Download(_) => 10,
+            YtAudioError::Decode(_) => 11,
+            YtAudioError::Upscale(_) => 12,
+            YtAudioError::Encode(_) => 13,
+            YtAudioError::Normalize(_) => 14,
+            YtAudioError::Metadata(_) => 15,
+            YtAudioError::Config(_) => ConfigError::EXIT_CODE,
+            YtAudioError::Io(_) => 74,
+            YtAudioError::Pipeline(_) => 1,
+            YtAudioError::Cancelled => 130,
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -48,6 +85,30 @@ pub enum DownloadError {
     #[error("Video unavailable or private: {0}")]
     VideoUnavailable(String),
 
+    #[error("Video is not available in this region: {0} (set --proxy or download.proxy to a server \
+             where it is)")]
+    GeoRestricted(String),
+
+    #[error("Video is age-restricted: {0} (yt-dlp needs a signed-in account's cookies; add \
+             --cookies-from-browser <browser> to its config file)")]
+    AgeRestricted(String),
+
+    #[error("YouTube asked to confirm this is not a bot: {0} (try --extractor-args \
+             \"youtube:player_client=android\", or cookies in yt-dlp's config file)")]
+    BotCheck(String),
+
+    #[error("HTTP error {0} from YouTube")]
+    Http(u16),
+
+    #[error("Network error: {0}")]
+    Network(String),
+
+    #[error("yt-dlp timed out after {0} seconds")]
+    Timeout(u64),
+
+    #[error("Already recorded in the download archive: {0}")]
+    AlreadyArchived(String),
+
     #[error("No audio stream available")]
     NoAudioStream,
 
@@ -58,21 +119,47 @@ pub enum DownloadError {
     Io(#[from] std::io::Error),
 }
 
+impl DownloadError {
+    /// Failures worth retrying: network trouble, throttling (403/429) and
+    /// server errors. Missing videos and bad URLs fail the same way every time.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            DownloadError::Http(status) => matches!(status, 403 | 429 | 500..=599),
+            // Usually a stalled connection
+            DownloadError::Network(_) | DownloadError::Timeout(_) => true,
+            _ => false,
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum DecodeError {
     #[error("FFmpeg not found. Install with: brew install ffmpeg")]
     FfmpegNotFound,
 
-    #[error("FFmpeg failed with exit code: {0:?}")]
-    FfmpegFailed(Option<i32>),
+    #[error("FFmpeg failed with exit code: {code:?}{}", stderr_suffix(.stderr))]
+    FfmpegFailed { code: Option<i32>, stderr: String },
 
     #[error("Unsupported audio format: {0}")]
     UnsupportedFormat(String),
 
+    #[error("Invalid time range {range:?}: {reason}")]
+    InvalidClip { range: String, reason: String },
+
+    #[error("FFmpeg rejected the filter chain {chain:?}{}", stderr_suffix(.stderr))]
+    InvalidFilters { chain: String, stderr: String },
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 }
 
+impl DecodeError {
+    /// [`DecodeError::FfmpegFailed`] for a finished FFmpeg run
+    pub(crate) fn failed(output: &Output) -> Self {
+        DecodeError::FfmpegFailed { code: output.status.code(), stderr: failure_stderr(output) }
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum NormalizeError {
     #[error("FFmpeg not found")]
@@ -93,8 +180,8 @@ pub enum EncodeError {
     #[error("FFmpeg not found")]
     FfmpegNotFound,
 
-    #[error("FFmpeg encoding failed with exit code: {0:?}")]
-    FfmpegFailed(Option<i32>),
+    #[error("FFmpeg encoding failed with exit code: {code:?}{}", stderr_suffix(.stderr))]
+    FfmpegFailed { code: Option<i32>, stderr: String },
 
     #[error("Unsupported output format: {0}")]
     UnsupportedFormat(String),
@@ -103,6 +190,13 @@ pub enum EncodeError {
     Io(#[from] std::io::Error),
 }
 
+impl EncodeError {
+    /// [`EncodeError::FfmpegFailed`] for a finished FFmpeg run
+    pub(crate) fn failed(output: &Output) -> Self {
+        EncodeError::FfmpegFailed { code: output.status.code(), stderr: failure_stderr(output) }
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum MetadataError {
     #[error("FFmpeg not found")]
@@ -114,6 +208,12 @@ pub enum MetadataError {
     #[error("Missing metadata: {0}")]
     MissingMetadata(String),
 
+    #[error("Unsupported ID3 version: {0} (expected 2.3 or 2.4)")]
+    UnsupportedId3Version(String),
+
+    #[error("Unknown overwrite policy: {0} (expected skip, overwrite or rename)")]
+    UnknownOverwritePolicy(String),
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 }
@@ -126,6 +226,56 @@ pub enum ConfigError {
     #[error("Invalid config value: {0}")]
     InvalidValue(String),
 
+    #[error("Unknown config key: {0}")]
+    UnknownKey(String),
+
+    #[error("No profile named \"{name}\" in the config ({})", profile_list(.available))]
+    UnknownProfile { name: String, available: Vec<String> },
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 }
+
+impl ConfigError {
+    /// Exit code for configuration failures, also when raised outside a
+    /// pipeline (see [`YtAudioError::exit_code`])
+    pub const EXIT_CODE: i32 = 20;
+}
+
+fn profile_list(available: &[String]) -> String {
+    if available.is_empty() {
+        "it defines no [profiles.<name>] tables".to_string()
+    } else {
+        format!("available: {}", available.join(", "))
+    }
+}
+
+/// The end of FFmpeg's stderr on its own lines after an error message, or
+/// nothing when FFmpeg printed nothing
+fn stderr_suffix(stderr: &str) -> String {
+    if stderr.is_empty() {
+        String::new()
+    } else {
+        format!("\n{}", stderr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ffmpeg_failed_display() {
+        let error = DecodeError::FfmpegFailed {
+            code: Some(1),
+            stderr: "Invalid data found when processing input".to_string(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "FFmpeg failed with exit code: Some(1)\nInvalid data found when processing input"
+        );
+
+        let error = EncodeError::FfmpegFailed { code: None, stderr: String::new() };
+        assert_eq!(error.to_string(), "FFmpeg encoding failed with exit code: None");
+    }
+}
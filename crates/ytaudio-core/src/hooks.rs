@@ -0,0 +1,166 @@
+//! User commands run after each item, e.g. to import it into a library
+//!
+//! A hook is a command template run through `sh -c`. `{output}`, `{url}`,
+//! `{title}` and `{error}` are replaced with the item's values, quoted for
+//! the shell; other braces are left alone. Hooks need a POSIX shell: `cmd`
+//! has no quoting that keeps arbitrary values literal, so they don't run on
+//! Windows.
+
+use crate::config::HooksConfig;
+use crate::process::log_command;
+use std::path::Path;
+use std::process::ExitStatus;
+use tokio::process::Command;
+use tracing::{info, warn};
+
+/// Values substituted into a hook template
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HookContext<'a> {
+    /// Primary output file; empty after a failure
+    pub output: Option<&'a Path>,
+    /// Input URL, or the path of a local file
+    pub url: &'a str,
+    /// Title as tagged; empty when the run failed before it was known
+    pub title: Option<&'a str>,
+    /// Error message; empty after a success
+    pub error: Option<&'a str>,
+}
+
+/// Replace the placeholders in `template`, each value quoted for the shell.
+/// One pass over the template, so placeholders inside a substituted value
+/// stay literal.
+pub fn render_hook(template: &str, context: &HookContext) -> String {
+    let output = context.output.map(|path| path.to_string_lossy());
+
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        rendered.push_str(&rest[..open]);
+        let after = &rest[open..];
+        let Some(close) = after.find('}') else {
+            break;
+        };
+        let value = match &after[1..close] {
+            "output" => Some(output.as_deref()),
+            "url" => Some(Some(context.url)),
+            "title" => Some(context.title),
+            "error" => Some(context.error),
+            _ => None,
+        };
+        match value {
+            Some(value) => rendered.push_str(&shell_quote(value.unwrap_or_default())),
+            None => rendered.push_str(&after[..=close]),
+        }
+        rest = &after[close + 1..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Run the rendered `template` through `sh` and wait for it. Always an
+/// `Unsupported` error on Windows.
+pub async fn run_hook(template: &str, context: &HookContext<'_>) -> std::io::Result<ExitStatus> {
+    if cfg!(windows) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "hooks need a POSIX shell and are not supported on Windows",
+        ));
+    }
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(render_hook(template, context));
+    log_command(&cmd);
+    cmd.stdin(std::process::Stdio::null()).status().await
+}
+
+/// Run the hook configured for how the item ended, if any. Failures are
+/// logged and never affect the item's result.
+pub async fn run_hooks(hooks: &HooksConfig, context: &HookContext<'_>) {
+    let (name, template) = match context.error {
+        None => ("post_success", &hooks.post_success),
+        Some(_) => ("post_failure", &hooks.post_failure),
+    };
+    let Some(template) = template else {
+        return;
+    };
+    info!("Running {} hook", name);
+    match run_hook(template, context).await {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!("hooks.{} exited with {}", name, status),
+        Err(e) => warn!("Failed to run hooks.{}: {}", name, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_hook() {
+        let context = HookContext {
+            output: Some(Path::new("/music/It's Here.flac")),
+            url: "https://youtu.be/abc",
+            title: Some("It's Here"),
+            error: None,
+        };
+        assert_eq!(
+            render_hook("beet import {output} # {title} from {url}", &context),
+            r"beet import '/music/It'\''s Here.flac' # 'It'\''s Here' from 'https://youtu.be/abc'"
+        );
+        // Missing values render empty; unknown placeholders are kept
+        assert_eq!(render_hook("notify {error} {artist}", &context), "notify '' {artist}");
+    }
+
+    #[test]
+    fn test_render_hook_single_pass() {
+        // A sanitized filename keeps braces, `$`, parentheses and quotes
+        let title = "x{title}{output}'$(echo INJECTED >&2)'";
+        let output = format!("/music/{}.flac", title);
+        let context = HookContext {
+            output: Some(Path::new(&output)),
+            url: "{error}",
+            title: Some(title),
+            error: None,
+        };
+        assert_eq!(
+            render_hook("cp {output} /lib # {title} {url}", &context),
+            r"cp '/music/x{title}{output}'\''$(echo INJECTED >&2)'\''.flac' /lib # 'x{title}{output}'\''$(echo INJECTED >&2)'\''' '{error}'"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_hook_quotes_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("marker");
+        let context = HookContext {
+            output: Some(&marker),
+            url: "https://youtu.be/abc",
+            title: Some("$(touch pwned); `echo`"),
+            error: None,
+        };
+        let status = run_hook("printf '%s' {title} > {output}", &context).await.unwrap();
+        assert!(status.success());
+        assert_eq!(std::fs::read_to_string(&marker).unwrap(), "$(touch pwned); `echo`");
+
+        // Placeholders in values are not expanded again
+        let title = "x{title}{output}'$(touch pwned)'";
+        let output = dir.path().join(format!("{}.txt", title));
+        let cwd = dir.path().to_string_lossy();
+        let context = HookContext {
+            output: Some(&output),
+            url: &cwd,
+            title: Some(title),
+            error: None,
+        };
+        let status = run_hook("cd {url} && printf '%s' {title} > {output}", &context)
+            .await
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), title);
+        assert!(!dir.path().join("pwned").exists());
+    }
+}
@@ -1,13 +1,19 @@
 //! ytaudio-core: Core pipeline for YouTube audio extraction with neural upscaling
 
+pub mod cancel;
 pub mod config;
 pub mod decoder;
 pub mod downloader;
 pub mod encoder;
 pub mod error;
+pub mod hooks;
 pub mod metadata;
 pub mod normalizer;
 pub mod pipeline;
+mod process;
+#[cfg(test)]
+mod test_support;
 
 pub use config::Config;
+pub use encoder::OutputFormat;
 pub use error::{YtAudioError, Result};
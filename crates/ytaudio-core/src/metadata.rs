@@ -1,19 +1,196 @@
 //! Metadata and artwork embedding using FFmpeg
 
-use crate::downloader::VideoMetadata;
+use crate::downloader::{Chapter, VideoMetadata};
+use crate::encoder::OutputFormat;
 use crate::error::MetadataError;
+use crate::normalizer::ReplayGain;
+use crate::process::{ffmpeg_loglevel, ffmpeg_status, probe_format_tags};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::LazyLock;
 use tokio::process::Command;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
+
+/// ID3v2 revision written to MP3 files
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Id3Version {
+    /// ID3v2.3, the revision older players and car head units read
+    #[default]
+    V2_3,
+    /// ID3v2.4, FFmpeg's own default
+    V2_4,
+}
+
+impl Id3Version {
+    /// FFmpeg `-id3v2_version` value
+    pub fn minor(&self) -> u8 {
+        match self {
+            Id3Version::V2_3 => 3,
+            Id3Version::V2_4 => 4,
+        }
+    }
+}
+
+impl FromStr for Id3Version {
+    type Err = MetadataError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "2.3" => Ok(Id3Version::V2_3),
+            "2.4" => Ok(Id3Version::V2_4),
+            _ => Err(MetadataError::UnsupportedId3Version(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for Id3Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "2.{}", self.minor())
+    }
+}
+
+/// What to do when an output file already exists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Keep the existing file and write nothing
+    Skip,
+    /// Replace the existing file
+    #[default]
+    Overwrite,
+    /// Write beside it as "name (1).ext", "name (2).ext", ...
+    Rename,
+}
+
+impl OverwritePolicy {
+    /// Where to write `path` under this policy, `None` to keep the file
+    /// already there
+    pub fn apply(&self, path: PathBuf) -> Option<PathBuf> {
+        if !path.exists() {
+            return Some(path);
+        }
+        match self {
+            OverwritePolicy::Skip => None,
+            OverwritePolicy::Overwrite => Some(path),
+            OverwritePolicy::Rename => Some(numbered_path(&path)),
+        }
+    }
+}
+
+impl FromStr for OverwritePolicy {
+    type Err = MetadataError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "skip" => Ok(OverwritePolicy::Skip),
+            "overwrite" => Ok(OverwritePolicy::Overwrite),
+            "rename" => Ok(OverwritePolicy::Rename),
+            _ => Err(MetadataError::UnknownOverwritePolicy(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for OverwritePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            OverwritePolicy::Skip => "skip",
+            OverwritePolicy::Overwrite => "overwrite",
+            OverwritePolicy::Rename => "rename",
+        })
+    }
+}
+
+/// First free path of the form "stem (n).ext", counting up from 1
+pub fn numbered_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let ext = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    (1..)
+        .map(|n| path.with_file_name(format!("{} ({}){}", stem, n, ext)))
+        .find(|candidate| !candidate.exists())
+        .expect("some numbered name is free")
+}
+
+/// Reserve `path`, or failing that the first free "stem (n).ext", by
+/// creating it empty with `create_new`. Parallel runs can't claim the same
+/// name, as a check for a free one followed by a write could.
+pub fn claim_free_path(path: &Path) -> std::io::Result<PathBuf> {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let ext = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    let candidates = std::iter::once(path.to_path_buf())
+        .chain((1..).map(|n| path.with_file_name(format!("{} ({}){}", stem, n, ext))));
+    for candidate in candidates {
+        match std::fs::OpenOptions::new().write(true).create_new(true).open(&candidate) {
+            Ok(_) => return Ok(candidate),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!("the numbered names never run out")
+}
+
+/// Size of the embedded spectrogram image, in pixels
+const SPECTROGRAM_SIZE: &str = "1024x512";
+
+/// Description tag of the embedded spectrogram picture
+const SPECTROGRAM_DESCRIPTION: &str = "Spectrogram";
 
 #[derive(Debug)]
 pub struct MetadataEmbedder {
     ffmpeg_path: PathBuf,
+    square_artwork: bool,
+    id3_version: Id3Version,
+    spectrogram: bool,
 }
 
 impl MetadataEmbedder {
     pub fn new(ffmpeg_path: PathBuf) -> Self {
-        Self { ffmpeg_path }
+        Self {
+            ffmpeg_path,
+            square_artwork: false,
+            id3_version: Id3Version::default(),
+            spectrogram: false,
+        }
+    }
+
+    /// ID3v2 revision for MP3 outputs (2.3 by default); other formats ignore it
+    pub fn with_id3_version(mut self, version: Id3Version) -> Self {
+        self.id3_version = version;
+        self
+    }
+
+    /// Center-crop the artwork to a square, as music players expect, instead
+    /// of embedding a 16:9 thumbnail as it is
+    pub fn with_square_artwork(mut self, square: bool) -> Self {
+        self.square_artwork = square;
+        self
+    }
+
+    /// Render a spectrogram of the audio and embed it as an extra picture
+    /// after the cover art. Only MP3 and FLAC hold more than one picture;
+    /// other formats are written without it.
+    pub fn with_spectrogram(mut self, spectrogram: bool) -> Self {
+        self.spectrogram = spectrogram;
+        self
+    }
+
+    /// Whether `output` gets a spectrogram picture
+    pub fn embeds_spectrogram(&self, output: &Path) -> bool {
+        self.spectrogram
+            && output
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| matches!(ext, "mp3" | "flac"))
+    }
+
+    /// Build the FFmpeg invocation rendering the spectrogram of `audio` to a PNG
+    pub fn spectrogram_command(&self, audio: &Path) -> Command {
+        let mut cmd = Command::new(&self.ffmpeg_path);
+        cmd.kill_on_drop(true);
+        cmd.args(["-hide_banner", "-loglevel", ffmpeg_loglevel()]);
+        cmd.arg("-i").arg(audio);
+        cmd.args(["-lavfi", &format!("showspectrumpic=s={}:legend=1", SPECTROGRAM_SIZE)]);
+        cmd.arg("-y").arg(spectrogram_path(audio));
+        cmd
     }
 
     /// Embed metadata and artwork into audio file
@@ -23,11 +200,51 @@ impl MetadataEmbedder {
         output: &Path,
         metadata: &VideoMetadata,
         artwork: Option<&Path>,
+        replaygain: Option<&ReplayGain>,
     ) -> Result<(), MetadataError> {
         info!("Embedding metadata: {}", metadata.title);
 
+        if !metadata.chapters.is_empty() {
+            debug!("Writing {} chapter markers", metadata.chapters.len());
+            tokio::fs::write(chapters_path(audio), render_chapters(&metadata.chapters)).await?;
+        }
+
+        if self.embeds_spectrogram(output) {
+            debug!("Rendering spectrogram");
+            let status = ffmpeg_status(&mut self.spectrogram_command(audio)).await?;
+            if !status.success() {
+                return Err(MetadataError::FfmpegFailed(status.code()));
+            }
+        } else if self.spectrogram {
+            warn!("{} holds a single picture, not embedding the spectrogram", output.display());
+        }
+
+        let status =
+            ffmpeg_status(&mut self.command(audio, output, metadata, artwork, replaygain)).await?;
+
+        if !status.success() {
+            return Err(MetadataError::FfmpegFailed(status.code()));
+        }
+
+        debug!("Embedded metadata to: {}", output.display());
+        Ok(())
+    }
+
+    /// Build the FFmpeg invocation used by [`MetadataEmbedder::embed`]
+    pub fn command(
+        &self,
+        audio: &Path,
+        output: &Path,
+        metadata: &VideoMetadata,
+        artwork: Option<&Path>,
+        replaygain: Option<&ReplayGain>,
+    ) -> Command {
         let mut cmd = Command::new(&self.ffmpeg_path);
-        cmd.args(["-hide_banner", "-loglevel", "error"]);
+        cmd.kill_on_drop(true);
+        cmd.args(["-hide_banner", "-loglevel", ffmpeg_loglevel()]);
+
+        // The WavPack muxer holds a single audio stream, so no cover art
+        let artwork = artwork.filter(|_| output.extension().is_none_or(|ext| ext != "wv"));
 
         // Input audio
         cmd.arg("-i").arg(audio);
@@ -35,9 +252,49 @@ impl MetadataEmbedder {
         // Input artwork if available
         if let Some(art) = artwork {
             cmd.arg("-i").arg(art);
-            cmd.args(["-map", "0:a", "-map", "1:v"]);
-            cmd.args(["-c:v", "mjpeg"]);
-            cmd.args(["-disposition:v", "attached_pic"]);
+        }
+
+        // Spectrogram rendered by `embed`
+        let spectrogram = self.embeds_spectrogram(output);
+        if spectrogram {
+            cmd.arg("-i").arg(spectrogram_path(audio));
+        }
+
+        // Chapter markers, from the ffmetadata file written by `embed`
+        let has_chapters = !metadata.chapters.is_empty();
+        if has_chapters {
+            cmd.args(["-f", "ffmetadata", "-i"]).arg(chapters_path(audio));
+        }
+
+        if artwork.is_some() || spectrogram {
+            cmd.args(["-map", "0:a"]);
+        }
+        if artwork.is_some() {
+            cmd.args(["-map", "1:v"]);
+            cmd.args(["-c:v:0", "mjpeg"]);
+            if self.square_artwork {
+                // crop centers the window by default
+                cmd.args(["-filter:v:0", "crop='min(iw,ih)':'min(iw,ih)'"]);
+            }
+            cmd.args(["-disposition:v:0", "attached_pic"]);
+        }
+        if spectrogram {
+            // A second picture after the cover, typed "Other" with a
+            // description so players don't take it for the cover
+            let (input, stream) = if artwork.is_some() { (2, 1) } else { (1, 0) };
+            cmd.args(["-map", &format!("{}:v", input)]);
+            cmd.args([&format!("-c:v:{}", stream), "png"]);
+            cmd.args([&format!("-disposition:v:{}", stream), "attached_pic"]);
+            cmd.args([
+                &format!("-metadata:s:v:{}", stream),
+                &format!("title={}", SPECTROGRAM_DESCRIPTION),
+            ]);
+            cmd.args([&format!("-metadata:s:v:{}", stream), "comment=Other"]);
+        }
+
+        if has_chapters {
+            let index = 1 + artwork.is_some() as usize + spectrogram as usize;
+            cmd.args(["-map_chapters", &index.to_string()]);
         }
 
         // Add metadata tags
@@ -50,10 +307,22 @@ impl MetadataEmbedder {
             cmd.args(["-metadata", &format!("artist={}", uploader)]);
         }
 
-        if let Some(ref album) = metadata.album {
+        // Playlist entries share the playlist title as album and are
+        // numbered by position, unless the video says otherwise
+        if let Some(album) = metadata.album.as_ref().or(metadata.playlist_title.as_ref()) {
             cmd.args(["-metadata", &format!("album={}", album)]);
         }
 
+        match (metadata.track, metadata.playlist_index, metadata.playlist_count) {
+            (Some((number, total)), _, _) | (None, Some(number), Some(total)) => {
+                cmd.args(["-metadata", &format!("track={}/{}", number, total)]);
+            }
+            (None, Some(number), None) => {
+                cmd.args(["-metadata", &format!("track={}", number)]);
+            }
+            (None, None, _) => {}
+        }
+
         if let Some(ref date) = metadata.upload_date {
             // YouTube date format is YYYYMMDD, convert to YYYY-MM-DD
             let formatted_date = if date.len() == 8 {
@@ -65,61 +334,288 @@ impl MetadataEmbedder {
         }
 
         // Add comment with video ID for reference
-        cmd.args(["-metadata", &format!("comment=YouTube: {}", metadata.id)]);
+        if !metadata.id.is_empty() {
+            cmd.args(["-metadata", &format!("comment=YouTube: {}", metadata.id)]);
+        }
 
-        // Copy audio codec (no re-encoding)
-        cmd.args(["-c:a", "copy"]);
+        // Provenance: the video page, and the channel that published it
+        if let Some(url) = metadata.source_url() {
+            cmd.args(["-metadata", &format!("purl={}", url)]);
+            cmd.args(["-metadata", &format!("source={}", url)]);
+        }
+        if let Some(ref uploader) = metadata.uploader {
+            cmd.args(["-metadata", &format!("organization={}", uploader)]);
+        }
+        if let Some(ref channel_url) = metadata.channel_url {
+            cmd.args(["-metadata", &format!("channel_url={}", channel_url)]);
+        }
 
-        cmd.arg("-y").arg(output);
+        if let Some(ref lyrics) = metadata.lyrics {
+            // Vorbis comments use the upper-case field name by convention;
+            // the ID3 and MP4 muxers map the lower-case key to their lyrics frame
+            let key = match output.extension().and_then(|ext| ext.to_str()) {
+                Some("flac" | "opus" | "ogg") => "LYRICS",
+                _ => "lyrics",
+            };
+            cmd.args(["-metadata", &format!("{}={}", key, lyrics)]);
+        }
 
-        let status = cmd.status().await?;
+        // Opus players read R128 gains relative to -23 LUFS, without peaks
+        let opus = output.extension().is_some_and(|ext| ext == "opus");
+        if let Some(rg) = replaygain.filter(|_| opus) {
+            if let Some(gain) = rg.r128_track_gain_tag() {
+                cmd.args(["-metadata", &format!("R128_TRACK_GAIN={}", gain)]);
+            }
+            if let Some(gain) = rg.r128_album_gain_tag() {
+                cmd.args(["-metadata", &format!("R128_ALBUM_GAIN={}", gain)]);
+            }
+        } else if let Some(rg) = replaygain {
+            if let Some(gain) = rg.gain_tag() {
+                cmd.args(["-metadata", &format!("REPLAYGAIN_TRACK_GAIN={}", gain)]);
+            }
+            cmd.args(["-metadata", &format!("REPLAYGAIN_TRACK_PEAK={}", rg.peak_tag())]);
+            if let Some(gain) = rg.album_gain_tag() {
+                cmd.args(["-metadata", &format!("REPLAYGAIN_ALBUM_GAIN={}", gain)]);
+            }
+            if let Some(peak) = rg.album_peak_tag() {
+                cmd.args(["-metadata", &format!("REPLAYGAIN_ALBUM_PEAK={}", peak)]);
+            }
+        }
 
-        if !status.success() {
-            return Err(MetadataError::FfmpegFailed(status.code()));
+        // The MP4 muxer drops tags outside its fixed atom set (source,
+        // ReplayGain) unless asked to keep them
+        if output.extension().is_some_and(|ext| ext == "m4a") {
+            cmd.args(["-movflags", "+use_metadata_tags"]);
         }
 
-        debug!("Embedded metadata to: {}", output.display());
-        Ok(())
+        if output.extension().is_some_and(|ext| ext == "mp3") {
+            cmd.args(["-id3v2_version", &self.id3_version.minor().to_string()]);
+        }
+
+        // Copy audio codec (no re-encoding)
+        cmd.args(["-c:a", "copy"]);
+
+        cmd.arg("-y").arg(output);
+        cmd
     }
 
-    /// Embed metadata only (no artwork) with simple approach
+    /// Embed metadata without artwork: the same tags, chapters and lyrics as
+    /// [`MetadataEmbedder::embed`], for players that choke on cover art
     pub async fn embed_simple(
         &self,
         input: &Path,
         output: &Path,
         metadata: &VideoMetadata,
+        replaygain: Option<&ReplayGain>,
     ) -> Result<(), MetadataError> {
-        let mut cmd = Command::new(&self.ffmpeg_path);
-        cmd.args(["-hide_banner", "-loglevel", "error"]);
-        cmd.arg("-i").arg(input);
+        self.embed(input, output, metadata, None, replaygain).await
+    }
+}
 
-        // Add metadata
-        cmd.args(["-metadata", &format!("title={}", metadata.title)]);
+/// Sidecar ffmetadata file holding the chapters for an intermediate audio file
+fn chapters_path(audio: &Path) -> PathBuf {
+    audio.with_extension("chapters.txt")
+}
 
-        if let Some(ref artist) = metadata.artist.as_ref().or(metadata.uploader.as_ref()) {
-            cmd.args(["-metadata", &format!("artist={}", artist)]);
+/// Sidecar spectrogram image for an intermediate audio file
+fn spectrogram_path(audio: &Path) -> PathBuf {
+    audio.with_extension("spectrogram.png")
+}
+
+/// Render chapters as an FFmpeg metadata file (`-f ffmetadata`)
+pub fn render_chapters(chapters: &[Chapter]) -> String {
+    let mut out = String::from(";FFMETADATA1\n");
+    for chapter in chapters {
+        out.push_str("[CHAPTER]\nTIMEBASE=1/1000\n");
+        out.push_str(&format!("START={}\n", (chapter.start_time * 1000.0).round() as u64));
+        out.push_str(&format!("END={}\n", (chapter.end_time * 1000.0).round() as u64));
+        out.push_str(&format!("title={}\n", escape_ffmetadata(&chapter.title)));
+    }
+    out
+}
+
+/// Escape the characters ffmetadata treats as syntax
+fn escape_ffmetadata(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
         }
+        escaped.push(c);
+    }
+    escaped
+}
 
-        if let Some(ref date) = metadata.upload_date {
-            let formatted_date = if date.len() == 8 {
-                format!("{}-{}-{}", &date[0..4], &date[4..6], &date[6..8])
-            } else {
-                date.clone()
-            };
-            cmd.args(["-metadata", &format!("date={}", formatted_date)]);
+/// Read a subtitle file fetched by the downloader and convert it with
+/// [`subtitles_to_lyrics`]. `None`, with a warning, when it cannot be read.
+pub async fn load_lyrics(subtitles: &Path, timed: bool) -> Option<String> {
+    match tokio::fs::read_to_string(subtitles).await {
+        Ok(text) => subtitles_to_lyrics(&text, timed),
+        Err(e) => {
+            warn!("Cannot read subtitles {}: {}", subtitles.display(), e);
+            None
         }
+    }
+}
 
-        cmd.args(["-metadata", &format!("comment=YouTube: {}", metadata.id)]);
-        cmd.args(["-c:a", "copy"]);
-        cmd.arg("-y").arg(output);
+/// Convert WebVTT or SRT subtitles to lyrics: LRC (`[mm:ss.xx]line`) when
+/// `timed`, otherwise plain text. Formatting tags are stripped, and lines
+/// repeated from the previous cue (the rolling display of YouTube's automatic
+/// captions) are dropped. `None` when no text remains.
+pub fn subtitles_to_lyrics(subtitles: &str, timed: bool) -> Option<String> {
+    let mut out = String::new();
+    let mut previous: Vec<String> = Vec::new();
 
-        let status = cmd.status().await?;
+    for cue in subtitles.replace("\r\n", "\n").split("\n\n") {
+        let mut lines = cue.lines().skip_while(|l| !l.contains("-->"));
+        let Some(start) = lines
+            .next()
+            .and_then(|timing| parse_cue_time(timing.split("-->").next()?))
+        else {
+            continue;
+        };
 
-        if !status.success() {
-            return Err(MetadataError::FfmpegFailed(status.code()));
+        let text: Vec<String> = lines.map(strip_cue_markup).filter(|l| !l.is_empty()).collect();
+        for line in &text {
+            if previous.contains(line) {
+                continue;
+            }
+            if timed {
+                let centis = (start * 100.0).round() as u64;
+                out.push_str(&format!(
+                    "[{:02}:{:02}.{:02}]",
+                    centis / 6000,
+                    centis / 100 % 60,
+                    centis % 100
+                ));
+            }
+            out.push_str(line);
+            out.push('\n');
         }
+        previous = text;
+    }
 
-        Ok(())
+    (!out.is_empty()).then(|| out.trim_end().to_string())
+}
+
+/// Seconds from a cue timestamp: `HH:MM:SS.mmm` or `MM:SS.mmm` (SRT uses `,`)
+fn parse_cue_time(value: &str) -> Option<f64> {
+    let value = value.trim().replace(',', ".");
+    value
+        .split(':')
+        .try_fold(0.0, |total, part| Some(total * 60.0 + part.parse::<f64>().ok()?))
+}
+
+/// Cue text without `<...>` tags and with the common entities decoded
+fn strip_cue_markup(line: &str) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut in_tag = false;
+    for c in line.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
+
+/// Words that describe the upload rather than the recording. A bracketed
+/// group, or a `|` / ` - ` separated part, made only of these is dropped.
+const TITLE_NOISE: &[&str] = &[
+    "official", "music", "video", "audio", "lyric", "lyrics", "letra", "visualizer", "visualiser",
+    "videoclip", "clip", "oficial", "officiel", "vídeo", "mv", "hd", "hq", "uhd", "4k", "8k",
+    "1080p", "720p", "60fps",
+];
+
+/// Any `(...)`, `[...]` or `【...】` group
+static BRACKETED: LazyLock<regex::Regex> =
+    LazyLock::new(|| regex::Regex::new(r"\s*[(\[【]([^()\[\]【】]*)[)\]】]").expect("valid regex"));
+
+/// Tags left bare at the end, like "Title 4K" or "Title Official MV". Other
+/// noise words only go in brackets: "Audio" may well be the song.
+static TRAILING_NOISE: LazyLock<regex::Regex> = LazyLock::new(|| {
+    regex::Regex::new(
+        r"(?i)(\s+(official(\s+(music|lyrics?|audio|video|mv|m/v|visuali[sz]er))*|4k|8k|uhd|hd|hq|1080p|720p))+$",
+    )
+    .expect("valid regex")
+});
+
+/// A video title made fit for tagging by [`clean_title`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CleanTitle {
+    /// The part before " - ", when the title reads "Artist - Title"
+    pub artist: Option<String>,
+    pub title: String,
+}
+
+/// Strip the decorations YouTube titles carry ("(Official Music Video)",
+/// "[Lyrics]", "(HD)", a trailing "4K") and split "Artist - Title" at the
+/// first dash. Anything else in brackets ("(feat. X)", "(Live)",
+/// "(Remastered 2011)") is kept. A title that would end up empty is only
+/// trimmed.
+pub fn clean_title(title: &str) -> CleanTitle {
+    let is_noise = |text: &str| {
+        let text = text.to_lowercase().replace("m/v", "mv");
+        let mut words = text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .peekable();
+        words.peek().is_some() && words.all(|w| TITLE_NOISE.contains(&w))
+    };
+
+    let stripped = BRACKETED.replace_all(title, |caps: &regex::Captures| {
+        if is_noise(&caps[1]) { String::new() } else { caps[0].to_string() }
+    });
+    let stripped = stripped.replace(['–', '—'], "-").replace(" -- ", " - ");
+
+    // "Artist - Title | Official Video", "Title - Official Audio"
+    let parts: Vec<String> = stripped
+        .split(" | ")
+        .flat_map(|part| part.split(" - "))
+        .map(|part| {
+            TRAILING_NOISE
+                .replace(part.trim(), "")
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .filter(|part| !part.is_empty() && !is_noise(part))
+        .collect();
+
+    // 'Artist - "Title"'
+    let unquote = |s: &str| {
+        [('"', '"'), ('“', '”'), ('\'', '\'')]
+            .iter()
+            .find_map(|&(open, close)| {
+                s.strip_prefix(open)?
+                    .strip_suffix(close)
+                    .filter(|inner| !inner.trim().is_empty())
+            })
+            .unwrap_or(s)
+            .trim()
+            .to_string()
+    };
+
+    match parts.as_slice() {
+        [] => CleanTitle {
+            artist: None,
+            title: title.trim().to_string(),
+        },
+        [title] => CleanTitle {
+            artist: None,
+            title: unquote(title),
+        },
+        [artist, rest @ ..] => CleanTitle {
+            artist: Some(artist.clone()),
+            title: unquote(&rest.join(" - ")),
+        },
     }
 }
 
@@ -135,9 +631,148 @@ pub fn sanitize_filename(name: &str) -> String {
         .to_string()
 }
 
+/// Output filename template used when none is configured
+pub const DEFAULT_FILENAME_TEMPLATE: &str = "{title}";
+
+/// Check that a filename template stays inside the output directory
+pub fn is_valid_filename_template(template: &str) -> bool {
+    !template.trim().is_empty()
+        && !template.starts_with(['/', '\\'])
+        && template.split('/').all(|part| part != "." && part != "..")
+}
+
+/// Metadata for a local audio file: the title, artist, album, date and track
+/// tags and the audio codec ffprobe finds in it, with the file name as the
+/// fallback title. The ID stays empty, so no YouTube reference is tagged.
+/// Without ffprobe only the file name is used.
+pub async fn local_file_metadata(ffmpeg_path: &Path, path: &Path) -> VideoMetadata {
+    let probe = probe_format_tags(ffmpeg_path, path).await.unwrap_or_else(|e| {
+        warn!("Cannot read tags of {}: {}", path.display(), e);
+        String::new()
+    });
+    metadata_from_probe(path, &probe)
+}
+
+fn metadata_from_probe(path: &Path, probe: &str) -> VideoMetadata {
+    let mut tags = HashMap::new();
+    let mut duration = None;
+    let mut acodec = None;
+    let mut abr = None;
+    for line in probe.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.strip_prefix("TAG:") {
+            // ID3 keys come back lowercase, Vorbis comments often uppercase
+            Some(tag) if !value.is_empty() => {
+                tags.entry(tag.to_ascii_lowercase()).or_insert_with(|| value.to_string());
+            }
+            None if key == "duration" => duration = value.parse().ok(),
+            None if key == "codec_name" && !value.is_empty() => acodec = Some(value.to_string()),
+            // The stream's rate comes first; the container's stands in when it is N/A
+            None if key == "bit_rate" => {
+                abr = abr.or(value.parse::<f64>().ok().map(|bps| bps / 1000.0))
+            }
+            _ => {}
+        }
+    }
+
+    // "3/12" keeps the total; a bare "3" is written back as the track number alone
+    let (track, playlist_index) = match tags.get("track").map(|t| t.split_once('/')) {
+        Some(Some((number, total))) => (
+            number.trim().parse().ok().zip(total.trim().parse().ok()),
+            None,
+        ),
+        Some(None) => (None, tags["track"].trim().parse().ok()),
+        None => (None, None),
+    };
+
+    VideoMetadata {
+        title: tags.remove("title").unwrap_or_else(|| {
+            path.file_stem().unwrap_or_default().to_string_lossy().into_owned()
+        }),
+        artist: tags.remove("artist").or_else(|| tags.remove("album_artist")),
+        album: tags.remove("album"),
+        upload_date: tags.remove("date"),
+        duration,
+        ext: path.extension().unwrap_or_default().to_string_lossy().into_owned(),
+        acodec,
+        abr,
+        playlist_index,
+        track,
+        ..Default::default()
+    }
+}
+
+/// Expand a filename template into a path relative to the output directory.
+///
+/// Placeholders: `{title}`, `{artist}`, `{uploader}`, `{id}`, `{date}`
+/// (YYYY-MM-DD) and `{ext}`. `title` is passed separately because chapter
+/// tracks use a composed name. Missing fields expand to an empty string and
+/// unknown placeholders are kept literally. `/` starts a subdirectory, and
+/// components that end up empty are dropped. `.{ext}` is appended when the
+/// template does not place it.
+pub fn render_filename_template(
+    template: &str,
+    title: &str,
+    metadata: &VideoMetadata,
+    format: OutputFormat,
+) -> PathBuf {
+    let date = metadata.upload_date.as_deref().map(|d| match d.len() {
+        8 if d.bytes().all(|b| b.is_ascii_digit()) => {
+            format!("{}-{}-{}", &d[..4], &d[4..6], &d[6..])
+        }
+        _ => d.to_string(),
+    });
+
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        rendered.push_str(&rest[..open]);
+        let after = &rest[open..];
+        let Some(close) = after.find('}') else {
+            break;
+        };
+        let value = match &after[1..close] {
+            "title" => Some(title),
+            "artist" => Some(metadata.artist.as_deref().unwrap_or_default()),
+            "uploader" => Some(metadata.uploader.as_deref().unwrap_or_default()),
+            "id" => Some(metadata.id.as_str()),
+            "date" => Some(date.as_deref().unwrap_or_default()),
+            "ext" => Some(format.extension()),
+            _ => None,
+        };
+        match value {
+            Some(value) => rendered.push_str(&sanitize_filename(value)),
+            None => rendered.push_str(&after[..=close]),
+        }
+        rest = &after[close + 1..];
+    }
+    rendered.push_str(rest);
+
+    let mut path: PathBuf = rendered
+        .split('/')
+        .map(str::trim)
+        .filter(|part| !part.is_empty() && *part != "." && *part != "..")
+        .collect();
+
+    if path.as_os_str().is_empty() {
+        path.push(sanitize_filename(&metadata.id));
+    }
+    if !template.contains("{ext}") {
+        let mut name = path.into_os_string();
+        name.push(".");
+        name.push(format.extension());
+        path = PathBuf::from(name);
+    }
+    path
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::args;
 
     #[test]
     fn test_sanitize_filename() {
@@ -145,4 +780,422 @@ mod tests {
         assert_eq!(sanitize_filename("Title/With:Special*Chars"), "Title_With_Special_Chars");
         assert_eq!(sanitize_filename("  Spaces  "), "Spaces");
     }
+
+    #[test]
+    fn test_clean_title() {
+        let clean = |title: &str| {
+            let cleaned = clean_title(title);
+            (cleaned.artist, cleaned.title)
+        };
+        let split = |artist: &str, title: &str| (Some(artist.to_string()), title.to_string());
+        let whole = |title: &str| (None, title.to_string());
+
+        assert_eq!(
+            clean("Rick Astley - Never Gonna Give You Up (Official Music Video)"),
+            split("Rick Astley", "Never Gonna Give You Up")
+        );
+        assert_eq!(clean("Adele - Hello [Lyrics]"), split("Adele", "Hello"));
+        assert_eq!(clean("Some Song (HD)"), whole("Some Song"));
+        assert_eq!(clean("Kendrick Lamar - HUMBLE. 4K"), split("Kendrick Lamar", "HUMBLE."));
+        assert_eq!(clean("Song Title HD 1080p"), whole("Song Title"));
+        assert_eq!(
+            clean("Artist - Title (feat. Someone) [Official Video] (HD)"),
+            split("Artist", "Title (feat. Someone)")
+        );
+        assert_eq!(
+            clean("AC/DC - Back In Black (Official 4K Video)"),
+            split("AC/DC", "Back In Black")
+        );
+        assert_eq!(clean("Jay-Z - 99 Problems"), split("Jay-Z", "99 Problems"));
+        assert_eq!(clean("Artist - Title | Official Music Video"), split("Artist", "Title"));
+        assert_eq!(clean("Title - Official Audio"), whole("Title"));
+        assert_eq!(
+            clean("Queen – Bohemian Rhapsody (Official Video) [M/V]"),
+            split("Queen", "Bohemian Rhapsody")
+        );
+        assert_eq!(
+            clean("Artist — Title (Lyric Video)"),
+            split("Artist", "Title")
+        );
+        assert_eq!(
+            clean("Artist - \"Quoted Title\" (Official Lyric Video)"),
+            split("Artist", "Quoted Title")
+        );
+        assert_eq!(
+            clean("BTS (방탄소년단) 'Dynamite' Official MV"),
+            whole("BTS (방탄소년단) 'Dynamite'")
+        );
+        assert_eq!(
+            clean("Artista - Canción (Video Oficial)"),
+            split("Artista", "Canción")
+        );
+        assert_eq!(clean("Artist - Song【MV】"), split("Artist", "Song"));
+        assert_eq!(clean("DJ Mix - Part 1 - Part 2"), split("DJ Mix", "Part 1 - Part 2"));
+
+        // Musical information in brackets stays
+        assert_eq!(
+            clean("Artist - Title (Live at Wembley)"),
+            split("Artist", "Title (Live at Wembley)")
+        );
+        assert_eq!(
+            clean("Queen - Bohemian Rhapsody (Remastered 2011)"),
+            split("Queen", "Bohemian Rhapsody (Remastered 2011)")
+        );
+        assert_eq!(
+            clean("Artist - Title (Radio Edit) [Official Audio]"),
+            split("Artist", "Title (Radio Edit)")
+        );
+
+        // Nothing to do, or nothing would be left
+        assert_eq!(clean("Just A Title"), whole("Just A Title"));
+        assert_eq!(clean("Hyphen-Ated-Title"), whole("Hyphen-Ated-Title"));
+        assert_eq!(clean("  (Official Video)  "), whole("(Official Video)"));
+    }
+
+    #[test]
+    fn test_overwrite_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Song.flac");
+        for policy in [OverwritePolicy::Skip, OverwritePolicy::Overwrite, OverwritePolicy::Rename] {
+            assert_eq!(policy.apply(path.clone()), Some(path.clone()));
+        }
+
+        std::fs::write(&path, b"").unwrap();
+        std::fs::write(dir.path().join("Song (1).flac"), b"").unwrap();
+        assert_eq!(OverwritePolicy::Skip.apply(path.clone()), None);
+        assert_eq!(
+            OverwritePolicy::Overwrite.apply(path.clone()),
+            Some(path.clone())
+        );
+        assert_eq!(
+            OverwritePolicy::Rename.apply(path.clone()),
+            Some(dir.path().join("Song (2).flac"))
+        );
+
+        // Claiming creates the file, so the next claim moves on
+        assert_eq!(claim_free_path(&path).unwrap(), dir.path().join("Song (2).flac"));
+        assert_eq!(claim_free_path(&path).unwrap(), dir.path().join("Song (3).flac"));
+        assert!(dir.path().join("Song (3).flac").exists());
+        let fresh = dir.path().join("Other.opus");
+        assert_eq!(claim_free_path(&fresh).unwrap(), fresh);
+        assert_eq!(claim_free_path(&fresh).unwrap(), dir.path().join("Other (1).opus"));
+        assert!(claim_free_path(&dir.path().join("missing/Song.flac")).is_err());
+
+        assert_eq!("Rename".parse::<OverwritePolicy>().unwrap(), OverwritePolicy::Rename);
+        assert!("replace".parse::<OverwritePolicy>().is_err());
+    }
+
+    #[test]
+    fn test_render_filename_template() {
+        let metadata = VideoMetadata {
+            id: "abc123".to_string(),
+            title: "Song: Live".to_string(),
+            artist: Some("AC/DC".to_string()),
+            album: None,
+            uploader: Some("Channel".to_string()),
+            upload_date: Some("20240131".to_string()),
+            duration: None,
+            description: None,
+            ext: "webm".to_string(),
+            ..Default::default()
+        };
+        let render = |template| {
+            render_filename_template(template, &metadata.title, &metadata, OutputFormat::Flac)
+        };
+
+        assert_eq!(render("{title}"), PathBuf::from("Song_ Live.flac"));
+        assert_eq!(render("{artist} - {title}"), PathBuf::from("AC_DC - Song_ Live.flac"));
+        assert_eq!(render("{date}/{title}.{ext}"), PathBuf::from("2024-01-31/Song_ Live.flac"));
+        assert_eq!(render("{uploader}/{album}/{id}"), PathBuf::from("Channel/{album}/abc123.flac"));
+
+        let bare = VideoMetadata { artist: None, upload_date: None, ..metadata.clone() };
+        assert_eq!(
+            render_filename_template("{date}/{artist}", "Song", &bare, OutputFormat::Mp3),
+            PathBuf::from("abc123.mp3")
+        );
+    }
+
+    #[test]
+    fn test_is_valid_filename_template() {
+        assert!(is_valid_filename_template("{date}/{title}"));
+        assert!(!is_valid_filename_template(""));
+        assert!(!is_valid_filename_template("/tmp/{title}"));
+        assert!(!is_valid_filename_template("../{title}"));
+    }
+
+    #[test]
+    fn test_render_chapters() {
+        let chapters = vec![
+            Chapter { start_time: 0.0, end_time: 65.25, title: "Intro".to_string() },
+            Chapter { start_time: 65.25, end_time: 200.0, title: "A=B; #1".to_string() },
+        ];
+        assert_eq!(
+            render_chapters(&chapters),
+            ";FFMETADATA1\n\
+             [CHAPTER]\nTIMEBASE=1/1000\nSTART=0\nEND=65250\ntitle=Intro\n\
+             [CHAPTER]\nTIMEBASE=1/1000\nSTART=65250\nEND=200000\ntitle=A\\=B\\; \\#1\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_embed_passes_chapters_file() {
+        use crate::test_support::{arg_after, StubFfmpeg};
+
+        let stub = StubFfmpeg::new();
+        let audio = stub.dir().join("encoded.flac");
+        let metadata = VideoMetadata {
+            title: "Mix".to_string(),
+            chapters: vec![Chapter { title: "Intro".to_string(), start_time: 0.0, end_time: 12.5 }],
+            ..Default::default()
+        };
+        MetadataEmbedder::new(stub.path())
+            .embed_simple(&audio, &stub.dir().join("Mix.flac"), &metadata, None)
+            .await
+            .unwrap();
+
+        let chapters = chapters_path(&audio);
+        assert_eq!(
+            std::fs::read_to_string(&chapters).unwrap(),
+            render_chapters(&metadata.chapters)
+        );
+        let calls = stub.calls();
+        assert_eq!(calls.len(), 1);
+        assert!(calls[0]
+            .windows(4)
+            .any(|w| w == ["-f", "ffmetadata", "-i", chapters.to_str().unwrap()]));
+        assert_eq!(arg_after(&calls[0], "-map_chapters"), Some("1"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_embed_failure() {
+        use crate::test_support::StubFfmpeg;
+
+        let stub = StubFfmpeg::new().with_exit_code(1);
+        let metadata = VideoMetadata { title: "Song".to_string(), ..Default::default() };
+        let err = MetadataEmbedder::new(stub.path())
+            .embed_simple(
+                &stub.dir().join("encoded.mp3"),
+                &stub.dir().join("Song.mp3"),
+                &metadata,
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, MetadataError::FfmpegFailed(Some(1))), "{:?}", err);
+    }
+
+    #[test]
+    fn test_command_writes_provenance() {
+        let embedder = MetadataEmbedder::new(PathBuf::from("ffmpeg"));
+        let metadata = VideoMetadata {
+            id: "abc123".to_string(),
+            title: "Song".to_string(),
+            uploader: Some("Some Channel".to_string()),
+            channel_url: Some("https://www.youtube.com/channel/UC123".to_string()),
+            ..Default::default()
+        };
+        let args = args(&embedder.command(
+            Path::new("in.m4a"),
+            Path::new("out.m4a"),
+            &metadata,
+            None,
+            None,
+        ));
+
+        assert!(args.contains(&"source=https://youtube.com/watch?v=abc123".to_string()));
+        assert!(args.contains(&"purl=https://youtube.com/watch?v=abc123".to_string()));
+        assert!(args.contains(&"organization=Some Channel".to_string()));
+        assert!(args.contains(&"channel_url=https://www.youtube.com/channel/UC123".to_string()));
+        assert!(args.windows(2).any(|w| w == ["-movflags", "+use_metadata_tags"]));
+    }
+
+    #[test]
+    fn test_command_writes_album_replaygain() {
+        use crate::normalizer::AlbumGain;
+
+        let embedder = MetadataEmbedder::new(PathBuf::from("ffmpeg"));
+        let album = AlbumGain { integrated_lufs: -12.0, gain: -2.0, peak: 0.95, tracks: 3 };
+        let replaygain = ReplayGain::from_measurement(-15.0, -1.0).with_album(&album);
+        let tagged = |output: &str| {
+            let metadata = VideoMetadata::default();
+            args(&embedder.command(
+                Path::new("in.flac"),
+                Path::new(output),
+                &metadata,
+                None,
+                Some(&replaygain),
+            ))
+        };
+
+        let flac = tagged("out.flac");
+        assert!(flac.contains(&"REPLAYGAIN_TRACK_GAIN=-3.00 dB".to_string()));
+        assert!(flac.contains(&"REPLAYGAIN_ALBUM_GAIN=-6.00 dB".to_string()));
+        assert!(flac.contains(&"REPLAYGAIN_ALBUM_PEAK=0.950000".to_string()));
+
+        let opus = tagged("out.opus");
+        assert!(opus.contains(&"R128_TRACK_GAIN=-2048".to_string()), "{:?}", opus);
+        assert!(opus.contains(&"R128_ALBUM_GAIN=-2816".to_string()), "{:?}", opus);
+        assert!(!opus.iter().any(|a| a.starts_with("REPLAYGAIN_")), "{:?}", opus);
+    }
+
+    #[test]
+    fn test_command_square_artwork() {
+        let metadata = VideoMetadata { title: "Song".to_string(), ..Default::default() };
+        let args = |embedder: MetadataEmbedder, output: &str| {
+            let cover = Some(Path::new("cover.jpg"));
+            args(&embedder.command(Path::new("in.flac"), Path::new(output), &metadata, cover, None))
+        };
+        let crop = ["-filter:v:0", "crop='min(iw,ih)':'min(iw,ih)'"];
+
+        let square = args(
+            MetadataEmbedder::new(PathBuf::from("ffmpeg")).with_square_artwork(true),
+            "out.flac",
+        );
+        assert!(square.windows(2).any(|w| w == crop));
+
+        let original = args(MetadataEmbedder::new(PathBuf::from("ffmpeg")), "out.flac");
+        assert!(original.iter().any(|a| a == "attached_pic"));
+        assert!(!original.windows(2).any(|w| w == crop));
+
+        // No artwork stream in WavPack, so nothing to crop
+        let wavpack = args(
+            MetadataEmbedder::new(PathBuf::from("ffmpeg")).with_square_artwork(true),
+            "out.wv",
+        );
+        assert!(!wavpack.iter().any(|a| a == "-filter:v:0"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_embed_spectrogram_as_second_picture() {
+        use crate::test_support::{arg_after, StubFfmpeg};
+
+        let stub = StubFfmpeg::new();
+        let audio = stub.dir().join("encoded.flac");
+        let cover = stub.dir().join("cover.jpg");
+        let metadata = VideoMetadata { title: "Song".to_string(), ..Default::default() };
+        let embedder = MetadataEmbedder::new(stub.path()).with_spectrogram(true);
+        embedder
+            .embed(
+                &audio,
+                &stub.dir().join("Song.flac"),
+                &metadata,
+                Some(&cover),
+                None,
+            )
+            .await
+            .unwrap();
+
+        let calls = stub.calls();
+        assert_eq!(calls.len(), 2);
+        let png = spectrogram_path(&audio);
+        assert_eq!(calls[0].last().map(String::as_str), png.to_str());
+        assert!(arg_after(&calls[0], "-lavfi").unwrap().starts_with("showspectrumpic="));
+
+        let embed = &calls[1];
+        assert!(embed.windows(2).any(|w| w == ["-i", png.to_str().unwrap()]));
+        assert!(embed.windows(2).any(|w| w == ["-map", "2:v"]));
+        assert_eq!(arg_after(embed, "-c:v:1"), Some("png"));
+        assert_eq!(arg_after(embed, "-disposition:v:1"), Some("attached_pic"));
+        assert!(embed.windows(2).any(|w| w == ["-metadata:s:v:1", "title=Spectrogram"]));
+
+        // Opus holds no second picture: nothing rendered, only the embed runs
+        embedder
+            .embed(
+                &audio,
+                &stub.dir().join("Song.opus"),
+                &metadata,
+                Some(&cover),
+                None,
+            )
+            .await
+            .unwrap();
+        let calls = stub.calls();
+        assert_eq!(calls.len(), 3);
+        assert!(!calls[2].iter().any(|a| a == png.to_str().unwrap()));
+    }
+
+    #[test]
+    fn test_command_id3_version() {
+        let metadata = VideoMetadata { title: "Song".to_string(), ..Default::default() };
+        let args = |embedder: MetadataEmbedder, output: &str| {
+            args(&embedder.command(Path::new("in.mp3"), Path::new(output), &metadata, None, None))
+        };
+        let embedder = || MetadataEmbedder::new(PathBuf::from("ffmpeg"));
+
+        assert!(args(embedder(), "out.mp3").windows(2).any(|w| w == ["-id3v2_version", "3"]));
+        let v24 = args(embedder().with_id3_version("2.4".parse().unwrap()), "out.mp3");
+        assert!(v24.windows(2).any(|w| w == ["-id3v2_version", "4"]));
+        assert!(!args(embedder(), "out.flac").iter().any(|a| a == "-id3v2_version"));
+        assert!("2.2".parse::<Id3Version>().is_err());
+    }
+
+    #[test]
+    fn test_command_numbers_playlist_entries() {
+        let embedder = MetadataEmbedder::new(PathBuf::from("ffmpeg"));
+        let args = |metadata: &VideoMetadata| {
+            args(&embedder.command(
+                Path::new("in.flac"),
+                Path::new("out.flac"),
+                metadata,
+                None,
+                None,
+            ))
+        };
+        let single = VideoMetadata {
+            id: "abc123".to_string(),
+            title: "Song".to_string(),
+            ..Default::default()
+        };
+        let entry = VideoMetadata {
+            playlist_index: Some(3),
+            playlist_count: Some(12),
+            playlist_title: Some("The Album".to_string()),
+            ..single.clone()
+        };
+
+        assert!(!args(&single).iter().any(|a| a.starts_with("track=") || a.starts_with("album=")));
+        assert!(args(&entry).contains(&"track=3/12".to_string()));
+        assert!(args(&entry).contains(&"album=The Album".to_string()));
+    }
+
+    #[test]
+    fn test_subtitles_to_lyrics() {
+        // YouTube automatic captions repeat each line as it scrolls up
+        let vtt = "WEBVTT\nKind: captions\nLanguage: en\n\n\
+                   00:00:01.000 --> 00:00:03.000 align:start position:0%\n \nhello<00:00:01.500><c> world</c>\n\n\
+                   00:00:03.000 --> 00:00:03.010 align:start position:0%\nhello world\n \n\n\
+                   00:01:03.010 --> 00:01:05.000 align:start position:0%\nhello world\nrock &amp; roll\n";
+        assert_eq!(
+            subtitles_to_lyrics(vtt, true).as_deref(),
+            Some("[00:01.00]hello world\n[01:03.01]rock & roll")
+        );
+
+        let srt = "1\r\n00:00:12,340 --> 00:00:14,000\r\n<i>First line</i>\r\n\r\n2\r\n00:00:15,000 --> 00:00:16,000\r\nSecond\r\n";
+        assert_eq!(subtitles_to_lyrics(srt, false).as_deref(), Some("First line\nSecond"));
+
+        assert_eq!(subtitles_to_lyrics("WEBVTT\n\n", true), None);
+    }
+
+    #[test]
+    fn test_metadata_from_probe() {
+        let probe = "codec_name=mp3\nbit_rate=N/A\nduration=183.5\nbit_rate=192000\nTAG:TITLE=Song\nTAG:ARTIST=Band\nTAG:album=Record\nTAG:date=1998\nTAG:track=3/12\n";
+        let metadata = metadata_from_probe(Path::new("/music/03 song.mp3"), probe);
+        assert_eq!(metadata.title, "Song");
+        assert_eq!(metadata.artist.as_deref(), Some("Band"));
+        assert_eq!(metadata.album.as_deref(), Some("Record"));
+        assert_eq!(metadata.upload_date.as_deref(), Some("1998"));
+        assert_eq!(metadata.track, Some((3, 12)));
+        assert_eq!(metadata.duration, Some(183.5));
+        assert_eq!(metadata.ext, "mp3");
+        assert_eq!(metadata.acodec.as_deref(), Some("mp3"));
+        assert_eq!(metadata.abr, Some(192.0));
+        assert_eq!(metadata.source_url(), None);
+
+        let untagged = metadata_from_probe(Path::new("/music/03 song.mp3"), "TAG:track=7\n");
+        assert_eq!(untagged.title, "03 song");
+        assert_eq!((untagged.track, untagged.playlist_index), (None, Some(7)));
+    }
 }
@@ -1,14 +1,284 @@
 //! LUFS loudness normalization using FFmpeg
 
 use crate::error::NormalizeError;
-use serde::Deserialize;
+use crate::process::{
+    ffmpeg_loglevel, ffmpeg_output, ffmpeg_output_with_progress, ffmpeg_status,
+    probe_audio_duration, DEFAULT_SAMPLE_RATE,
+};
+use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use tokio::process::Command;
-use tracing::{debug, info};
+use tokio::sync::watch;
+use tracing::{debug, info, trace, warn};
+
+/// ReplayGain 2.0 reference loudness
+pub const REPLAYGAIN_REFERENCE_LUFS: f32 = -18.0;
+
+/// Reference loudness of the Opus `R128_*_GAIN` tags (RFC 7845)
+pub const R128_REFERENCE_LUFS: f32 = -23.0;
+
+/// How loudness normalization is applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NormalizeMode {
+    /// Re-encode through FFmpeg loudnorm (alters samples)
+    Loudnorm,
+    /// Write ReplayGain tags and leave samples untouched
+    #[serde(rename = "replaygain")]
+    ReplayGainTags,
+    /// Single-pass FFmpeg dynaudnorm: quiet passages are brought up, so
+    /// there is no loudness target (alters samples)
+    Dynamic,
+}
+
+/// Default dynaudnorm frame length in milliseconds
+pub const DEFAULT_DYNAMIC_FRAME_MS: u32 = 500;
+
+/// Default dynaudnorm Gaussian window size, in frames (odd)
+pub const DEFAULT_DYNAMIC_GAUSS_SIZE: u32 = 31;
+
+/// Track-level ReplayGain values, plus album-level ones when the track was
+/// measured as part of an album
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplayGain {
+    /// Gain in dB needed to reach the ReplayGain reference loudness; `None`
+    /// for digital silence, which no gain brings there
+    pub track_gain: Option<f32>,
+    /// True peak as linear amplitude (1.0 = 0 dBFS)
+    pub track_peak: f32,
+    /// Gain in dB bringing the whole album to the reference loudness
+    pub album_gain: Option<f32>,
+    /// Highest true peak of any track of the album, as linear amplitude
+    pub album_peak: Option<f32>,
+}
+
+impl ReplayGain {
+    /// Derive ReplayGain values from measured integrated loudness and true peak
+    pub fn from_measurement(integrated_lufs: f32, true_peak_dbtp: f32) -> Self {
+        let gain = REPLAYGAIN_REFERENCE_LUFS - integrated_lufs;
+        Self {
+            track_gain: gain.is_finite().then_some(gain),
+            track_peak: 10f32.powf(true_peak_dbtp / 20.0),
+            album_gain: None,
+            album_peak: None,
+        }
+    }
+
+    /// Add the album values, shared by every track of `album`
+    pub fn with_album(mut self, album: &AlbumGain) -> Self {
+        // An album of digital silence has nothing to bring up
+        let gain = REPLAYGAIN_REFERENCE_LUFS - album.integrated_lufs;
+        self.album_gain = Some(if gain.is_finite() { gain } else { 0.0 });
+        self.album_peak = Some(album.peak);
+        self
+    }
+
+    /// Tag value for `REPLAYGAIN_TRACK_GAIN`, e.g. `-3.20 dB`; `None` for silence
+    pub fn gain_tag(&self) -> Option<String> {
+        self.track_gain.map(|gain| format!("{:.2} dB", gain))
+    }
+
+    /// Tag value for `REPLAYGAIN_TRACK_PEAK`, e.g. `0.988553`
+    pub fn peak_tag(&self) -> String {
+        format!("{:.6}", self.track_peak)
+    }
+
+    /// Tag value for `REPLAYGAIN_ALBUM_GAIN`, when measured as an album
+    pub fn album_gain_tag(&self) -> Option<String> {
+        self.album_gain.map(|gain| format!("{:.2} dB", gain))
+    }
+
+    /// Tag value for `REPLAYGAIN_ALBUM_PEAK`, when measured as an album
+    pub fn album_peak_tag(&self) -> Option<String> {
+        self.album_peak.map(|peak| format!("{:.6}", peak))
+    }
+
+    /// Tag value for Opus's `R128_TRACK_GAIN`: the gain to -23 LUFS in
+    /// 1/256 dB steps (Q7.8), e.g. `-2048` for -8 dB
+    pub fn r128_track_gain_tag(&self) -> Option<String> {
+        self.track_gain.map(r128_gain)
+    }
+
+    /// Tag value for Opus's `R128_ALBUM_GAIN`, when measured as an album
+    pub fn r128_album_gain_tag(&self) -> Option<String> {
+        self.album_gain.map(r128_gain)
+    }
+}
+
+/// A ReplayGain gain (relative to -18 LUFS) as an R128 tag value
+fn r128_gain(replaygain_db: f32) -> String {
+    let gain = replaygain_db + R128_REFERENCE_LUFS - REPLAYGAIN_REFERENCE_LUFS;
+    ((gain * 256.0).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16).to_string()
+}
+
+/// One gain for every track of an album, chosen from their combined loudness
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AlbumGain {
+    /// Integrated loudness of all measured tracks together, in LUFS
+    pub integrated_lufs: f32,
+    /// Gain in dB applied to every track
+    pub gain: f32,
+    /// Highest true peak of any track, as linear amplitude
+    pub peak: f32,
+    /// Number of tracks measured
+    pub tracks: usize,
+}
+
+/// Meeting point for the pipelines of one batch run with album normalization.
+/// Each track submits its loudness through an [`AlbumSlot`] and waits until
+/// every track has either submitted or dropped out; then all of them get the
+/// same [`AlbumGain`].
+#[derive(Debug)]
+pub struct AlbumLoudness {
+    target_lufs: f32,
+    state: Mutex<AlbumState>,
+    decided: watch::Sender<Option<AlbumGain>>,
+}
+
+#[derive(Debug)]
+struct AlbumState {
+    /// Slots neither submitted nor dropped yet
+    pending: usize,
+    /// Integrated loudness (LUFS) and duration (seconds) per submitted track
+    measured: Vec<(f32, f64)>,
+    /// Highest true peak submitted so far, as linear amplitude
+    peak: f32,
+}
+
+impl AlbumLoudness {
+    /// Album of `tracks` tracks, to be brought to `target_lufs` as a whole
+    pub fn new(tracks: usize, target_lufs: f32) -> Self {
+        Self {
+            target_lufs,
+            state: Mutex::new(AlbumState { pending: tracks, measured: Vec::new(), peak: 0.0 }),
+            decided: watch::channel(None).0,
+        }
+    }
+
+    /// Claim one track's place. Dropping the slot without submitting (the
+    /// track failed, was skipped or cancelled) stops the others waiting for it.
+    pub fn slot(&self) -> AlbumSlot<'_> {
+        AlbumSlot { album: self, submitted: false }
+    }
+
+    /// The chosen gain, once every track is in
+    pub fn gain(&self) -> Option<AlbumGain> {
+        *self.decided.borrow()
+    }
+
+    fn settle(&self, measurement: Option<(f32, f32, f64)>) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.pending = state.pending.saturating_sub(1);
+        if let Some((integrated_lufs, peak, duration)) = measurement {
+            state.measured.push((integrated_lufs, duration));
+            state.peak = state.peak.max(peak);
+        }
+        if state.pending > 0 || state.measured.is_empty() {
+            return;
+        }
+
+        let integrated_lufs = album_loudness(&state.measured);
+        // An album of digital silence has nothing to bring up
+        let gain = if integrated_lufs.is_finite() {
+            self.target_lufs - integrated_lufs
+        } else {
+            0.0
+        };
+        info!(
+            "Album loudness {:.1} LUFS, gain {:+.1} dB",
+            integrated_lufs, gain
+        );
+        self.decided.send_replace(Some(AlbumGain {
+            integrated_lufs,
+            gain,
+            peak: state.peak,
+            tracks: state.measured.len(),
+        }));
+    }
+}
+
+/// One track's place in an [`AlbumLoudness`]
+#[derive(Debug)]
+pub struct AlbumSlot<'a> {
+    album: &'a AlbumLoudness,
+    submitted: bool,
+}
+
+impl AlbumSlot<'_> {
+    /// Submit this track's integrated loudness, true peak (linear) and
+    /// duration, then wait for the rest of the album
+    pub async fn submit(mut self, integrated_lufs: f32, peak: f32, duration: f64) -> AlbumGain {
+        let mut decided = self.album.decided.subscribe();
+        self.submitted = true;
+        self.album.settle(Some((integrated_lufs, peak, duration)));
+
+        let gain = *decided
+            .wait_for(Option::is_some)
+            .await
+            .expect("the album owns the sender");
+        gain.expect("waited for a decision")
+    }
+}
+
+impl Drop for AlbumSlot<'_> {
+    fn drop(&mut self) {
+        if !self.submitted {
+            self.album.settle(None);
+        }
+    }
+}
+
+/// Loudness of tracks played back to back: their energy averaged by
+/// duration. Tracks without a known duration count equally.
+pub fn album_loudness(tracks: &[(f32, f64)]) -> f32 {
+    let total: f64 = tracks.iter().map(|&(_, duration)| duration).sum();
+    let weight = |duration: f64| {
+        if total > 0.0 {
+            duration / total
+        } else {
+            1.0 / tracks.len() as f64
+        }
+    };
+    let energy: f64 = tracks
+        .iter()
+        .map(|&(lufs, duration)| weight(duration) * 10f64.powf(lufs as f64 / 10.0))
+        .sum();
+    (10.0 * energy.log10()) as f32
+}
+
+/// Loudness measured by FFmpeg's loudnorm filter
+///
+/// `input_*` describe the audio going in. `output_*` describe what loudnorm
+/// wrote; for a measurement-only pass they are loudnorm's prediction.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LoudnessStats {
+    /// Integrated loudness in LUFS
+    pub input_i: f32,
+    /// True peak in dBTP
+    pub input_tp: f32,
+    /// Loudness range in LU
+    pub input_lra: f32,
+    pub output_i: f32,
+    pub output_tp: f32,
+    pub output_lra: f32,
+}
+
+/// Loudness of a file as FFmpeg's ebur128 filter reports it
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LoudnessReport {
+    /// Integrated loudness in LUFS (-70.0 for silence)
+    pub integrated_lufs: f32,
+    /// Loudness range in LU
+    pub lra: f32,
+    /// True peak in dBTP (-inf for silence)
+    pub true_peak: f32,
+}
 
 #[derive(Debug)]
 pub struct Normalizer {
     ffmpeg_path: PathBuf,
+    sample_rate: u32,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -17,15 +287,52 @@ struct LoudnormStats {
     input_tp: String,
     input_lra: String,
     input_thresh: String,
+    output_i: String,
+    output_tp: String,
+    output_lra: String,
     target_offset: String,
 }
 
+impl TryFrom<&LoudnormStats> for LoudnessStats {
+    type Error = NormalizeError;
+
+    fn try_from(raw: &LoudnormStats) -> Result<Self, Self::Error> {
+        // loudnorm reports values as strings, including "-inf" for silence
+        let parse = |v: &str| {
+            v.trim()
+                .parse::<f32>()
+                .map_err(|_| NormalizeError::LoudnessParseError)
+        };
+        Ok(Self {
+            input_i: parse(&raw.input_i)?,
+            input_tp: parse(&raw.input_tp)?,
+            input_lra: parse(&raw.input_lra)?,
+            output_i: parse(&raw.output_i)?,
+            output_tp: parse(&raw.output_tp)?,
+            output_lra: parse(&raw.output_lra)?,
+        })
+    }
+}
+
 impl Normalizer {
     pub fn new(ffmpeg_path: PathBuf) -> Self {
-        Self { ffmpeg_path }
+        Self {
+            ffmpeg_path,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+        }
+    }
+
+    /// Sample rate of the audio being processed (default 48kHz). Written
+    /// audio keeps this rate; loudnorm would otherwise output 192kHz.
+    pub fn with_sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = sample_rate;
+        self
     }
 
-    /// Apply EBU R128 loudness normalization (two-pass for accuracy)
+    /// Apply EBU R128 loudness normalization (two-pass for accuracy).
+    ///
+    /// Returns the second pass's report: the loudness before normalization
+    /// and what was actually written.
     pub async fn normalize(
         &self,
         input: &Path,
@@ -33,44 +340,332 @@ impl Normalizer {
         target_lufs: f32,
         true_peak: f32,
         lra: f32,
-    ) -> Result<(), NormalizeError> {
+    ) -> Result<LoudnessStats, NormalizeError> {
+        self.normalize_with_progress(input, output, target_lufs, true_peak, lra, |_| {}).await
+    }
+
+    /// [`Normalizer::normalize`], calling `on_progress` with 0.0 as it starts,
+    /// 0.5 once the measurement pass is done, and then the applying pass's
+    /// progress as FFmpeg reports it, up to 1.0
+    pub async fn normalize_with_progress(
+        &self,
+        input: &Path,
+        output: &Path,
+        target_lufs: f32,
+        true_peak: f32,
+        lra: f32,
+        mut on_progress: impl FnMut(f32),
+    ) -> Result<LoudnessStats, NormalizeError> {
         info!("Normalizing to {:.1} LUFS", target_lufs);
+        on_progress(0.0);
 
         // First pass: measure loudness
+        let measured = self.measure_loudness(input, target_lufs, true_peak, lra).await?;
+        on_progress(0.5);
+
+        // Second pass: apply normalization with measured values. Without a
+        // duration its progress can't be told, so it jumps straight to done.
+        let duration = probe_audio_duration(&self.ffmpeg_path, input).await.ok().flatten();
+        let applied = self
+            .apply_normalization(
+                input,
+                output,
+                target_lufs,
+                true_peak,
+                lra,
+                &measured,
+                duration,
+                |fraction| on_progress(0.5 + fraction * 0.5),
+            )
+            .await?;
+        on_progress(1.0);
+        let stats = LoudnessStats::try_from(&applied)?;
+
+        info!(
+            "Loudness: {:.1} -> {:.1} LUFS, true peak {:.1} -> {:.1} dBTP",
+            stats.input_i, stats.output_i, stats.input_tp, stats.output_tp
+        );
+        debug!("Normalized to: {}", output.display());
+        Ok(stats)
+    }
+
+    /// Measure loudness without writing any audio
+    pub async fn measure(
+        &self,
+        input: &Path,
+        target_lufs: f32,
+        true_peak: f32,
+        lra: f32,
+    ) -> Result<LoudnessStats, NormalizeError> {
         let stats = self.measure_loudness(input, target_lufs, true_peak, lra).await?;
+        LoudnessStats::try_from(&stats)
+    }
+
+    /// Measure the true peak in dBTP (oversampled, so inter-sample peaks count)
+    /// without writing any audio
+    pub async fn measure_true_peak(&self, input: &Path) -> Result<f32, NormalizeError> {
+        let output = ffmpeg_output(&mut self.true_peak_command(input)).await?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        trace!("True peak measurement stderr: {}", stderr);
+
+        let stats = LoudnessStats::try_from(&parse_loudnorm_output(&stderr)?)?;
+        debug!("Measured true peak: {:.2} dBTP", stats.input_tp);
+        Ok(stats.input_tp)
+    }
+
+    /// Measure integrated loudness, loudness range and true peak with the
+    /// ebur128 filter, reading the file only
+    pub async fn measure_ebur128(&self, input: &Path) -> Result<LoudnessReport, NormalizeError> {
+        let output = ffmpeg_output(&mut self.ebur128_command(input)).await?;
+        if !output.status.success() {
+            return Err(NormalizeError::FfmpegFailed(output.status.code()));
+        }
 
-        // Second pass: apply normalization with measured values
-        self.apply_normalization(input, output, target_lufs, true_peak, lra, &stats).await?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        trace!("ebur128 stderr: {}", stderr);
+        parse_ebur128_summary(&stderr).ok_or(NormalizeError::LoudnessParseError)
+    }
+
+    /// Build the FFmpeg invocation for [`Normalizer::measure_ebur128`]
+    pub fn ebur128_command(&self, input: &Path) -> Command {
+        let mut cmd = Command::new(&self.ffmpeg_path);
+        cmd.kill_on_drop(true);
+        cmd.args([
+            "-hide_banner",
+            "-nostats",
+            // The summary is logged at info level; the per-frame lines go to verbose
+            "-loglevel", "info",
+        ]);
+        cmd.arg("-i").arg(input);
+        cmd.args([
+            "-vn",
+            "-af", "ebur128=peak=true:framelog=verbose",
+            "-f", "null",
+            "-",
+        ]);
+        cmd
+    }
+
+    /// Build the FFmpeg invocation for [`Normalizer::normalize_dynamic`]
+    pub fn dynamic_command(
+        &self,
+        input: &Path,
+        output: &Path,
+        frame_ms: u32,
+        gauss_size: u32,
+    ) -> Command {
+        self.filter_command(
+            input,
+            output,
+            &format!("dynaudnorm=f={}:g={}", frame_ms, gauss_size),
+        )
+    }
+
+    /// Dynamic normalization with dynaudnorm in a single pass: the gain
+    /// follows the audio frame by frame (`frame_ms` long, smoothed over
+    /// `gauss_size` frames), so quiet passages are brought up instead of the
+    /// whole file moving to one loudness
+    pub async fn normalize_dynamic(
+        &self,
+        input: &Path,
+        output: &Path,
+        frame_ms: u32,
+        gauss_size: u32,
+    ) -> Result<(), NormalizeError> {
+        info!("Dynamic normalization ({} ms frames, gauss size {})", frame_ms, gauss_size);
+
+        let status =
+            ffmpeg_status(&mut self.dynamic_command(input, output, frame_ms, gauss_size)).await?;
+        if !status.success() {
+            return Err(NormalizeError::FfmpegFailed(status.code()));
+        }
 
         debug!("Normalized to: {}", output.display());
         Ok(())
     }
 
-    async fn measure_loudness(
+    /// Build the FFmpeg invocation for [`Normalizer::measure_true_peak`]
+    pub fn true_peak_command(&self, input: &Path) -> Command {
+        // The loudnorm targets don't affect the input_* measurement
+        self.measure_command(input, REPLAYGAIN_REFERENCE_LUFS, -1.0, 7.0)
+    }
+
+    /// Build the FFmpeg invocation for [`Normalizer::limit_true_peak`]
+    pub fn limit_command(&self, input: &Path, output: &Path, ceiling_dbtp: f32) -> Command {
+        self.filter_command(input, output, &self.limiter_filter(ceiling_dbtp))
+    }
+
+    /// Build the FFmpeg invocation for [`Normalizer::apply_gain`]
+    pub fn gain_command(
+        &self,
+        input: &Path,
+        output: &Path,
+        gain_db: f32,
+        ceiling_dbtp: Option<f32>,
+    ) -> Command {
+        let mut filter = format!("volume={:.2}dB", gain_db);
+        if let Some(ceiling) = ceiling_dbtp {
+            filter = format!("{},{}", filter, self.limiter_filter(ceiling));
+        }
+        self.filter_command(input, output, &filter)
+    }
+
+    /// Apply a fixed gain, as album normalization does. With `ceiling_dbtp`,
+    /// peaks the gain pushes above it are limited.
+    pub async fn apply_gain(
+        &self,
+        input: &Path,
+        output: &Path,
+        gain_db: f32,
+        ceiling_dbtp: Option<f32>,
+    ) -> Result<(), NormalizeError> {
+        info!("Applying {:+.1} dB gain", gain_db);
+
+        let status =
+            ffmpeg_status(&mut self.gain_command(input, output, gain_db, ceiling_dbtp)).await?;
+        if !status.success() {
+            return Err(NormalizeError::FfmpegFailed(status.code()));
+        }
+
+        debug!("Gain applied: {}", output.display());
+        Ok(())
+    }
+
+    /// Peak normalization: measure the true peak, then apply the one gain
+    /// that brings it to `target_dbtp`, with the limiter catching any
+    /// overshoot. Loudness is not looked at, so tracks with the same peak can
+    /// still sound louder or quieter than each other. Returns the gain in dB;
+    /// silent audio is copied at 0 dB.
+    pub async fn peak_normalize(
+        &self,
+        input: &Path,
+        output: &Path,
+        target_dbtp: f32,
+    ) -> Result<f32, NormalizeError> {
+        let true_peak = self.measure_true_peak(input).await?;
+        let gain = if true_peak.is_finite() {
+            target_dbtp - true_peak
+        } else {
+            warn!("No peak measured (silent audio?), leaving the level as it is");
+            0.0
+        };
+        info!("Peak normalizing: {:.1} dBTP -> {:.1} dBTP", true_peak, target_dbtp);
+        self.apply_gain(input, output, gain, Some(target_dbtp)).await?;
+        Ok(gain)
+    }
+
+    fn limiter_filter(&self, ceiling_dbtp: f32) -> String {
+        // Limiting at 4x the working rate catches the peaks that would
+        // otherwise appear between samples once the audio is reconstructed
+        format!(
+            "aresample={},alimiter=limit={:.4}:attack=1:release=50:level=false,aresample={}",
+            self.sample_rate * 4,
+            10f32.powf(ceiling_dbtp / 20.0),
+            self.sample_rate
+        )
+    }
+
+    /// 24-bit WAV at the working rate, run through `filter`
+    fn filter_command(&self, input: &Path, output: &Path, filter: &str) -> Command {
+        let sample_rate = self.sample_rate.to_string();
+
+        let mut cmd = Command::new(&self.ffmpeg_path);
+        cmd.kill_on_drop(true);
+        cmd.args([
+            "-hide_banner",
+            "-loglevel", ffmpeg_loglevel(),
+            "-i", input.to_str().unwrap(),
+            "-af", filter,
+            "-c:a", "pcm_s24le",
+            "-ar", &sample_rate,
+            "-y",
+            output.to_str().unwrap(),
+        ]);
+        cmd
+    }
+
+    /// Brick-wall limit the audio to `ceiling_dbtp` true peak. Unlike
+    /// [`Normalizer::normalize`], overall loudness is left alone; only the
+    /// peaks above the ceiling are pulled down.
+    pub async fn limit_true_peak(
+        &self,
+        input: &Path,
+        output: &Path,
+        ceiling_dbtp: f32,
+    ) -> Result<(), NormalizeError> {
+        info!("Limiting true peak to {:.1} dBTP", ceiling_dbtp);
+
+        let status = ffmpeg_status(&mut self.limit_command(input, output, ceiling_dbtp)).await?;
+        if !status.success() {
+            return Err(NormalizeError::FfmpegFailed(status.code()));
+        }
+
+        debug!("Limited to: {}", output.display());
+        Ok(())
+    }
+
+    /// Measure track gain and peak for ReplayGain tagging (no audio is written)
+    pub async fn compute_replaygain(
+        &self,
+        input: &Path,
+        true_peak: f32,
+        lra: f32,
+    ) -> Result<ReplayGain, NormalizeError> {
+        info!("Computing ReplayGain");
+
+        // The loudnorm targets only shape its second pass; the input_* values
+        // reported by the measurement pass are independent of them
+        let stats = self
+            .measure(input, REPLAYGAIN_REFERENCE_LUFS, true_peak, lra)
+            .await?;
+        let replaygain = ReplayGain::from_measurement(stats.input_i, stats.input_tp);
+
+        debug!(
+            "ReplayGain: gain={}, peak={}",
+            replaygain.gain_tag().as_deref().unwrap_or("none (silent)"),
+            replaygain.peak_tag()
+        );
+        Ok(replaygain)
+    }
+
+    /// Build the FFmpeg invocation for the loudness measurement pass
+    pub fn measure_command(
         &self,
         input: &Path,
         target_lufs: f32,
         true_peak: f32,
         lra: f32,
-    ) -> Result<LoudnormStats, NormalizeError> {
+    ) -> Command {
         let filter = format!(
             "loudnorm=I={}:TP={}:LRA={}:print_format=json",
             target_lufs, true_peak, lra
         );
 
-        let output = Command::new(&self.ffmpeg_path)
-            .args([
-                "-hide_banner",
-                "-i", input.to_str().unwrap(),
-                "-af", &filter,
-                "-f", "null",
-                "-"
-            ])
-            .output()
-            .await?;
+        let mut cmd = Command::new(&self.ffmpeg_path);
+        cmd.kill_on_drop(true);
+        cmd.args([
+            "-hide_banner",
+            "-i", input.to_str().unwrap(),
+            "-af", &filter,
+            "-f", "null",
+            "-"
+        ]);
+        cmd
+    }
+
+    async fn measure_loudness(
+        &self,
+        input: &Path,
+        target_lufs: f32,
+        true_peak: f32,
+        lra: f32,
+    ) -> Result<LoudnormStats, NormalizeError> {
+        let output =
+            ffmpeg_output(&mut self.measure_command(input, target_lufs, true_peak, lra)).await?;
 
         // Parse JSON from stderr
         let stderr = String::from_utf8_lossy(&output.stderr);
+        trace!("Loudness measurement stderr: {}", stderr);
         let stats = parse_loudnorm_output(&stderr)?;
 
         debug!(
@@ -81,6 +676,7 @@ impl Normalizer {
         Ok(stats)
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn apply_normalization(
         &self,
         input: &Path,
@@ -89,39 +685,72 @@ impl Normalizer {
         true_peak: f32,
         lra: f32,
         stats: &LoudnormStats,
-    ) -> Result<(), NormalizeError> {
+        duration: Option<f64>,
+        on_progress: impl FnMut(f32),
+    ) -> Result<LoudnormStats, NormalizeError> {
         let filter = format!(
             "loudnorm=I={}:TP={}:LRA={}:\
              measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:\
-             offset={}:linear=true",
+             offset={}:linear=true:print_format=json",
             target_lufs, true_peak, lra,
             stats.input_i, stats.input_tp, stats.input_lra,
             stats.input_thresh, stats.target_offset
         );
 
-        let status = Command::new(&self.ffmpeg_path)
-            .args([
-                "-hide_banner",
-                "-loglevel", "error",
-                "-i", input.to_str().unwrap(),
-                "-af", &filter,
-                // Keep as 48kHz 24-bit WAV
-                "-c:a", "pcm_s24le",
-                "-ar", "48000",
-                "-y",
-                output.to_str().unwrap(),
-            ])
-            .status()
-            .await?;
+        // The JSON report is logged at info level, so stderr is captured here
+        // rather than inherited at the usual `ffmpeg_loglevel()`
+        let sample_rate = self.sample_rate.to_string();
+        let mut cmd = Command::new(&self.ffmpeg_path);
+        cmd.kill_on_drop(true).args([
+            "-hide_banner",
+            "-nostats",
+            "-progress", "pipe:1",
+            "-i", input.to_str().unwrap(),
+            "-af", &filter,
+            // Keep as 24-bit WAV at the working rate
+            "-c:a", "pcm_s24le",
+            "-ar", &sample_rate,
+            "-y",
+            output.to_str().unwrap(),
+        ]);
+        let output = ffmpeg_output_with_progress(&mut cmd, duration, on_progress).await?;
 
-        if !status.success() {
-            return Err(NormalizeError::FfmpegFailed(status.code()));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        trace!("Loudness normalization stderr: {}", stderr);
+
+        if !output.status.success() {
+            return Err(NormalizeError::FfmpegFailed(output.status.code()));
         }
 
-        Ok(())
+        parse_loudnorm_output(&stderr)
     }
 }
 
+/// Read the summary ebur128 logs at the end of a run:
+///
+/// ```text
+///   Integrated loudness:
+///     I:         -14.1 LUFS
+///   Loudness range:
+///     LRA:         6.0 LU
+///   True peak:
+///     Peak:        0.4 dBFS
+/// ```
+fn parse_ebur128_summary(stderr: &str) -> Option<LoudnessReport> {
+    let (_, summary) = stderr.rsplit_once("Summary:")?;
+    let value = |label: &str| {
+        summary.lines().find_map(|line| {
+            let rest = line.trim().strip_prefix(label)?;
+            rest.split_whitespace().next()?.parse::<f32>().ok()
+        })
+    };
+    Some(LoudnessReport {
+        integrated_lufs: value("I:")?,
+        lra: value("LRA:")?,
+        true_peak: value("Peak:")?,
+    })
+}
+
 fn parse_loudnorm_output(stderr: &str) -> Result<LoudnormStats, NormalizeError> {
     // Find the JSON block in FFmpeg output
     // It looks like:
@@ -142,3 +771,268 @@ fn parse_loudnorm_output(stderr: &str) -> Result<LoudnormStats, NormalizeError>
 
     serde_json::from_str(json_str).map_err(|_| NormalizeError::LoudnessParseError)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::args;
+
+    /// The loudnorm report as FFmpeg prints it at the end of stderr
+    const LOUDNORM_STDERR: &str = r#"[Parsed_loudnorm_0 @ 0x600000d34000]
+{
+	"input_i" : "-9.87",
+	"input_tp" : "0.42",
+	"input_lra" : "6.10",
+	"input_thresh" : "-20.01",
+	"output_i" : "-14.02",
+	"output_tp" : "-1.00",
+	"output_lra" : "5.90",
+	"output_thresh" : "-24.13",
+	"normalization_type" : "linear",
+	"target_offset" : "0.02"
+}"#;
+
+    #[test]
+    fn test_replaygain_from_measurement() {
+        let rg = ReplayGain::from_measurement(-14.0, -1.0);
+        assert_eq!(rg.gain_tag().as_deref(), Some("-4.00 dB"));
+        assert_eq!(rg.peak_tag(), "0.891251");
+
+        let rg = ReplayGain::from_measurement(-23.5, -6.02);
+        assert_eq!(rg.gain_tag().as_deref(), Some("5.50 dB"));
+
+        // Digital silence measures -inf: no gain tag rather than `inf dB`
+        let rg = ReplayGain::from_measurement(f32::NEG_INFINITY, f32::NEG_INFINITY);
+        assert_eq!(rg.track_gain, None);
+        assert_eq!(rg.gain_tag(), None);
+        assert_eq!(rg.r128_track_gain_tag(), None);
+        assert_eq!(rg.peak_tag(), "0.000000");
+    }
+
+    #[test]
+    fn test_loudness_stats_from_loudnorm_output() {
+        let raw = parse_loudnorm_output(LOUDNORM_STDERR).unwrap();
+        let stats = LoudnessStats::try_from(&raw).unwrap();
+        assert_eq!(stats.input_i, -9.87);
+        assert_eq!(stats.input_tp, 0.42);
+        assert_eq!(stats.output_i, -14.02);
+        assert_eq!(stats.output_lra, 5.90);
+
+        // Digital silence measures as -inf rather than failing to parse
+        let silent = LoudnormStats { input_i: "-inf".to_string(), ..raw };
+        assert!(LoudnessStats::try_from(&silent).unwrap().input_i.is_infinite());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_normalize_feeds_measurement_into_second_pass() {
+        use crate::test_support::{arg_after, StubFfmpeg};
+
+        let stub = StubFfmpeg::new().with_stderr(LOUDNORM_STDERR);
+        let input = stub.dir().join("processed.wav");
+        let output = stub.dir().join("normalized.wav");
+        let mut progress = Vec::new();
+        let stats = Normalizer::new(stub.path())
+            .normalize_with_progress(&input, &output, -14.0, -1.0, 11.0, |p| progress.push(p))
+            .await
+            .unwrap();
+        assert_eq!(stats.input_i, -9.87);
+        assert_eq!(stats.output_i, -14.02);
+        assert_eq!(progress, [0.0, 0.5, 1.0]);
+
+        let calls = stub.calls();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(
+            arg_after(&calls[0], "-af"),
+            Some("loudnorm=I=-14:TP=-1:LRA=11:print_format=json")
+        );
+        let filter = arg_after(&calls[1], "-af").unwrap();
+        assert!(
+            filter.contains("measured_I=-9.87:measured_TP=0.42:measured_LRA=6.10:measured_thresh=-20.01:offset=0.02"),
+            "{}",
+            filter
+        );
+        assert_eq!(calls[1].last().map(String::as_str), output.to_str());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_measure_without_report_fails_to_parse() {
+        use crate::test_support::StubFfmpeg;
+
+        let stub = StubFfmpeg::new().with_stderr("[in#0 @ 0x1] Error opening input\n");
+        let err = Normalizer::new(stub.path())
+            .measure(&stub.dir().join("missing.wav"), -14.0, -1.0, 11.0)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, NormalizeError::LoudnessParseError), "{:?}", err);
+    }
+
+    #[test]
+    fn test_parse_ebur128_summary() {
+        let stderr = "[Parsed_ebur128_0 @ 0x1] t: 1.2  M: -20.1 S:-120.7  I: -19.9 LUFS  LRA:   0.0 LU\n\
+                      [Parsed_ebur128_0 @ 0x1] Summary:\n\
+                      \n  Integrated loudness:\n    I:         -14.1 LUFS\n    Threshold: -24.4 LUFS\n\
+                      \n  Loudness range:\n    LRA:         6.0 LU\n    Threshold:  -34.3 LUFS\n\
+                      \x20   LRA low:    -19.5 LUFS\n    LRA high:   -13.5 LUFS\n\
+                      \n  True peak:\n    Peak:        0.4 dBFS\n";
+        assert_eq!(
+            parse_ebur128_summary(stderr),
+            Some(LoudnessReport { integrated_lufs: -14.1, lra: 6.0, true_peak: 0.4 })
+        );
+
+        let silent = "Summary:\n    I:         -70.0 LUFS\n    LRA:         0.0 LU\n    Peak:       -inf dBFS\n";
+        assert!(parse_ebur128_summary(silent).unwrap().true_peak.is_infinite());
+        assert_eq!(parse_ebur128_summary("no summary"), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_measure_ebur128_reads_only() {
+        use crate::test_support::{arg_after, StubFfmpeg};
+
+        let stub = StubFfmpeg::new()
+            .with_stderr("[Parsed_ebur128_0 @ 0x1] Summary:\n    I:   -9.5 LUFS\n    LRA:   4.2 LU\n    Peak:   1.1 dBFS\n");
+        let input = stub.dir().join("track.flac");
+        let report = Normalizer::new(stub.path()).measure_ebur128(&input).await.unwrap();
+        assert_eq!(report, LoudnessReport { integrated_lufs: -9.5, lra: 4.2, true_peak: 1.1 });
+
+        let calls = stub.calls();
+        assert_eq!(arg_after(&calls[0], "-i"), input.to_str());
+        assert_eq!(arg_after(&calls[0], "-f"), Some("null"));
+        assert_eq!(calls[0].last().map(String::as_str), Some("-"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_peak_normalize() {
+        use crate::test_support::{arg_after, StubFfmpeg};
+
+        let stub = StubFfmpeg::new().with_stderr(LOUDNORM_STDERR);
+        let input = stub.dir().join("processed.wav");
+        let output = stub.dir().join("normalized.wav");
+        let gain = Normalizer::new(stub.path())
+            .peak_normalize(&input, &output, -1.0)
+            .await
+            .unwrap();
+        assert!((gain + 1.42).abs() < 1e-4, "{}", gain);
+
+        let calls = stub.calls();
+        assert_eq!(calls.len(), 2);
+        let filter = arg_after(&calls[1], "-af").unwrap();
+        assert!(
+            filter.starts_with("volume=-1.42dB,") && filter.contains("alimiter=limit=0.8913:"),
+            "{}",
+            filter
+        );
+        assert_eq!(calls[1].last().map(String::as_str), output.to_str());
+    }
+
+    #[test]
+    fn test_limit_command_ceiling() {
+        let normalizer = Normalizer::new(PathBuf::from("ffmpeg"));
+        let command = normalizer.limit_command(Path::new("in.wav"), Path::new("out.wav"), -1.0);
+        let args = args(&command);
+        let filter = &args[args.iter().position(|a| a == "-af").unwrap() + 1];
+        assert!(filter.contains("alimiter=limit=0.8913:"), "{}", filter);
+        assert!(filter.contains("level=false"), "{}", filter);
+    }
+
+    #[test]
+    fn test_gain_command() {
+        let normalizer = Normalizer::new(PathBuf::from("ffmpeg"));
+        let filter = |ceiling| {
+            let command =
+                normalizer.gain_command(Path::new("in.wav"), Path::new("out.wav"), 3.5, ceiling);
+            let args = args(&command);
+            args[args.iter().position(|a| a == "-af").unwrap() + 1].clone()
+        };
+        assert_eq!(filter(None), "volume=3.50dB");
+        assert!(
+            filter(Some(-1.0)).starts_with("volume=3.50dB,aresample=192000,alimiter=limit=0.8913:")
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_normalize_dynamic_single_pass() {
+        use crate::test_support::{arg_after, StubFfmpeg};
+
+        let stub = StubFfmpeg::new();
+        let input = stub.dir().join("in.wav");
+        let output = stub.dir().join("out.wav");
+        Normalizer::new(stub.path()).normalize_dynamic(&input, &output, 250, 15).await.unwrap();
+
+        let calls = stub.calls();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(arg_after(&calls[0], "-af"), Some("dynaudnorm=f=250:g=15"));
+        assert_eq!(calls[0].last().map(String::as_str), output.to_str());
+    }
+
+    #[test]
+    fn test_album_loudness() {
+        assert!((album_loudness(&[(-14.0, 200.0), (-14.0, 100.0)]) + 14.0).abs() < 1e-4);
+        // Energy, not LUFS, is averaged: the loud track dominates
+        assert!((album_loudness(&[(-10.0, 100.0), (-20.0, 100.0)]) + 12.596).abs() < 1e-3);
+        // Longer tracks weigh more
+        assert!(album_loudness(&[(-10.0, 300.0), (-20.0, 100.0)]) > -11.5);
+        assert!((album_loudness(&[(-10.0, 0.0), (-20.0, 0.0)]) + 12.596).abs() < 1e-3);
+    }
+
+    #[tokio::test]
+    async fn test_album_waits_for_every_slot() {
+        let album = AlbumLoudness::new(3, -14.0);
+        let first = album.slot();
+        let second = album.slot();
+        let dropped = album.slot();
+
+        let waiting = first.submit(-20.0, 0.5, 100.0);
+        tokio::pin!(waiting);
+        assert!(futures::poll!(waiting.as_mut()).is_pending());
+        drop(dropped);
+        assert!(futures::poll!(waiting.as_mut()).is_pending());
+
+        let (a, b) = tokio::join!(waiting, second.submit(-20.0, 0.8, 100.0));
+        assert_eq!(a, b);
+        assert_eq!(a.tracks, 2);
+        assert!((a.gain - 6.0).abs() < 1e-4);
+        assert_eq!(a.peak, 0.8);
+        assert_eq!(album.gain(), Some(a));
+    }
+
+    #[test]
+    fn test_replaygain_with_album() {
+        let album = AlbumGain { integrated_lufs: -12.0, gain: -2.0, peak: 0.95, tracks: 3 };
+        let rg = ReplayGain::from_measurement(-15.0, -1.0).with_album(&album);
+        assert_eq!(rg.gain_tag().as_deref(), Some("-3.00 dB"));
+        assert_eq!(rg.album_gain_tag().as_deref(), Some("-6.00 dB"));
+        assert_eq!(rg.album_peak_tag().as_deref(), Some("0.950000"));
+        assert_eq!(ReplayGain::from_measurement(-15.0, -1.0).album_gain_tag(), None);
+    }
+
+    #[test]
+    fn test_r128_gain_tags() {
+        let album = AlbumGain { integrated_lufs: -12.0, gain: -2.0, peak: 0.95, tracks: 3 };
+        let rg = ReplayGain::from_measurement(-15.0, -1.0).with_album(&album);
+        // -15 LUFS needs -8 dB to reach -23 LUFS, the album -11 dB
+        assert_eq!(rg.r128_track_gain_tag().as_deref(), Some("-2048"));
+        assert_eq!(rg.r128_album_gain_tag().as_deref(), Some("-2816"));
+        assert_eq!(
+            ReplayGain::from_measurement(-15.0, -1.0).r128_album_gain_tag(),
+            None
+        );
+        assert_eq!(
+            ReplayGain::from_measurement(-30.1, -20.0)
+                .r128_track_gain_tag()
+                .as_deref(),
+            Some("1818")
+        );
+        // Clamped to the 16-bit range
+        assert_eq!(
+            ReplayGain::from_measurement(-200.0, -90.0)
+                .r128_track_gain_tag()
+                .as_deref(),
+            Some("32767")
+        );
+    }
+}
@@ -1,77 +1,183 @@
 //! Pipeline orchestration for audio extraction and processing
 
-use crate::config::PathsConfig;
-use crate::decoder::Decoder;
-use crate::downloader::Downloader;
-use crate::encoder::{self, Encoder};
-use crate::error::YtAudioError;
-use crate::metadata::{sanitize_filename, MetadataEmbedder};
-use crate::normalizer::Normalizer;
+use crate::cancel::CancelFlag;
+use crate::config::{HooksConfig, PathsConfig, TEMP_PREFIX};
+use crate::decoder::{Clip, Decoder, HIGH_BAND_CUTOFF_HZ};
+use crate::downloader::{normalize_url, DownloadResult, Downloader, VideoMetadata};
+use crate::encoder::{BitDepth, EncodeOptions, Encoder, FormatChoice};
+use crate::error::{ConfigError, DownloadError, YtAudioError};
+use crate::hooks::{run_hooks, HookContext};
+use crate::metadata::{
+    claim_free_path, clean_title, load_lyrics, render_filename_template, MetadataEmbedder,
+    OverwritePolicy,
+};
+use crate::normalizer::{
+    AlbumLoudness, AlbumSlot, LoudnessStats, NormalizeMode, Normalizer, ReplayGain,
+    REPLAYGAIN_REFERENCE_LUFS,
+};
+use crate::process::{
+    available_space, is_disk_full, probe_audio_duration, probe_sample_rate, DEFAULT_SAMPLE_RATE,
+};
 use crate::Config;
 
-use std::path::PathBuf;
+use futures::stream::{self, Stream};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::sync::mpsc;
-use tracing::{debug, info};
-use ytaudio_upscale::{UpscaleMethod, Upscaler};
+use tokio::process::Command;
+use tokio::sync::{mpsc, Semaphore};
+use tracing::{debug, info, warn};
+use ytaudio_upscale::{
+    is_valid_output_rate, short_command_line, FlashSRWorker, UpscaleMethod, Upscaler,
+};
 
 // Re-export args types for convenience
 pub mod args {
     pub use crate::config::PathsConfig;
+    pub use crate::encoder::OutputFormat;
 
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-    pub enum OutputFormat {
-        Flac,
-        Wav,
-        Mp3,
-        Aac,
-        Opus,
+    pub enum UpscaleQuality {
+        Best,
+        Fast,
     }
+}
 
-    impl OutputFormat {
-        pub fn extension(&self) -> &'static str {
-            match self {
-                OutputFormat::Flac => "flac",
-                OutputFormat::Wav => "wav",
-                OutputFormat::Mp3 => "mp3",
-                OutputFormat::Aac => "m4a",
-                OutputFormat::Opus => "opus",
-            }
-        }
-    }
+pub use args::{OutputFormat, UpscaleQuality};
 
-    impl std::fmt::Display for OutputFormat {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            match self {
-                OutputFormat::Flac => write!(f, "FLAC"),
-                OutputFormat::Wav => write!(f, "WAV"),
-                OutputFormat::Mp3 => write!(f, "MP3"),
-                OutputFormat::Aac => write!(f, "AAC"),
-                OutputFormat::Opus => write!(f, "Opus"),
-            }
+impl UpscaleQuality {
+    /// The upscaler backend for this quality, with AudioSR tuned from config
+    pub fn method(self, app_config: &Config) -> UpscaleMethod {
+        match self {
+            UpscaleQuality::Best => UpscaleMethod::AudioSR {
+                model: app_config.upscale.audiosr.model,
+                ddim_steps: app_config.upscale.audiosr.ddim_steps,
+                guidance_scale: app_config.upscale.audiosr.guidance_scale,
+                chunk_secs: app_config.upscale.audiosr.chunk_secs,
+                overlap_secs: app_config.upscale.audiosr.overlap_secs,
+                seed: app_config.upscale.audiosr.seed,
+            },
+            UpscaleQuality::Fast => UpscaleMethod::FlashSR,
         }
     }
+}
 
-    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-    pub enum UpscaleQuality {
-        Best,
-        Fast,
-    }
+/// Where the pipeline gets its audio from
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum PipelineInput {
+    /// Download with yt-dlp, then run every stage
+    YouTubeUrl(String),
+    /// Audio already on disk (any format FFmpeg decodes), tagged with the
+    /// given metadata. The download stage is skipped and yt-dlp is not needed.
+    LocalFile(PathBuf, VideoMetadata),
 }
 
-pub use args::{OutputFormat, UpscaleQuality};
+impl std::fmt::Display for PipelineInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PipelineInput::YouTubeUrl(url) => write!(f, "{}", url),
+            PipelineInput::LocalFile(path, _) => write!(f, "{}", path.display()),
+        }
+    }
+}
 
 /// Pipeline configuration
 #[derive(Debug, Clone)]
 pub struct PipelineConfig {
-    pub url: String,
+    pub input: PipelineInput,
     pub output_dir: PathBuf,
-    pub format: OutputFormat,
+    /// Output path template relative to `output_dir` (see [`render_filename_template`])
+    pub filename_template: String,
+    /// Primary output format; `auto` is resolved per source after upscaling
+    pub format: FormatChoice,
+    /// Additional formats encoded from the same processed audio
+    pub also: Vec<OutputFormat>,
+    /// Bitrate overrides for the lossy formats
+    pub encode_options: EncodeOptions,
     pub enhance: bool,
+    /// Upscale even when the source already has content above 16kHz
+    pub force_upscale: bool,
     pub upscale_quality: UpscaleQuality,
     pub normalize: bool,
+    pub normalize_mode: NormalizeMode,
+    /// Peak-normalize to this true peak in dBTP instead of targeting
+    /// loudness; takes the place of `normalize`
+    pub peak_target: Option<f32>,
+    /// Limit true peaks to `normalize.true_peak` even without normalization
+    pub limit_peaks: bool,
+    /// High-pass cutoff in Hz applied while decoding; `None` or 0 disables
+    pub highpass: Option<u32>,
+    /// Low-pass cutoff in Hz applied while decoding; `None` or 0 disables
+    pub lowpass: Option<u32>,
+    /// Cut leading and trailing silence before loudness is measured
+    pub trim_silence: bool,
+    /// Fade-in length in seconds; `None` or 0 disables
+    pub fade_in: Option<f32>,
+    /// Fade-out length in seconds; `None` or 0 disables
+    pub fade_out: Option<f32>,
+    /// FFmpeg `-af` chain appended to the decode's filters, unchecked until run
+    pub extra_af: Option<String>,
+    /// Only this part of the input is decoded, so later stages process just the clip
+    pub clip: Option<Clip>,
+    /// Working sample rate; `None` keeps the source rate (48kHz when upscaling)
+    pub resample: Option<u32>,
+    /// Keep hi-res sources at up to 96kHz and at least 24-bit, even when
+    /// upscaling is on, and store upscaled audio at 96kHz
+    pub preserve_hires: bool,
+    /// Rate the upscalers write instead of 48kHz, resampled once in Python;
+    /// a run with a rate outside 32-48kHz fails with a config error
+    pub target_rate: Option<u32>,
     pub target_lufs: f32,
     pub keep_temp: bool,
+    /// Work in a per-video temp directory that survives failures, and reuse the
+    /// intermediate WAVs a previous run of the same input left there
+    pub resume: bool,
+    /// Device for the neural models (`cpu`, `cuda:0`, `mps`); auto-selected when `None`
+    pub device: Option<String>,
+    /// AudioSR seed, replacing upscale.audiosr.seed
+    pub audiosr_seed: Option<u64>,
+    /// SponsorBlock categories to cut from the audio (drops chapter markers)
+    pub sponsorblock: Option<Vec<String>>,
+    /// yt-dlp `-f` selector; the built-in Opus/AAC preference when `None`
+    pub format_selector: Option<String>,
+    /// Proxy URL for every yt-dlp call
+    pub proxy: Option<String>,
+    /// yt-dlp download rate cap, e.g. "1M" or "500K"
+    pub rate_limit: Option<String>,
+    /// yt-dlp `--extractor-args`; a bot check is retried with the Android client when `None`
+    pub extractor_args: Option<String>,
+    /// Retries for transient download failures
+    pub max_retries: u32,
+    /// yt-dlp download archive: IDs listed there are skipped, new ones recorded
+    pub archive_file: Option<PathBuf>,
+    /// Subtitle language to embed as lyrics (yt-dlp `--sub-langs` syntax); off when `None`
+    pub lyrics: Option<String>,
+    /// Upper bound on automatic fallbacks for this run (0 disables them)
+    pub max_fallbacks: u32,
+    /// Report planned stages and commands, then return without executing
+    pub dry_run: bool,
+    /// Write one file per chapter instead of a single file
+    pub split_chapters: bool,
+    /// Fetch the thumbnail and embed it as cover art
+    pub embed_artwork: bool,
+    /// Embed a spectrogram of each output as a second picture (MP3 and FLAC)
+    pub embed_spectrogram: bool,
+    /// Tidy the title and split "Artist - Title" before tagging and naming
+    pub clean_titles: bool,
+    /// Write the encoded audio without tags, artwork or chapters
+    pub no_metadata: bool,
+    /// Skip the item when its output already exists (costs one metadata-only yt-dlp call)
+    pub skip_existing: bool,
+    /// What to do when an output file is already there; `Skip` also skips
+    /// the item up front, like `skip_existing`
+    pub on_exists: OverwritePolicy,
+    /// Probe every output with ffprobe and fail unless it holds audio of the expected length
+    pub verify: bool,
+    /// Commands to run once the item succeeds or fails; empty to run none
+    pub hooks: HooksConfig,
+    /// Tool paths; each one set here wins over `paths` in the app config
     pub paths: PathsConfig,
 }
 
@@ -81,97 +187,524 @@ pub enum PipelineStage {
     Downloading { progress: f32, title: String },
     Decoding,
     Upscaling { method: String, progress: f32 },
-    Normalizing { target_lufs: f32 },
-    Encoding { format: String },
+    /// `enhance` was set, but the source already has high-band content
+    /// (mean level above 16kHz, in dBFS), so upscaling is skipped
+    UpscaleSkipped { high_band_level: f32 },
+    /// Trimming silence and/or applying fades
+    Trimming,
+    /// Two-pass loudnorm: the measurement pass is the first half of `progress`
+    Normalizing { target_lufs: f32, progress: f32 },
+    Normalized { stats: LoudnessStats },
+    /// True peak of the audio before normalization, in dBTP
+    PeakMeasured { true_peak: f32 },
+    Limiting { ceiling: f32 },
+    ComputingReplayGain,
+    /// Single-pass dynaudnorm
+    NormalizingDynamic,
+    /// Gain to bring the true peak to `target_dbtp`
+    NormalizingPeak { target_dbtp: f32 },
+    /// Loudness measured for album normalization; waiting for the other tracks
+    AlbumMeasured { integrated_lufs: f32 },
+    /// Applying the album's shared gain, in dB
+    ApplyingAlbumGain { gain: f32 },
+    Splitting { chapters: usize },
+    /// Encoding one output; `progress` runs from 0.0 to 1.0 per file
+    Encoding { format: String, progress: f32 },
     EmbeddingMetadata,
-    Complete { output: PathBuf, duration: Duration },
+    Verifying,
+    /// A stage's output from an earlier run was found valid and reused
+    Resumed { stage: String, path: PathBuf },
+    Planned { stage: String, command: String },
+    Skipped { output: PathBuf },
+    /// The video is already listed in the download archive; nothing was written
+    AlreadyArchived,
+    Fallback { stage: String, from: String, to: String, attempt: u32, max: u32 },
+    /// Done; `also` lists the files written besides `output` (further
+    /// formats, the other chapter tracks)
+    Complete { output: PathBuf, also: Vec<PathBuf>, duration: Duration, timings: StageTimings },
     Failed { stage: String, error: String },
 }
 
+/// Item of [`Pipeline::run_stream`]: progress, then exactly one `Finished`
+/// carrying what [`Pipeline::run`] would have returned
+#[derive(Debug)]
+pub enum PipelineEvent {
+    Stage(PipelineStage),
+    Finished(Result<PathBuf, YtAudioError>),
+}
+
+/// Wall-clock time spent in each stage of one run. Encode and metadata add up
+/// over every output file; stages that did not run stay at zero.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StageTimings {
+    pub download: Duration,
+    pub decode: Duration,
+    pub upscale: Duration,
+    /// Trimming and fades, peak measurement and limiting, loudnorm or ReplayGain analysis
+    pub normalize: Duration,
+    pub encode: Duration,
+    pub metadata: Duration,
+}
+
+impl StageTimings {
+    /// Stage names and durations, in pipeline order
+    pub fn stages(&self) -> [(&'static str, Duration); 6] {
+        [
+            ("download", self.download),
+            ("decode", self.decode),
+            ("upscale", self.upscale),
+            ("normalize", self.normalize),
+            ("encode", self.encode),
+            ("metadata", self.metadata),
+        ]
+    }
+}
+
+impl std::ops::AddAssign for StageTimings {
+    fn add_assign(&mut self, other: Self) {
+        self.download += other.download;
+        self.decode += other.decode;
+        self.upscale += other.upscale;
+        self.normalize += other.normalize;
+        self.encode += other.encode;
+        self.metadata += other.metadata;
+    }
+}
+
+/// Mean level above [`HIGH_BAND_CUTOFF_HZ`] from which a source counts as
+/// full-band; band-limited lossy encodes measure well below it
+const FULL_BAND_LEVEL_DB: f32 = -70.0;
+
+/// Both upscalers write 48kHz audio, and are fed 48kHz unless `resample` says otherwise
+pub const UPSCALE_SAMPLE_RATE: u32 = 48000;
+
+/// Highest rate `preserve_hires` keeps, and the rate it stores upscaled audio at
+pub const HIRES_SAMPLE_RATE: u32 = 96000;
+
+/// One output track: the audio to encode, its tags, and its file name
+struct Track {
+    audio: PathBuf,
+    metadata: VideoMetadata,
+    name: String,
+    /// Length the encoded file should have, when known
+    duration: Option<f64>,
+}
+
+impl Track {
+    fn whole(audio: &Path, metadata: &VideoMetadata) -> Self {
+        Self {
+            audio: audio.to_path_buf(),
+            metadata: metadata.clone(),
+            name: metadata.title.clone(),
+            duration: metadata.duration,
+        }
+    }
+}
+
+/// Tracks automatic fallbacks so chained recoveries stay bounded per run
+#[derive(Debug)]
+struct FallbackBudget {
+    max: u32,
+    used: u32,
+}
+
+impl FallbackBudget {
+    fn new(max: u32) -> Self {
+        Self { max, used: 0 }
+    }
+
+    /// Claim one fallback attempt, returning false once the budget is spent
+    fn try_consume(&mut self) -> bool {
+        if self.used >= self.max {
+            debug!("Fallback budget exhausted ({}/{})", self.used, self.max);
+            return false;
+        }
+        self.used += 1;
+        true
+    }
+
+    fn used(&self) -> u32 {
+        self.used
+    }
+
+    fn max(&self) -> u32 {
+        self.max
+    }
+}
+
 /// Main processing pipeline
+#[derive(Clone)]
 pub struct Pipeline {
     config: PipelineConfig,
     progress_tx: mpsc::Sender<PipelineStage>,
+    cancel: CancelFlag,
+    flashsr_worker: Option<Arc<FlashSRWorker>>,
+    album: Option<Arc<AlbumLoudness>>,
+    app_config: Config,
+    upscale_limit: Option<Arc<Semaphore>>,
+    download_limit: Option<Arc<Semaphore>>,
+    ffmpeg_limit: Option<Arc<Semaphore>>,
 }
 
 impl Pipeline {
     pub fn new(config: PipelineConfig, progress_tx: mpsc::Sender<PipelineStage>) -> Self {
-        Self { config, progress_tx }
+        Self {
+            config,
+            progress_tx,
+            cancel: CancelFlag::new(),
+            flashsr_worker: None,
+            album: None,
+            app_config: Config::default(),
+            upscale_limit: None,
+            download_limit: None,
+            ffmpeg_limit: None,
+        }
+    }
+
+    /// Pipeline that reports progress only through [`Pipeline::run_stream`];
+    /// [`Pipeline::run`] on it runs silently
+    pub fn without_progress(config: PipelineConfig) -> Self {
+        // Sends to a closed channel fail, and every send ignores the error
+        let (progress_tx, _) = mpsc::channel(1);
+        Self::new(config, progress_tx)
+    }
+
+    /// Stop the run when `cancel` fires: the running child process is killed,
+    /// temp files are handled as on any failure, and `run` returns
+    /// [`YtAudioError::Cancelled`]
+    pub fn with_cancel(mut self, cancel: CancelFlag) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Run FlashSR through a shared worker that keeps the model loaded, so
+    /// pipelines in a batch don't each start Python and rebuild the session
+    pub fn with_flashsr_worker(mut self, worker: Arc<FlashSRWorker>) -> Self {
+        self.flashsr_worker = Some(worker);
+        self
+    }
+
+    /// Hold a permit of `limit` while upscaling, so pipelines sharing it
+    /// take turns on the GPU while their other stages run in parallel
+    pub fn with_upscale_limit(mut self, limit: Arc<Semaphore>) -> Self {
+        self.upscale_limit = Some(limit);
+        self
+    }
+
+    /// Hold a permit of `limit` while yt-dlp runs (the download and metadata
+    /// fetches), so pipelines sharing it don't saturate the network
+    pub fn with_download_limit(mut self, limit: Arc<Semaphore>) -> Self {
+        self.download_limit = Some(limit);
+        self
+    }
+
+    /// Hold a permit of `limit` while FFmpeg decodes, filters, measures or
+    /// encodes. With the download and upscale limits, a batch pipelines its
+    /// tracks: one downloads while another upscales and a third encodes,
+    /// without any resource running more jobs than it has slots.
+    pub fn with_ffmpeg_limit(mut self, limit: Arc<Semaphore>) -> Self {
+        self.ffmpeg_limit = Some(limit);
+        self
+    }
+
+    /// Read stage settings and tool paths from `app_config` rather than the
+    /// built-in defaults. The pipeline never loads config files itself, so a
+    /// long-running program controls when changes apply (see [`Config::reload`]).
+    pub fn with_app_config(mut self, app_config: Config) -> Self {
+        self.app_config = app_config;
+        self
+    }
+
+    /// Normalize as one track of `album` instead of on its own: the run
+    /// measures its loudness, waits for the album's other pipelines, and
+    /// applies the shared gain. Replaces `normalize` for this run. Every
+    /// pipeline given the album must be run, or the others wait forever.
+    pub fn with_album_loudness(mut self, album: Arc<AlbumLoudness>) -> Self {
+        self.album = Some(album);
+        self
+    }
+
+    /// Run the pipeline as a stream of [`PipelineEvent`]s, for callers that
+    /// would rather poll than drain a channel. Stages go to the stream instead
+    /// of the sender given to [`Pipeline::new`], and the stream ends after
+    /// [`PipelineEvent::Finished`]. Dropping it cancels the run like
+    /// dropping the [`Pipeline::run`] future.
+    pub fn run_stream(&self) -> impl Stream<Item = PipelineEvent> {
+        let (progress_tx, progress_rx) = mpsc::channel(32);
+        let pipeline = Pipeline { progress_tx, ..self.clone() };
+        // The run owns the only sender, so the channel closes once it returns
+        let run = Box::pin(async move { pipeline.run().await });
+
+        stream::unfold((Some(run), progress_rx, None), |(mut run, mut rx, mut result)| async move {
+            loop {
+                match run.as_mut() {
+                    Some(future) => tokio::select! {
+                        biased;
+                        Some(stage) = rx.recv() => {
+                            return Some((PipelineEvent::Stage(stage), (run, rx, result)));
+                        }
+                        finished = future => {
+                            result = Some(finished);
+                            run = None;
+                        }
+                    },
+                    // Deliver the stages still buffered, then the result
+                    None => match rx.recv().await {
+                        Some(stage) => {
+                            return Some((PipelineEvent::Stage(stage), (run, rx, result)));
+                        }
+                        None => {
+                            return result
+                                .take()
+                                .map(|r| (PipelineEvent::Finished(r), (run, rx, None)));
+                        }
+                    },
+                }
+            }
+        })
     }
 
     pub async fn run(&self) -> Result<PathBuf, YtAudioError> {
-        let start_time = Instant::now();
+        let mut title = None;
+        let mut wrote = false;
+        let result = self.run_item(&mut title, &mut wrote).await;
+        if !self.config.dry_run {
+            self.run_hooks(&result, title.as_deref(), wrote).await;
+        }
+        result
+    }
 
-        // Create temp directory
-        let temp_dir = tempfile::tempdir()?;
-        let temp_path = temp_dir.path().to_path_buf();
+    /// The pipeline proper. `title` is set once the item's metadata is known,
+    /// so it stays `None` for items skipped before the download; `wrote` once
+    /// an output file is written, so it stays `false` when `on_exists = skip`
+    /// kept every one that was there.
+    async fn run_item(
+        &self,
+        title: &mut Option<String>,
+        wrote: &mut bool,
+    ) -> Result<PathBuf, YtAudioError> {
+        let start_time = Instant::now();
+        // Claimed first, so that any early return or failure lets the rest of
+        // the album stop waiting for this track
+        let album_slot = self.album.as_deref().map(AlbumLoudness::slot);
 
-        info!("Starting pipeline for: {}", self.config.url);
-        debug!("Temp directory: {}", temp_path.display());
+        if let Some(rate) = self
+            .config
+            .target_rate
+            .filter(|rate| !is_valid_output_rate(*rate))
+        {
+            return Err(ConfigError::InvalidValue(format!(
+                "target_rate = {} (expected 32000 to 48000)",
+                rate
+            ))
+            .into());
+        }
 
         // Get tool paths
-        let app_config = Config::load(None)?;
-        let yt_dlp_path = app_config.yt_dlp_path()?;
+        let app_config = self.app_config();
         let ffmpeg_path = app_config.ffmpeg_path()?;
         let python_path = app_config.python_path()?;
 
-        // 1. Download
-        let _ = self.progress_tx.send(PipelineStage::Downloading {
-            progress: 0.0,
-            title: "Starting download...".to_string(),
-        }).await;
+        if self.config.dry_run {
+            return self.plan(&app_config, ffmpeg_path, python_path).await;
+        }
+
+        if self.config.skip_existing || self.config.on_exists == OverwritePolicy::Skip {
+            if let Some(existing) = self.existing_output(&app_config).await? {
+                info!("Skipping, output already exists: {}", existing.display());
+                let _ = self.progress_tx.send(PipelineStage::Skipped {
+                    output: existing.clone(),
+                }).await;
+                return Ok(existing);
+            }
+        }
+
+        // A bad --af chain would otherwise only fail after the download
+        self.cancellable(self.decoder(&app_config, &ffmpeg_path).check_filters())
+            .await
+            .map_err(|e| {
+                let _ = self.progress_tx.try_send(PipelineStage::Failed {
+                    stage: "decode".to_string(),
+                    error: e.to_string(),
+                });
+                e
+            })?;
+
+        // Detach up front when keeping temp files, so they survive a failed run too.
+        // `temp.cleanup = false` in config acts as an implicit keep_temp.
+        let keep_temp = self.config.keep_temp || !app_config.temp.cleanup;
+
+        // Resume mode reuses one directory per input and removes it only after
+        // success; otherwise create a fresh one, in temp.directory when configured
+        let (temp_path, temp_dir) = if self.config.resume {
+            (self.resume_dir(&app_config).await?, None)
+        } else {
+            // Named after the video when known, to find kept files by ID
+            let prefix = match self.config.input {
+                PipelineInput::YouTubeUrl(ref url) => normalize_url(url)
+                    .map(|normalized| format!("{}{}-", TEMP_PREFIX, normalized.id)),
+                PipelineInput::LocalFile(..) => None,
+            };
+            let mut builder = tempfile::Builder::new();
+            builder.prefix(prefix.as_deref().unwrap_or(TEMP_PREFIX));
+            let temp_dir = match app_config.temp.directory {
+                Some(ref dir) => {
+                    tokio::fs::create_dir_all(dir).await?;
+                    builder.tempdir_in(dir)?
+                }
+                None => builder.tempdir()?,
+            };
+            let temp_path = temp_dir.path().to_path_buf();
+            if keep_temp {
+                let _ = temp_dir.keep();
+                (temp_path, None)
+            } else {
+                (temp_path, Some(temp_dir))
+            }
+        };
+
+        info!("Starting pipeline for: {}", self.config.input);
+        debug!("Temp directory: {}", temp_path.display());
+        if keep_temp {
+            info!("Keeping temp files at: {}", temp_path.display());
+        }
+
+        let mut fallbacks = FallbackBudget::new(self.config.max_fallbacks);
+        let mut timings = StageTimings::default();
+
+        // 1. Download (local inputs go straight to decoding)
+        let stage_start = Instant::now();
+        let mut download_result = match self.config.input {
+            PipelineInput::YouTubeUrl(ref url) => {
+                self.download(&app_config, url, &temp_path).await?
+            }
+            PipelineInput::LocalFile(ref path, ref metadata) => DownloadResult {
+                audio_path: path.clone(),
+                metadata: metadata.clone(),
+                thumbnail_path: None,
+                subtitles_path: None,
+            },
+        };
+        timings.download = stage_start.elapsed();
+        self.clean_metadata(&mut download_result.metadata);
+        *title = Some(download_result.metadata.title.clone());
 
-        let downloader = Downloader::new(yt_dlp_path, temp_path.clone());
-        let download_result = downloader.download(&self.config.url).await.map_err(|e| {
+        if self.config.lyrics.is_some() {
+            // SponsorBlock cuts and clips shift the audio, so keep the text but drop the timings
+            let timed = self.config.sponsorblock.is_none() && self.config.clip.is_none();
+            download_result.metadata.lyrics = match download_result.subtitles_path {
+                Some(ref subtitles) => load_lyrics(subtitles, timed).await,
+                None => None,
+            };
+            if download_result.metadata.lyrics.is_none() {
+                info!("No subtitles available, writing no lyrics");
+            }
+        }
+
+        if let Some(clip) = self.config.clip {
+            let metadata = &mut download_result.metadata;
+            if let Some(duration) = metadata.duration {
+                clip.check_within(duration).map_err(|e| {
+                    let e = YtAudioError::from(e);
+                    let _ = self.progress_tx.try_send(PipelineStage::Failed {
+                        stage: "decode".to_string(),
+                        error: e.to_string(),
+                    });
+                    e
+                })?;
+            }
+            shift_timeline(metadata, clip.start, clip.length());
+        }
+
+        self.check_temp_space(&temp_path, download_result.metadata.duration).map_err(|e| {
             let _ = self.progress_tx.try_send(PipelineStage::Failed {
-                stage: "download".to_string(),
+                stage: "decode".to_string(),
                 error: e.to_string(),
             });
             e
         })?;
 
-        let _ = self.progress_tx.send(PipelineStage::Downloading {
-            progress: 1.0,
-            title: download_result.metadata.title.clone(),
-        }).await;
-
         // 2. Decode to WAV
         let _ = self.progress_tx.send(PipelineStage::Decoding).await;
+        let stage_start = Instant::now();
 
-        let decoder = Decoder::new(ffmpeg_path.clone());
+        let decoder = self
+            .decoder(&app_config, &ffmpeg_path)
+            .with_source_codec(download_result.metadata.acodec.clone());
         let decoded_wav = temp_path.join("decoded.wav");
-        decoder.decode_to_wav(&download_result.audio_path, &decoded_wav).await.map_err(|e| {
-            let _ = self.progress_tx.try_send(PipelineStage::Failed {
-                stage: "decode".to_string(),
-                error: e.to_string(),
-            });
-            e
-        })?;
+        let decoded_rate = match self.checkpoint("decode", &ffmpeg_path, &decoded_wav).await {
+            Some(rate) => rate,
+            None => {
+                let rate = self.cancellable(self.on_ffmpeg(decoder.decode_to_wav(
+                    &download_result.audio_path,
+                    &decoded_wav,
+                    self.decode_sample_rate(),
+                ))).await.map_err(|e| {
+                    let _ = self.progress_tx.try_send(PipelineStage::Failed {
+                        stage: "decode".to_string(),
+                        error: e.to_string(),
+                    });
+                    e
+                })?;
+                self.mark_checkpoint(&decoded_wav).await;
+                rate
+            }
+        };
+        timings.decode = stage_start.elapsed();
 
-        // 3. Upscale (if enabled)
-        let processed_audio = if self.config.enhance {
-            let method_name = match self.config.upscale_quality {
-                UpscaleQuality::Best => "AudioSR",
-                UpscaleQuality::Fast => "FlashSR",
-            };
+        // 3. Upscale (if enabled and the source needs it)
+        let stage_start = Instant::now();
+        let enhance = self.config.enhance && !self.is_full_band(&decoder, &decoded_wav).await?;
+        let upscaled_wav = temp_path.join("upscaled.wav");
+        let processed_audio = if enhance
+            && self
+                .checkpoint("upscale", &ffmpeg_path, &upscaled_wav)
+                .await
+                .is_some()
+        {
+            upscaled_wav
+        } else if enhance {
+            let method = self.upscale_method(&app_config);
+            let mut method_name = method.to_string();
 
             let _ = self.progress_tx.send(PipelineStage::Upscaling {
-                method: method_name.to_string(),
+                method: method_name.clone(),
                 progress: 0.0,
             }).await;
 
-            let upscaler = Upscaler::new(python_path);
-            let upscaled_wav = temp_path.join("upscaled.wav");
+            let upscaler = Upscaler::new(python_path)
+                .with_device(self.config.device.clone())
+                .with_flashsr_sha256(app_config.upscale.flashsr_sha256.clone())
+                .with_flashsr_worker(self.flashsr_worker.clone())
+                .with_output_rate(self.config.target_rate)
+                // Kept temp files double as a bug report, so keep the models' output too
+                .with_log_file(self.config.keep_temp.then(|| temp_path.join("upscale.log")));
 
-            let method = match self.config.upscale_quality {
-                UpscaleQuality::Best => UpscaleMethod::AudioSR {
-                    ddim_steps: app_config.upscale.audiosr.ddim_steps,
-                    guidance_scale: app_config.upscale.audiosr.guidance_scale,
-                },
-                UpscaleQuality::Fast => UpscaleMethod::FlashSR,
-            };
+            let result = self.cancellable(limited(self.upscale_limit.as_deref(), "upscale", async {
+                match upscaler.upscale(&decoded_wav, &upscaled_wav, method.clone()).await {
+                    // AudioSR is the fragile path (GPU memory, torch versions); when
+                    // asked to, FlashSR still yields an enhanced result if it falls over.
+                    Err(e)
+                        if app_config.fallback.upscale
+                            && matches!(method, UpscaleMethod::AudioSR { .. })
+                            && fallbacks.try_consume() =>
+                    {
+                        warn!("AudioSR failed, falling back to FlashSR: {}", e);
+                        let _ = self.progress_tx.send(PipelineStage::Fallback {
+                            stage: "upscale".to_string(),
+                            from: method_name.clone(),
+                            to: UpscaleMethod::FlashSR.to_string(),
+                            attempt: fallbacks.used(),
+                            max: fallbacks.max(),
+                        }).await;
+                        method_name = UpscaleMethod::FlashSR.to_string();
+                        upscaler.upscale(&decoded_wav, &upscaled_wav, UpscaleMethod::FlashSR).await
+                    }
+                    other => other,
+                }
+            })).await;
 
-            upscaler.upscale(&decoded_wav, &upscaled_wav, method).await.map_err(|e| {
+            result.map_err(|e| {
                 let _ = self.progress_tx.try_send(PipelineStage::Failed {
                     stage: "upscale".to_string(),
                     error: e.to_string(),
@@ -180,112 +713,1878 @@ impl Pipeline {
             })?;
 
             let _ = self.progress_tx.send(PipelineStage::Upscaling {
-                method: method_name.to_string(),
+                method: method_name,
                 progress: 1.0,
             }).await;
 
+            self.mark_checkpoint(&upscaled_wav).await;
             upscaled_wav
         } else {
             decoded_wav
         };
 
-        // 4. Normalize (if enabled)
-        let normalized_audio = if self.config.normalize {
-            let _ = self.progress_tx.send(PipelineStage::Normalizing {
-                target_lufs: self.config.target_lufs,
-            }).await;
+        // The models write 48kHz; in hi-res mode that is stored at 96kHz
+        let (processed_audio, sample_rate) = match enhance {
+            true if self.config.preserve_hires => {
+                let hires_wav = temp_path.join("hires.wav");
+                let resampler = Decoder::new(ffmpeg_path.clone()).with_bit_depth(self.bit_depth());
+                self.cancellable(self.on_ffmpeg(resampler.decode_to_wav(
+                    &processed_audio,
+                    &hires_wav,
+                    Some(HIRES_SAMPLE_RATE),
+                )))
+                .await
+                .map_err(|e| {
+                    let _ = self.progress_tx.try_send(PipelineStage::Failed {
+                        stage: "upscale".to_string(),
+                        error: e.to_string(),
+                    });
+                    e
+                })?;
+                (hires_wav, HIRES_SAMPLE_RATE)
+            }
+            true => (processed_audio, self.config.target_rate.unwrap_or(UPSCALE_SAMPLE_RATE)),
+            false => (processed_audio, decoded_rate),
+        };
+        timings.upscale = stage_start.elapsed();
 
-            let normalizer = Normalizer::new(ffmpeg_path.clone());
-            let normalized_wav = temp_path.join("normalized.wav");
+        // 3b. Trim and fade first, so peak and loudness measurements see the final audio
+        let stage_start = Instant::now();
+        let processed_audio = self
+            .trim_and_fade(
+                &decoder,
+                &ffmpeg_path,
+                processed_audio,
+                &temp_path,
+                &mut download_result.metadata,
+            )
+            .await?;
 
-            normalizer.normalize(
-                &processed_audio,
-                &normalized_wav,
-                self.config.target_lufs,
-                app_config.normalize.true_peak,
-                app_config.normalize.lra,
-            ).await.map_err(|e| {
-                let _ = self.progress_tx.try_send(PipelineStage::Failed {
-                    stage: "normalize".to_string(),
-                    error: e.to_string(),
-                });
+        // 3c. Upscalers can push inter-sample peaks past 0 dBFS; measure after
+        // enhancing, and limit when asked so un-normalized output doesn't clip
+        let processed_audio = if self.config.enhance || self.config.limit_peaks {
+            self.check_peaks(
+                &app_config,
+                &ffmpeg_path,
+                sample_rate,
+                processed_audio,
+                &temp_path,
+            )
+            .await?
+        } else {
+            processed_audio
+        };
+
+        // 4. Normalize (if enabled): loudnorm rewrites the samples, while
+        // ReplayGain is measured once here and tagged onto every output below
+        let mut replaygain = None;
+        let normalized_audio = match (self.config.normalize, self.config.normalize_mode) {
+            // Album ReplayGain is measured once on the shared WAV, so every
+            // format written below carries the same track and album tags
+            _ if album_slot.is_some()
+                && self.config.normalize_mode == NormalizeMode::ReplayGainTags =>
+            {
+                let slot = album_slot.expect("checked above");
+                if self.config.no_metadata {
+                    warn!("Not computing ReplayGain: no tags are written without metadata");
+                } else {
+                    replaygain = Some(
+                        self.album_replaygain(
+                            slot,
+                            &app_config,
+                            &ffmpeg_path,
+                            sample_rate,
+                            &processed_audio,
+                        )
+                        .await?,
+                    );
+                }
+                processed_audio
+            }
+            _ if album_slot.is_some() => {
+                let slot = album_slot.expect("checked above");
+                self.apply_album_gain(
+                    slot,
+                    &app_config,
+                    &ffmpeg_path,
+                    sample_rate,
+                    processed_audio,
+                    &temp_path,
+                )
+                .await?
+            }
+            _ if self.config.peak_target.is_some() => {
+                let target_dbtp = self.config.peak_target.expect("checked above");
+                let normalized_wav = temp_path.join("normalized.wav");
+                if self
+                    .checkpoint("normalize", &ffmpeg_path, &normalized_wav)
+                    .await
+                    .is_none()
+                {
+                    let _ = self
+                        .progress_tx
+                        .send(PipelineStage::NormalizingPeak { target_dbtp })
+                        .await;
+
+                    let normalizer =
+                        Normalizer::new(ffmpeg_path.clone()).with_sample_rate(sample_rate);
+                    self.cancellable(self.on_ffmpeg(normalizer.peak_normalize(
+                        &processed_audio,
+                        &normalized_wav,
+                        target_dbtp,
+                    )))
+                    .await
+                    .map_err(|e| {
+                        let _ = self.progress_tx.try_send(PipelineStage::Failed {
+                            stage: "normalize".to_string(),
+                            error: e.to_string(),
+                        });
+                        e
+                    })?;
+
+                    self.mark_checkpoint(&normalized_wav).await;
+                }
+
+                normalized_wav
+            }
+            (true, NormalizeMode::Loudnorm) => {
+                let normalized_wav = temp_path.join("normalized.wav");
+                if self
+                    .checkpoint("normalize", &ffmpeg_path, &normalized_wav)
+                    .await
+                    .is_none()
+                {
+                    let target_lufs = self.config.target_lufs;
+                    let progress_tx = self.progress_tx.clone();
+                    let on_progress = move |progress| {
+                        let _ = progress_tx.try_send(PipelineStage::Normalizing {
+                            target_lufs,
+                            progress,
+                        });
+                    };
+
+                    let normalizer =
+                        Normalizer::new(ffmpeg_path.clone()).with_sample_rate(sample_rate);
+                    let stats = self
+                        .cancellable(self.on_ffmpeg(normalizer.normalize_with_progress(
+                            &processed_audio,
+                            &normalized_wav,
+                            target_lufs,
+                            app_config.normalize.true_peak,
+                            app_config.normalize.lra,
+                            on_progress,
+                        )))
+                        .await
+                        .map_err(|e| {
+                            let _ = self.progress_tx.try_send(PipelineStage::Failed {
+                                stage: "normalize".to_string(),
+                                error: e.to_string(),
+                            });
+                            e
+                        })?;
+
+                    let _ = self
+                        .progress_tx
+                        .send(PipelineStage::Normalized { stats })
+                        .await;
+                    self.mark_checkpoint(&normalized_wav).await;
+                }
+
+                normalized_wav
+            }
+            (true, NormalizeMode::Dynamic) => {
+                let normalized_wav = temp_path.join("normalized.wav");
+                if self.checkpoint("normalize", &ffmpeg_path, &normalized_wav).await.is_none() {
+                    let _ = self.progress_tx.send(PipelineStage::NormalizingDynamic).await;
+
+                    let normalizer =
+                        Normalizer::new(ffmpeg_path.clone()).with_sample_rate(sample_rate);
+                    self.cancellable(self.on_ffmpeg(normalizer.normalize_dynamic(
+                        &processed_audio,
+                        &normalized_wav,
+                        app_config.normalize.dynamic_frame_ms,
+                        app_config.normalize.dynamic_gauss_size,
+                    ))).await.map_err(|e| {
+                        let _ = self.progress_tx.try_send(PipelineStage::Failed {
+                            stage: "normalize".to_string(),
+                            error: e.to_string(),
+                        });
+                        e
+                    })?;
+
+                    self.mark_checkpoint(&normalized_wav).await;
+                }
+
+                normalized_wav
+            }
+            (true, NormalizeMode::ReplayGainTags) if self.config.no_metadata => {
+                warn!("Not computing ReplayGain: no tags are written without metadata");
+                processed_audio
+            }
+            (true, NormalizeMode::ReplayGainTags) => {
+                let _ = self.progress_tx.send(PipelineStage::ComputingReplayGain).await;
+
+                let normalizer = Normalizer::new(ffmpeg_path.clone()).with_sample_rate(sample_rate);
+                replaygain = Some(self.cancellable(self.on_ffmpeg(normalizer.compute_replaygain(
+                    &processed_audio,
+                    app_config.normalize.true_peak,
+                    app_config.normalize.lra,
+                ))).await.map_err(|e| {
+                    let _ = self.progress_tx.try_send(PipelineStage::Failed {
+                        stage: "replaygain".to_string(),
+                        error: e.to_string(),
+                    });
+                    e
+                })?);
+
+                processed_audio
+            }
+            (false, _) => processed_audio,
+        };
+        timings.normalize = stage_start.elapsed();
+
+        // Optionally cut the processed audio into one track per chapter
+        let tracks = if self.config.split_chapters {
+            self.split_tracks(
+                &decoder,
+                &normalized_audio,
+                &temp_path,
+                &download_result.metadata,
+            )
+            .await?
+        } else {
+            vec![Track::whole(&normalized_audio, &download_result.metadata)]
+        };
+
+        // 5-6. Encode and tag every requested format from the same processed
+        // WAV, so all copies share one loudness pass and identical tags
+        tokio::fs::create_dir_all(&self.config.output_dir).await?;
+
+        let encoder = Encoder::new(ffmpeg_path.clone()).with_strip_tags(self.config.no_metadata);
+        let embedder = MetadataEmbedder::new(ffmpeg_path.clone())
+            .with_square_artwork(app_config.metadata.square_artwork)
+            .with_id3_version(app_config.metadata.id3_version.parse().unwrap_or_default())
+            .with_spectrogram(self.config.embed_spectrogram);
+        let mut outputs = Vec::new();
+        let (primary_format, encode_options) =
+            self.resolve_format(&app_config, &download_result.metadata, enhance);
+
+        for (index, track) in tracks.iter().enumerate() {
+            for format in self.output_formats(primary_format) {
+                // Decided before encoding, as the embed below writes with -y
+                let path = self.final_path(&track.name, &track.metadata, format);
+                let Some(final_path) = self.config.on_exists.apply(path.clone()) else {
+                    info!("Keeping existing output: {}", path.display());
+                    // Its length is not this run's to check
+                    outputs.push((path, None));
+                    continue;
+                };
+
+                // 5. Encode to target format
+                let _ = self.progress_tx.send(PipelineStage::Encoding {
+                    format: format.to_string(),
+                    progress: 0.0,
+                }).await;
+                let progress_tx = self.progress_tx.clone();
+                let on_progress = move |progress| {
+                    let _ = progress_tx.try_send(PipelineStage::Encoding {
+                        format: format.to_string(),
+                        progress,
+                    });
+                };
+                let stage_start = Instant::now();
+
+                let encoded_file = if tracks.len() > 1 {
+                    temp_path.join(format!("encoded_{:02}.{}", index + 1, format.extension()))
+                } else {
+                    temp_path.join(format!("encoded.{}", format.extension()))
+                };
+
+                self.cancellable(self.on_ffmpeg(encoder.encode_with_progress(
+                    &track.audio,
+                    &encoded_file,
+                    format,
+                    &encode_options,
+                    track.duration,
+                    on_progress,
+                ))).await.map_err(|e| {
+                    let _ = self.progress_tx.try_send(PipelineStage::Failed {
+                        stage: "encode".to_string(),
+                        error: e.to_string(),
+                    });
+                    e
+                })?;
+                timings.encode += stage_start.elapsed();
+
+                if let Some(parent) = final_path.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                // Under `rename` the name is only settled once claimed, so
+                // that parallel runs can't both pick the same free one
+                let claimed = self.config.on_exists == OverwritePolicy::Rename;
+                let final_path = if claimed { claim_free_path(&path)? } else { final_path };
+                if final_path != path {
+                    info!("{} exists, writing {}", path.display(), final_path.display());
+                }
+                // A claimed name left empty by a failed write is given back
+                let release = || {
+                    if claimed {
+                        let _ = std::fs::remove_file(&final_path);
+                    }
+                };
+
+                // With `no_metadata` the untagged encode is the output as it is
+                if self.config.no_metadata {
+                    tokio::fs::copy(&encoded_file, &final_path).await.map_err(|e| {
+                        release();
+                        e
+                    })?;
+                    *wrote = true;
+                    outputs.push((final_path, track.duration));
+                    continue;
+                }
+
+                // 6. Embed metadata
+                let _ = self.progress_tx.send(PipelineStage::EmbeddingMetadata).await;
+                let stage_start = Instant::now();
+
+                let embed = async {
+                    if self.config.embed_artwork {
+                        embedder.embed(
+                            &encoded_file,
+                            &final_path,
+                            &track.metadata,
+                            download_result.thumbnail_path.as_deref(),
+                            replaygain.as_ref(),
+                        ).await
+                    } else {
+                        embedder
+                            .embed_simple(
+                                &encoded_file,
+                                &final_path,
+                                &track.metadata,
+                                replaygain.as_ref(),
+                            )
+                            .await
+                    }
+                };
+                self.cancellable(self.on_ffmpeg(embed)).await.map_err(|e| {
+                    let _ = self.progress_tx.try_send(PipelineStage::Failed {
+                        stage: "metadata".to_string(),
+                        error: e.to_string(),
+                    });
+                    release();
+                    e
+                })?;
+                timings.metadata += stage_start.elapsed();
+
+                *wrote = true;
+                outputs.push((final_path, track.duration));
+            }
+        }
+
+        // 7. Confirm every file holds audio before reporting success
+        if self.config.verify {
+            let _ = self.progress_tx.send(PipelineStage::Verifying).await;
+            // SponsorBlock cuts shorten the audio by an unknown amount
+            let cut = self.config.sponsorblock.is_some();
+            for (path, expected) in &outputs {
+                self.verify_output(&ffmpeg_path, path, expected.filter(|_| !cut))
+                    .await
+                    .map_err(|e| {
+                        let _ = self.progress_tx.try_send(PipelineStage::Failed {
+                            stage: "verify".to_string(),
+                            error: e.to_string(),
+                        });
+                        e
+                    })?;
+            }
+        }
+
+        let mut outputs = outputs.into_iter().map(|(path, _)| path);
+        let final_path = outputs.next().expect("at least one output is always written");
+        let also: Vec<PathBuf> = outputs.collect();
+        for extra in &also {
+            info!("Also wrote: {}", extra.display());
+        }
+
+        let duration = start_time.elapsed();
+        info!(
+            "Pipeline complete: {} ({:.1}s, {}/{} fallbacks used)",
+            final_path.display(),
+            duration.as_secs_f32(),
+            fallbacks.used(),
+            fallbacks.max()
+        );
+
+        let _ = self.progress_tx.send(PipelineStage::Complete {
+            output: final_path.clone(),
+            also,
+            duration,
+            timings,
+        }).await;
+
+        // Cleanup temp directory (a no-op when keep_temp detached it)
+        drop(temp_dir);
+        if self.config.resume && !keep_temp {
+            if let Err(e) = tokio::fs::remove_dir_all(&temp_path).await {
+                warn!("Failed to remove {}: {}", temp_path.display(), e);
+            }
+        }
+
+        Ok(final_path)
+    }
+
+    /// Run `hooks.post_success` for a written item, `hooks.post_failure` for
+    /// a failed one. Items skipped as existing or archived, items whose
+    /// outputs all existed already, and cancelled runs, run neither.
+    async fn run_hooks(
+        &self,
+        result: &Result<PathBuf, YtAudioError>,
+        title: Option<&str>,
+        wrote: bool,
+    ) {
+        let url = match self.config.input {
+            PipelineInput::YouTubeUrl(ref url) => url.clone(),
+            PipelineInput::LocalFile(ref path, _) => path.display().to_string(),
+        };
+        let error = match result {
+            Ok(_) if !wrote => return,
+            Err(
+                YtAudioError::Download(DownloadError::AlreadyArchived(_)) | YtAudioError::Cancelled,
+            ) => return,
+            Ok(_) => None,
+            Err(e) => Some(e.to_string()),
+        };
+        let context = HookContext {
+            output: result.as_ref().ok().map(PathBuf::as_path),
+            url: &url,
+            title,
+            error: error.as_deref(),
+        };
+        run_hooks(&self.config.hooks, &context).await;
+    }
+
+    async fn download(
+        &self,
+        app_config: &Config,
+        url: &str,
+        temp_path: &Path,
+    ) -> Result<DownloadResult, YtAudioError> {
+        let _ = self.progress_tx.send(PipelineStage::Downloading {
+            progress: 0.0,
+            title: "Starting download...".to_string(),
+        }).await;
+
+        let downloader = self.downloader(app_config, temp_path.to_path_buf())?;
+        let progress_tx = self.progress_tx.clone();
+        let on_progress = move |progress| {
+            let _ = progress_tx.try_send(PipelineStage::Downloading {
+                progress,
+                title: url.to_string(),
+            });
+        };
+        let download = downloader.download_with_progress(url, on_progress);
+        let download_result = self
+            .cancellable(self.on_network(download))
+            .await
+            .map_err(|e| {
+                let stage = match &e {
+                    YtAudioError::Download(DownloadError::AlreadyArchived(_)) => {
+                        PipelineStage::AlreadyArchived
+                    }
+                    e => PipelineStage::Failed {
+                        stage: "download".to_string(),
+                        error: e.to_string(),
+                    },
+                };
+                let _ = self.progress_tx.try_send(stage);
                 e
             })?;
 
-            normalized_wav
+        let _ = self.progress_tx.send(PipelineStage::Downloading {
+            progress: 1.0,
+            title: download_result.metadata.title.clone(),
+        }).await;
+
+        Ok(download_result)
+    }
+
+    /// Report the resolved stages and their exact commands without running them.
+    ///
+    /// Nothing is downloaded or written. For URLs the metadata is fetched to
+    /// name the output; if that fails the path keeps the template placeholders.
+    async fn plan(
+        &self,
+        app_config: &Config,
+        ffmpeg_path: PathBuf,
+        python_path: PathBuf,
+    ) -> Result<PathBuf, YtAudioError> {
+        info!("Dry run for: {}", self.config.input);
+
+        let temp_path = app_config.temp_dir().join("ytaudio-dry-run");
+
+        let (audio_path, placeholder) = match self.config.input {
+            PipelineInput::YouTubeUrl(ref url) => {
+                let downloader = self.downloader(app_config, temp_path.clone())?;
+                self.emit_plan("download", &downloader.command(url)).await;
+
+                match self.cancellable(self.on_network(downloader.fetch_metadata(url))).await {
+                    Ok(mut metadata) => {
+                        self.clean_metadata(&mut metadata);
+                        (temp_path.join(format!("{}.{{ext}}", metadata.id)), metadata)
+                    }
+                    Err(YtAudioError::Cancelled) => return Err(YtAudioError::Cancelled),
+                    Err(e) => {
+                        warn!(
+                            "Cannot fetch the video metadata, the output path keeps its \
+                             placeholders: {}",
+                            e
+                        );
+                        let placeholder = VideoMetadata {
+                            id: "{id}".to_string(),
+                            title: "{title}".to_string(),
+                            artist: Some("{artist}".to_string()),
+                            uploader: Some("{uploader}".to_string()),
+                            upload_date: Some("{date}".to_string()),
+                            ..Default::default()
+                        };
+                        (temp_path.join("{id}.{ext}"), placeholder)
+                    }
+                }
+            }
+            PipelineInput::LocalFile(ref path, ref metadata) => (path.clone(), metadata.clone()),
+        };
+
+        let decoded_wav = temp_path.join("decoded.wav");
+        let decoder = self.decoder(app_config, &ffmpeg_path);
+        self.emit_plan(
+            "decode",
+            &decoder.command(&audio_path, &decoded_wav, self.decode_sample_rate()),
+        )
+        .await;
+        // The source rate is unknown before download; plan with the usual one
+        let sample_rate = match self.config.enhance {
+            true if self.config.preserve_hires => HIRES_SAMPLE_RATE,
+            true => self.config.target_rate.unwrap_or(UPSCALE_SAMPLE_RATE),
+            false => self.decode_sample_rate().unwrap_or(DEFAULT_SAMPLE_RATE),
+        };
+
+        let processed_audio = if self.config.enhance {
+            if !self.config.force_upscale {
+                self.emit_plan("bandwidth check", &decoder.high_band_command(&decoded_wav)).await;
+            }
+            let upscaled_wav = temp_path.join("upscaled.wav");
+            let upscaler = Upscaler::new(python_path)
+                .with_device(self.config.device.clone())
+                .with_output_rate(self.config.target_rate);
+            let method = self.upscale_method(app_config);
+            self.emit_plan(
+                &format!("upscale ({})", method),
+                &upscaler.command(&decoded_wav, &upscaled_wav, &method),
+            ).await;
+            if self.config.preserve_hires {
+                let hires_wav = temp_path.join("hires.wav");
+                let resampler = Decoder::new(ffmpeg_path.clone()).with_bit_depth(self.bit_depth());
+                self.emit_plan(
+                    "hi-res resample",
+                    &resampler.command(&upscaled_wav, &hires_wav, Some(HIRES_SAMPLE_RATE)),
+                )
+                .await;
+                hires_wav
+            } else {
+                upscaled_wav
+            }
+        } else {
+            decoded_wav
+        };
+
+        if self.config.trim_silence {
+            self.emit_plan(
+                "trim silence (measurement pass)",
+                &decoder.silence_command(&processed_audio),
+            )
+            .await;
+        }
+
+        let processed_audio = if self.config.limit_peaks {
+            let normalizer = Normalizer::new(ffmpeg_path.clone()).with_sample_rate(sample_rate);
+            let ceiling = app_config.normalize.true_peak;
+            self.emit_plan(
+                "true peak (measurement pass)",
+                &normalizer.true_peak_command(&processed_audio),
+            )
+            .await;
+
+            let limited_wav = temp_path.join("limited.wav");
+            self.emit_plan(
+                &format!("limit true peak to {:.1} dBTP (if above)", ceiling),
+                &normalizer.limit_command(&processed_audio, &limited_wav, ceiling),
+            ).await;
+            limited_wav
         } else {
             processed_audio
         };
 
-        // 5. Encode to target format
-        let format_str = self.config.format.to_string();
-        let _ = self.progress_tx.send(PipelineStage::Encoding {
-            format: format_str.clone(),
-        }).await;
+        let normalized_audio = match (self.config.normalize, self.config.normalize_mode) {
+            _ if self.album.is_some() => {
+                let normalizer = Normalizer::new(ffmpeg_path.clone()).with_sample_rate(sample_rate);
+                let command = normalizer.measure_command(
+                    &processed_audio,
+                    self.config.target_lufs,
+                    app_config.normalize.true_peak,
+                    app_config.normalize.lra,
+                );
+                self.emit_plan(
+                    "album loudness (measurement pass, gain applied once all tracks are in)",
+                    &command,
+                )
+                .await;
+                temp_path.join("album.wav")
+            }
+            _ if self.config.peak_target.is_some() => {
+                let target_dbtp = self.config.peak_target.expect("checked above");
+                let normalizer = Normalizer::new(ffmpeg_path.clone()).with_sample_rate(sample_rate);
+                let normalized_wav = temp_path.join("normalized.wav");
+                self.emit_plan(
+                    "true peak (measurement pass)",
+                    &normalizer.true_peak_command(&processed_audio),
+                )
+                .await;
+                self.emit_plan(
+                    &format!(
+                        "peak normalize to {:.1} dBTP (gain from the measured peak)",
+                        target_dbtp
+                    ),
+                    &normalizer.gain_command(
+                        &processed_audio,
+                        &normalized_wav,
+                        0.0,
+                        Some(target_dbtp),
+                    ),
+                )
+                .await;
+                normalized_wav
+            }
+            (true, NormalizeMode::Loudnorm) => {
+                let normalizer = Normalizer::new(ffmpeg_path.clone()).with_sample_rate(sample_rate);
+                let command = normalizer.measure_command(
+                    &processed_audio,
+                    self.config.target_lufs,
+                    app_config.normalize.true_peak,
+                    app_config.normalize.lra,
+                );
+                self.emit_plan(
+                    &format!("normalize to {:.1} LUFS (measurement pass)", self.config.target_lufs),
+                    &command,
+                ).await;
+                temp_path.join("normalized.wav")
+            }
+            (true, NormalizeMode::Dynamic) => {
+                let normalizer = Normalizer::new(ffmpeg_path.clone()).with_sample_rate(sample_rate);
+                let normalized_wav = temp_path.join("normalized.wav");
+                let command = normalizer.dynamic_command(
+                    &processed_audio,
+                    &normalized_wav,
+                    app_config.normalize.dynamic_frame_ms,
+                    app_config.normalize.dynamic_gauss_size,
+                );
+                self.emit_plan("dynamic normalize (single pass)", &command).await;
+                normalized_wav
+            }
+            (true, NormalizeMode::ReplayGainTags) => {
+                let normalizer = Normalizer::new(ffmpeg_path.clone()).with_sample_rate(sample_rate);
+                let command = normalizer.measure_command(
+                    &processed_audio,
+                    REPLAYGAIN_REFERENCE_LUFS,
+                    app_config.normalize.true_peak,
+                    app_config.normalize.lra,
+                );
+                self.emit_plan("replaygain (measurement pass)", &command).await;
+                processed_audio
+            }
+            (false, _) => processed_audio,
+        };
+
+        let embedder = MetadataEmbedder::new(ffmpeg_path.clone())
+            .with_square_artwork(app_config.metadata.square_artwork)
+            .with_id3_version(app_config.metadata.id3_version.parse().unwrap_or_default())
+            .with_spectrogram(self.config.embed_spectrogram);
+        let encoder = Encoder::new(ffmpeg_path).with_strip_tags(self.config.no_metadata);
+        let mut outputs = Vec::new();
+        let (primary_format, encode_options) =
+            self.resolve_format(app_config, &placeholder, self.config.enhance);
+
+        for format in self.output_formats(primary_format) {
+            let encoded_file = temp_path.join(format!("encoded.{}", format.extension()));
+            self.emit_plan(
+                &format!("encode ({})", format),
+                &encoder.command(
+                    &normalized_audio,
+                    &encoded_file,
+                    format,
+                    &encode_options,
+                ),
+            ).await;
 
-        let encoder = Encoder::new(ffmpeg_path.clone());
-        let encoded_file = temp_path.join(format!(
-            "encoded.{}",
-            self.config.format.extension()
-        ));
+            let final_path = self.final_path(&placeholder.title, &placeholder, format);
+            if !self.config.no_metadata {
+                if embedder.embeds_spectrogram(&final_path) {
+                    self.emit_plan("spectrogram", &embedder.spectrogram_command(&encoded_file))
+                        .await;
+                }
+                self.emit_plan(
+                    "metadata",
+                    &embedder.command(&encoded_file, &final_path, &placeholder, None, None),
+                )
+                .await;
+            }
+            outputs.push(final_path);
+        }
+
+        Ok(outputs.remove(0))
+    }
 
-        let encoder_format = match self.config.format {
-            args::OutputFormat::Flac => encoder::OutputFormat::Flac,
-            args::OutputFormat::Wav => encoder::OutputFormat::Wav,
-            args::OutputFormat::Mp3 => encoder::OutputFormat::Mp3,
-            args::OutputFormat::Aac => encoder::OutputFormat::Aac,
-            args::OutputFormat::Opus => encoder::OutputFormat::Opus,
+    /// Whether the decoded audio already has real content above
+    /// [`HIGH_BAND_CUTOFF_HZ`], making upscaling pointless. Always `false`
+    /// with `force_upscale`, or when the measurement fails.
+    async fn is_full_band(&self, decoder: &Decoder, audio: &Path) -> Result<bool, YtAudioError> {
+        if self.config.force_upscale {
+            return Ok(false);
+        }
+
+        let level = match self.cancellable(self.on_ffmpeg(decoder.high_band_level(audio))).await {
+            Ok(Some(level)) => level,
+            Ok(None) => return Ok(false),
+            Err(YtAudioError::Cancelled) => return Err(YtAudioError::Cancelled),
+            Err(e) => {
+                warn!("Bandwidth check failed, upscaling anyway: {}", e);
+                return Ok(false);
+            }
         };
+        debug!("Mean level above {} Hz: {:.1} dBFS", HIGH_BAND_CUTOFF_HZ, level);
 
-        encoder.encode(&normalized_audio, &encoded_file, encoder_format).await.map_err(|e| {
+        if level <= FULL_BAND_LEVEL_DB {
+            return Ok(false);
+        }
+        warn!(
+            "Source already has content above {} Hz ({:.1} dBFS), skipping upscaling (use \
+             --force-upscale)",
+            HIGH_BAND_CUTOFF_HZ, level
+        );
+        let _ = self
+            .progress_tx
+            .send(PipelineStage::UpscaleSkipped {
+                high_band_level: level,
+            })
+            .await;
+        Ok(true)
+    }
+
+    /// Cut leading and trailing silence and apply the fades, as configured.
+    /// Chapter times and the expected duration follow the trimmed timeline.
+    async fn trim_and_fade(
+        &self,
+        decoder: &Decoder,
+        ffmpeg_path: &Path,
+        audio: PathBuf,
+        temp_path: &Path,
+        metadata: &mut VideoMetadata,
+    ) -> Result<PathBuf, YtAudioError> {
+        let fade_in = f64::from(self.config.fade_in.unwrap_or(0.0));
+        let fade_out = f64::from(self.config.fade_out.unwrap_or(0.0));
+        if !self.config.trim_silence && fade_in <= 0.0 && fade_out <= 0.0 {
+            return Ok(audio);
+        }
+
+        let duration = match self.cancellable(probe_audio_duration(ffmpeg_path, &audio)).await {
+            Ok(Some(duration)) => duration,
+            Ok(None) | Err(YtAudioError::Io(_)) => {
+                warn!("Cannot measure the audio length, skipping silence trimming and fades");
+                return Ok(audio);
+            }
+            Err(e) => return Err(e),
+        };
+
+        let _ = self.progress_tx.send(PipelineStage::Trimming).await;
+        let fail = |e: YtAudioError| {
             let _ = self.progress_tx.try_send(PipelineStage::Failed {
-                stage: "encode".to_string(),
+                stage: "trim".to_string(),
                 error: e.to_string(),
             });
             e
-        })?;
+        };
 
-        // 6. Embed metadata
-        let _ = self.progress_tx.send(PipelineStage::EmbeddingMetadata).await;
+        let (start, end) = match self.config.trim_silence {
+            true => self
+                .cancellable(self.on_ffmpeg(decoder.audible_range(&audio, duration)))
+                .await
+                .map_err(fail)?,
+            false => (0.0, duration),
+        };
+        if (start, end) == (0.0, duration) && fade_in <= 0.0 && fade_out <= 0.0 {
+            debug!("No silence to trim");
+            return Ok(audio);
+        }
 
-        let safe_title = sanitize_filename(&download_result.metadata.title);
-        let final_filename = format!("{}.{}", safe_title, self.config.format.extension());
-        let final_path = self.config.output_dir.join(&final_filename);
+        let trimmed_wav = temp_path.join("trimmed.wav");
+        self.cancellable(self.on_ffmpeg(decoder.trim(
+            &audio,
+            &trimmed_wav,
+            (start, end),
+            fade_in,
+            fade_out,
+        )))
+        .await
+        .map_err(fail)?;
 
-        // Ensure output directory exists
-        tokio::fs::create_dir_all(&self.config.output_dir).await?;
+        if (start, end) != (0.0, duration) {
+            info!(
+                "Trimmed {:.2}s of leading and {:.2}s of trailing silence",
+                start,
+                duration - end
+            );
+            shift_timeline(metadata, start, end - start);
+        }
+
+        Ok(trimmed_wav)
+    }
 
-        let embedder = MetadataEmbedder::new(ffmpeg_path);
-        embedder.embed(
-            &encoded_file,
-            &final_path,
-            &download_result.metadata,
-            download_result.thumbnail_path.as_deref(),
-        ).await.map_err(|e| {
+    /// Measure the true peak and report it; with `limit_peaks`, return a
+    /// limited copy when it exceeds the configured ceiling
+    async fn check_peaks(
+        &self,
+        app_config: &Config,
+        ffmpeg_path: &Path,
+        sample_rate: u32,
+        audio: PathBuf,
+        temp_path: &Path,
+    ) -> Result<PathBuf, YtAudioError> {
+        let normalizer = Normalizer::new(ffmpeg_path.to_path_buf()).with_sample_rate(sample_rate);
+        let ceiling = app_config.normalize.true_peak;
+
+        let true_peak = self
+            .cancellable(self.on_ffmpeg(normalizer.measure_true_peak(&audio)))
+            .await
+            .map_err(|e| {
+                let _ = self.progress_tx.try_send(PipelineStage::Failed {
+                    stage: "peak".to_string(),
+                    error: e.to_string(),
+                });
+                e
+            })?;
+        let _ = self
+            .progress_tx
+            .send(PipelineStage::PeakMeasured { true_peak })
+            .await;
+
+        if true_peak <= ceiling {
+            return Ok(audio);
+        }
+        if !self.config.limit_peaks {
+            if true_peak > 0.0 && !self.config.normalize && self.config.peak_target.is_none() {
+                warn!("True peak {:.1} dBTP will clip on playback (use --limit-peaks)", true_peak);
+            }
+            return Ok(audio);
+        }
+
+        let _ = self.progress_tx.send(PipelineStage::Limiting { ceiling }).await;
+
+        let limited_wav = temp_path.join("limited.wav");
+        self.cancellable(self.on_ffmpeg(normalizer.limit_true_peak(&audio, &limited_wav, ceiling)))
+            .await
+            .map_err(|e| {
+                let _ = self.progress_tx.try_send(PipelineStage::Failed {
+                    stage: "limit".to_string(),
+                    error: e.to_string(),
+                });
+                e
+            })?;
+        Ok(limited_wav)
+    }
+
+    /// Measure this track, wait for the rest of the album, then apply the
+    /// album's gain. Only tracks the gain would push past the true peak
+    /// ceiling go through the limiter.
+    async fn apply_album_gain(
+        &self,
+        slot: AlbumSlot<'_>,
+        app_config: &Config,
+        ffmpeg_path: &Path,
+        sample_rate: u32,
+        audio: PathBuf,
+        temp_path: &Path,
+    ) -> Result<PathBuf, YtAudioError> {
+        let normalizer = Normalizer::new(ffmpeg_path.to_path_buf()).with_sample_rate(sample_rate);
+        let ceiling = app_config.normalize.true_peak;
+        let fail = |e: YtAudioError| {
             let _ = self.progress_tx.try_send(PipelineStage::Failed {
-                stage: "metadata".to_string(),
+                stage: "normalize".to_string(),
                 error: e.to_string(),
             });
             e
-        })?;
+        };
 
-        let duration = start_time.elapsed();
-        info!("Pipeline complete: {} ({:.1}s)", final_path.display(), duration.as_secs_f32());
+        let stats = self
+            .cancellable(self.on_ffmpeg(normalizer.measure(
+                &audio,
+                self.config.target_lufs,
+                ceiling,
+                app_config.normalize.lra,
+            )))
+            .await
+            .map_err(fail)?;
+        let duration = self
+            .cancellable(probe_audio_duration(ffmpeg_path, &audio))
+            .await
+            .map_err(fail)?
+            .unwrap_or_default();
+        let _ = self.progress_tx.send(PipelineStage::AlbumMeasured {
+            integrated_lufs: stats.input_i,
+        }).await;
 
-        let _ = self.progress_tx.send(PipelineStage::Complete {
-            output: final_path.clone(),
-            duration,
+        let peak = 10f32.powf(stats.input_tp / 20.0);
+        let album = self
+            .cancellable(async {
+                Ok::<_, YtAudioError>(slot.submit(stats.input_i, peak, duration).await)
+            })
+            .await?;
+        let _ = self.progress_tx.send(PipelineStage::ApplyingAlbumGain { gain: album.gain }).await;
+
+        let album_wav = temp_path.join("album.wav");
+        let limit = (stats.input_tp + album.gain > ceiling).then_some(ceiling);
+        self.cancellable(
+            self.on_ffmpeg(normalizer.apply_gain(&audio, &album_wav, album.gain, limit)),
+        )
+        .await
+        .map_err(fail)?;
+        Ok(album_wav)
+    }
+
+    /// Measure this track's ReplayGain, wait for the rest of the album, then
+    /// add the album values. The samples are left untouched.
+    async fn album_replaygain(
+        &self,
+        slot: AlbumSlot<'_>,
+        app_config: &Config,
+        ffmpeg_path: &Path,
+        sample_rate: u32,
+        audio: &Path,
+    ) -> Result<ReplayGain, YtAudioError> {
+        let normalizer = Normalizer::new(ffmpeg_path.to_path_buf()).with_sample_rate(sample_rate);
+        let fail = |e: YtAudioError| {
+            let _ = self.progress_tx.try_send(PipelineStage::Failed {
+                stage: "replaygain".to_string(),
+                error: e.to_string(),
+            });
+            e
+        };
+
+        let _ = self.progress_tx.send(PipelineStage::ComputingReplayGain).await;
+        let replaygain = self
+            .cancellable(self.on_ffmpeg(normalizer.compute_replaygain(
+                audio,
+                app_config.normalize.true_peak,
+                app_config.normalize.lra,
+            )))
+            .await
+            .map_err(fail)?;
+        let duration = self
+            .cancellable(probe_audio_duration(ffmpeg_path, audio))
+            .await
+            .map_err(fail)?
+            .unwrap_or_default();
+        let integrated_lufs = replaygain
+            .track_gain
+            .map_or(f32::NEG_INFINITY, |gain| REPLAYGAIN_REFERENCE_LUFS - gain);
+        let _ = self.progress_tx.send(PipelineStage::AlbumMeasured { integrated_lufs }).await;
+
+        let album = self
+            .cancellable(async {
+                Ok::<_, YtAudioError>(
+                    slot.submit(integrated_lufs, replaygain.track_peak, duration)
+                        .await,
+                )
+            })
+            .await?;
+        Ok(replaygain.with_album(&album))
+    }
+
+    /// Probe a written file and fail unless it holds audio whose length is
+    /// within max(2s, 2%) of `expected`. Skipped with a warning when ffprobe
+    /// is unavailable.
+    async fn verify_output(
+        &self,
+        ffmpeg_path: &Path,
+        path: &Path,
+        expected: Option<f64>,
+    ) -> Result<(), YtAudioError> {
+        let actual = match self
+            .cancellable(probe_audio_duration(ffmpeg_path, path))
+            .await
+        {
+            Ok(actual) => actual,
+            Err(YtAudioError::Io(e)) => {
+                warn!("Cannot run ffprobe, skipping verification of {}: {}", path.display(), e);
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+
+        let Some(actual) = actual else {
+            return Err(YtAudioError::Pipeline(format!(
+                "{} has no audio stream or zero duration",
+                path.display()
+            )));
+        };
+        if let Some(expected) = expected {
+            let tolerance = (expected * 0.02).max(2.0);
+            if (actual - expected).abs() > tolerance {
+                return Err(YtAudioError::Pipeline(format!(
+                    "{} is {:.1}s long, expected about {:.1}s",
+                    path.display(),
+                    actual,
+                    expected
+                )));
+            }
+        }
+        debug!("Verified {} ({:.1}s)", path.display(), actual);
+        Ok(())
+    }
+
+    /// Cut the processed audio at the chapter marks, one track per chapter.
+    /// Falls back to the whole file when the video has no chapters.
+    async fn split_tracks(
+        &self,
+        decoder: &Decoder,
+        audio: &Path,
+        temp_path: &Path,
+        metadata: &VideoMetadata,
+    ) -> Result<Vec<Track>, YtAudioError> {
+        if metadata.chapters.is_empty() {
+            warn!("No chapters found, writing a single file");
+            return Ok(vec![Track::whole(audio, metadata)]);
+        }
+
+        let total = metadata.chapters.len();
+        let _ = self.progress_tx.send(PipelineStage::Splitting { chapters: total }).await;
+
+        let album = metadata.album.clone().unwrap_or_else(|| metadata.title.clone());
+        let mut tracks = Vec::with_capacity(total);
+
+        for (i, chapter) in metadata.chapters.iter().enumerate() {
+            let number = i + 1;
+            let segment = temp_path.join(format!("segment_{:02}.wav", number));
+
+            self.cancellable(self.on_ffmpeg(decoder.cut_segment(
+                audio,
+                &segment,
+                chapter.start_time,
+                chapter.end_time,
+            )))
+            .await
+            .map_err(|e| {
+                let _ = self.progress_tx.try_send(PipelineStage::Failed {
+                    stage: "split".to_string(),
+                    error: e.to_string(),
+                });
+                e
+            })?;
+
+            tracks.push(Track {
+                audio: segment,
+                name: format!("{} - {:02} - {}", album, number, chapter.title),
+                metadata: VideoMetadata {
+                    title: chapter.title.clone(),
+                    album: Some(album.clone()),
+                    track: Some((number, total)),
+                    chapters: Vec::new(),
+                    // Timed lyrics follow the whole video, not the chapter
+                    lyrics: None,
+                    ..metadata.clone()
+                },
+                duration: Some(chapter.end_time - chapter.start_time),
+            });
+        }
+
+        Ok(tracks)
+    }
+
+    /// Fetch the metadata with a download-free yt-dlp call and return the
+    /// primary output path if every requested format is already on disk
+    async fn existing_output(
+        &self,
+        app_config: &Config,
+    ) -> Result<Option<PathBuf>, YtAudioError> {
+        if self.config.split_chapters {
+            // Chapter file names are only known after the full metadata download
+            debug!("Cannot predict split-chapter outputs, not skipping");
+            return Ok(None);
+        }
+
+        let mut metadata = match self.config.input {
+            PipelineInput::YouTubeUrl(ref url) => {
+                let downloader = self.downloader(app_config, app_config.temp_dir())?;
+                self.cancellable(self.on_network(downloader.fetch_metadata(url)))
+                    .await
+                    .map_err(|e| {
+                        let _ = self.progress_tx.try_send(PipelineStage::Failed {
+                            stage: "download".to_string(),
+                            error: e.to_string(),
+                        });
+                        e
+                    })?
+            }
+            PipelineInput::LocalFile(_, ref metadata) => metadata.clone(),
+        };
+        self.clean_metadata(&mut metadata);
+
+        // Whether upscaling will run isn't known yet: a full-band source skips
+        // it, which can change the `auto` format, so accept either outcome
+        let lossy = app_config.output.auto_lossy_format.parse().unwrap_or(OutputFormat::Opus);
+        let mut primaries = Vec::new();
+        for upscaled in [self.config.enhance, false] {
+            let (format, _) = self.config.format.resolve(
+                lossy,
+                metadata.acodec.as_deref(),
+                metadata.abr,
+                upscaled,
+            );
+            if !primaries.contains(&format) {
+                primaries.push(format);
+            }
+        }
+
+        for primary_format in primaries {
+            let mut outputs = self
+                .output_formats(primary_format)
+                .into_iter()
+                .map(|format| self.final_path(&metadata.title, &metadata, format));
+            let primary = outputs.next().expect("primary format is always present");
+            if primary.exists() && outputs.all(|p| p.exists()) {
+                return Ok(Some(primary));
+            }
+        }
+        Ok(None)
+    }
+
+    /// FFmpeg decoder with this run's filters, bit depth and channels, and the configured hwaccel
+    fn decoder(&self, app_config: &Config, ffmpeg_path: &Path) -> Decoder {
+        Decoder::new(ffmpeg_path.to_path_buf())
+            .with_hwaccel(app_config.decode.hwaccel.clone())
+            .with_filters(self.config.highpass, self.config.lowpass)
+            .with_extra_filters(self.config.extra_af.clone())
+            .with_clip(self.config.clip)
+            .with_bit_depth(self.bit_depth())
+            .with_channels(self.config.encode_options.channels)
+            .with_max_sample_rate(self.config.preserve_hires.then_some(HIRES_SAMPLE_RATE))
+    }
+
+    /// Sample format of the working WAVs; `preserve_hires` keeps at least 24 bits
+    fn bit_depth(&self) -> BitDepth {
+        match self.config.encode_options.bit_depth {
+            BitDepth::Int16 if self.config.preserve_hires => BitDepth::Int24,
+            bit_depth => bit_depth,
+        }
+    }
+
+    /// yt-dlp wrapper with this run's SponsorBlock, network, retry and archive settings
+    fn downloader(
+        &self,
+        app_config: &Config,
+        temp_dir: PathBuf,
+    ) -> Result<Downloader, YtAudioError> {
+        Ok(Downloader::new(app_config.yt_dlp_path()?, temp_dir)
+            .with_sponsorblock(self.config.sponsorblock.clone())
+            .with_proxy(self.config.proxy.clone())
+            .with_rate_limit(self.config.rate_limit.clone())
+            .with_extractor_args(self.config.extractor_args.clone())
+            .with_retries(self.config.max_retries)
+            .with_timeout(Some(Duration::from_secs(app_config.download.timeout_secs)))
+            .with_archive(self.config.archive_file.clone())
+            .with_lyrics(self.config.lyrics.clone())
+            .with_format_selector(self.config.format_selector.clone())
+            .with_thumbnail(self.config.embed_artwork && !self.config.no_metadata))
+    }
+
+    /// Run one stage, abandoning it as soon as the cancel flag fires. Dropping
+    /// the stage future kills its child process (`kill_on_drop`).
+    async fn cancellable<T, E>(
+        &self,
+        stage: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, YtAudioError>
+    where
+        YtAudioError: From<E>,
+    {
+        tokio::select! {
+            biased;
+            _ = self.cancel.cancelled() => {
+                info!("Cancelled");
+                Err(YtAudioError::Cancelled)
+            }
+            result = stage => result.map_err(|e| match YtAudioError::from(e) {
+                e if is_disk_full(&e.to_string()) => {
+                    insufficient_space(format!("a stage ran out of disk space ({})", e))
+                }
+                e => e,
+            }),
+        }
+    }
+
+    /// Fail before decoding when the temp filesystem can't hold the working
+    /// WAVs of a track `duration` seconds long. An estimate: each stage that
+    /// rewrites the audio keeps its own copy until the run ends. Skipped when
+    /// the duration or the free space is unknown.
+    fn check_temp_space(
+        &self,
+        temp_path: &Path,
+        duration: Option<f64>,
+    ) -> Result<(), YtAudioError> {
+        let (Some(duration), Some(available)) = (duration, available_space(temp_path)) else {
+            return Ok(());
+        };
+        let needed = self.temp_space_needed(duration);
+        debug!("Temp space: about {} MB needed, {} MB free", needed >> 20, available >> 20);
+        if needed > available {
+            return Err(insufficient_space(format!(
+                "about {} MB needed in {}, {} MB free",
+                needed >> 20,
+                temp_path.display(),
+                available >> 20
+            )));
+        }
+        Ok(())
+    }
+
+    /// Estimated bytes of working WAVs for `duration` seconds of audio
+    fn temp_space_needed(&self, duration: f64) -> u64 {
+        let rate = match self.config.enhance {
+            true if self.config.preserve_hires => HIRES_SAMPLE_RATE,
+            true => self.config.target_rate.unwrap_or(UPSCALE_SAMPLE_RATE),
+            false => self.decode_sample_rate().unwrap_or(DEFAULT_SAMPLE_RATE),
+        }
+        .max(self.decode_sample_rate().unwrap_or(DEFAULT_SAMPLE_RATE));
+        let channels = self.config.encode_options.channels.count().unwrap_or(2);
+        let wav_bytes = duration * f64::from(rate * channels * self.bit_depth().bytes_per_sample());
+
+        // Decoded and encoded copies, plus one per stage that may rewrite the audio
+        let copies = 2
+            + u64::from(self.config.enhance)
+            + u64::from(self.config.enhance && self.config.preserve_hires)
+            + u64::from(
+                self.config.trim_silence
+                    || self.config.fade_in.is_some()
+                    || self.config.fade_out.is_some(),
+            )
+            + u64::from(self.config.enhance || self.config.limit_peaks)
+            + u64::from(self.config.normalize || self.album.is_some())
+            + u64::from(self.config.split_chapters);
+        (wav_bytes * copies as f64) as u64
+    }
+
+    /// Run an FFmpeg stage under the shared FFmpeg limit, if any
+    async fn on_ffmpeg<F: Future>(&self, stage: F) -> F::Output {
+        limited(self.ffmpeg_limit.as_deref(), "FFmpeg", stage).await
+    }
+
+    /// Run a yt-dlp stage under the shared download limit, if any
+    async fn on_network<F: Future>(&self, stage: F) -> F::Output {
+        limited(self.download_limit.as_deref(), "download", stage).await
+    }
+
+    /// The app config with the tool paths from [`PipelineConfig::paths`] applied
+    fn app_config(&self) -> Config {
+        let mut app_config = self.app_config.clone();
+        let paths = &self.config.paths;
+        app_config.paths = PathsConfig {
+            yt_dlp: paths.yt_dlp.clone().or(app_config.paths.yt_dlp),
+            ffmpeg: paths.ffmpeg.clone().or(app_config.paths.ffmpeg),
+            python: paths.python.clone().or(app_config.paths.python),
+            python_prefer: paths.python_prefer.or(app_config.paths.python_prefer),
+        };
+        app_config
+    }
+
+    async fn emit_plan(&self, stage: &str, command: &Command) {
+        let command = short_command_line(command);
+        debug!("[dry-run] {}: {}", stage, command);
+        let _ = self.progress_tx.send(PipelineStage::Planned {
+            stage: stage.to_string(),
+            command,
         }).await;
+    }
 
-        // Cleanup temp directory (unless keep_temp is set)
-        if !self.config.keep_temp {
-            drop(temp_dir);
-        } else {
-            // Prevent cleanup by forgetting the temp dir
-            std::mem::forget(temp_dir);
-            debug!("Temp files kept at: {}", temp_path.display());
+    /// With `clean_titles`, strip the video decorations from the title and
+    /// take the artist from "Artist - Title" when the video names none
+    fn clean_metadata(&self, metadata: &mut VideoMetadata) {
+        if !self.config.clean_titles {
+            return;
         }
+        let cleaned = clean_title(&metadata.title);
+        debug!("Cleaned title: {:?} -> {:?}", metadata.title, cleaned);
+        metadata.title = cleaned.title;
+        if metadata.artist.is_none() {
+            metadata.artist = cleaned.artist;
+        }
+    }
 
-        Ok(final_path)
+    /// Output path for one track, expanded from the filename template
+    fn final_path(&self, name: &str, metadata: &VideoMetadata, format: OutputFormat) -> PathBuf {
+        self.config.output_dir.join(render_filename_template(
+            &self.config.filename_template,
+            name,
+            metadata,
+            format,
+        ))
+    }
+
+    /// Rate to decode to, `None` to keep the source rate. Upscaling decodes
+    /// at the models' rate unless `preserve_hires` keeps the source for when
+    /// the upscale is skipped.
+    fn decode_sample_rate(&self) -> Option<u32> {
+        self.config
+            .resample
+            .or((self.config.enhance && !self.config.preserve_hires).then_some(UPSCALE_SAMPLE_RATE))
+    }
+
+    /// Primary output format for this source, with the encode options to use
+    /// (an `auto` choice matches the source bitrate unless one was given)
+    fn resolve_format(
+        &self,
+        app_config: &Config,
+        metadata: &VideoMetadata,
+        upscaled: bool,
+    ) -> (OutputFormat, EncodeOptions) {
+        let lossy = app_config
+            .output
+            .auto_lossy_format
+            .parse()
+            .unwrap_or(OutputFormat::Opus);
+        let (format, bitrate_kbps) =
+            self.config
+                .format
+                .resolve(lossy, metadata.acodec.as_deref(), metadata.abr, upscaled);
+        if self.config.format == FormatChoice::Auto {
+            info!(
+                "Auto format: {} (source {} at {} kbps{})",
+                format,
+                metadata.acodec.as_deref().unwrap_or("unknown codec"),
+                metadata.abr.map_or("unknown".to_string(), |kbps| format!("{:.0}", kbps)),
+                if upscaled { ", upscaled" } else { "" },
+            );
+        }
+
+        let mut encode_options = self.config.encode_options;
+        encode_options.bit_depth = self.bit_depth();
+        if encode_options.bitrate_kbps.is_none() {
+            encode_options.bitrate_kbps = bitrate_kbps;
+        }
+        (format, encode_options)
+    }
+
+    /// Primary format first, then any `also` formats, without duplicates
+    fn output_formats(&self, primary: OutputFormat) -> Vec<OutputFormat> {
+        let mut formats = vec![primary];
+        for format in &self.config.also {
+            if !formats.contains(format) {
+                formats.push(*format);
+            }
+        }
+        formats
+    }
+
+    /// Stable working directory for resume mode, keyed by video ID (or a hash of
+    /// the input). Emptied first when it was left by a run with other settings.
+    async fn resume_dir(&self, app_config: &Config) -> Result<PathBuf, YtAudioError> {
+        let key = match &self.config.input {
+            PipelineInput::YouTubeUrl(url) => normalize_url(url)
+                .map(|normalized| normalized.id)
+                .unwrap_or_else(|| format!("{:016x}", fnv1a(url))),
+            PipelineInput::LocalFile(path, _) => format!("{:016x}", fnv1a(&path.to_string_lossy())),
+        };
+        let dir = app_config.temp_dir().join("ytaudio-resume").join(key);
+
+        // Every setting that changes the bytes of a checkpointed WAV
+        let settings = format!(
+            "{}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{} {} {} {} {}\n{}\n",
+            self.config.input,
+            self.config.format_selector,
+            self.config.sponsorblock,
+            (
+                self.decode_sample_rate(),
+                self.config.preserve_hires,
+                self.config.highpass,
+                self.config.lowpass,
+                &self.config.extra_af,
+                self.config.clip,
+            ),
+            (
+                self.config.encode_options.bit_depth,
+                self.config.encode_options.channels
+            ),
+            self.config
+                .enhance
+                .then(|| (self.upscale_method(app_config), self.config.target_rate)),
+            (
+                self.config.trim_silence,
+                self.config.fade_in,
+                self.config.fade_out,
+                self.config.limit_peaks
+            ),
+            (self.config.normalize_mode, self.config.peak_target),
+            self.config.target_lufs,
+            app_config.normalize.true_peak,
+            app_config.normalize.lra,
+            app_config.normalize.dynamic_frame_ms,
+            app_config.normalize.dynamic_gauss_size,
+            self.config.normalize,
+        );
+        let settings_file = dir.join("settings");
+        match tokio::fs::read_to_string(&settings_file).await {
+            Ok(previous) if previous == settings => {
+                info!("Resuming in {}", dir.display());
+                return Ok(dir);
+            }
+            Ok(_) => {
+                info!(
+                    "Settings changed since the last run, discarding checkpoints in {}",
+                    dir.display()
+                );
+                tokio::fs::remove_dir_all(&dir).await?;
+            }
+            Err(_) => {}
+        }
+
+        tokio::fs::create_dir_all(&dir).await?;
+        tokio::fs::write(&settings_file, settings).await?;
+        Ok(dir)
+    }
+
+    /// In resume mode, the sample rate of a stage output left by an earlier run,
+    /// when it was completed (see [`Pipeline::mark_checkpoint`]), is non-empty
+    /// and ffprobe reads it as audio. `None` means the stage has to run.
+    async fn checkpoint(&self, stage: &str, ffmpeg_path: &Path, path: &Path) -> Option<u32> {
+        if !self.config.resume
+            || !tokio::fs::try_exists(checkpoint_marker(path))
+                .await
+                .unwrap_or(false)
+        {
+            return None;
+        }
+        if tokio::fs::metadata(path).await.map(|m| m.len()).unwrap_or(0) == 0 {
+            warn!("Ignoring empty checkpoint {}", path.display());
+            return None;
+        }
+        match probe_audio_duration(ffmpeg_path, path).await {
+            Ok(Some(duration)) if duration > 0.0 => {}
+            Ok(_) => {
+                warn!("Ignoring unreadable checkpoint {}", path.display());
+                return None;
+            }
+            Err(e) => {
+                warn!("Cannot validate checkpoint {} without ffprobe: {}", path.display(), e);
+                return None;
+            }
+        }
+        let sample_rate = probe_sample_rate(ffmpeg_path, path).await?;
+
+        info!("Reusing {} output from an earlier run: {}", stage, path.display());
+        let _ = self.progress_tx.send(PipelineStage::Resumed {
+            stage: stage.to_string(),
+            path: path.to_path_buf(),
+        }).await;
+        Some(sample_rate)
+    }
+
+    /// Record that `path` was written completely, so a later resume may trust it
+    async fn mark_checkpoint(&self, path: &Path) {
+        if self.config.resume {
+            if let Err(e) = tokio::fs::write(checkpoint_marker(path), b"").await {
+                warn!("Failed to mark checkpoint {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    fn upscale_method(&self, app_config: &Config) -> UpscaleMethod {
+        let mut method = self.config.upscale_quality.method(app_config);
+        if let UpscaleMethod::AudioSR { ref mut seed, .. } = method {
+            *seed = self.config.audiosr_seed.or(*seed);
+        }
+        method
+    }
+}
+
+/// The error for a run that needs more temp space than there is
+fn insufficient_space(detail: String) -> YtAudioError {
+    YtAudioError::Pipeline(format!(
+        "insufficient temp space: {}. Free some space, lower --parallel, or set temp.directory to \
+         a larger disk",
+        detail
+    ))
+}
+
+/// Run `stage` holding a permit of `limit`; with no limit it runs at once
+async fn limited<F: Future>(limit: Option<&Semaphore>, resource: &str, stage: F) -> F::Output {
+    let _permit = match limit {
+        Some(limit) => {
+            debug!("Waiting for a free {} slot", resource);
+            Some(limit.acquire().await.expect("stage semaphores are never closed"))
+        }
+        None => None,
+    };
+    stage.await
+}
+
+/// Move `metadata` onto the timeline of the `length` seconds starting at
+/// `start`: chapters shift and are cut to it, dropping those left empty
+fn shift_timeline(metadata: &mut VideoMetadata, start: f64, length: f64) {
+    metadata.duration = Some(length);
+    for chapter in &mut metadata.chapters {
+        chapter.start_time = (chapter.start_time - start).clamp(0.0, length);
+        chapter.end_time = (chapter.end_time - start).clamp(0.0, length);
+    }
+    metadata.chapters.retain(|chapter| chapter.end_time > chapter.start_time);
+}
+
+/// Completion marker written next to a checkpointed stage output
+fn checkpoint_marker(path: &Path) -> PathBuf {
+    let mut marker = path.as_os_str().to_owned();
+    marker.push(".done");
+    PathBuf::from(marker)
+}
+
+/// FNV-1a: stable across runs and Rust versions, unlike `DefaultHasher`
+fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_support::StubFfmpeg;
+
+    fn local_config(input: PathBuf, output_dir: PathBuf, paths: PathsConfig) -> PipelineConfig {
+        PipelineConfig {
+            input: PipelineInput::LocalFile(
+                input,
+                VideoMetadata {
+                    title: "Song".to_string(),
+                    ..Default::default()
+                },
+            ),
+            output_dir,
+            filename_template: "{title}".to_string(),
+            format: FormatChoice::Fixed(OutputFormat::Wav),
+            also: Vec::new(),
+            encode_options: EncodeOptions::default(),
+            enhance: false,
+            force_upscale: false,
+            upscale_quality: UpscaleQuality::Fast,
+            normalize: false,
+            normalize_mode: NormalizeMode::Loudnorm,
+            peak_target: None,
+            limit_peaks: false,
+            highpass: None,
+            lowpass: None,
+            trim_silence: false,
+            fade_in: None,
+            fade_out: None,
+            extra_af: None,
+            clip: None,
+            resample: None,
+            preserve_hires: false,
+            target_rate: None,
+            target_lufs: -14.0,
+            keep_temp: false,
+            resume: false,
+            device: None,
+            audiosr_seed: None,
+            sponsorblock: None,
+            format_selector: None,
+            proxy: None,
+            rate_limit: None,
+            extractor_args: None,
+            max_retries: 0,
+            archive_file: None,
+            lyrics: None,
+            max_fallbacks: 0,
+            dry_run: false,
+            split_chapters: false,
+            embed_artwork: false,
+            embed_spectrogram: false,
+            clean_titles: false,
+            no_metadata: false,
+            skip_existing: false,
+            on_exists: OverwritePolicy::Overwrite,
+            verify: false,
+            hooks: HooksConfig::default(),
+            paths,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_uses_pipeline_paths() {
+        let stub = StubFfmpeg::new();
+        let input = stub.dir().join("in.wav");
+        std::fs::write(&input, b"").unwrap();
+        let paths = PathsConfig {
+            yt_dlp: None,
+            ffmpeg: Some(stub.path()),
+            python: Some(stub.path()),
+            python_prefer: None,
+        };
+
+        // The pipeline's paths win over the app config's
+        let mut app_config = Config::default();
+        app_config.paths.ffmpeg = Some(PathBuf::from("/nonexistent/ffmpeg"));
+        app_config.temp.directory = Some(stub.dir().join("tmp"));
+        let pipeline =
+            Pipeline::without_progress(local_config(input.clone(), stub.dir().join("out"), paths))
+                .with_app_config(app_config);
+        // The stub writes no audio, so the run itself need not succeed
+        let _ = pipeline.run().await;
+
+        let calls = stub.calls();
+        assert!(!calls.is_empty());
+        assert!(calls[0].contains(&input.to_string_lossy().to_string()), "{:?}", calls[0]);
+    }
+
+    #[tokio::test]
+    async fn test_existing_output_auto_format_without_upscale() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = PathsConfig { yt_dlp: None, ffmpeg: None, python: None, python_prefer: None };
+        let mut config = local_config(dir.path().join("in.webm"), dir.path().to_path_buf(), paths);
+        config.input = PipelineInput::LocalFile(
+            dir.path().join("in.webm"),
+            VideoMetadata {
+                title: "Song".to_string(),
+                acodec: Some("opus".to_string()),
+                abr: Some(128.0),
+                ..Default::default()
+            },
+        );
+        config.format = FormatChoice::Auto;
+        config.enhance = true;
+        let pipeline = Pipeline::without_progress(config);
+        let app_config = Config::default();
+
+        assert_eq!(pipeline.existing_output(&app_config).await.unwrap(), None);
+        // A full-band source skipped the upscale and was written as Opus, not FLAC
+        std::fs::write(dir.path().join("Song.opus"), b"").unwrap();
+        assert_eq!(
+            pipeline.existing_output(&app_config).await.unwrap(),
+            Some(dir.path().join("Song.opus"))
+        );
+        std::fs::write(dir.path().join("Song.flac"), b"").unwrap();
+        assert_eq!(
+            pipeline.existing_output(&app_config).await.unwrap(),
+            Some(dir.path().join("Song.flac"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_target_rate() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = PathsConfig { yt_dlp: None, ffmpeg: None, python: None, python_prefer: None };
+        let mut config = local_config(dir.path().join("in.wav"), dir.path().to_path_buf(), paths);
+        config.enhance = true;
+        config.target_rate = Some(16000);
+        let err = Pipeline::without_progress(config).run().await.unwrap_err();
+        assert!(matches!(err, YtAudioError::Config(ConfigError::InvalidValue(_))), "{}", err);
+        assert!(err.to_string().contains("target_rate"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_hooks_skip_items_that_wrote_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("hook-ran");
+        let paths = PathsConfig { yt_dlp: None, ffmpeg: None, python: None, python_prefer: None };
+        let mut config = local_config(dir.path().join("in.wav"), dir.path().to_path_buf(), paths);
+        config.hooks.post_success = Some(format!("touch {}", marker.display()));
+        let pipeline = Pipeline::without_progress(config);
+        let output = Ok(dir.path().join("Song.wav"));
+
+        // Every output was kept as it was
+        pipeline.run_hooks(&output, Some("Song"), false).await;
+        assert!(!marker.exists());
+
+        pipeline.run_hooks(&output, Some("Song"), true).await;
+        assert!(marker.exists());
+    }
+
+    #[tokio::test]
+    async fn test_full_band_level() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = PathsConfig { yt_dlp: None, ffmpeg: None, python: None, python_prefer: None };
+        let config = local_config(dir.path().join("in.wav"), dir.path().to_path_buf(), paths);
+        let pipeline = Pipeline::without_progress(config);
+        let audio = dir.path().join("decoded.wav");
+
+        for (level, full_band) in [
+            (-60.0, true),
+            (-69.9, true),
+            (FULL_BAND_LEVEL_DB, false),
+            (-85.0, false),
+        ] {
+            let stderr = format!("[Parsed_volumedetect_0 @ 0x1] mean_volume: {:.1} dB", level);
+            let stub = StubFfmpeg::new().with_stderr(&stderr);
+            let decoder = Decoder::new(stub.path());
+            assert_eq!(
+                pipeline.is_full_band(&decoder, &audio).await.unwrap(),
+                full_band,
+                "{} dBFS",
+                level
+            );
+        }
+
+        // --force-upscale never measures
+        let stub = StubFfmpeg::new().with_stderr("mean_volume: -20.0 dB");
+        let paths = PathsConfig { yt_dlp: None, ffmpeg: None, python: None, python_prefer: None };
+        let mut config = local_config(dir.path().join("in.wav"), dir.path().to_path_buf(), paths);
+        config.force_upscale = true;
+        let forced = Pipeline::without_progress(config);
+        assert!(!forced.is_full_band(&Decoder::new(stub.path()), &audio).await.unwrap());
+        assert!(stub.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_resume_reuses_a_checkpoint() {
+        let stub = StubFfmpeg::new().with_ffprobe(44100, 3.0);
+        let input = stub.dir().join("in.wav");
+        std::fs::write(&input, b"").unwrap();
+        let paths = PathsConfig {
+            yt_dlp: None,
+            ffmpeg: Some(stub.path()),
+            python: Some(stub.path()),
+            python_prefer: None,
+        };
+        let mut config = local_config(input, stub.dir().join("out"), paths);
+        config.resume = true;
+        let mut app_config = Config::default();
+        app_config.temp.directory = Some(stub.dir().join("tmp"));
+        let (progress_tx, mut progress_rx) = mpsc::channel(64);
+        let pipeline = Pipeline::new(config, progress_tx).with_app_config(app_config.clone());
+        let decodes = || {
+            stub.calls()
+                .iter()
+                .filter(|call| call.last().is_some_and(|arg| arg.ends_with("decoded.wav")))
+                .count()
+        };
+
+        // The stub writes no audio, so the runs themselves need not succeed
+        let _ = pipeline.run().await;
+        assert_eq!(decodes(), 1);
+
+        let decoded_wav = pipeline.resume_dir(&app_config).await.unwrap().join("decoded.wav");
+        std::fs::write(&decoded_wav, b"RIFF").unwrap();
+        pipeline.mark_checkpoint(&decoded_wav).await;
+        while progress_rx.try_recv().is_ok() {}
+
+        let _ = pipeline.run().await;
+        assert_eq!(decodes(), 1);
+        let mut resumed = Vec::new();
+        while let Ok(stage) = progress_rx.try_recv() {
+            if let PipelineStage::Resumed { stage, path } = stage {
+                resumed.push((stage, path));
+            }
+        }
+        assert_eq!(resumed, [("decode".to_string(), decoded_wav)]);
+    }
+
+    #[tokio::test]
+    async fn test_limited_waits_for_a_permit() {
+        let limit = Semaphore::new(1);
+        let held = limit.acquire().await.unwrap();
+        let stage = limited(Some(&limit), "test", async { 42 });
+        tokio::pin!(stage);
+        assert!(futures::poll!(stage.as_mut()).is_pending());
+        drop(held);
+        assert_eq!(stage.await, 42);
+        assert_eq!(limit.available_permits(), 1);
+
+        assert_eq!(limited(None, "test", async { 7 }).await, 7);
+    }
+
+    #[tokio::test]
+    async fn test_ffmpeg_stages_wait_for_the_ffmpeg_limit() {
+        let stub = StubFfmpeg::new();
+        let input = stub.dir().join("in.wav");
+        std::fs::write(&input, b"").unwrap();
+        let paths = PathsConfig {
+            yt_dlp: None,
+            ffmpeg: Some(stub.path()),
+            python: Some(stub.path()),
+            python_prefer: None,
+        };
+
+        let mut app_config = Config::default();
+        app_config.temp.directory = Some(stub.dir().join("tmp"));
+        let limit = Arc::new(Semaphore::new(0));
+        let pipeline =
+            Pipeline::without_progress(local_config(input, stub.dir().join("out"), paths))
+                .with_app_config(app_config)
+                .with_ffmpeg_limit(limit.clone());
+        let run = pipeline.run();
+        tokio::pin!(run);
+
+        // No FFmpeg call starts while every slot is taken
+        assert!(tokio::time::timeout(Duration::from_millis(200), run.as_mut()).await.is_err());
+        assert!(stub.calls().is_empty());
+
+        limit.add_permits(1);
+        let _ = run.await;
+        assert!(!stub.calls().is_empty());
+    }
+
+    #[test]
+    fn test_app_config_paths_fall_back() {
+        use crate::config::PythonPrefer;
+
+        let mut app_config = Config::default();
+        app_config.paths.python_prefer = Some(PythonPrefer::Active);
+        let paths = |python_prefer| PathsConfig {
+            yt_dlp: None,
+            ffmpeg: None,
+            python: None,
+            python_prefer,
+        };
+
+        let config = local_config(PathBuf::from("in.wav"), PathBuf::from("out"), paths(None));
+        let pipeline = Pipeline::without_progress(config).with_app_config(app_config.clone());
+        assert_eq!(pipeline.app_config().paths.python_prefer, Some(PythonPrefer::Active));
+
+        let config = local_config(
+            PathBuf::from("in.wav"),
+            PathBuf::from("out"),
+            paths(Some(PythonPrefer::Ytaudio)),
+        );
+        let pipeline = Pipeline::without_progress(config).with_app_config(app_config);
+        assert_eq!(pipeline.app_config().paths.python_prefer, Some(PythonPrefer::Ytaudio));
+    }
+
+    #[tokio::test]
+    async fn test_disk_full_reported_as_temp_space() {
+        let stub = StubFfmpeg::new()
+            .with_stderr("Error writing trailer of decoded.wav: No space left on device\n")
+            .with_exit_code(1);
+        let input = stub.dir().join("in.wav");
+        std::fs::write(&input, b"").unwrap();
+        let paths = PathsConfig {
+            yt_dlp: None,
+            ffmpeg: Some(stub.path()),
+            python: Some(stub.path()),
+            python_prefer: None,
+        };
+
+        let mut app_config = Config::default();
+        app_config.temp.directory = Some(stub.dir().join("tmp"));
+        let pipeline =
+            Pipeline::without_progress(local_config(input, stub.dir().join("out"), paths))
+                .with_app_config(app_config);
+        let err = pipeline.run().await.unwrap_err();
+        assert!(matches!(err, YtAudioError::Pipeline(_)), "{:?}", err);
+        assert!(err.to_string().contains("insufficient temp space"), "{}", err);
     }
 }
@@ -0,0 +1,292 @@
+//! Helpers shared by the stages that shell out to external tools
+
+use std::path::Path;
+use std::process::{ExitStatus, Output, Stdio};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::process::Command;
+use tracing::{debug, trace, warn, Level};
+pub(crate) use ytaudio_upscale::log_command;
+
+/// Sample rate FFmpeg resamples to when a stage has nothing better to go on
+pub(crate) const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+/// Lines of FFmpeg's stderr logged when a stage fails
+const STDERR_TAIL_LINES: usize = 20;
+
+/// FFmpeg `-loglevel` matching the active tracing verbosity.
+///
+/// [`ffmpeg_run`] logs FFmpeg's full stderr at trace level, so there it
+/// includes verbose output; otherwise only errors are printed.
+pub(crate) fn ffmpeg_loglevel() -> &'static str {
+    if tracing::enabled!(Level::TRACE) {
+        "verbose"
+    } else {
+        "error"
+    }
+}
+
+/// Run FFmpeg with stderr captured, logging the command line first and, if
+/// it fails, the last lines of its stderr
+pub(crate) async fn ffmpeg_output(cmd: &mut Command) -> std::io::Result<Output> {
+    log_command(cmd);
+    let output = cmd.output().await?;
+    warn_on_failure(&output);
+    Ok(output)
+}
+
+/// [`ffmpeg_run`] for a command run with `-progress pipe:1`, calling
+/// `on_progress` with the fraction of `duration` (in seconds) written so far.
+/// Without a duration there is nothing to measure against and no calls are
+/// made. The returned stdout is empty, as it only carried the progress.
+pub(crate) async fn ffmpeg_output_with_progress(
+    cmd: &mut Command,
+    duration: Option<f64>,
+    mut on_progress: impl FnMut(f32),
+) -> std::io::Result<Output> {
+    log_command(cmd);
+    let mut child = cmd.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let mut stdout_lines = BufReader::new(child.stdout.take().expect("stdout is piped")).lines();
+    let mut stderr_pipe = child.stderr.take().expect("stderr is piped");
+
+    // Drain both pipes together so a chatty stderr cannot stall FFmpeg
+    let read_progress = async {
+        while let Some(line) = stdout_lines.next_line().await? {
+            if let (Some(written), Some(total)) =
+                (parse_progress_time(&line), duration.filter(|d| *d > 0.0))
+            {
+                on_progress((written / total).clamp(0.0, 1.0) as f32);
+            }
+        }
+        Ok::<_, std::io::Error>(())
+    };
+    let read_stderr = async {
+        let mut stderr = Vec::new();
+        stderr_pipe.read_to_end(&mut stderr).await.map(|_| stderr)
+    };
+    let (progress, stderr) = tokio::join!(read_progress, read_stderr);
+    progress?;
+
+    let output = Output { status: child.wait().await?, stdout: Vec::new(), stderr: stderr? };
+    warn_on_failure(&output);
+    trace_on_success(&output);
+    Ok(output)
+}
+
+fn warn_on_failure(output: &Output) {
+    if !output.status.success() {
+        warn!(
+            "FFmpeg exited with {}; end of its output:\n{}",
+            output.status,
+            failure_stderr(output)
+        );
+    }
+}
+
+fn trace_on_success(output: &Output) {
+    if output.status.success() && !output.stderr.is_empty() {
+        trace!("FFmpeg output:\n{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+}
+
+/// Seconds written so far from an `out_time=HH:MM:SS.micros` line of
+/// FFmpeg's `-progress` report; `None` for other keys and `N/A`
+fn parse_progress_time(line: &str) -> Option<f64> {
+    let time = line.strip_prefix("out_time=")?.trim();
+    let mut parts = time.splitn(3, ':');
+    let hours: f64 = parts.next()?.parse().ok()?;
+    let minutes: f64 = parts.next()?.parse().ok()?;
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    Some(hours * 3600.0 + minutes * 60.0 + seconds)
+}
+
+/// [`ffmpeg_output`] for stages that don't parse FFmpeg's stderr; a
+/// successful run's stderr goes to the trace log
+pub(crate) async fn ffmpeg_run(cmd: &mut Command) -> std::io::Result<Output> {
+    let output = ffmpeg_output(cmd).await?;
+    trace_on_success(&output);
+    Ok(output)
+}
+
+/// [`ffmpeg_run`] for stages that only need the exit status
+pub(crate) async fn ffmpeg_status(cmd: &mut Command) -> std::io::Result<ExitStatus> {
+    Ok(ffmpeg_run(cmd).await?.status)
+}
+
+/// Bytes free for unprivileged writes on the filesystem holding `dir`, or
+/// `None` where that can't be queried
+pub(crate) fn available_space(dir: &Path) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        rustix::fs::statvfs(dir).ok().map(|stat| stat.f_bavail.saturating_mul(stat.f_frsize))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = dir;
+        None
+    }
+}
+
+/// Whether an error message (an I/O error or a tool's stderr) says the disk
+/// filled up
+pub(crate) fn is_disk_full(message: &str) -> bool {
+    message.contains("No space left on device")
+        || message.contains("There is not enough space on the disk")
+}
+
+/// The end of a failed run's stderr, as carried in the stage errors
+pub(crate) fn failure_stderr(output: &Output) -> String {
+    stderr_tail(&String::from_utf8_lossy(&output.stderr), STDERR_TAIL_LINES)
+}
+
+/// The last `lines` non-empty lines of `stderr`
+fn stderr_tail(stderr: &str, lines: usize) -> String {
+    let kept: Vec<_> = stderr.lines().filter(|l| !l.trim().is_empty()).collect();
+    kept[kept.len().saturating_sub(lines)..].join("\n")
+}
+
+/// Sample rate of the first audio stream, read with the `ffprobe` that sits
+/// next to `ffmpeg_path`. `None` when ffprobe is missing or reports nothing.
+pub(crate) async fn probe_sample_rate(ffmpeg_path: &Path, input: &Path) -> Option<u32> {
+    let ffprobe = ffmpeg_path.with_file_name(format!("ffprobe{}", std::env::consts::EXE_SUFFIX));
+    let mut cmd = Command::new(&ffprobe);
+    cmd.kill_on_drop(true)
+        .args([
+            "-v", "error",
+            "-select_streams", "a:0",
+            "-show_entries", "stream=sample_rate",
+            "-of", "default=noprint_wrappers=1:nokey=1",
+        ])
+        .arg(input);
+    log_command(&cmd);
+    let output = cmd
+        .output()
+        .await
+        .inspect_err(|e| debug!("ffprobe ({}) unavailable: {}", ffprobe.display(), e))
+        .ok()?;
+
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+}
+
+/// Duration in seconds of a file with at least one audio stream, read with the
+/// `ffprobe` next to `ffmpeg_path`. `Ok(None)` when ffprobe finds no audio
+/// stream or no usable duration; `Err` when ffprobe itself cannot run.
+pub(crate) async fn probe_audio_duration(
+    ffmpeg_path: &Path,
+    input: &Path,
+) -> std::io::Result<Option<f64>> {
+    let ffprobe = ffmpeg_path.with_file_name(format!("ffprobe{}", std::env::consts::EXE_SUFFIX));
+    let mut cmd = Command::new(&ffprobe);
+    cmd.kill_on_drop(true)
+        .args([
+            "-v", "error",
+            "-select_streams", "a:0",
+            "-show_entries", "stream=codec_type:format=duration",
+            "-of", "default=noprint_wrappers=1",
+        ])
+        .arg(input);
+    log_command(&cmd);
+    let output = cmd.output().await?;
+
+    Ok(parse_audio_duration(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Container duration and tags of `input` as ffprobe prints them: one
+/// `duration=...` line, one `TAG:<key>=<value>` line per tag, the first
+/// audio stream's `codec_name=...`, and `bit_rate=...` lines for the stream
+/// and then the container
+pub(crate) async fn probe_format_tags(ffmpeg_path: &Path, input: &Path) -> std::io::Result<String> {
+    let ffprobe = ffmpeg_path.with_file_name(format!("ffprobe{}", std::env::consts::EXE_SUFFIX));
+    let mut cmd = Command::new(&ffprobe);
+    cmd.kill_on_drop(true)
+        .args([
+            "-v", "error",
+            "-select_streams", "a:0",
+            "-show_entries", "stream=codec_name,bit_rate:format=duration,bit_rate:format_tags",
+            "-of", "default=noprint_wrappers=1",
+        ])
+        .arg(input);
+    log_command(&cmd);
+    let output = cmd.output().await?;
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn parse_audio_duration(probe: &str) -> Option<f64> {
+    let mut has_audio = false;
+    let mut duration = None;
+    for line in probe.lines() {
+        match line.trim().split_once('=') {
+            Some(("codec_type", "audio")) => has_audio = true,
+            Some(("duration", value)) => duration = value.parse::<f64>().ok(),
+            _ => {}
+        }
+    }
+    duration.filter(|d| has_audio && d.is_finite() && *d > 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_audio_duration() {
+        assert_eq!(parse_audio_duration("codec_type=audio\nduration=212.480000\n"), Some(212.48));
+        // No audio stream selected
+        assert_eq!(parse_audio_duration("duration=212.480000\n"), None);
+        assert_eq!(parse_audio_duration("codec_type=audio\nduration=N/A\n"), None);
+        assert_eq!(parse_audio_duration("codec_type=audio\nduration=0.000000\n"), None);
+    }
+
+    #[test]
+    fn test_command_line_quoting() {
+        use ytaudio_upscale::command_line;
+
+        let mut cmd = Command::new("/usr/bin/ffmpeg");
+        cmd.args(["-i", "My Song's.webm", "-af", "volume=3dB", "-metadata", "", "out.flac"]);
+        assert_eq!(
+            command_line(&cmd),
+            r#"/usr/bin/ffmpeg -i 'My Song'\''s.webm' -af volume=3dB -metadata '' out.flac"#
+        );
+    }
+
+    #[test]
+    fn test_short_command_line() {
+        use ytaudio_upscale::short_command_line;
+
+        let mut cmd = Command::new("python3");
+        cmd.args(["-c", "import sys\nprint(sys.argv)\n", "in put.wav"]);
+        assert_eq!(short_command_line(&cmd), "python3 -c <inline script, 2 lines> 'in put.wav'");
+    }
+
+    #[test]
+    fn test_parse_progress_time() {
+        assert_eq!(parse_progress_time("out_time=00:01:02.500000"), Some(62.5));
+        assert_eq!(parse_progress_time("out_time=01:00:00.000000"), Some(3600.0));
+        assert_eq!(parse_progress_time("out_time=N/A"), None);
+        assert_eq!(parse_progress_time("out_time_us=62500000"), None);
+        assert_eq!(parse_progress_time("progress=continue"), None);
+    }
+
+    #[test]
+    fn test_is_disk_full() {
+        let stderr =
+            "[out#0/wav @ 0x1] Error writing trailer: No space left on device\nConversion failed!";
+        assert!(is_disk_full(stderr));
+        assert!(!is_disk_full("Invalid data found when processing input"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_available_space() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(available_space(dir.path()).is_some_and(|bytes| bytes > 0));
+        assert_eq!(available_space(&dir.path().join("missing")), None);
+    }
+
+    #[test]
+    fn test_stderr_tail() {
+        let stderr = "one\n\ntwo\nthree\n";
+        assert_eq!(stderr_tail(stderr, 2), "two\nthree");
+        assert_eq!(stderr_tail(stderr, 10), "one\ntwo\nthree");
+    }
+}
@@ -0,0 +1,125 @@
+//! Test doubles for the external tools the stages shell out to, and helpers
+//! for inspecting the commands built for them. The stubs are shell scripts,
+//! so they exist on Unix only.
+
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use tempfile::TempDir;
+use tokio::process::Command;
+
+/// Records each invocation's arguments to `call.<n>` (NUL-separated), replays
+/// `stderr` and exits with the code in `status`, all from its own directory
+#[cfg(unix)]
+const STUB_SCRIPT: &str = r#"#!/bin/sh
+[ -n "$STUB_PROBE" ] && exit 0
+dir=$(dirname "$0")
+n=$(ls "$dir" | grep -c '^call\.')
+printf '%s\0' "$@" > "$dir/call.$n"
+[ -f "$dir/stderr" ] && cat "$dir/stderr" >&2
+exit "$(cat "$dir/status" 2>/dev/null || echo 0)"
+"#;
+
+/// Answers the sample rate and duration probes for any input
+#[cfg(unix)]
+const FFPROBE_SCRIPT: &str = r#"#!/bin/sh
+[ -n "$STUB_PROBE" ] && exit 0
+case "$*" in
+*stream=sample_rate*) echo {rate} ;;
+*) printf 'codec_type=audio\nduration={duration}\n' ;;
+esac
+"#;
+
+/// A stand-in `ffmpeg` in a temp directory: a shell script that records its
+/// argv and emits canned stderr, so the code around FFmpeg runs without it.
+/// There is no `ffprobe` next to it unless [`StubFfmpeg::with_ffprobe`] adds
+/// one, so probes see an unavailable tool.
+#[cfg(unix)]
+pub(crate) struct StubFfmpeg {
+    dir: TempDir,
+}
+
+#[cfg(unix)]
+impl StubFfmpeg {
+    /// A stub that succeeds without output
+    pub fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        install_script(&dir.path().join("ffmpeg"), STUB_SCRIPT);
+        Self { dir }
+    }
+
+    /// Add an `ffprobe` that reports every input as `duration` seconds of
+    /// audio at `sample_rate`
+    pub fn with_ffprobe(self, sample_rate: u32, duration: f64) -> Self {
+        let script = FFPROBE_SCRIPT
+            .replace("{rate}", &sample_rate.to_string())
+            .replace("{duration}", &duration.to_string());
+        install_script(&self.dir.path().join("ffprobe"), &script);
+        self
+    }
+
+    /// Print `stderr` on every run
+    pub fn with_stderr(self, stderr: &str) -> Self {
+        std::fs::write(self.dir.path().join("stderr"), stderr).unwrap();
+        self
+    }
+
+    /// Exit with `code` on every run
+    pub fn with_exit_code(self, code: i32) -> Self {
+        std::fs::write(self.dir.path().join("status"), code.to_string()).unwrap();
+        self
+    }
+
+    /// Path to pass as `ffmpeg_path`
+    pub fn path(&self) -> PathBuf {
+        self.dir.path().join("ffmpeg")
+    }
+
+    /// Scratch directory for inputs and outputs
+    pub fn dir(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Arguments of each run so far, oldest first
+    pub fn calls(&self) -> Vec<Vec<String>> {
+        (0..)
+            .map(|n| self.dir.path().join(format!("call.{}", n)))
+            .take_while(|path| path.exists())
+            .map(|path| {
+                let raw = std::fs::read_to_string(path).unwrap();
+                raw.split_terminator('\0').map(str::to_string).collect()
+            })
+            .collect()
+    }
+}
+
+/// Arguments of `cmd`, without the program
+pub(crate) fn args(cmd: &Command) -> Vec<String> {
+    cmd.as_std().get_args().map(|arg| arg.to_string_lossy().into_owned()).collect()
+}
+
+/// The value following `flag` in `args`
+pub(crate) fn arg_after<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter().position(|arg| arg == flag).and_then(|i| args.get(i + 1)).map(String::as_str)
+}
+
+/// Write an executable script to `path`
+#[cfg(unix)]
+fn install_script(path: &Path, script: &str) {
+    std::fs::write(path, script).unwrap();
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    // A process forked by a parallel test while the script was open for
+    // writing makes exec fail with ETXTBSY until it execs itself
+    loop {
+        match std::process::Command::new(path).env("STUB_PROBE", "1").status() {
+            Err(e) if e.raw_os_error() == Some(26) => std::thread::yield_now(),
+            result => {
+                result.unwrap();
+                break;
+            }
+        }
+    }
+}
@@ -1,41 +1,99 @@
 //! AudioSR diffusion-based audio upscaling
 
-use crate::UpscaleError;
+use crate::{append_log, log_command, python_literal, AudioSRModel, UpscaleError, MODEL_SAMPLE_RATE};
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
 use std::path::{Path, PathBuf};
 use tokio::process::Command;
 use tracing::{debug, info};
 
+/// Largest seed AudioSR accepts: it seeds numpy, whose seeds are 32-bit
+pub const MAX_AUDIOSR_SEED: u64 = u32::MAX as u64;
+
 /// AudioSR upscaler (highest quality, slower)
 #[derive(Debug)]
 pub struct AudioSR {
     python_path: PathBuf,
+    device: Option<String>,
+    model: AudioSRModel,
+    seed: u64,
+    output_rate: u32,
+    log_file: Option<PathBuf>,
 }
 
 impl AudioSR {
     pub fn new(python_path: PathBuf) -> Self {
-        Self { python_path }
+        Self {
+            python_path,
+            device: None,
+            model: AudioSRModel::Basic,
+            seed: random_seed(),
+            output_rate: MODEL_SAMPLE_RATE,
+            log_file: None,
+        }
     }
 
-    /// Upscale audio using AudioSR diffusion model
-    pub async fn upscale(
+    /// Append the script's full stdout and stderr to this file after each
+    /// run, e.g. to keep them with the temp files of a debug run
+    pub fn with_log_file(mut self, log_file: Option<PathBuf>) -> Self {
+        self.log_file = log_file;
+        self
+    }
+
+    /// Sample rate to write, resampled from the model's 48kHz with librosa
+    /// after the chunks are joined; `None` keeps 48kHz
+    pub fn with_output_rate(mut self, rate: Option<u32>) -> Self {
+        self.output_rate = rate.unwrap_or(MODEL_SAMPLE_RATE);
+        self
+    }
+
+    /// Diffusion seed, for reproducing a result; `None` keeps the random one
+    /// picked at construction. Logged when upscaling either way.
+    pub fn with_seed(mut self, seed: Option<u64>) -> Self {
+        if let Some(seed) = seed {
+            self.seed = seed;
+        }
+        self
+    }
+
+    /// Checkpoint to load; `speech` suits spoken-word material
+    pub fn with_model(mut self, model: AudioSRModel) -> Self {
+        self.model = model;
+        self
+    }
+
+    /// Torch device to run on (`cpu`, `cuda:1`, `mps`); auto-selected when `None`.
+    /// A device torch cannot use is reported as [`UpscaleError::AudioSRFailed`].
+    pub fn with_device(mut self, device: Option<String>) -> Self {
+        self.device = device;
+        self
+    }
+
+    /// Build the Python invocation used by [`AudioSR::upscale`]
+    ///
+    /// With `chunk_secs > 0`, inputs longer than one chunk are processed in
+    /// windows overlapping by `overlap_secs`, joined with an equal-power crossfade.
+    pub fn command(
         &self,
         input: &Path,
         output: &Path,
         ddim_steps: u32,
         guidance_scale: f32,
-    ) -> Result<(), UpscaleError> {
-        info!("Running AudioSR upscaling (ddim_steps={}, guidance_scale={})", ddim_steps, guidance_scale);
-
+        chunk_secs: f32,
+        overlap_secs: f32,
+    ) -> Command {
         // Inline Python script for AudioSR
         let script = format!(
             r#"
 import sys
 import os
+import tempfile
 
 # Suppress warnings
 os.environ['TF_CPP_MIN_LOG_LEVEL'] = '3'
 
 try:
+    import numpy as np
     import torch
     import soundfile as sf
     from audiosr import build_model, super_resolution
@@ -43,8 +101,16 @@ except ImportError as e:
     print(f"Missing dependency: {{e}}", file=sys.stderr)
     sys.exit(1)
 
-# Select device
-if torch.backends.mps.is_available():
+# Select device, honoring an explicit choice
+requested_device = {device}
+if requested_device:
+    device = requested_device
+    try:
+        torch.zeros(1).to(device)
+    except Exception as e:
+        print(f"Invalid device '{{device}}': {{e}}", file=sys.stderr)
+        sys.exit(5)
+elif torch.backends.mps.is_available():
     device = "mps"
 elif torch.cuda.is_available():
     device = "cuda"
@@ -55,28 +121,74 @@ print(f"Using device: {{device}}", file=sys.stderr)
 
 # Build model
 try:
-    audiosr = build_model(model_name="basic", device=device)
+    audiosr = build_model(model_name="{model}", device=device)
 except Exception as e:
     print(f"Failed to build model: {{e}}", file=sys.stderr)
     sys.exit(2)
 
-# Run super-resolution
-try:
+MODEL_SR = {model_rate}
+OUT_SR = {output_rate}
+
+def upscale(path):
     waveform = super_resolution(
         audiosr,
-        "{input}",
-        seed=42,
+        path,
+        seed={seed},
         guidance_scale={guidance_scale},
         ddim_steps={ddim_steps},
         latent_t_per_second=12.8
     )
+    return np.asarray(waveform).squeeze()
+
+# Run super-resolution, in overlapping windows for long inputs
+try:
+    chunk_secs = {chunk_secs}
+    overlap_secs = {overlap_secs}
+    info = sf.info("{input}")
+
+    if chunk_secs <= 0 or info.duration <= chunk_secs:
+        result = upscale("{input}")
+    else:
+        audio, sr = sf.read("{input}", always_2d=True)
+        window = int(chunk_secs * sr)
+        hop = int((chunk_secs - overlap_secs) * sr)
+        fade_len = int(round((window - hop) / sr * MODEL_SR))
+        total = (max(len(audio) - window, 0) + hop - 1) // hop + 1
+
+        result = None
+        with tempfile.TemporaryDirectory() as tmp:
+            for i in range(total):
+                start = i * hop
+                chunk = audio[start:start + window]
+                chunk_path = os.path.join(tmp, f"chunk_{{i}}.wav")
+                sf.write(chunk_path, chunk, sr, subtype='PCM_24')
+
+                print(f"Chunk {{i + 1}}/{{total}}", file=sys.stderr)
+                # AudioSR pads its input, so trim back to the chunk's true length
+                expected = int(round(len(chunk) / sr * MODEL_SR))
+                piece = upscale(chunk_path)[..., :expected]
+
+                if result is None:
+                    result = piece
+                    continue
+
+                # Equal-power crossfade keeps the summed energy flat across the join
+                n = min(fade_len, piece.shape[-1], result.shape[-1])
+                t = np.linspace(0.0, 1.0, n)
+                fade_in = np.sin(t * np.pi / 2)
+                fade_out = np.cos(t * np.pi / 2)
+                result[..., -n:] = result[..., -n:] * fade_out + piece[..., :n] * fade_in
+                result = np.concatenate([result, piece[..., n:]], axis=-1)
 except Exception as e:
     print(f"Inference failed: {{e}}", file=sys.stderr)
     sys.exit(3)
 
-# Save output
+# Save output, resampled once when another rate was asked for
 try:
-    sf.write("{output}", waveform.squeeze(), samplerate=48000, subtype='PCM_24')
+    if OUT_SR != MODEL_SR:
+        import librosa
+        result = librosa.resample(result, orig_sr=MODEL_SR, target_sr=OUT_SR)
+    sf.write("{output}", result.T, samplerate=OUT_SR, subtype='PCM_24')
     print("Upscaling complete")
 except Exception as e:
     print(f"Failed to save output: {{e}}", file=sys.stderr)
@@ -86,12 +198,56 @@ except Exception as e:
             output = output.display(),
             ddim_steps = ddim_steps,
             guidance_scale = guidance_scale,
+            chunk_secs = chunk_secs,
+            overlap_secs = overlap_secs,
+            device = python_literal(self.device.as_deref()),
+            model = self.model,
+            seed = self.seed,
+            model_rate = MODEL_SAMPLE_RATE,
+            output_rate = self.output_rate,
         );
 
-        let result = Command::new(&self.python_path)
-            .args(["-c", &script])
-            .output()
-            .await?;
+        let mut cmd = Command::new(&self.python_path);
+        cmd.kill_on_drop(true);
+        cmd.args(["-c", &script]);
+        cmd
+    }
+
+    /// Upscale audio using AudioSR diffusion model
+    pub async fn upscale(
+        &self,
+        input: &Path,
+        output: &Path,
+        ddim_steps: u32,
+        guidance_scale: f32,
+        chunk_secs: f32,
+        overlap_secs: f32,
+    ) -> Result<(), UpscaleError> {
+        info!(
+            "Running AudioSR upscaling (model={}, ddim_steps={}, guidance_scale={}, seed={})",
+            self.model, ddim_steps, guidance_scale, self.seed
+        );
+
+        if chunk_secs > 0.0 && !(0.0..chunk_secs).contains(&overlap_secs) {
+            return Err(UpscaleError::AudioSRFailed(format!(
+                "overlap_secs ({}) must be at least 0 and below chunk_secs ({})",
+                overlap_secs, chunk_secs
+            )));
+        }
+
+        let mut cmd = self.command(
+            input,
+            output,
+            ddim_steps,
+            guidance_scale,
+            chunk_secs,
+            overlap_secs,
+        );
+        log_command(&cmd);
+        let result = cmd.output().await?;
+        if let Some(ref log) = self.log_file {
+            append_log(log, "AudioSR", &result).await;
+        }
 
         let stdout = String::from_utf8_lossy(&result.stdout);
         let stderr = String::from_utf8_lossy(&result.stderr);
@@ -104,12 +260,17 @@ except Exception as e:
         }
 
         if !result.status.success() {
+            if let Some(device) = out_of_memory_device(&stderr) {
+                return Err(UpscaleError::OutOfMemory { device: device.to_string() });
+            }
+
             let exit_code = result.status.code().unwrap_or(-1);
             let error_msg = match exit_code {
                 1 => "Missing Python dependencies. Run: ytaudio update-models".to_string(),
                 2 => format!("Failed to build AudioSR model: {}", stderr.trim()),
                 3 => format!("AudioSR inference failed: {}", stderr.trim()),
                 4 => format!("Failed to save output: {}", stderr.trim()),
+                5 => stderr.trim().to_string(),
                 _ => format!("AudioSR failed: {}", stderr.trim()),
             };
             return Err(UpscaleError::AudioSRFailed(error_msg));
@@ -119,3 +280,60 @@ except Exception as e:
         Ok(())
     }
 }
+
+/// A seed in AudioSR's range, different on every call
+fn random_seed() -> u64 {
+    RandomState::new().hash_one(()) % (MAX_AUDIOSR_SEED + 1)
+}
+
+/// The device whose memory ran out, if `stderr` holds one of torch's
+/// out-of-memory errors
+fn out_of_memory_device(stderr: &str) -> Option<&'static str> {
+    if stderr.contains("CUDA out of memory") || stderr.contains("CUDA error: out of memory") {
+        Some("CUDA")
+    } else if stderr.contains("MPS backend out of memory") {
+        Some("MPS")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::args;
+
+    #[test]
+    fn test_command_output_rate() {
+        let script = |audiosr: AudioSR| {
+            let cmd = audiosr.command(Path::new("in.wav"), Path::new("out.wav"), 50, 3.5, 0.0, 0.0);
+            args(&cmd)[1].clone()
+        };
+        let default = script(AudioSR::new(PathBuf::from("python3")));
+        assert!(default.contains("MODEL_SR = 48000\nOUT_SR = 48000\n"), "{}", default);
+        let custom = script(AudioSR::new(PathBuf::from("python3")).with_output_rate(Some(32000)));
+        assert!(custom.contains("MODEL_SR = 48000\nOUT_SR = 32000\n"), "{}", custom);
+    }
+
+    #[test]
+    fn test_out_of_memory_device() {
+        assert_eq!(
+            out_of_memory_device(
+                "torch.cuda.OutOfMemoryError: CUDA out of memory. Tried to allocate 2.00 GiB"
+            ),
+            Some("CUDA")
+        );
+        assert_eq!(out_of_memory_device("RuntimeError: CUDA error: out of memory"), Some("CUDA"));
+        assert_eq!(
+            out_of_memory_device(
+                "RuntimeError: MPS backend out of memory (MPS allocated: 9.00 GB)"
+            ),
+            Some("MPS")
+        );
+        assert_eq!(
+            out_of_memory_device("RuntimeError: CUDA error: device-side assert triggered"),
+            None
+        );
+        assert_eq!(out_of_memory_device(""), None);
+    }
+}
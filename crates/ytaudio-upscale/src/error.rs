@@ -22,6 +22,12 @@ pub enum UpscaleError {
     #[error("FlashSR inference failed: {0}")]
     FlashSRFailed(String),
 
+    #[error(
+        "AudioSR ran out of {device} memory. Lower upscale.audiosr.ddim_steps or \
+         upscale.audiosr.chunk_secs, or use --device cpu or --quality fast (FlashSR)"
+    )]
+    OutOfMemory { device: String },
+
     #[error("Upscaling timeout after {0} seconds")]
     Timeout(u64),
 
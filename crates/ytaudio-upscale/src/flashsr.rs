@@ -1,27 +1,68 @@
 //! FlashSR ONNX-based audio upscaling
 
-use crate::UpscaleError;
+use crate::{append_log, log_command, python_literal, UpscaleError, MODEL_SAMPLE_RATE};
 use std::path::{Path, PathBuf};
-use tokio::process::Command;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use std::sync::Mutex;
+use tokio::sync::Semaphore;
 use tracing::{debug, info};
 
 /// FlashSR upscaler (22x faster than AudioSR)
 #[derive(Debug)]
 pub struct FlashSR {
     python_path: PathBuf,
+    device: Option<String>,
+    model_sha256: Option<String>,
+    output_rate: u32,
+    log_file: Option<PathBuf>,
 }
 
 impl FlashSR {
     pub fn new(python_path: PathBuf) -> Self {
-        Self { python_path }
+        Self {
+            python_path,
+            device: None,
+            model_sha256: None,
+            output_rate: MODEL_SAMPLE_RATE,
+            log_file: None,
+        }
     }
 
-    /// Upscale audio using FlashSR ONNX model
-    pub async fn upscale(&self, input: &Path, output: &Path) -> Result<(), UpscaleError> {
-        info!("Running FlashSR upscaling");
+    /// Append the script's full stdout and stderr to this file after each
+    /// run, e.g. to keep them with the temp files of a debug run
+    pub fn with_log_file(mut self, log_file: Option<PathBuf>) -> Self {
+        self.log_file = log_file;
+        self
+    }
 
-        // Inline Python script for FlashSR
-        let script = format!(
+    /// Sample rate to write, resampled from the model's 48kHz with librosa;
+    /// `None` keeps 48kHz. Callers check it with [`crate::is_valid_output_rate`].
+    pub fn with_output_rate(mut self, rate: Option<u32>) -> Self {
+        self.output_rate = rate.unwrap_or(MODEL_SAMPLE_RATE);
+        self
+    }
+
+    /// Hash the cached model before loading it and fail on a different
+    /// SHA256 (hex), e.g. after a truncated download; unchecked when `None`
+    pub fn with_model_sha256(mut self, sha256: Option<String>) -> Self {
+        self.model_sha256 = sha256;
+        self
+    }
+
+    /// Device to run on, mapped to an ONNX Runtime execution provider
+    /// (`cpu`, `cuda:N`, `mps` for CoreML); ONNX Runtime's default when `None`
+    pub fn with_device(mut self, device: Option<String>) -> Self {
+        self.device = device;
+        self
+    }
+
+    /// Python prelude shared by the one-shot and worker scripts: imports,
+    /// model download and verification, device selection and session setup, plus the
+    /// `load`/`infer`/`save` helpers
+    fn setup_script(&self) -> String {
+        format!(
             r#"
 import sys
 import numpy as np
@@ -46,41 +87,138 @@ except Exception as e:
     print(f"Failed to download model: {{e}}", file=sys.stderr)
     sys.exit(2)
 
-# Load audio at 16kHz (FlashSR input requirement)
+# Refuse a cached model that does not match the expected digest
+expected_sha256 = {sha256}
+if expected_sha256:
+    import hashlib
+    digest = hashlib.sha256()
+    with open(model_path, "rb") as f:
+        for chunk in iter(lambda: f.read(1 << 20), b""):
+            digest.update(chunk)
+    if digest.hexdigest() != expected_sha256.lower():
+        print(f"{{model_path}} has SHA256 {{digest.hexdigest()}}, expected {{expected_sha256}}", file=sys.stderr)
+        sys.exit(7)
+
+# Map the requested device to ONNX Runtime execution providers
+requested_device = {device}
+providers = None
+if requested_device:
+    available = ort.get_available_providers()
+    kind, _, index = requested_device.partition(":")
+    index = index or "0"
+    if kind == "cpu":
+        providers = ["CPUExecutionProvider"]
+    elif kind == "cuda" and index.isdigit() and "CUDAExecutionProvider" in available:
+        providers = [("CUDAExecutionProvider", {{"device_id": int(index)}})]
+    elif kind == "mps" and "CoreMLExecutionProvider" in available:
+        providers = ["CoreMLExecutionProvider"]
+    else:
+        print(f"Invalid device '{{requested_device}}' (available providers: {{available}})", file=sys.stderr)
+        sys.exit(6)
+
+try:
+    session = ort.InferenceSession(model_path, providers=providers)
+except Exception as e:
+    print(f"Failed to load model: {{e}}", file=sys.stderr)
+    sys.exit(4)
+
+def load(path):
+    # Load audio at 16kHz (FlashSR input requirement)
+    y, _ = librosa.load(path, sr=16000)
+    return y[np.newaxis, :].astype(np.float32)
+
+def infer(lowres_wav):
+    return session.run(["reconstruction"], {{"audio_values": lowres_wav}})[0]
+
+MODEL_SR = {model_rate}
+OUT_SR = {output_rate}
+
+def save(path, output):
+    # FlashSR reconstructs at 48kHz; resample once for another target rate
+    y = output.squeeze(0)
+    if OUT_SR != MODEL_SR:
+        y = librosa.resample(y, orig_sr=MODEL_SR, target_sr=OUT_SR)
+    sf.write(path, y, samplerate=OUT_SR, subtype='PCM_24')
+"#,
+            device = python_literal(self.device.as_deref()),
+            sha256 = python_literal(self.model_sha256.as_deref()),
+            model_rate = MODEL_SAMPLE_RATE,
+            output_rate = self.output_rate,
+        )
+    }
+
+    /// Build the Python invocation used by [`FlashSR::upscale`]
+    pub fn command(&self, input: &Path, output: &Path) -> Command {
+        // Inline Python script for FlashSR
+        let script = format!(
+            r#"{setup}
 try:
-    y, sr = librosa.load("{input}", sr=16000)
-    lowres_wav = y[np.newaxis, :].astype(np.float32)
+    lowres_wav = load("{input}")
 except Exception as e:
     print(f"Failed to load audio: {{e}}", file=sys.stderr)
     sys.exit(3)
 
 # Run ONNX inference
 try:
-    session = ort.InferenceSession(model_path)
-    output = session.run(
-        ["reconstruction"],
-        {{"audio_values": lowres_wav}}
-    )[0]
+    output = infer(lowres_wav)
 except Exception as e:
     print(f"Inference failed: {{e}}", file=sys.stderr)
     sys.exit(4)
 
-# Save at 48kHz
 try:
-    sf.write("{output}", output.squeeze(0), samplerate=48000, subtype='PCM_24')
+    save("{output}", output)
     print("Upscaling complete")
 except Exception as e:
     print(f"Failed to save output: {{e}}", file=sys.stderr)
     sys.exit(5)
 "#,
+            setup = self.setup_script(),
             input = input.display(),
             output = output.display(),
         );
 
-        let result = Command::new(&self.python_path)
-            .args(["-c", &script])
-            .output()
-            .await?;
+        let mut cmd = Command::new(&self.python_path);
+        cmd.kill_on_drop(true);
+        cmd.args(["-c", &script]);
+        cmd
+    }
+
+    /// Build the long-lived Python invocation used by [`FlashSRWorker`]
+    fn worker_command(&self) -> Command {
+        let script = format!(
+            r#"{setup}
+# Serve jobs: one "input<TAB>output" line in, one "OK" or "ERR <message>" line out
+print("READY", flush=True)
+for line in sys.stdin:
+    input_path, sep, output_path = line.rstrip("\n").partition("\t")
+    if not sep:
+        continue
+    try:
+        save(output_path, infer(load(input_path)))
+    except Exception as e:
+        print("ERR " + " ".join(str(e).split()), flush=True)
+    else:
+        print("OK", flush=True)
+"#,
+            setup = self.setup_script(),
+        );
+
+        let mut cmd = Command::new(&self.python_path);
+        cmd.kill_on_drop(true);
+        cmd.args(["-c", &script]);
+        cmd
+    }
+
+    /// Upscale audio using FlashSR ONNX model
+    pub async fn upscale(&self, input: &Path, output: &Path) -> Result<(), UpscaleError> {
+        info!("Running FlashSR upscaling");
+
+        let mut cmd = self.command(input, output);
+        log_command(&cmd);
+        let result = cmd.output().await?;
+        if let Some(ref log) = self.log_file {
+            append_log(log, "FlashSR", &result).await;
+        }
 
         let stdout = String::from_utf8_lossy(&result.stdout);
         let stderr = String::from_utf8_lossy(&result.stderr);
@@ -93,19 +231,240 @@ except Exception as e:
         }
 
         if !result.status.success() {
-            let exit_code = result.status.code().unwrap_or(-1);
-            let error_msg = match exit_code {
-                1 => "Missing Python dependencies. Run: ytaudio update-models".to_string(),
-                2 => "Failed to download FlashSR model".to_string(),
-                3 => format!("Failed to load audio: {}", stderr.trim()),
-                4 => format!("ONNX inference failed: {}", stderr.trim()),
-                5 => format!("Failed to save output: {}", stderr.trim()),
-                _ => format!("FlashSR failed: {}", stderr.trim()),
-            };
-            return Err(UpscaleError::FlashSRFailed(error_msg));
+            return Err(UpscaleError::FlashSRFailed(failure_message(result.status.code(), &stderr)));
         }
 
         info!("FlashSR upscaling complete");
         Ok(())
     }
 }
+
+/// Explain a FlashSR script exit code
+fn failure_message(exit_code: Option<i32>, stderr: &str) -> String {
+    match exit_code.unwrap_or(-1) {
+        1 => "Missing Python dependencies. Run: ytaudio update-models".to_string(),
+        2 => "Failed to download FlashSR model".to_string(),
+        3 => format!("Failed to load audio: {}", stderr.trim()),
+        4 => format!("ONNX inference failed: {}", stderr.trim()),
+        5 => format!("Failed to save output: {}", stderr.trim()),
+        6 => stderr.trim().to_string(),
+        7 => format!(
+            "FlashSR model failed SHA256 verification: {}. Delete the cached file and run: ytaudio \
+             update-models",
+            stderr.trim()
+        ),
+        _ => format!("FlashSR failed: {}", stderr.trim()),
+    }
+}
+
+/// Long-lived FlashSR process that loads the model once and serves many jobs,
+/// so a batch pays the Python start-up and session setup a single time.
+///
+/// Jobs go to the script as `input<TAB>output` lines on stdin and are answered
+/// with `OK` or `ERR <message>`. Each process runs one job at a time. By
+/// default there is a single process, so concurrent callers are served one
+/// after another; [`FlashSRWorker::with_processes`] allows more, each holding
+/// its own copy of the model. Processes start on demand and are replaced
+/// after a crash, and a job abandoned mid-flight (e.g. on cancellation) kills
+/// its process.
+#[derive(Debug)]
+pub struct FlashSRWorker {
+    flashsr: FlashSR,
+    /// One permit per process that may run
+    slots: Semaphore,
+    /// Started processes waiting for a job
+    idle: Mutex<Vec<WorkerProcess>>,
+}
+
+#[derive(Debug)]
+struct WorkerProcess {
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+}
+
+impl FlashSRWorker {
+    /// Worker running with the given FlashSR settings (Python path, device)
+    pub fn new(flashsr: FlashSR) -> Self {
+        Self {
+            flashsr,
+            slots: Semaphore::new(1),
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Run up to `processes` jobs at once, in as many Python processes
+    /// (at least one)
+    pub fn with_processes(mut self, processes: usize) -> Self {
+        self.slots = Semaphore::new(processes.max(1));
+        self
+    }
+
+    /// Upscale one file, starting the worker process if it is not running
+    pub async fn upscale(&self, input: &Path, output: &Path) -> Result<(), UpscaleError> {
+        let (input_str, output_str) = (input.to_string_lossy(), output.to_string_lossy());
+        if [&input_str, &output_str].iter().any(|p| p.contains(['\t', '\n'])) {
+            // Not representable in the line protocol
+            return self.flashsr.upscale(input, output).await;
+        }
+
+        info!("Running FlashSR upscaling (worker)");
+
+        let _slot = self.slots.acquire().await.expect("the semaphore is never closed");
+        // Owned by this call while the job runs: if this future is dropped
+        // the process goes with it, so no stale reply is left for the next job
+        let idle = self.idle.lock().unwrap_or_else(|e| e.into_inner()).pop();
+        let mut process = match idle {
+            Some(process) => process,
+            None => Self::spawn(&self.flashsr).await?,
+        };
+
+        let job = format!("{}\t{}\n", input_str, output_str);
+        process.stdin.write_all(job.as_bytes()).await?;
+        process.stdin.flush().await?;
+
+        loop {
+            let Some(line) = process.stdout.next_line().await? else {
+                return Err(UpscaleError::FlashSRFailed(
+                    "FlashSR worker exited unexpectedly".to_string(),
+                ));
+            };
+            if line == "OK" {
+                self.release(process);
+                info!("FlashSR upscaling complete");
+                return Ok(());
+            }
+            if let Some(message) = line.strip_prefix("ERR ") {
+                let message = message.to_string();
+                self.release(process);
+                return Err(UpscaleError::FlashSRFailed(message));
+            }
+            debug!("FlashSR worker stdout: {}", line);
+        }
+    }
+
+    /// Hand a process that finished its job to the next caller
+    fn release(&self, process: WorkerProcess) {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner()).push(process);
+    }
+
+    /// Start the Python process and wait until the model is loaded
+    async fn spawn(flashsr: &FlashSR) -> Result<WorkerProcess, UpscaleError> {
+        info!("Starting FlashSR worker");
+
+        let mut cmd = flashsr.worker_command();
+        log_command(&cmd);
+        let mut child = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped")).lines();
+        let mut stderr = child.stderr.take().expect("stderr is piped");
+
+        loop {
+            match stdout.next_line().await? {
+                Some(line) if line == "READY" => break,
+                Some(line) => debug!("FlashSR worker stdout: {}", line),
+                None => {
+                    // Set-up failed; the exit code says which step
+                    let mut message = String::new();
+                    stderr.read_to_string(&mut message).await?;
+                    let status = child.wait().await?;
+                    return Err(UpscaleError::FlashSRFailed(failure_message(
+                        status.code(),
+                        &message,
+                    )));
+                }
+            }
+        }
+
+        // Keep draining stderr so library warnings cannot fill the pipe
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                debug!("FlashSR worker stderr: {}", line);
+            }
+        });
+
+        Ok(WorkerProcess {
+            _child: child,
+            stdin,
+            stdout,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::args;
+
+    #[test]
+    fn test_command_output_rate() {
+        let script = |flashsr: FlashSR| {
+            let cmd = flashsr.command(Path::new("in.wav"), Path::new("out.wav"));
+            args(&cmd)[1].clone()
+        };
+        let default = script(FlashSR::new(PathBuf::from("python3")));
+        assert!(default.contains("MODEL_SR = 48000\nOUT_SR = 48000\n"), "{}", default);
+        let custom = script(FlashSR::new(PathBuf::from("python3")).with_output_rate(Some(44100)));
+        assert!(custom.contains("MODEL_SR = 48000\nOUT_SR = 44100\n"), "{}", custom);
+    }
+
+    /// A stand-in interpreter speaking the worker protocol: it counts its
+    /// starts in `starts`, then copies each job's input to its output
+    #[cfg(unix)]
+    fn stub_python(dir: &Path) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = dir.join("python");
+        let script = format!(
+            "#!/bin/sh\necho start >> '{}'\necho READY\ntab=$(printf '\\t')\n\
+             while IFS=\"$tab\" read -r input output; do\n\
+             if cp \"$input\" \"$output\" 2>/dev/null; then echo OK; else echo \"ERR cannot read $input\"; fi\n\
+             done\n",
+            dir.join("starts").display()
+        );
+        std::fs::write(&path, script).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_worker_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let worker = FlashSRWorker::new(FlashSR::new(stub_python(dir.path())));
+        let input = dir.path().join("in.wav");
+        std::fs::write(&input, b"audio").unwrap();
+
+        worker.upscale(&input, &dir.path().join("out1.wav")).await.unwrap();
+        let missing = dir.path().join("missing.wav");
+        let err = worker.upscale(&missing, &dir.path().join("out2.wav")).await.unwrap_err();
+        assert!(err.to_string().contains("cannot read"), "{}", err);
+        worker.upscale(&input, &dir.path().join("out3.wav")).await.unwrap();
+
+        assert_eq!(std::fs::read(dir.path().join("out3.wav")).unwrap(), b"audio");
+        // A failed job leaves the process running for the next one
+        let starts = std::fs::read_to_string(dir.path().join("starts")).unwrap();
+        assert_eq!(starts.lines().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_worker_processes_run_side_by_side() {
+        let dir = tempfile::tempdir().unwrap();
+        let worker = FlashSRWorker::new(FlashSR::new(stub_python(dir.path()))).with_processes(2);
+        let input = dir.path().join("in.wav");
+        std::fs::write(&input, b"audio").unwrap();
+
+        let (a, b) = (dir.path().join("a.wav"), dir.path().join("b.wav"));
+        let (a, b) = tokio::join!(worker.upscale(&input, &a), worker.upscale(&input, &b));
+        a.unwrap();
+        b.unwrap();
+        let starts = std::fs::read_to_string(dir.path().join("starts")).unwrap();
+        assert_eq!(starts.lines().count(), 2);
+    }
+}
@@ -7,13 +7,125 @@
 mod error;
 mod flashsr;
 mod audiosr;
+#[cfg(test)]
+mod test_support;
 
 pub use error::UpscaleError;
-pub use flashsr::FlashSR;
-pub use audiosr::AudioSR;
+pub use flashsr::{FlashSR, FlashSRWorker};
+pub use audiosr::{AudioSR, MAX_AUDIOSR_SEED};
 
+use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
-use tracing::info;
+use std::process::Output;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+
+/// Rate both models reconstruct at
+pub const MODEL_SAMPLE_RATE: u32 = 48000;
+
+/// Lowest output rate the upscalers accept: below it the band the models add
+/// (up to 24kHz) would be cut off again
+pub const MIN_OUTPUT_RATE: u32 = 32000;
+
+/// Whether the upscalers can write `rate`: from [`MIN_OUTPUT_RATE`] up to
+/// [`MODEL_SAMPLE_RATE`], since resampling higher adds no content
+pub fn is_valid_output_rate(rate: u32) -> bool {
+    (MIN_OUTPUT_RATE..=MODEL_SAMPLE_RATE).contains(&rate)
+}
+
+/// `cmd` as a shell command line, with every argument that needs it quoted,
+/// so a logged invocation can be pasted back into a terminal
+pub fn command_line(cmd: &Command) -> String {
+    join_args(cmd, shell_quote)
+}
+
+/// [`command_line`] with inline scripts (arguments spanning several lines)
+/// replaced by a short placeholder, for a command meant to be read
+pub fn short_command_line(cmd: &Command) -> String {
+    join_args(cmd, |arg| match arg.lines().count() {
+        0 | 1 => shell_quote(arg),
+        lines => format!("<inline script, {} lines>", lines),
+    })
+}
+
+fn join_args(cmd: &Command, show: impl Fn(&str) -> String) -> String {
+    let cmd = cmd.as_std();
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| show(&arg.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Log the full command line at debug level; call just before spawning
+pub fn log_command(cmd: &Command) {
+    debug!("Running: {}", command_line(cmd));
+}
+
+/// Append a finished run's stdout and stderr to `log`, under a `name`
+/// heading. A log that cannot be written is only warned about.
+async fn append_log(log: &Path, name: &str, output: &Output) {
+    let entry = format!(
+        "=== {} ({}) ===\n--- stdout ---\n{}\n--- stderr ---\n{}\n",
+        name,
+        output.status,
+        String::from_utf8_lossy(&output.stdout).trim_end(),
+        String::from_utf8_lossy(&output.stderr).trim_end(),
+    );
+    let written = async {
+        let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(log).await?;
+        file.write_all(entry.as_bytes()).await
+    };
+    if let Err(e) = written.await {
+        warn!("Cannot write {}: {}", log.display(), e);
+    }
+}
+
+fn shell_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-+=%@:,./".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Render an optional string as a Python literal (`None` or a quoted string)
+fn python_literal(value: Option<&str>) -> String {
+    match value {
+        Some(d) => format!("{:?}", d),
+        None => "None".to_string(),
+    }
+}
+
+/// AudioSR checkpoint, passed to `build_model(model_name=...)`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioSRModel {
+    /// General-purpose model for music and mixed content
+    #[default]
+    Basic,
+    /// Tuned for spoken word (podcasts, lectures)
+    Speech,
+}
+
+impl AudioSRModel {
+    /// Model name as AudioSR expects it
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AudioSRModel::Basic => "basic",
+            AudioSRModel::Speech => "speech",
+        }
+    }
+}
+
+impl std::fmt::Display for AudioSRModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
 
 /// Upscaling method selection
 #[derive(Debug, Clone)]
@@ -22,8 +134,15 @@ pub enum UpscaleMethod {
     FlashSR,
     /// AudioSR - High-quality diffusion-based upscaling
     AudioSR {
+        model: AudioSRModel,
         ddim_steps: u32,
         guidance_scale: f32,
+        /// Window length for long inputs in seconds (0 = process in one pass)
+        chunk_secs: f32,
+        /// Crossfaded overlap between consecutive windows in seconds
+        overlap_secs: f32,
+        /// Diffusion seed; a random one per run when `None`
+        seed: Option<u64>,
     },
 }
 
@@ -40,11 +159,92 @@ impl std::fmt::Display for UpscaleMethod {
 #[derive(Debug)]
 pub struct Upscaler {
     python_path: PathBuf,
+    device: Option<String>,
+    flashsr_sha256: Option<String>,
+    flashsr_worker: Option<Arc<FlashSRWorker>>,
+    output_rate: Option<u32>,
+    log_file: Option<PathBuf>,
 }
 
 impl Upscaler {
     pub fn new(python_path: PathBuf) -> Self {
-        Self { python_path }
+        Self {
+            python_path,
+            device: None,
+            flashsr_sha256: None,
+            flashsr_worker: None,
+            output_rate: None,
+            log_file: None,
+        }
+    }
+
+    /// Append the Python script's full stdout and stderr to this file after
+    /// every run (see [`FlashSR::with_log_file`]). A shared worker only logs
+    /// through tracing.
+    pub fn with_log_file(mut self, log_file: Option<PathBuf>) -> Self {
+        self.log_file = log_file;
+        self
+    }
+
+    /// Write the result at `rate` instead of the models' 48kHz, resampling
+    /// once in Python (see [`FlashSR::with_output_rate`]). A shared worker
+    /// keeps its own setting.
+    pub fn with_output_rate(mut self, rate: Option<u32>) -> Self {
+        self.output_rate = rate;
+        self
+    }
+
+    /// Send FlashSR jobs to a shared long-lived worker instead of starting
+    /// Python per call. The worker keeps the device it was created with.
+    pub fn with_flashsr_worker(mut self, worker: Option<Arc<FlashSRWorker>>) -> Self {
+        self.flashsr_worker = worker;
+        self
+    }
+
+    /// Check the FlashSR model against this SHA256 before loading it (see
+    /// [`FlashSR::with_model_sha256`]). A shared worker keeps its own setting.
+    pub fn with_flashsr_sha256(mut self, sha256: Option<String>) -> Self {
+        self.flashsr_sha256 = sha256;
+        self
+    }
+
+    /// Run the models on a specific device (`cpu`, `cuda:0`, `mps`) instead of
+    /// auto-selecting one. An unusable device fails the upscale rather than
+    /// silently falling back.
+    pub fn with_device(mut self, device: Option<String>) -> Self {
+        self.device = device.filter(|d| !d.is_empty());
+        self
+    }
+
+    /// Build the Python invocation for the specified method
+    pub fn command(&self, input: &Path, output: &Path, method: &UpscaleMethod) -> Command {
+        match *method {
+            UpscaleMethod::FlashSR => FlashSR::new(self.python_path.clone())
+                .with_device(self.device.clone())
+                .with_model_sha256(self.flashsr_sha256.clone())
+                .with_output_rate(self.output_rate)
+                .command(input, output),
+            UpscaleMethod::AudioSR {
+                model,
+                ddim_steps,
+                guidance_scale,
+                chunk_secs,
+                overlap_secs,
+                seed,
+            } => AudioSR::new(self.python_path.clone())
+                .with_device(self.device.clone())
+                .with_model(model)
+                .with_seed(seed)
+                .with_output_rate(self.output_rate)
+                .command(
+                    input,
+                    output,
+                    ddim_steps,
+                    guidance_scale,
+                    chunk_secs,
+                    overlap_secs,
+                ),
+        }
     }
 
     /// Upscale audio using the specified method
@@ -57,14 +257,33 @@ impl Upscaler {
         info!("Upscaling with {}", method);
 
         match method {
-            UpscaleMethod::FlashSR => {
-                FlashSR::new(self.python_path.clone())
-                    .upscale(input, output)
-                    .await
-            }
-            UpscaleMethod::AudioSR { ddim_steps, guidance_scale } => {
+            UpscaleMethod::FlashSR => match self.flashsr_worker {
+                Some(ref worker) => worker.upscale(input, output).await,
+                None => {
+                    FlashSR::new(self.python_path.clone())
+                        .with_device(self.device.clone())
+                        .with_model_sha256(self.flashsr_sha256.clone())
+                        .with_output_rate(self.output_rate)
+                        .with_log_file(self.log_file.clone())
+                        .upscale(input, output)
+                        .await
+                }
+            },
+            UpscaleMethod::AudioSR {
+                model,
+                ddim_steps,
+                guidance_scale,
+                chunk_secs,
+                overlap_secs,
+                seed,
+            } => {
                 AudioSR::new(self.python_path.clone())
-                    .upscale(input, output, ddim_steps, guidance_scale)
+                    .with_device(self.device.clone())
+                    .with_model(model)
+                    .with_seed(seed)
+                    .with_output_rate(self.output_rate)
+                    .with_log_file(self.log_file.clone())
+                    .upscale(input, output, ddim_steps, guidance_scale, chunk_secs, overlap_secs)
                     .await
             }
         }
@@ -0,0 +1,8 @@
+//! Helpers for inspecting the commands built for the Python workers
+
+use tokio::process::Command;
+
+/// Arguments of `cmd`, without the program
+pub(crate) fn args(cmd: &Command) -> Vec<String> {
+    cmd.as_std().get_args().map(|arg| arg.to_string_lossy().into_owned()).collect()
+}